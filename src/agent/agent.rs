@@ -1,10 +1,13 @@
 use std::{collections::HashMap, rc::Rc};
 
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::{environment::environment::Env, scheduler::scheduler::Position};
 
-use super::{learning_agent::LearningAgent, state::State, swarm_agent::SwarmAgent};
+use super::{
+    learning_agent::LearningAgent, q_table::QTableError, state::State, swarm_agent::SwarmAgent,
+};
 
 pub type QTable = HashMap<Q, f32>;
 pub type Reward = f32;
@@ -38,23 +41,28 @@ pub trait IsAgent {
 
     fn set_q_value(&mut self, state: State, action: u32, value: f32);
 
-    /// Saves the q_table to a file
-    fn save_q_table(&self, filepath: &str);
+    /// Saves the q_table to a file (see `q_table::save_q_table`)
+    fn save_q_table(&self, filepath: &str) -> Result<(), QTableError>;
 
-    /// Load a q_table from a file
-    fn load_q_table(&mut self, filepath: &str);
+    /// Load a q_table from a file, trained with these `actions` and states like the one of
+    /// the agent
+    fn load_q_table(&mut self, filepath: &str, actions: &[Action]) -> Result<(), QTableError>;
 
-    /// Epsilon-greedy action selection
-    fn choose_action(&self, state: &State, actions: &Vec<u32>) -> u32;
+    /// Epsilon-greedy action selection, drawing from `rng`
+    fn choose_action(&self, state: &State, actions: &[u32], rng: &mut impl Rng) -> u32;
 
-    /// Q-learning update
+    /// Q-learning update. A `done` transition has no future. `rng` draws what the agent
+    /// learns from again, if it replays or plans.
+    #[allow(clippy::too_many_arguments)]
     fn update(
         &mut self,
         state: &State,
         action: &u32,
         reward: f32,
         next_state: &State,
-        next_actions: &Vec<u32>,
+        next_actions: &[u32],
+        done: Done,
+        rng: &mut impl Rng,
     );
 
     fn step(
@@ -109,24 +117,24 @@ impl IsAgent for Agent {
         };
     }
 
-    fn save_q_table(&self, filepath: &str) {
+    fn save_q_table(&self, filepath: &str) -> Result<(), QTableError> {
         match self {
             Agent::Learning(learning_agent) => learning_agent.save_q_table(filepath),
             Agent::Swarm(swarm_agent) => swarm_agent.save_q_table(filepath),
         }
     }
 
-    fn load_q_table(&mut self, filepath: &str) {
+    fn load_q_table(&mut self, filepath: &str, actions: &[Action]) -> Result<(), QTableError> {
         match self {
-            Agent::Learning(learning_agent) => learning_agent.load_q_table(filepath),
-            Agent::Swarm(swarm_agent) => swarm_agent.load_q_table(filepath),
+            Agent::Learning(learning_agent) => learning_agent.load_q_table(filepath, actions),
+            Agent::Swarm(swarm_agent) => swarm_agent.load_q_table(filepath, actions),
         }
     }
 
-    fn choose_action(&self, state: &State, actions: &Vec<u32>) -> u32 {
+    fn choose_action(&self, state: &State, actions: &[u32], rng: &mut impl Rng) -> u32 {
         match self {
-            Agent::Learning(learning_agent) => learning_agent.choose_action(state, actions),
-            Agent::Swarm(swarm_agent) => swarm_agent.choose_action(state, actions),
+            Agent::Learning(learning_agent) => learning_agent.choose_action(state, actions, rng),
+            Agent::Swarm(swarm_agent) => swarm_agent.choose_action(state, actions, rng),
         }
     }
 
@@ -136,14 +144,16 @@ impl IsAgent for Agent {
         action: &u32,
         reward: f32,
         next_state: &State,
-        next_actions: &Vec<u32>,
+        next_actions: &[u32],
+        done: Done,
+        rng: &mut impl Rng,
     ) {
         match self {
             Agent::Learning(learning_agent) => {
-                learning_agent.update(state, action, reward, next_state, next_actions)
+                learning_agent.update(state, action, reward, next_state, next_actions, done, rng)
             }
             Agent::Swarm(swarm_agent) => {
-                swarm_agent.update(state, action, reward, next_state, next_actions)
+                swarm_agent.update(state, action, reward, next_state, next_actions, done, rng)
             }
         }
     }
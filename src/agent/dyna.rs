@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use super::{
+    agent::{Action, Done, Reward, Q},
+    replay::Transition,
+    state::State,
+};
+
+/// Settings of Dyna-Q planning
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DynaConfig {
+    /// Simulated updates after each real step
+    pub planning_steps: usize,
+    /// Dyna-Q+: bonus `exploration_bonus * sqrt(steps since the pair was last tried)` added to
+    /// the simulated rewards, so that the agent tries again what it has not tried for long.
+    /// 0 for plain Dyna-Q.
+    pub exploration_bonus: f32,
+}
+
+impl Default for DynaConfig {
+    fn default() -> Self {
+        DynaConfig {
+            planning_steps: 10,
+            exploration_bonus: 0.,
+        }
+    }
+}
+
+/// What the model remembers of a state and action: what happened the last time it was tried
+#[derive(Clone, Debug, PartialEq)]
+struct Outcome {
+    next_state: State,
+    next_actions: Vec<Action>,
+    reward: Reward,
+    done: Done,
+    /// Real step when the pair was last tried
+    last: u64,
+}
+
+/// Model of the environment learned from the real steps of an agent, assuming the last
+/// outcome of a state and action is what always happens.
+///
+/// ## Example
+/// ```rust
+/// let mut model = Model::default();
+/// model.observe(&transition, &env.actions, config.exploration_bonus > 0.);
+/// for _ in 0..config.planning_steps {
+///     let simulated = model.simulate(&mut rng, config.exploration_bonus).unwrap();
+///     agent.learn(&simulated);
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Model {
+    outcomes: HashMap<Q, Outcome>,
+    /// Keys of `outcomes`, to draw them uniformly
+    pairs: Vec<Q>,
+    /// Number of real steps observed
+    steps: u64,
+}
+
+impl Model {
+    /// Remember the outcome of a real step. With `untried_actions`, the actions never tried
+    /// in a new state are assumed to lead back to it with no reward (as Dyna-Q+ does), so the
+    /// exploration bonus also draws the agent to them.
+    pub fn observe(&mut self, transition: &Transition, actions: &[Action], untried_actions: bool) {
+        self.steps += 1;
+
+        let is_new_state = !actions
+            .iter()
+            .chain([&transition.action])
+            .any(|action| self.outcomes.contains_key(&q(&transition.state, *action)));
+        if untried_actions && is_new_state {
+            for action in actions {
+                self.insert(
+                    q(&transition.state, *action),
+                    Outcome {
+                        next_state: transition.state.clone(),
+                        next_actions: actions.to_vec(),
+                        reward: 0.,
+                        done: false,
+                        last: 0,
+                    },
+                );
+            }
+        }
+
+        self.insert(
+            q(&transition.state, transition.action),
+            Outcome {
+                next_state: transition.next_state.clone(),
+                next_actions: transition.next_actions.clone(),
+                reward: transition.reward,
+                done: transition.done,
+                last: self.steps,
+            },
+        );
+    }
+
+    /// A transition of a state and action tried before, drawn uniformly. `None` if nothing
+    /// was observed yet.
+    pub fn simulate<R: Rng>(&self, rng: &mut R, exploration_bonus: f32) -> Option<Transition> {
+        if self.pairs.is_empty() {
+            return None;
+        }
+
+        let q = &self.pairs[rng.random_range(0..self.pairs.len())];
+        let outcome = &self.outcomes[q];
+        let staleness = (self.steps - outcome.last) as f32;
+        Some(Transition {
+            state: q.state.clone(),
+            action: q.action,
+            reward: outcome.reward + exploration_bonus * staleness.sqrt(),
+            next_state: outcome.next_state.clone(),
+            next_actions: outcome.next_actions.clone(),
+            done: outcome.done,
+        })
+    }
+
+    /// Number of states and actions known
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    fn insert(&mut self, q: Q, outcome: Outcome) {
+        if self.outcomes.insert(q.clone(), outcome).is_none() {
+            self.pairs.push(q);
+        }
+    }
+}
+
+fn q(state: &State, action: Action) -> Q {
+    Q {
+        state: state.clone(),
+        action,
+    }
+}
+
+/// Model and settings of an agent planning with Dyna-Q
+#[derive(Clone, Debug, Default)]
+pub struct Dyna {
+    pub config: DynaConfig,
+    pub model: Model,
+}
+
+impl Dyna {
+    pub fn new(config: DynaConfig) -> Self {
+        Dyna {
+            config,
+            model: Model::default(),
+        }
+    }
+
+    /// Learn the real transition in the model, then make `planning_steps` simulated updates
+    /// with `learn`
+    pub fn plan<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        transition: &Transition,
+        actions: &[Action],
+        mut learn: impl FnMut(&Transition),
+    ) {
+        let bonus = self.config.exploration_bonus;
+        self.model.observe(transition, actions, bonus > 0.);
+        for _ in 0..self.config.planning_steps {
+            if let Some(simulated) = self.model.simulate(rng, bonus) {
+                learn(&simulated);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::agent::state::Value;
+
+    fn transition(state: i32, action: Action, reward: Reward, next_state: i32) -> Transition {
+        Transition {
+            state: vec![Value::VI32(state)],
+            action,
+            reward,
+            next_state: vec![Value::VI32(next_state)],
+            next_actions: vec![0, 1],
+            done: false,
+        }
+    }
+
+    #[test]
+    fn model() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut model = Model::default();
+        assert!(model.simulate(&mut rng, 0.).is_none());
+
+        model.observe(&transition(0, 1, -1., 1), &[0, 1], false);
+        model.observe(&transition(0, 1, 5., 2), &[0, 1], false);
+        assert_eq!(model.len(), 1);
+        // The last outcome is remembered
+        assert_eq!(model.simulate(&mut rng, 0.), Some(transition(0, 1, 5., 2)));
+
+        // Dyna-Q+: the untried action stays in place, and the bonus grows with time
+        let mut model = Model::default();
+        model.observe(&transition(0, 1, -1., 1), &[0, 1], true);
+        for _ in 0..3 {
+            model.observe(&transition(1, 0, 0., 1), &[0, 1], true);
+        }
+        assert_eq!(model.len(), 4);
+        let simulated: Vec<Transition> = (0..100)
+            .filter_map(|_| model.simulate(&mut rng, 0.5))
+            .collect();
+        assert!(simulated.contains(&transition(0, 0, 0.5 * 4f32.sqrt(), 0)));
+        assert!(simulated.contains(&transition(0, 1, -1. + 0.5 * 3f32.sqrt(), 1)));
+        assert!(simulated.contains(&transition(1, 0, 0., 1)));
+    }
+
+    #[test]
+    fn planning_steps() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut dyna = Dyna::new(DynaConfig {
+            planning_steps: 4,
+            ..DynaConfig::default()
+        });
+
+        let mut simulated = Vec::new();
+        for i in 0..3 {
+            dyna.plan(
+                &mut rng,
+                &transition(i, 0, 1., i + 1),
+                &[0, 1],
+                |transition| simulated.push(transition.clone()),
+            );
+            assert_eq!(simulated.len(), 4 * (i as usize + 1));
+        }
+        // Only what was really observed is simulated
+        assert!(simulated
+            .iter()
+            .all(|simulated| (0..3).any(|i| *simulated == transition(i, 0, 1., i + 1))));
+    }
+}
@@ -1,16 +1,15 @@
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufReader, BufWriter},
-    rc::Rc,
-};
+use std::{collections::HashMap, rc::Rc};
 
-use macroquad::rand::ChooseRandom;
+use rand::{seq::IndexedRandom, Rng};
 
 use crate::{environment::environment::Env, scheduler::scheduler::Position};
 
 use super::{
-    agent::{Done, IsAgent, QTable, Reward, StepFunction, Q},
+    agent::{Action, Done, IsAgent, QTable, Reward, StepFunction, Q},
+    dyna::Dyna,
+    merge::Visits,
+    q_table::{self, Fingerprint, QTableError},
+    replay::{ReplayRef, Transition},
     state::State,
 };
 
@@ -24,6 +23,14 @@ pub struct LearningAgent {
     pub state: State,
     /// Q-values
     q_table: QTable,
+    /// Real steps learned from since the table was last replaced, to merge it
+    visits: Visits,
+    /// Number of real steps learned from since the table was last replaced
+    updates: u64,
+    /// Past transitions learned from again, none without experience replay
+    pub replay: Option<ReplayRef>,
+    /// Model of the environment to plan with, none without Dyna-Q
+    pub dyna: Option<Dyna>,
     /// alpha / learning rate
     pub learning_rate: f32,
     /// gamma / discount factor
@@ -64,69 +71,88 @@ impl IsAgent for LearningAgent {
         self.q_table.insert(Q { state, action }, value);
     }
 
-    fn save_q_table(&self, filepath: &str) {
-        let file = File::create(filepath).expect("Failed to create file");
-        let mut writer = BufWriter::new(file);
-
-        bincode::serialize_into(&mut writer, &self.q_table).expect("Failed to write q_table");
+    fn save_q_table(&self, filepath: &str) -> Result<(), QTableError> {
+        q_table::save_q_table(&self.q_table, filepath)
     }
 
-    fn load_q_table(&mut self, filepath: &str) {
-        let file = match File::open(filepath) {
-            Ok(file) => file,
-            Err(_) => return, // We do not wish to crash if the file is non-existant
-        };
-
-        let mut reader = BufReader::new(file);
-
-        let q_table: QTable =
-            bincode::deserialize_from(&mut reader).expect("Failed to read q_table");
-        self.q_table = q_table;
+    fn load_q_table(&mut self, filepath: &str, actions: &[Action]) -> Result<(), QTableError> {
+        let expected = Fingerprint::expected(actions, &self.state);
+        self.q_table = q_table::load_q_table(filepath, Some(&expected))?;
+        Ok(())
     }
 
-    /// Epsilon-greedy action selection
-    fn choose_action(&self, state: &State, actions: &Vec<u32>) -> u32 {
-        if rand::random_range(0.0..1.) < self.exploration_rate || self.q_table.is_empty() {
-            return *actions.choose().unwrap();
+    /// Epsilon-greedy action selection. The actions are looked at in their order, so the
+    /// choice only depends on `rng`.
+    fn choose_action(&self, state: &State, actions: &[u32], rng: &mut impl Rng) -> u32 {
+        if rng.random_range(0.0..1.) < self.exploration_rate || self.q_table.is_empty() {
+            return *actions.choose(rng).unwrap();
         }
 
-        let q_values = self.q_values_subset(state, actions);
-
-        let max_entry = q_values.iter().max_by(|a, b| a.1.total_cmp(b.1));
+        let q_values: Vec<(Action, f32)> = actions
+            .iter()
+            .filter_map(|action| {
+                self.q_table
+                    .get(&Q {
+                        state: state.clone(),
+                        action: *action,
+                    })
+                    .map(|value| (*action, *value))
+            })
+            .collect();
+        let max = q_values
+            .iter()
+            .map(|(_, value)| *value)
+            .max_by(f32::total_cmp);
 
-        if let Some((_, val)) = max_entry {
-            let max = val;
-            let possible_actions: HashMap<_, _> =
-                q_values.iter().filter(|(_, &v)| v == *max).collect();
-            let possible_actions: Vec<_> = possible_actions
+        if let Some(max) = max {
+            let possible_actions: Vec<Action> = q_values
                 .iter()
-                .map(|(&q, _)| q.action.clone())
+                .filter(|(_, value)| *value == max)
+                .map(|(action, _)| *action)
                 .collect();
-            return *possible_actions.choose().unwrap();
+            return *possible_actions.choose(rng).unwrap();
         }
 
-        return *actions.choose().unwrap();
+        *actions.choose(rng).unwrap()
     }
 
-    /// Q-learning update
-    ///
-    /// Q-learning update rule:
-    /// Q(s, a) <- Q(s, a) + alpha * (reward + gamma * max_a' Q(s', a') - Q(s, a))
+    /// Q-learning update of the transition, then replay of past ones if the agent has a
+    /// replay buffer and planning if it uses Dyna-Q
     fn update(
         &mut self,
         state: &State,
         action: &u32,
         reward: f32,
         next_state: &State,
-        next_actions: &Vec<u32>,
+        next_actions: &[u32],
+        done: Done,
+        rng: &mut impl Rng,
     ) {
-        let old_q_value = self.get_q_value(state.clone(), *action);
-        let future_q_value = self.max_q_val(&self.q_values_subset(next_state, next_actions));
+        let transition = Transition {
+            state: state.clone(),
+            action: *action,
+            reward,
+            next_state: next_state.clone(),
+            next_actions: next_actions.to_vec(),
+            done,
+        };
+        self.learn(&transition);
+        self.visit(state, *action);
+
+        if let Some(replay) = self.replay.clone() {
+            let mut replay = replay.borrow_mut();
+            replay.push(transition.clone());
+            if replay.is_due() {
+                replay.replay_with(rng, |transition| self.learn(transition));
+            }
+        }
 
-        // Q-learning update
-        let new_q_value = old_q_value
-            + self.learning_rate * (reward + self.discount_factor * future_q_value - old_q_value);
-        self.set_q_value(state.clone(), *action, new_q_value);
+        if let Some(mut dyna) = self.dyna.take() {
+            dyna.plan(rng, &transition, next_actions, |simulated| {
+                self.learn(simulated);
+            });
+            self.dyna = Some(dyna);
+        }
     }
 
     fn step(
@@ -136,11 +162,12 @@ impl IsAgent for LearningAgent {
         state: &State,
         action: &super::agent::Action,
     ) -> (Position, State, Reward, Done) {
-        (self.step_fn)(&self, env, position, state, action)
+        (self.step_fn)(self, env, position, state, action)
     }
 }
 
 impl LearningAgent {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: u32,
         agent_type: &'static str,
@@ -149,32 +176,94 @@ impl LearningAgent {
         discount_factor: Option<f32>,
         exploration_rate: Option<f32>,
         step_fn: &StepFunction<LearningAgent>,
-        q_table_filepath: Option<&str>,
+        q_table: QTable,
     ) -> Self {
         let learning_rate = learning_rate.unwrap_or(0.1);
         let discount_factor = discount_factor.unwrap_or(0.9);
         let exploration_rate = exploration_rate.unwrap_or(0.2);
 
-        let mut new_agent = LearningAgent {
+        LearningAgent {
             id,
             agent_type,
             state,
-            q_table: HashMap::new(),
+            q_table,
+            visits: Visits::new(),
+            updates: 0,
+            replay: None,
+            dyna: None,
             learning_rate,
             discount_factor,
             exploration_rate,
             step_fn: Rc::clone(step_fn),
+        }
+    }
+
+    pub fn q_table(&self) -> &QTable {
+        &self.q_table
+    }
+
+    pub fn visits(&self) -> &Visits {
+        &self.visits
+    }
+
+    /// Replace the Q-table, by a merged one for example, and forget the visits
+    pub fn replace_q_table(&mut self, q_table: QTable) {
+        self.q_table = q_table;
+        self.visits.clear();
+        self.updates = 0;
+    }
+
+    /// Q-learning update
+    ///
+    /// Q-learning update rule:
+    /// Q(s, a) <- Q(s, a) + alpha * (reward + gamma * max_a' Q(s', a') - Q(s, a))
+    ///
+    /// There is no future (max_a' Q(s', a') = 0) when the transition is done.
+    /// Returns the TD error. Replayed and simulated transitions are learned this way too, so
+    /// it does not count as a visit (see `update`).
+    pub fn learn(&mut self, transition: &Transition) -> f32 {
+        let Transition {
+            state,
+            action,
+            reward,
+            next_state,
+            next_actions,
+            done,
+        } = transition;
+        let old_q_value = self.get_q_value(state.clone(), *action);
+        let future_q_value = if *done {
+            0.
+        } else {
+            self.max_q_val(&self.q_values_subset(next_state, next_actions))
         };
 
-        if let Some(filepath) = q_table_filepath {
-            new_agent.load_q_table(filepath);
-        }
+        // Q-learning update
+        let td_error = reward + self.discount_factor * future_q_value - old_q_value;
+        self.set_q_value(
+            state.clone(),
+            *action,
+            old_q_value + self.learning_rate * td_error,
+        );
+
+        td_error
+    }
 
-        new_agent
+    /// Count a real step of the agent from `state` with `action`, to merge the tables
+    fn visit(&mut self, state: &State, action: Action) {
+        self.updates += 1;
+        let visit = self
+            .visits
+            .entry(Q {
+                state: state.clone(),
+                action,
+            })
+            .or_default();
+        visit.count += 1;
+        visit.last = self.updates;
     }
 
     /// Returns subset of q values with the same state and actions
-    fn q_values_subset(&self, state: &State, actions: &Vec<u32>) -> HashMap<&Q, &f32> {
+    fn q_values_subset(&self, state: &State, actions: &[u32]) -> HashMap<&Q, &f32> {
         self.q_table
             .iter()
             .filter(|(k, _)| k.state == *state && actions.contains(&k.action))
@@ -195,8 +284,14 @@ impl LearningAgent {
 mod tests {
     use masim::define_const;
 
+    use std::cell::RefCell;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
     use crate::agent::{
         agent::{Action, Done, Reward},
+        dyna::DynaConfig,
+        replay::{ReplayBuffer, ReplayConfig},
         state::Value,
     };
 
@@ -226,28 +321,33 @@ mod tests {
             None,
             Some(0.),
             &func,
-            None,
+            QTable::new(),
         );
 
         define_const!(ACTIONS => EAT, MOVE, DANCE, SING);
         let actions = Vec::from(ACTIONS);
+        // Seeded, so that the test always draws the same actions
+        let mut rng = StdRng::seed_from_u64(2);
 
         agent.set_q_value(default_state.clone(), EAT, 0.);
         agent.set_q_value(default_state.clone(), MOVE, 1.);
         agent.set_q_value(default_state.clone(), DANCE, 2.);
         agent.set_q_value(default_state.clone(), SING, 3.);
 
-        assert_eq!(agent.choose_action(&default_state, &actions), SING);
+        assert_eq!(
+            agent.choose_action(&default_state, &actions, &mut rng),
+            SING
+        );
         assert_ne!(
-            agent.choose_action(&vec![Value::VBool(true)], &actions),
+            agent.choose_action(&vec![Value::VBool(true)], &actions, &mut rng),
             SING
         );
 
         agent.set_q_value(default_state.clone(), EAT, 4.);
 
-        assert_eq!(agent.choose_action(&default_state, &actions), EAT);
+        assert_eq!(agent.choose_action(&default_state, &actions, &mut rng), EAT);
         assert_ne!(
-            agent.choose_action(&vec![Value::VBool(false)], &actions),
+            agent.choose_action(&vec![Value::VBool(false)], &actions, &mut rng),
             EAT
         );
 
@@ -257,7 +357,7 @@ mod tests {
         let mut count_move = 0;
 
         for _ in 0..1000 {
-            let result = agent.choose_action(&default_state, &actions);
+            let result = agent.choose_action(&default_state, &actions, &mut rng);
 
             match result {
                 EAT => count_eat += 1,
@@ -271,4 +371,98 @@ mod tests {
         assert!(count_eat > 0);
         assert!(count_move > 0);
     }
+
+    #[test]
+    fn replaying_terminal_transitions() {
+        let func: StepFunction<LearningAgent> =
+            Rc::new(|_, _, position, state, _| (position, state.clone(), 0., false));
+        let state = vec![Value::VI32(0)];
+        let mut agent = LearningAgent::new(
+            1,
+            "walker",
+            state.clone(),
+            Some(0.5),
+            Some(0.9),
+            None,
+            &func,
+            QTable::new(),
+        );
+        agent.replay = Some(Rc::new(RefCell::new(ReplayBuffer::new(ReplayConfig {
+            batch_size: 4,
+            ..ReplayConfig::default()
+        }))));
+
+        // Staying is worth 1 forever, leaving gives 1 and ends the episode
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..50 {
+            agent.update(&state, &0, 1., &state, &[0, 1], false, &mut rng);
+            agent.update(&state, &1, 1., &state, &[0, 1], true, &mut rng);
+        }
+
+        // Nothing after the end, but 1 / (1 - 0.9) when staying
+        assert!((agent.get_q_value(state.clone(), 1) - 1.).abs() < 1e-3);
+        assert!(agent.get_q_value(state.clone(), 0) > 9.);
+        assert_eq!(agent.replay.as_ref().unwrap().borrow().len(), 100);
+        // Only the real steps are visits, not the replayed transitions
+        let visits: u32 = agent.visits().values().map(|visit| visit.count).sum();
+        assert_eq!(visits, 100);
+    }
+
+    /// Real steps in a corridor of 5 cells, from the left end to the reward at the right
+    /// end, until the agent values going right from the start close to the optimum
+    fn steps_to_learn_the_corridor(dyna: Option<DynaConfig>) -> u32 {
+        let func: StepFunction<LearningAgent> =
+            Rc::new(|_, _, position, state, _| (position, state.clone(), 0., false));
+        let cell = |x: i32| vec![Value::VI32(x)];
+        let mut agent = LearningAgent::new(
+            1,
+            "walker",
+            cell(0),
+            Some(0.5),
+            Some(0.9),
+            Some(0.3),
+            &func,
+            QTable::new(),
+        );
+        agent.dyna = dyna.map(Dyna::new);
+
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut x = 0;
+        for steps in 1..10_000 {
+            let action = agent.choose_action(&cell(x), &[0, 1], &mut rng);
+            let next = if action == 1 { x + 1 } else { (x - 1).max(0) };
+            let done = next == 4;
+            let reward = if done { 1. } else { 0. };
+            agent.update(
+                &cell(x),
+                &action,
+                reward,
+                &cell(next),
+                &[0, 1],
+                done,
+                &mut rng,
+            );
+            x = if done { 0 } else { next };
+
+            if agent.get_q_value(cell(0), 1) > 0.9 * 0.9f32.powi(3) {
+                return steps;
+            }
+        }
+        panic!("The corridor was never learned");
+    }
+
+    #[test]
+    fn planning_learns_faster() {
+        let without = steps_to_learn_the_corridor(None);
+        let with = steps_to_learn_the_corridor(Some(DynaConfig {
+            planning_steps: 10,
+            exploration_bonus: 0.,
+        }));
+        assert!(
+            with * 2 < without,
+            "{} with planning, {} without",
+            with,
+            without
+        );
+    }
 }
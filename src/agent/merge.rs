@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use super::agent::{QTable, Q};
+
+/// How an entry of a Q-table was updated since the tables were last merged. Only the real
+/// steps of the agent count, not the replayed or simulated transitions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Visit {
+    /// Number of updates
+    pub count: u32,
+    /// Number of updates of the whole table when the entry was last updated. An agent acting
+    /// every tick updates its table once per tick, so this is the tick of the update.
+    pub last: u64,
+}
+
+pub type Visits = HashMap<Q, Visit>;
+
+/// How the values of an entry present in several Q-tables are combined
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// Mean weighted by the number of visits. Tables without visits weigh 1 per entry.
+    #[default]
+    Average,
+    /// Most optimistic value
+    Max,
+    /// Value of the table that updated the entry last. Without visits, the last table wins.
+    Recent,
+}
+
+/// Merging of the Q-tables of the learning agents of a same type, every `interval` ticks.
+/// Between two merges each agent learns on its own.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct QTableSync {
+    pub interval: u32,
+    #[serde(default)]
+    pub strategy: MergeStrategy,
+}
+
+impl QTableSync {
+    /// The tables are merged after this tick
+    pub fn is_due(&self, tick: u64) -> bool {
+        self.interval > 0 && tick.is_multiple_of(self.interval as u64)
+    }
+}
+
+/// One value of an entry, with how it was visited if known
+struct Candidate {
+    value: f32,
+    visit: Option<Visit>,
+    /// Index of its table
+    index: usize,
+}
+
+/// Merge tables, with the visits of their entries when known. An entry missing from a table
+/// is not taken into account for that table.
+///
+/// ## Example
+/// ```rust
+/// let merged = merge_q_tables(
+///     &[(&left, Some(&left_visits)), (&right, Some(&right_visits))],
+///     MergeStrategy::Average,
+/// );
+/// ```
+pub fn merge_q_tables(tables: &[(&QTable, Option<&Visits>)], strategy: MergeStrategy) -> QTable {
+    let mut candidates: HashMap<&Q, Vec<Candidate>> = HashMap::new();
+    for (index, (q_table, visits)) in tables.iter().enumerate() {
+        for (q, value) in q_table.iter() {
+            candidates.entry(q).or_default().push(Candidate {
+                value: *value,
+                visit: visits.map(|visits| visits.get(q).copied().unwrap_or_default()),
+                index,
+            });
+        }
+    }
+
+    candidates
+        .into_iter()
+        .map(|(q, candidates)| (q.clone(), merge_values(&candidates, strategy)))
+        .collect()
+}
+
+fn merge_values(candidates: &[Candidate], strategy: MergeStrategy) -> f32 {
+    match strategy {
+        MergeStrategy::Average => {
+            let weight = |candidate: &Candidate| match candidate.visit {
+                Some(visit) => visit.count as f32,
+                None => 1.,
+            };
+            let total: f32 = candidates.iter().map(weight).sum();
+            if total == 0. {
+                // Nobody learned anything new about it
+                candidates
+                    .iter()
+                    .map(|candidate| candidate.value)
+                    .sum::<f32>()
+                    / candidates.len() as f32
+            } else {
+                candidates
+                    .iter()
+                    .map(|candidate| candidate.value * weight(candidate))
+                    .sum::<f32>()
+                    / total
+            }
+        }
+        MergeStrategy::Max => candidates
+            .iter()
+            .map(|candidate| candidate.value)
+            .fold(f32::NEG_INFINITY, f32::max),
+        MergeStrategy::Recent => {
+            let recency = |candidate: &Candidate| match candidate.visit {
+                Some(visit) => (visit.count > 0, visit.last, candidate.index),
+                None => (true, 0, candidate.index),
+            };
+            candidates
+                .iter()
+                .max_by_key(|candidate| recency(candidate))
+                .unwrap()
+                .value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::state::Value;
+
+    fn q(state: i32, action: u32) -> Q {
+        Q {
+            state: vec![Value::VI32(state)],
+            action,
+        }
+    }
+
+    fn visit(count: u32, last: u64) -> Visit {
+        Visit { count, last }
+    }
+
+    #[test]
+    fn strategies() {
+        let left = QTable::from([(q(0, 0), 1.), (q(0, 1), 4.), (q(1, 0), -1.)]);
+        let right = QTable::from([(q(0, 0), 3.), (q(0, 1), 2.), (q(2, 0), 5.)]);
+        let left_visits = Visits::from([(q(0, 0), visit(3, 7)), (q(0, 1), visit(1, 2))]);
+        let right_visits = Visits::from([(q(0, 0), visit(1, 4)), (q(0, 1), visit(0, 0))]);
+        let tables = [(&left, Some(&left_visits)), (&right, Some(&right_visits))];
+
+        let average = merge_q_tables(&tables, MergeStrategy::Average);
+        assert_eq!(average[&q(0, 0)], (3. * 1. + 3.) / 4.);
+        assert_eq!(average[&q(0, 1)], 4.);
+        // Known by one table only
+        assert_eq!(average[&q(1, 0)], -1.);
+        assert_eq!(average[&q(2, 0)], 5.);
+        assert_eq!(average.len(), 4);
+
+        let max = merge_q_tables(&tables, MergeStrategy::Max);
+        assert_eq!(max[&q(0, 0)], 3.);
+        assert_eq!(max[&q(0, 1)], 4.);
+
+        let recent = merge_q_tables(&tables, MergeStrategy::Recent);
+        assert_eq!(recent[&q(0, 0)], 1.);
+        assert_eq!(recent[&q(0, 1)], 4.);
+
+        // Without visits every table weighs the same and the last one is the most recent
+        let tables = [(&left, None), (&right, None)];
+        assert_eq!(
+            merge_q_tables(&tables, MergeStrategy::Average)[&q(0, 0)],
+            2.
+        );
+        assert_eq!(merge_q_tables(&tables, MergeStrategy::Recent)[&q(0, 1)], 2.);
+    }
+}
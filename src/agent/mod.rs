@@ -1,4 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod agent;
+pub mod dyna;
 pub mod learning_agent;
+pub mod merge;
+pub mod parallel_agent;
+pub mod q_table;
+pub mod replay;
 pub mod state;
 pub mod swarm_agent;
@@ -0,0 +1,410 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Arc, RwLock},
+};
+
+use rand::{rngs::StdRng, seq::IndexedRandom, Rng};
+
+use crate::{environment::environment::Env, scheduler::scheduler::Position};
+
+use super::{
+    agent::{Action, Done, QTable, Reward, Q},
+    dyna::Dyna,
+    merge::Visits,
+    q_table::{load_q_table_or_empty, save_q_table, Fingerprint, QTableError},
+    replay::{SharedReplay, Transition},
+    state::State,
+};
+
+/// Number of shards of a `ShardedQTable` when none is given
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Step function of a `ParallelAgent`. It only reads the environment since the agents step
+/// at the same time, changes to the world go in the post step functions of the scheduler.
+/// Randomness must come from the given generator for seeded runs to be reproducible.
+pub type ParallelStepFunction = Arc<
+    dyn Fn(
+            &ParallelAgent,
+            &Env,
+            Position,
+            &State,
+            &Action,
+            &mut StdRng,
+        ) -> (Position, State, Reward, Done)
+        + Send
+        + Sync,
+>;
+
+/// Q-table split in shards behind their own lock, so that agents on different threads
+/// sharing it rarely wait for each other. A key always goes to the same shard.
+#[derive(Debug)]
+pub struct ShardedQTable {
+    shards: Vec<RwLock<QTable>>,
+}
+
+impl Default for ShardedQTable {
+    fn default() -> Self {
+        ShardedQTable::new(DEFAULT_SHARDS)
+    }
+}
+
+impl ShardedQTable {
+    pub fn new(shards: usize) -> Self {
+        ShardedQTable {
+            shards: (0..shards.max(1))
+                .map(|_| RwLock::new(QTable::new()))
+                .collect(),
+        }
+    }
+
+    pub fn from_q_table(q_table: QTable, shards: usize) -> Self {
+        let sharded = ShardedQTable::new(shards);
+        for (q, value) in q_table {
+            sharded.set(q, value);
+        }
+
+        sharded
+    }
+
+    pub fn get(&self, q: &Q) -> Option<f32> {
+        self.shard(q).read().unwrap().get(q).copied()
+    }
+
+    pub fn set(&self, q: Q, value: f32) {
+        self.shard(&q).write().unwrap().insert(q, value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replace every entry by those of `q_table`
+    pub fn replace(&self, q_table: QTable) {
+        for shard in &self.shards {
+            shard.write().unwrap().clear();
+        }
+        for (q, value) in q_table {
+            self.set(q, value);
+        }
+    }
+
+    /// Copy of every entry in a single table
+    pub fn to_q_table(&self) -> QTable {
+        let mut q_table = QTable::with_capacity(self.len());
+        for shard in &self.shards {
+            q_table.extend(
+                shard
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|(q, value)| (q.clone(), *value)),
+            );
+        }
+
+        q_table
+    }
+
+    /// Saved like the Q-table of any agent, so learning agents can load the file too
+    pub fn save(&self, filepath: &str) -> Result<(), QTableError> {
+        save_q_table(&self.to_q_table(), filepath)
+    }
+
+    /// Load a Q-table file, empty if the file does not exist
+    pub fn load(
+        filepath: &str,
+        shards: usize,
+        expected: &Fingerprint,
+    ) -> Result<Self, QTableError> {
+        let q_table = load_q_table_or_empty(filepath, Some(expected))?;
+        Ok(ShardedQTable::from_q_table(q_table, shards))
+    }
+
+    fn shard(&self, q: &Q) -> &RwLock<QTable> {
+        // `DefaultHasher::new` always uses the same keys, so is the sharding
+        let mut hasher = DefaultHasher::new();
+        q.hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+}
+
+/// Learning agent that can step on any thread (`Send + Sync`).
+/// Its Q-table can be shared by several agents, like a swarm.
+pub struct ParallelAgent {
+    /// unique id of the agent
+    pub id: u32,
+    /// The type of the agent. Example: wolf, sheep, etc.
+    pub agent_type: &'static str,
+    pub state: State,
+    /// Q-values, possibly shared with other agents
+    q_table: Arc<ShardedQTable>,
+    /// Real steps learned from since the table was last replaced, to merge it
+    visits: Visits,
+    /// Number of real steps learned from since the table was last replaced
+    updates: u64,
+    /// Past transitions learned from again, none without experience replay
+    pub replay: Option<SharedReplay>,
+    /// Model of the environment to plan with, none without Dyna-Q
+    pub dyna: Option<Dyna>,
+    /// alpha / learning rate
+    pub learning_rate: f32,
+    /// gamma / discount factor
+    pub discount_factor: f32,
+    /// epsilon / exploration rate
+    pub exploration_rate: f32,
+    /// Function representing a step
+    step_fn: ParallelStepFunction,
+}
+
+impl ParallelAgent {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: u32,
+        agent_type: &'static str,
+        state: State,
+        learning_rate: Option<f32>,
+        discount_factor: Option<f32>,
+        exploration_rate: Option<f32>,
+        step_fn: &ParallelStepFunction,
+        q_table: Arc<ShardedQTable>,
+    ) -> Self {
+        ParallelAgent {
+            id,
+            agent_type,
+            state,
+            q_table,
+            visits: Visits::new(),
+            updates: 0,
+            replay: None,
+            dyna: None,
+            learning_rate: learning_rate.unwrap_or(0.1),
+            discount_factor: discount_factor.unwrap_or(0.9),
+            exploration_rate: exploration_rate.unwrap_or(0.2),
+            step_fn: Arc::clone(step_fn),
+        }
+    }
+
+    pub fn q_table(&self) -> &Arc<ShardedQTable> {
+        &self.q_table
+    }
+
+    pub fn get_q_value(&self, state: &State, action: Action) -> f32 {
+        self.q_table
+            .get(&Q {
+                state: state.clone(),
+                action,
+            })
+            .unwrap_or(0.)
+    }
+
+    /// Epsilon-greedy action selection. Ties are broken with `rng` and the actions are always
+    /// looked at in the same order, so the choice only depends on the generator.
+    pub fn choose_action(&self, state: &State, actions: &[Action], rng: &mut StdRng) -> Action {
+        if rng.random_range(0.0..1.) < self.exploration_rate {
+            return *actions.choose(rng).unwrap();
+        }
+
+        let q_values: Vec<f32> = actions
+            .iter()
+            .map(|action| self.get_q_value(state, *action))
+            .collect();
+        let max = q_values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        let best: Vec<Action> = actions
+            .iter()
+            .zip(q_values)
+            .filter(|(_, value)| *value == max)
+            .map(|(action, _)| *action)
+            .collect();
+        *best.choose(rng).unwrap()
+    }
+
+    pub fn visits(&self) -> &Visits {
+        &self.visits
+    }
+
+    /// Replace the values of the Q-table, by merged ones for example, and forget the visits.
+    /// The agents sharing the table get the values too.
+    pub fn replace_q_table(&mut self, q_table: QTable) {
+        self.q_table.replace(q_table);
+        self.visits.clear();
+        self.updates = 0;
+    }
+
+    /// Forget the visits, when the table was replaced through another agent sharing it
+    pub fn clear_visits(&mut self) {
+        self.visits.clear();
+        self.updates = 0;
+    }
+
+    /// Q-learning update of the transition, then replay of past ones if the agent has a
+    /// replay buffer and planning if it uses Dyna-Q. A `done` transition has no future.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        state: &State,
+        action: Action,
+        reward: Reward,
+        next_state: &State,
+        next_actions: &[Action],
+        done: Done,
+        rng: &mut StdRng,
+    ) {
+        let transition = Transition {
+            state: state.clone(),
+            action,
+            reward,
+            next_state: next_state.clone(),
+            next_actions: next_actions.to_vec(),
+            done,
+        };
+        self.learn(&transition);
+
+        self.updates += 1;
+        let visit = self
+            .visits
+            .entry(Q {
+                state: state.clone(),
+                action,
+            })
+            .or_default();
+        visit.count += 1;
+        visit.last = self.updates;
+
+        if let Some(replay) = &self.replay {
+            let mut replay = replay.lock().unwrap();
+            replay.push(transition.clone());
+            if replay.is_due() {
+                replay.replay_with(rng, |transition| self.learn(transition));
+            }
+        }
+
+        if let Some(mut dyna) = self.dyna.take() {
+            dyna.plan(rng, &transition, next_actions, |simulated| {
+                self.learn(simulated);
+            });
+            self.dyna = Some(dyna);
+        }
+    }
+
+    /// Q-learning update
+    ///
+    /// Q-learning update rule:
+    /// Q(s, a) <- Q(s, a) + alpha * (reward + gamma * max_a' Q(s', a') - Q(s, a))
+    ///
+    /// There is no future (max_a' Q(s', a') = 0) when the transition is done.
+    /// Returns the TD error.
+    pub fn learn(&self, transition: &Transition) -> f32 {
+        let Transition {
+            state,
+            action,
+            reward,
+            next_state,
+            next_actions,
+            done,
+        } = transition;
+        let old_q_value = self.get_q_value(state, *action);
+        let future_q_value = if *done {
+            0.
+        } else {
+            next_actions
+                .iter()
+                .map(|action| self.get_q_value(next_state, *action))
+                .fold(None, |max: Option<f32>, value| {
+                    Some(max.map_or(value, |max| max.max(value)))
+                })
+                .unwrap_or(0.)
+        };
+
+        let td_error = reward + self.discount_factor * future_q_value - old_q_value;
+        self.q_table.set(
+            Q {
+                state: state.clone(),
+                action: *action,
+            },
+            old_q_value + self.learning_rate * td_error,
+        );
+
+        td_error
+    }
+
+    pub fn step(
+        &self,
+        env: &Env,
+        position: Position,
+        action: Action,
+        rng: &mut StdRng,
+    ) -> (Position, State, Reward, Done) {
+        (self.step_fn)(self, env, position, &self.state, &action, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::agent::state::Value;
+
+    fn q(x: i32, action: Action) -> Q {
+        Q {
+            state: vec![Value::VI32(x)],
+            action,
+        }
+    }
+
+    #[test]
+    fn sharded_q_table() {
+        let table = ShardedQTable::new(4);
+        for x in 0..20 {
+            table.set(q(x, 0), x as f32);
+        }
+        table.set(q(3, 0), -1.);
+
+        assert_eq!(table.len(), 20);
+        assert_eq!(table.get(&q(3, 0)), Some(-1.));
+        assert_eq!(table.get(&q(3, 1)), None);
+
+        let merged = ShardedQTable::from_q_table(table.to_q_table(), 3);
+        assert_eq!(merged.to_q_table(), table.to_q_table());
+    }
+
+    #[test]
+    fn greedy_choice() {
+        let step_fn: ParallelStepFunction =
+            Arc::new(|_, _, position, state, _, _| (position, state.clone(), 0., false));
+        let mut agent = ParallelAgent::new(
+            1,
+            "test",
+            vec![Value::VI32(0)],
+            None,
+            None,
+            Some(0.),
+            &step_fn,
+            Arc::new(ShardedQTable::default()),
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let state = agent.state.clone();
+        agent.update(
+            &state,
+            2,
+            1.,
+            &vec![Value::VI32(1)],
+            &[0, 1, 2],
+            false,
+            &mut rng,
+        );
+
+        for _ in 0..10 {
+            assert_eq!(agent.choose_action(&agent.state, &[0, 1, 2], &mut rng), 2);
+        }
+        assert!((agent.get_q_value(&agent.state, 2) - 0.1).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,912 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::environment::movement::MovementSet;
+
+use super::{
+    agent::{Action, QTable, Q},
+    state::{ParseValueError, State, Value},
+};
+
+/// An entry of an exported Q-table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QEntry {
+    /// The state as written by `Value`, like `[3, true]`
+    pub state: String,
+    pub action: Action,
+    /// Name of the action, ignored when the table is imported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_name: Option<String>,
+    pub value: f32,
+}
+
+/// Readable names of the actions of a Q-table, for the exports
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ActionNames(BTreeMap<Action, String>);
+
+impl ActionNames {
+    /// Names of the moves (`up`, `north_east`, etc.)
+    pub fn from_movement_set(movements: &MovementSet) -> Self {
+        ActionNames(
+            movements
+                .actions()
+                .into_iter()
+                .filter_map(|action| Some((action, movements.action_name(action)?.to_string())))
+                .collect(),
+        )
+    }
+
+    /// The name of action `i` is `names[i]`
+    pub fn from_list(names: &[&str]) -> Self {
+        ActionNames(
+            names
+                .iter()
+                .enumerate()
+                .map(|(action, name)| (action as Action, name.to_string()))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, action: Action) -> Option<&str> {
+        self.0.get(&action).map(String::as_str)
+    }
+
+    /// Name of the action, its number if it has none
+    pub fn name(&self, action: Action) -> String {
+        self.get(action)
+            .map_or_else(|| action.to_string(), str::to_string)
+    }
+}
+
+#[derive(Debug)]
+pub enum QTableError {
+    Io {
+        filepath: String,
+        error: io::Error,
+    },
+    /// Not a Q-table file, or a truncated one
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+    /// A line of a CSV file that cannot be read. **line** starts at 1.
+    Csv {
+        line: usize,
+        message: String,
+    },
+    /// A state of a JSON file that is not a `Value`
+    State {
+        state: String,
+        error: ParseValueError,
+    },
+    /// The same state and action twice
+    Duplicate {
+        state: String,
+        action: Action,
+    },
+    /// Written by a newer version of the format
+    UnsupportedVersion {
+        version: u32,
+    },
+    /// Trained with other actions or another layout of states
+    Incompatible {
+        expected: Fingerprint,
+        found: Fingerprint,
+    },
+}
+
+impl fmt::Display for QTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QTableError::Io { filepath, error } => {
+                write!(f, "could not access {}: {}", filepath, error)
+            }
+            QTableError::Bincode(error) => write!(f, "not a Q-table: {}", error),
+            QTableError::Json(error) => write!(f, "{}", error),
+            QTableError::Csv { line, message } => write!(f, "line {}: {}", line, message),
+            QTableError::State { state, error } => write!(f, "state {}: {}", state, error),
+            QTableError::Duplicate { state, action } => {
+                write!(f, "state {} has action {} twice", state, action)
+            }
+            QTableError::UnsupportedVersion { version } => write!(
+                f,
+                "format version {} is newer than the supported version {}",
+                version, FORMAT_VERSION
+            ),
+            QTableError::Incompatible { expected, found } => {
+                write!(f, "trained with {}, expected {}", found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QTableError {}
+
+impl From<bincode::Error> for QTableError {
+    fn from(error: bincode::Error) -> Self {
+        QTableError::Bincode(error)
+    }
+}
+
+impl From<serde_json::Error> for QTableError {
+    fn from(error: serde_json::Error) -> Self {
+        QTableError::Json(error)
+    }
+}
+
+fn io_error(filepath: &str) -> impl FnOnce(io::Error) -> QTableError + '_ {
+    move |error| QTableError::Io {
+        filepath: filepath.to_string(),
+        error,
+    }
+}
+
+/// First bytes of a Q-table file
+pub const MAGIC: [u8; 4] = *b"MASQ";
+/// Version of the Q-table files, increased when their layout changes. Files without a
+/// header are version 0. The layouts of version 1 did not describe lists and maps.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// What a Q-table was trained with, checked when it is loaded
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Actions, sorted
+    pub actions: Vec<Action>,
+    /// Layouts of the states (see `state_schema`), sorted
+    pub schemas: Vec<String>,
+}
+
+impl Fingerprint {
+    /// Actions and layouts of the states of a table
+    pub fn of(q_table: &QTable) -> Self {
+        let actions: BTreeSet<Action> = q_table.keys().map(|q| q.action).collect();
+        let schemas: BTreeSet<String> = q_table.keys().map(|q| state_schema(&q.state)).collect();
+        Fingerprint {
+            actions: actions.into_iter().collect(),
+            schemas: schemas.into_iter().collect(),
+        }
+    }
+
+    /// What an agent can use: the actions of its environment and the layout of its first
+    /// state. An empty state (a state only known after the first step) accepts any layout.
+    pub fn expected(actions: &[Action], state: &State) -> Self {
+        Fingerprint::expected_states(actions, std::slice::from_ref(state))
+    }
+
+    /// Same as `expected` for agents whose states take several layouts, like a placeholder
+    /// first state followed by observations
+    pub fn expected_states(actions: &[Action], states: &[State]) -> Self {
+        let mut actions = actions.to_vec();
+        actions.sort();
+        actions.dedup();
+        let schemas: BTreeSet<String> = states
+            .iter()
+            .filter(|state| !state.is_empty())
+            .map(state_schema)
+            .collect();
+        Fingerprint {
+            actions,
+            schemas: schemas.into_iter().collect(),
+        }
+    }
+
+    /// A table can be used when all its actions and layouts are expected. Empty expected
+    /// actions or layouts accept anything.
+    pub fn check(&self, expected: &Fingerprint) -> Result<(), QTableError> {
+        fn subset<T: PartialEq>(found: &[T], expected: &[T]) -> bool {
+            expected.is_empty() || found.iter().all(|item| expected.contains(item))
+        }
+
+        if subset(&self.actions, &expected.actions) && subset(&self.schemas, &expected.schemas) {
+            Ok(())
+        } else {
+            Err(QTableError::Incompatible {
+                expected: expected.clone(),
+                found: self.clone(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.actions.is_empty() {
+            write!(f, "any actions and states ")?;
+        } else {
+            let actions: Vec<String> = self.actions.iter().map(Action::to_string).collect();
+            write!(f, "actions [{}] and states ", actions.join(", "))?;
+        }
+        if self.schemas.is_empty() {
+            write!(f, "of any layout")
+        } else {
+            write!(f, "{}", self.schemas.join(" or "))
+        }
+    }
+}
+
+/// Kind of each value of a state, like `[i32, bool, [u32; 25]]`. A list of values of the
+/// same kind is written with its length, other lists with the kind of each value. A map is
+/// written with the kinds of its entries and its length, like `{string: i32; 3}`.
+pub fn state_schema(state: &State) -> String {
+    fn kind(value: &Value) -> String {
+        match value {
+            Value::VI32(_) => "i32".to_string(),
+            Value::VU32(_) => "u32".to_string(),
+            Value::VFloat(_) => "f32".to_string(),
+            Value::VString(_) => "string".to_string(),
+            Value::VBool(_) => "bool".to_string(),
+            Value::VPair((first, second)) => format!("({}, {})", kind(first), kind(second)),
+            Value::VVec(values) => list(values),
+            Value::VMap(map) => {
+                let entries: BTreeSet<String> = map
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", kind(key), kind(value)))
+                    .collect();
+                let entries: Vec<String> = entries.into_iter().collect();
+                format!("{{{}; {}}}", entries.join(", "), map.len())
+            }
+        }
+    }
+
+    fn list(values: &[Value]) -> String {
+        let kinds: Vec<String> = values.iter().map(kind).collect();
+        match kinds.first() {
+            Some(first) if kinds.len() > 1 && kinds.iter().all(|kind| kind == first) => {
+                format!("[{}; {}]", first, kinds.len())
+            }
+            _ => format!("[{}]", kinds.join(", ")),
+        }
+    }
+
+    let kinds: Vec<String> = state.iter().map(kind).collect();
+    format!("[{}]", kinds.join(", "))
+}
+
+/// Save a Q-table for the agents: `MAGIC`, `FORMAT_VERSION`, the `Fingerprint` of the table
+/// then its entries, with bincode
+pub fn save_q_table(q_table: &QTable, filepath: &str) -> Result<(), QTableError> {
+    let mut writer = BufWriter::new(File::create(filepath).map_err(io_error(filepath))?);
+    writer.write_all(&MAGIC).map_err(io_error(filepath))?;
+    bincode::serialize_into(&mut writer, &FORMAT_VERSION)?;
+    bincode::serialize_into(&mut writer, &Fingerprint::of(q_table))?;
+    bincode::serialize_into(&mut writer, q_table)?;
+    writer.flush().map_err(io_error(filepath))
+}
+
+/// Load a Q-table saved by `save_q_table`, or a file without header of the first versions.
+/// The table must match `expected` if there is one.
+pub fn load_q_table(filepath: &str, expected: Option<&Fingerprint>) -> Result<QTable, QTableError> {
+    let bytes = fs::read(filepath).map_err(io_error(filepath))?;
+
+    let Some(mut reader) = bytes.strip_prefix(&MAGIC) else {
+        // Version 0, only the entries
+        let q_table: QTable = bincode::deserialize(&bytes)?;
+        if let Some(expected) = expected {
+            Fingerprint::of(&q_table).check(expected)?;
+        }
+        return Ok(q_table);
+    };
+
+    let version: u32 = bincode::deserialize_from(&mut reader)?;
+    if version > FORMAT_VERSION {
+        return Err(QTableError::UnsupportedVersion { version });
+    }
+    let fingerprint: Fingerprint = bincode::deserialize_from(&mut reader)?;
+    if version < 2 {
+        // The layouts of the header do not tell the length of the lists, the entries do
+        let q_table: QTable = bincode::deserialize_from(&mut reader)?;
+        if let Some(expected) = expected {
+            Fingerprint::of(&q_table).check(expected)?;
+        }
+        return Ok(q_table);
+    }
+    // Checked before reading the entries
+    if let Some(expected) = expected {
+        fingerprint.check(expected)?;
+    }
+
+    Ok(bincode::deserialize_from(&mut reader)?)
+}
+
+/// `load_q_table`, with an empty table when the file does not exist yet
+pub fn load_q_table_or_empty(
+    filepath: &str,
+    expected: Option<&Fingerprint>,
+) -> Result<QTable, QTableError> {
+    match load_q_table(filepath, expected) {
+        Err(QTableError::Io { error, .. }) if error.kind() == io::ErrorKind::NotFound => {
+            Ok(QTable::new())
+        }
+        result => result,
+    }
+}
+
+/// Format of a Q-table file, from its extension: `.csv`, `.json`, bincode otherwise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QTableFormat {
+    Bincode,
+    Csv,
+    Json,
+}
+
+impl QTableFormat {
+    pub fn of(filepath: &str) -> Self {
+        match Path::new(filepath).extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => QTableFormat::Csv,
+            Some("json") => QTableFormat::Json,
+            _ => QTableFormat::Bincode,
+        }
+    }
+}
+
+/// Read a Q-table in the format of its extension (see `QTableFormat`)
+pub fn read_q_table(filepath: &str) -> Result<QTable, QTableError> {
+    match QTableFormat::of(filepath) {
+        QTableFormat::Bincode => load_q_table(filepath, None),
+        QTableFormat::Csv => from_csv(&fs::read_to_string(filepath).map_err(io_error(filepath))?),
+        QTableFormat::Json => from_json(&fs::read_to_string(filepath).map_err(io_error(filepath))?),
+    }
+}
+
+/// Write a Q-table in the format of its extension. The names are only written in CSV and
+/// JSON.
+pub fn write_q_table(
+    q_table: &QTable,
+    filepath: &str,
+    names: &ActionNames,
+) -> Result<(), QTableError> {
+    let text = match QTableFormat::of(filepath) {
+        QTableFormat::Bincode => return save_q_table(q_table, filepath),
+        QTableFormat::Csv => to_csv(q_table, names),
+        QTableFormat::Json => to_json(q_table, names)?,
+    };
+
+    fs::write(filepath, text).map_err(io_error(filepath))
+}
+
+/// Every entry of the table, sorted by state then action
+pub fn entries(q_table: &QTable, names: &ActionNames) -> Vec<QEntry> {
+    let mut entries: Vec<QEntry> = q_table
+        .iter()
+        .map(|(q, value)| QEntry {
+            state: Value::VVec(q.state.clone()).to_string(),
+            action: q.action,
+            action_name: names.get(q.action).map(str::to_string),
+            value: *value,
+        })
+        .collect();
+    entries.sort_by(|a, b| (&a.state, a.action).cmp(&(&b.state, b.action)));
+    entries
+}
+
+/// Q-table from parsed entries, each state and action once
+fn from_entries(
+    entries: impl IntoIterator<Item = (State, Action, f32)>,
+) -> Result<QTable, QTableError> {
+    let mut q_table = QTable::new();
+    for (state, action, value) in entries {
+        let q = Q { state, action };
+        if q_table.contains_key(&q) {
+            return Err(QTableError::Duplicate {
+                state: Value::VVec(q.state).to_string(),
+                action,
+            });
+        }
+        q_table.insert(q, value);
+    }
+    Ok(q_table)
+}
+
+fn parse_state(text: &str) -> Result<State, ParseValueError> {
+    match text.parse()? {
+        Value::VVec(state) => Ok(state),
+        _ => Err(ParseValueError {
+            position: 0,
+            message: "a state is a list of values, like [1, true]".to_string(),
+        }),
+    }
+}
+
+/// One line per entry: `state,action,action_name,value`
+pub fn to_csv(q_table: &QTable, names: &ActionNames) -> String {
+    let mut csv = String::from("state,action,action_name,value\n");
+    for entry in entries(q_table, names) {
+        csv += &format!(
+            "{},{},{},{}\n",
+            csv_field(&entry.state),
+            entry.action,
+            csv_field(entry.action_name.as_deref().unwrap_or("")),
+            entry.value
+        );
+    }
+    csv
+}
+
+/// Read the CSV written by `to_csv`. Only the header and the columns `state`, `action` and
+/// `value` are required.
+pub fn from_csv(text: &str) -> Result<QTable, QTableError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Err(QTableError::Csv {
+            line: 1,
+            message: "expected a header".to_string(),
+        });
+    };
+
+    let header = csv_fields(header).map_err(|message| QTableError::Csv { line: 1, message })?;
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column.trim() == name)
+            .ok_or_else(|| QTableError::Csv {
+                line: 1,
+                message: format!("expected a column {:?}", name),
+            })
+    };
+    let (state, action, value) = (column("state")?, column("action")?, column("value")?);
+
+    let mut entries = Vec::new();
+    for (line, text) in lines {
+        let csv_error = |message: String| QTableError::Csv { line, message };
+        let fields = csv_fields(text).map_err(csv_error)?;
+        if fields.len() != header.len() {
+            return Err(csv_error(format!(
+                "expected {} columns, found {}",
+                header.len(),
+                fields.len()
+            )));
+        }
+
+        entries.push((
+            parse_state(&fields[state])
+                .map_err(|error| csv_error(format!("state {}: {}", fields[state], error)))?,
+            fields[action]
+                .trim()
+                .parse()
+                .map_err(|_| csv_error(format!("invalid action {:?}", fields[action])))?,
+            fields[value]
+                .trim()
+                .parse()
+                .map_err(|_| csv_error(format!("invalid value {:?}", fields[value])))?,
+        ));
+    }
+
+    from_entries(entries)
+}
+
+/// Quote a field if it would break the line
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Fields of a CSV line, quoted fields may hold commas and doubled quotes
+fn csv_fields(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err("unterminated quoted field".to_string()),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                return Err("expected `,` after a quoted field".to_string());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                field.push(c);
+            }
+        }
+        fields.push(field);
+
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+/// A list of `QEntry`
+pub fn to_json(q_table: &QTable, names: &ActionNames) -> Result<String, QTableError> {
+    Ok(serde_json::to_string_pretty(&entries(q_table, names))?)
+}
+
+pub fn from_json(text: &str) -> Result<QTable, QTableError> {
+    let entries: Vec<QEntry> = serde_json::from_str(text)?;
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            let state = parse_state(&entry.state).map_err(|error| QTableError::State {
+                state: entry.state.clone(),
+                error,
+            })?;
+            Ok((state, entry.action, entry.value))
+        })
+        .collect::<Result<Vec<_>, QTableError>>()?;
+
+    from_entries(entries)
+}
+
+/// Best action of a state and its value
+pub type Greedy = (Action, f32);
+
+/// Best action of every state, as chosen without exploration. Ties go to the lowest action.
+pub fn greedy_policy(q_table: &QTable) -> HashMap<State, Greedy> {
+    let mut policy: HashMap<State, Greedy> = HashMap::new();
+    for (q, value) in q_table {
+        let best = policy.entry(q.state.clone()).or_insert((q.action, *value));
+        if *value > best.1 || (*value == best.1 && q.action < best.0) {
+            *best = (q.action, *value);
+        }
+    }
+    policy
+}
+
+/// Greedy policy sorted by the text of the states
+pub fn sorted_policy(q_table: &QTable) -> Vec<(String, Greedy)> {
+    let mut policy: Vec<(String, Greedy)> = greedy_policy(q_table)
+        .into_iter()
+        .map(|(state, greedy)| (Value::VVec(state).to_string(), greedy))
+        .collect();
+    policy.sort_by(|a, b| a.0.cmp(&b.0));
+    policy
+}
+
+/// Entries and values of an action
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ActionStats {
+    pub entries: usize,
+    pub mean: f32,
+    /// Number of states where the action is the greedy one
+    pub greedy: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QTableStats {
+    pub entries: usize,
+    /// Number of states visited
+    pub states: usize,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub actions: BTreeMap<Action, ActionStats>,
+}
+
+impl QTableStats {
+    pub fn of(q_table: &QTable) -> Self {
+        if q_table.is_empty() {
+            return QTableStats::default();
+        }
+
+        let mut actions: BTreeMap<Action, ActionStats> = BTreeMap::new();
+        let (mut min, mut max, mut sum) = (f32::INFINITY, f32::NEG_INFINITY, 0.);
+        for (q, value) in q_table {
+            min = min.min(*value);
+            max = max.max(*value);
+            sum += value;
+            let action = actions.entry(q.action).or_default();
+            action.entries += 1;
+            // Sum for now, divided below
+            action.mean += value;
+        }
+        for action in actions.values_mut() {
+            action.mean /= action.entries as f32;
+        }
+
+        let policy = greedy_policy(q_table);
+        for (action, _) in policy.values() {
+            actions.get_mut(action).unwrap().greedy += 1;
+        }
+
+        QTableStats {
+            entries: q_table.len(),
+            states: policy.len(),
+            min,
+            max,
+            mean: sum / q_table.len() as f32,
+            actions,
+        }
+    }
+}
+
+/// Differences between two Q-tables
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QTableDiff {
+    /// Entries of the left table only
+    pub removed: Vec<(Q, f32)>,
+    /// Entries of the right table only
+    pub added: Vec<(Q, f32)>,
+    /// Entries whose value changed more than the tolerance: left then right value
+    pub changed: Vec<(Q, f32, f32)>,
+    /// States of both tables with another greedy action: left then right action
+    pub policy: Vec<(State, Action, Action)>,
+}
+
+impl QTableDiff {
+    /// Every list is sorted by state and action
+    pub fn between(left: &QTable, right: &QTable, tolerance: f32) -> Self {
+        let mut diff = QTableDiff::default();
+        for (q, value) in left {
+            match right.get(q) {
+                None => diff.removed.push((q.clone(), *value)),
+                Some(other) if (value - other).abs() > tolerance => {
+                    diff.changed.push((q.clone(), *value, *other))
+                }
+                Some(_) => {}
+            }
+        }
+        for (q, value) in right {
+            if !left.contains_key(q) {
+                diff.added.push((q.clone(), *value));
+            }
+        }
+
+        let right_policy = greedy_policy(right);
+        let states: HashSet<&State> = right_policy.keys().collect();
+        for (state, (action, _)) in greedy_policy(left) {
+            if !states.contains(&state) {
+                continue;
+            }
+            let (other, _) = right_policy[&state];
+            if action != other {
+                diff.policy.push((state, action, other));
+            }
+        }
+
+        let key = |q: &Q| (Value::VVec(q.state.clone()).to_string(), q.action);
+        diff.removed.sort_by_cached_key(|(q, _)| key(q));
+        diff.added.sort_by_cached_key(|(q, _)| key(q));
+        diff.changed.sort_by_cached_key(|(q, _, _)| key(q));
+        diff.policy
+            .sort_by_cached_key(|(state, _, _)| Value::VVec(state.clone()).to_string());
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+            && self.added.is_empty()
+            && self.changed.is_empty()
+            && self.policy.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::state::to_value;
+
+    fn q(x: i32, flag: bool, action: Action) -> Q {
+        Q {
+            state: vec![to_value(x), to_value(flag)],
+            action,
+        }
+    }
+
+    fn q_table() -> QTable {
+        QTable::from([
+            (q(0, true, 0), 1.5),
+            (q(0, true, 1), -2.),
+            (q(1, false, 0), 0.),
+            (q(1, false, 3), 4.),
+            (
+                Q {
+                    state: vec![to_value("a, \"quoted\" text".to_string())],
+                    action: 2,
+                },
+                0.25,
+            ),
+        ])
+    }
+
+    #[test]
+    fn csv_and_json() {
+        let names = ActionNames::from_movement_set(&MovementSet::Four);
+        let csv = to_csv(&q_table(), &names);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "state,action,action_name,value");
+        assert_eq!(
+            lines[1],
+            "\"[\"\"a, \\\"\"quoted\\\"\" text\"\"]\",2,left,0.25"
+        );
+        assert_eq!(lines[2], "\"[0, true]\",0,up,1.5");
+        assert_eq!(from_csv(&csv).unwrap(), q_table());
+
+        let json = to_json(&q_table(), &names).unwrap();
+        assert!(json.contains("\"action_name\": \"right\""));
+        assert_eq!(from_json(&json).unwrap(), q_table());
+
+        // Edited by hand: without names, in another order
+        let edited = "action,value,state\n1, 3.5,\"[0, true]\"\n";
+        assert_eq!(
+            from_csv(edited).unwrap(),
+            QTable::from([(q(0, true, 1), 3.5)])
+        );
+    }
+
+    #[test]
+    fn import_errors() {
+        let error = |text: &str| from_csv(text).unwrap_err().to_string();
+        assert_eq!(
+            error("state,action\n"),
+            "line 1: expected a column \"value\""
+        );
+        assert!(error("state,action,value\n\"[0]\",0,1\n\"[1\",0,1\n").starts_with("line 3: "));
+        assert!(error("state,action,value\n\"[0]\",up,1\n").contains("invalid action"));
+        assert!(error("state,action,value\n3,0,1\n").contains("list of values"));
+        assert_eq!(
+            error("state,action,value\n\"[0]\",0,1\n\"[0]\",0,2\n"),
+            "state [0] has action 0 twice"
+        );
+        assert!(matches!(
+            from_json(r#"[{"state": "[0]", "action": 0}]"#),
+            Err(QTableError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn stats_and_policy() {
+        let stats = QTableStats::of(&q_table());
+        assert_eq!(stats.entries, 5);
+        assert_eq!(stats.states, 3);
+        assert_eq!((stats.min, stats.max), (-2., 4.));
+        assert_eq!(stats.actions[&0].entries, 2);
+        assert_eq!(stats.actions[&0].mean, 0.75);
+        assert_eq!(stats.actions[&0].greedy, 1);
+        assert_eq!(stats.actions[&3].greedy, 1);
+
+        let policy = sorted_policy(&q_table());
+        assert_eq!(policy[1], ("[0, true]".to_string(), (0, 1.5)));
+        assert_eq!(policy[2], ("[1, false]".to_string(), (3, 4.)));
+    }
+
+    #[test]
+    fn diff() {
+        let left = q_table();
+        let mut right = q_table();
+        right.remove(&q(1, false, 0));
+        right.insert(q(0, true, 1), 2.);
+        right.insert(q(0, true, 0), 1.5 + 1e-7);
+        right.insert(q(2, false, 0), 1.);
+
+        let diff = QTableDiff::between(&left, &right, 1e-6);
+        assert_eq!(diff.removed, vec![(q(1, false, 0), 0.)]);
+        assert_eq!(diff.added, vec![(q(2, false, 0), 1.)]);
+        assert_eq!(diff.changed, vec![(q(0, true, 1), -2., 2.)]);
+        assert_eq!(diff.policy, vec![(q(0, true, 0).state, 0, 1)]);
+        assert!(QTableDiff::between(&left, &left, 0.).is_empty());
+    }
+
+    #[test]
+    fn versions_and_fingerprints() {
+        let directory = std::env::temp_dir().join("masim_q_table_versions");
+        fs::create_dir_all(&directory).unwrap();
+        let filepath = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+        let q_table = QTable::from([(q(0, true, 0), 1.), (q(2, false, 3), 2.)]);
+        save_q_table(&q_table, &filepath("table.bin")).unwrap();
+        assert!(fs::read(filepath("table.bin")).unwrap().starts_with(&MAGIC));
+
+        let four = Fingerprint::expected(&[3, 2, 1, 0], &vec![to_value(0), to_value(false)]);
+        assert_eq!(
+            four.to_string(),
+            "actions [0, 1, 2, 3] and states [i32, bool]"
+        );
+        assert_eq!(
+            load_q_table(&filepath("table.bin"), Some(&four)).unwrap(),
+            q_table
+        );
+
+        // Another state layout or fewer actions
+        for expected in [
+            Fingerprint::expected(&[0, 1, 2, 3], &vec![to_value(0), to_value(0)]),
+            Fingerprint::expected(&[0, 1], &vec![to_value(0), to_value(false)]),
+        ] {
+            let error = load_q_table(&filepath("table.bin"), Some(&expected)).unwrap_err();
+            assert!(matches!(error, QTableError::Incompatible { .. }));
+        }
+        // Lists of another length
+        let grid = |cells: usize| vec![to_value(vec![0u32; cells]), to_value(false)];
+        let observations = QTable::from([(
+            Q {
+                state: grid(25),
+                action: 0,
+            },
+            1.,
+        )]);
+        assert_eq!(state_schema(&grid(25)), "[[u32; 25], bool]".to_string());
+        save_q_table(&observations, &filepath("grid.bin")).unwrap();
+        let larger = Fingerprint::expected_states(&[0], &[vec![to_value(0)], grid(49)]);
+        assert!(matches!(
+            load_q_table(&filepath("grid.bin"), Some(&larger)),
+            Err(QTableError::Incompatible { .. })
+        ));
+        let same = Fingerprint::expected_states(&[0], &[vec![to_value(0)], grid(25)]);
+        assert!(load_q_table(&filepath("grid.bin"), Some(&same)).is_ok());
+
+        // The header of version 1 does not describe the lists, the entries are checked
+        let mut version_1 = MAGIC.to_vec();
+        version_1.extend(bincode::serialize(&1u32).unwrap());
+        let coarse = Fingerprint {
+            actions: vec![0],
+            schemas: vec!["[vec, bool]".to_string()],
+        };
+        version_1.extend(bincode::serialize(&coarse).unwrap());
+        version_1.extend(bincode::serialize(&observations).unwrap());
+        fs::write(filepath("version_1.bin"), version_1).unwrap();
+        assert!(matches!(
+            load_q_table(&filepath("version_1.bin"), Some(&larger)),
+            Err(QTableError::Incompatible { .. })
+        ));
+        assert!(load_q_table(&filepath("version_1.bin"), Some(&same)).is_ok());
+
+        // A first state only known after the first step
+        let any_state = Fingerprint::expected(&[0, 1, 2, 3], &vec![]);
+        assert!(load_q_table(&filepath("table.bin"), Some(&any_state)).is_ok());
+
+        // Files of the first versions have no header
+        fs::write(filepath("old.bin"), bincode::serialize(&q_table).unwrap()).unwrap();
+        assert_eq!(
+            load_q_table(&filepath("old.bin"), Some(&four)).unwrap(),
+            q_table
+        );
+
+        let mut newer = MAGIC.to_vec();
+        newer.extend(bincode::serialize(&(FORMAT_VERSION + 1)).unwrap());
+        fs::write(filepath("newer.bin"), newer).unwrap();
+        assert!(matches!(
+            load_q_table(&filepath("newer.bin"), None),
+            Err(QTableError::UnsupportedVersion { .. })
+        ));
+
+        fs::write(filepath("corrupt.bin"), b"MASQ\x01\x00").unwrap();
+        assert!(matches!(
+            load_q_table(&filepath("corrupt.bin"), None),
+            Err(QTableError::Bincode(_))
+        ));
+        assert!(load_q_table_or_empty(&filepath("missing.bin"), Some(&four))
+            .unwrap()
+            .is_empty());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn files() {
+        let directory = std::env::temp_dir().join("masim_q_table_files");
+        fs::create_dir_all(&directory).unwrap();
+        let names = ActionNames::from_list(&["north", "south"]);
+
+        for name in ["table.bin", "table.csv", "table.json"] {
+            let filepath = directory.join(name).to_string_lossy().into_owned();
+            write_q_table(&q_table(), &filepath, &names).unwrap();
+            assert_eq!(read_q_table(&filepath).unwrap(), q_table());
+        }
+        assert!(matches!(
+            read_q_table("missing.bin"),
+            Err(QTableError::Io { .. })
+        ));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use rand::Rng;
+use serde::Deserialize;
+
+use super::{
+    agent::{Action, Done, Reward},
+    state::State,
+};
+
+/// Smallest priority, so that a transition already learned can still be replayed
+const MIN_PRIORITY: f32 = 1e-3;
+
+/// Buffer of one agent, or of all the agents of a type when it is shared
+pub type ReplayRef = Rc<RefCell<ReplayBuffer>>;
+
+/// `ReplayRef` of the parallel agents, which can move between threads
+pub type SharedReplay = Arc<Mutex<ReplayBuffer>>;
+
+/// What an agent went through during one step
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub state: State,
+    pub action: Action,
+    pub reward: Reward,
+    pub next_state: State,
+    /// Actions possible from the next state, to value its future
+    pub next_actions: Vec<Action>,
+    /// The episode of the agent ended, so the next state has no future
+    pub done: Done,
+}
+
+/// Settings of an experience replay buffer
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// Number of transitions kept, the oldest are forgotten first
+    pub capacity: usize,
+    /// Number of transitions replayed at a time
+    pub batch_size: usize,
+    /// A batch is replayed every `frequency` new transitions
+    pub frequency: u32,
+    /// Transitions are drawn with a probability proportional to their last TD error to the
+    /// power of this exponent. 0 draws them uniformly.
+    pub priority_exponent: f32,
+    /// One buffer for all the agents of a type instead of one each
+    pub shared: bool,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            capacity: 10_000,
+            batch_size: 32,
+            frequency: 1,
+            priority_exponent: 0.,
+            shared: false,
+        }
+    }
+}
+
+/// Past transitions of an agent, learned from again in random batches.
+///
+/// A new transition gets the highest priority so far, so it is replayed at least once soon.
+/// A replayed transition gets the absolute TD error of its replay as priority. The bias of
+/// the prioritised draws is not corrected (no importance sampling).
+///
+/// ## Example
+/// ```rust
+/// let mut replay = ReplayBuffer::new(ReplayConfig::default());
+/// replay.push(transition);
+/// if replay.is_due() {
+///     replay.replay_with(&mut env.rng, |transition| agent.learn(transition));
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ReplayBuffer {
+    pub config: ReplayConfig,
+    /// Transitions and their priority, oldest first
+    transitions: VecDeque<(Transition, f32)>,
+    max_priority: f32,
+    /// Number of transitions pushed so far
+    pushed: u64,
+}
+
+impl ReplayBuffer {
+    pub fn new(config: ReplayConfig) -> Self {
+        ReplayBuffer {
+            config,
+            transitions: VecDeque::with_capacity(config.capacity.min(1024)),
+            max_priority: 1.,
+            pushed: 0,
+        }
+    }
+
+    /// Keep a transition, forgetting the oldest one when the buffer is full
+    pub fn push(&mut self, transition: Transition) {
+        if self.config.capacity == 0 {
+            return;
+        }
+        if self.transitions.len() == self.config.capacity {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back((transition, self.max_priority));
+        self.pushed += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    /// A batch is due every `frequency` transitions, once there are enough of them
+    pub fn is_due(&self) -> bool {
+        self.config.batch_size > 0
+            && self.len() >= self.config.batch_size
+            && self
+                .pushed
+                .is_multiple_of(self.config.frequency.max(1) as u64)
+    }
+
+    /// Indices of a batch, drawn with replacement. Drawn uniformly when the priorities give
+    /// no usable distribution.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Vec<usize> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let uniform = |rng: &mut R| {
+            (0..self.config.batch_size)
+                .map(|_| rng.random_range(0..self.len()))
+                .collect()
+        };
+        let exponent = self.config.priority_exponent;
+        if exponent == 0. {
+            return uniform(rng);
+        }
+
+        let mut cumulative = Vec::with_capacity(self.len());
+        let mut total = 0.;
+        for (_, priority) in &self.transitions {
+            total += priority.powf(exponent);
+            cumulative.push(total);
+        }
+        if total <= 0. || !total.is_finite() {
+            return uniform(rng);
+        }
+        (0..self.config.batch_size)
+            .map(|_| {
+                let drawn = rng.random_range(0.0..total);
+                cumulative
+                    .partition_point(|sum| *sum <= drawn)
+                    .min(self.len() - 1)
+            })
+            .collect()
+    }
+
+    /// Learn from a batch drawn with `rng`. `learn` applies one transition and returns its
+    /// TD error.
+    pub fn replay_with<R: Rng>(&mut self, rng: &mut R, mut learn: impl FnMut(&Transition) -> f32) {
+        for i in self.sample(rng) {
+            let td_error = learn(&self.transitions[i].0);
+            let priority = td_error.abs().max(MIN_PRIORITY);
+            self.transitions[i].1 = priority;
+            self.max_priority = self.max_priority.max(priority);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::agent::state::Value;
+
+    fn transition(i: i32) -> Transition {
+        Transition {
+            state: vec![Value::VI32(i)],
+            action: 0,
+            reward: i as f32,
+            next_state: vec![Value::VI32(i + 1)],
+            next_actions: vec![0],
+            done: false,
+        }
+    }
+
+    #[test]
+    fn capacity_and_frequency() {
+        let mut replay = ReplayBuffer::new(ReplayConfig {
+            capacity: 3,
+            batch_size: 2,
+            frequency: 2,
+            ..ReplayConfig::default()
+        });
+
+        replay.push(transition(0));
+        assert!(!replay.is_due());
+        replay.push(transition(1));
+        assert!(replay.is_due());
+        replay.push(transition(2));
+        assert!(!replay.is_due());
+        replay.push(transition(3));
+        assert!(replay.is_due());
+
+        // The oldest was forgotten
+        assert_eq!(replay.len(), 3);
+        let mut replayed = Vec::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            replay.replay_with(&mut rng, |transition| {
+                replayed.push(transition.reward);
+                0.
+            });
+        }
+        assert!(!replayed.contains(&0.));
+        assert_eq!(replayed.len(), 40);
+    }
+
+    #[test]
+    fn priorities() {
+        let mut replay = ReplayBuffer::new(ReplayConfig {
+            capacity: 10,
+            batch_size: 1,
+            priority_exponent: 1.,
+            ..ReplayConfig::default()
+        });
+        for i in 0..10 {
+            replay.push(transition(i));
+        }
+
+        // Only the transition 7 is still surprising
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..100 {
+            replay.replay_with(
+                &mut rng,
+                |transition| {
+                    if transition.reward == 7. {
+                        1.
+                    } else {
+                        0.
+                    }
+                },
+            );
+        }
+
+        let drawn = (0..1000)
+            .flat_map(|_| replay.sample(&mut rng))
+            .filter(|i| *i == 7)
+            .count();
+        assert!(drawn > 900, "{}", drawn);
+    }
+
+    #[test]
+    fn vanishing_priorities() {
+        let mut replay = ReplayBuffer::new(ReplayConfig {
+            capacity: 5,
+            batch_size: 5,
+            priority_exponent: 200.,
+            ..ReplayConfig::default()
+        });
+        for i in 0..5 {
+            replay.push(transition(i));
+        }
+
+        // Once everything is learned, the priorities to the power of 200 are all 0
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut replayed = Vec::new();
+        for _ in 0..50 {
+            replay.replay_with(&mut rng, |transition| {
+                replayed.push(transition.reward);
+                0.
+            });
+        }
+        assert_eq!(replayed.len(), 250);
+        // Drawn uniformly
+        assert!((0..5).all(|i| replayed[200..].contains(&(i as f32))));
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
     collections::HashMap,
+    fmt,
     hash::{Hash, Hasher},
+    str::FromStr,
 };
 
 use serde::{Deserialize, Serialize};
@@ -79,8 +81,8 @@ pub enum Value {
     /// let val: Value =
     ///     HashMap::from([("rusty".to_string(), 3.4), ("crab".to_string(), 7.5)]).into();
     /// let result: HashMap<String, f32> = val.eq_type();
-    /// assert_eq!(result.get(&"rusty".to_string()), Some(&3.4));
-    /// assert_eq!(result.get(&"crab".to_string()), Some(&7.5));
+    /// assert_eq!(result.get("rusty"), Some(&3.4));
+    /// assert_eq!(result.get("crab"), Some(&7.5));
     ///
     ///
     /// // inserting value from reference
@@ -198,7 +200,7 @@ impl ValueTyped for f32 {
 
 impl From<f32> for Value {
     fn from(value: f32) -> Self {
-        Value::VFloat(value.to_bits() as u32)
+        Value::VFloat(value.to_bits())
     }
 }
 
@@ -296,7 +298,7 @@ where
     fn from_value(value: &Value) -> Self {
         match value {
             Value::VMap(m) => m
-                .into_iter()
+                .iter()
                 .map(|(k, v)| (T1::from_value(k), T2::from_value(v)))
                 .collect(),
             // Value::VMap(m) => m.clone(),
@@ -344,6 +346,231 @@ impl Value {
     }
 }
 
+/// Readable text of a value that `Value::from_str` turns back into the same value:
+/// `-3` (VI32), `3u` (VU32), `0.5f` (VFloat), `"text"`, `true`, `(a, b)`, `[a, b]` and
+/// `{key: value}` (keys sorted)
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::VI32(x) => write!(f, "{}", x),
+            Value::VU32(x) => write!(f, "{}u", x),
+            Value::VFloat(x) => write!(f, "{}f", f32::from_bits(*x)),
+            Value::VString(s) => write!(f, "{:?}", s),
+            Value::VBool(b) => write!(f, "{}", b),
+            Value::VPair((l, r)) => write!(f, "({}, {})", l, r),
+            Value::VVec(v) => {
+                let values: Vec<String> = v.iter().map(Value::to_string).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            Value::VMap(map) => {
+                let mut entries: Vec<String> =
+                    map.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                entries.sort();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseValueError {
+    /// Byte offset of the error in the text
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at character {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseValueError {}
+
+impl FromStr for Value {
+    type Err = ParseValueError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = ValueParser { text, position: 0 };
+        let value = parser.value()?;
+        parser.skip_spaces();
+        if parser.position < text.len() {
+            return parser.error("unexpected text after the value");
+        }
+        Ok(value)
+    }
+}
+
+/// Recursive descent over the text written by `Display`
+struct ValueParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl ValueParser<'_> {
+    fn error<T>(&self, message: &str) -> Result<T, ParseValueError> {
+        Err(ParseValueError {
+            position: self.position,
+            message: message.to_string(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseValueError> {
+        self.skip_spaces();
+        if self.next() == Some(expected) {
+            Ok(())
+        } else {
+            self.position -= self.text[..self.position]
+                .chars()
+                .next_back()
+                .map_or(0, char::len_utf8);
+            self.error(&format!("expected `{}`", expected))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseValueError> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('[') => Ok(Value::VVec(self.list('[', ']', Self::value)?)),
+            Some('{') => {
+                let entries = self.list('{', '}', |parser| {
+                    let key = parser.value()?;
+                    parser.expect(':')?;
+                    Ok((key, parser.value()?))
+                })?;
+                Ok(Value::VMap(entries.into_iter().collect()))
+            }
+            Some('(') => {
+                self.next();
+                let first = self.value()?;
+                self.expect(',')?;
+                let second = self.value()?;
+                self.expect(')')?;
+                Ok(Value::VPair((Box::new(first), Box::new(second))))
+            }
+            Some('"') => self.string().map(Value::VString),
+            Some(_) => self.scalar(),
+            None => self.error("expected a value"),
+        }
+    }
+
+    /// Items separated by commas between `open` and `close`
+    fn list<T>(
+        &mut self,
+        open: char,
+        close: char,
+        item: fn(&mut Self) -> Result<T, ParseValueError>,
+    ) -> Result<Vec<T>, ParseValueError> {
+        self.expect(open)?;
+        let mut items = Vec::new();
+        self.skip_spaces();
+        if self.peek() == Some(close) {
+            self.next();
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            self.skip_spaces();
+            match self.next() {
+                Some(',') => continue,
+                Some(c) if c == close => return Ok(items),
+                _ => return self.error(&format!("expected `,` or `{}`", close)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseValueError> {
+        self.next();
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('u') => self.unicode()?,
+                        Some(c @ ('\\' | '"' | '\'')) => c,
+                        _ => return self.error("unknown escape"),
+                    };
+                    string.push(c);
+                }
+                Some(c) => string.push(c),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    /// `{XXXX}` of a `\u{XXXX}` escape
+    fn unicode(&mut self) -> Result<char, ParseValueError> {
+        let rest = &self.text[self.position..];
+        let code = rest
+            .strip_prefix('{')
+            .and_then(|rest| rest.split_once('}'))
+            .map(|(code, _)| code);
+        let Some(c) = code
+            .and_then(|code| u32::from_str_radix(code, 16).ok())
+            .and_then(char::from_u32)
+        else {
+            return self.error("invalid unicode escape");
+        };
+        self.position += code.unwrap().len() + 2;
+        Ok(c)
+    }
+
+    /// Number or boolean, up to the next separator
+    fn scalar(&mut self) -> Result<Value, ParseValueError> {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !",:)]}".contains(c))
+        {
+            self.next();
+        }
+        let token = &self.text[start..self.position];
+
+        let value = match token {
+            "true" => Some(Value::VBool(true)),
+            "false" => Some(Value::VBool(false)),
+            _ => {
+                if let Some(number) = token.strip_suffix('u') {
+                    number.parse().ok().map(Value::VU32)
+                } else if let Some(number) = token.strip_suffix('f') {
+                    number
+                        .parse::<f32>()
+                        .ok()
+                        .map(|x| Value::VFloat(x.to_bits()))
+                } else {
+                    token.parse().ok().map(Value::VI32)
+                }
+            }
+        };
+
+        value.ok_or(ParseValueError {
+            position: start,
+            message: format!("unknown value {:?}", token),
+        })
+    }
+}
+
 pub fn to_value<T>(value: T) -> Value
 where
     T: Into<Value>,
@@ -354,6 +581,7 @@ where
 pub type State = Vec<Value>;
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_to_owned)]
 mod tests {
     use super::*;
 
@@ -418,6 +646,38 @@ mod tests {
         assert_eq!(true, result[2]);
     }
 
+    #[test]
+    fn text() {
+        let mut map = HashMap::new();
+        map.insert(Value::VString("b\"\n".to_string()), Value::VU32(2));
+        map.insert(Value::VString("a".to_string()), Value::VBool(false));
+        let value = Value::VVec(vec![
+            Value::VI32(-3),
+            Value::VU32(3),
+            Value::VFloat(0.1_f32.to_bits()),
+            (1_i32, 2.5_f32).into(),
+            Value::VMap(map),
+            Value::VVec(vec![]),
+        ]);
+
+        let text = value.to_string();
+        assert_eq!(
+            text,
+            r#"[-3, 3u, 0.1f, (1, 2.5f), {"a": false, "b\"\n": 2u}, []]"#
+        );
+        assert_eq!(text.parse::<Value>(), Ok(value));
+        assert_eq!(
+            " [ 1 ,true ] ".parse(),
+            Ok(Value::VVec(vec![1.into(), true.into()]))
+        );
+        assert_eq!("\"\\u{e9}\"".parse(), Ok(Value::VString("é".to_string())));
+
+        let error = "[1, 2".parse::<Value>().unwrap_err();
+        assert_eq!(error.position, 5);
+        assert_eq!("[1, two]".parse::<Value>().unwrap_err().position, 4);
+        assert!("1 2".parse::<Value>().is_err());
+    }
+
     #[test]
     fn test_map() {
         let val: Value = HashMap::from([(1, 3.4), (2, 7.5)]).into();
@@ -1,17 +1,14 @@
-use std::{
-    cell::RefCell,
-    collections::HashMap,
-    fs::File,
-    io::{BufReader, BufWriter},
-    rc::Rc,
-};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use macroquad::rand::ChooseRandom;
+use rand::{seq::IndexedRandom, Rng};
 
 use crate::{environment::environment::Env, scheduler::scheduler::Position};
 
 use super::{
     agent::{Action, Done, IsAgent, QTable, Reward, StepFunction, Q},
+    dyna::Dyna,
+    q_table::{self, Fingerprint, QTableError},
+    replay::{ReplayRef, Transition},
     state::State,
 };
 
@@ -27,6 +24,12 @@ pub struct SwarmAgent {
     /// Pointers to a mutable Q-values
     /// NOTE this will possibly be Arc<Mutex<QTable>> in the future if we want threads
     q_table: Rc<RefCell<QTable>>,
+    /// Past transitions learned from again, none without experience replay. Usually shared
+    /// by the swarm, like the Q-table.
+    pub replay: Option<ReplayRef>,
+    /// Model of the environment to plan with, none without Dyna-Q. Each member has its own
+    /// model but the simulated updates go to the table of the swarm.
+    pub dyna: Option<Dyna>,
     /// alpha / learning rate
     pub learning_rate: f32,
     /// gamma / discount factor
@@ -70,68 +73,89 @@ impl IsAgent for SwarmAgent {
         q_table.insert(Q { state, action }, value);
     }
 
-    fn save_q_table(&self, filepath: &str) {
-        let file = File::create(filepath).expect("Failed to create file");
-        let mut writer = BufWriter::new(file);
-
-        let q_table = self.q_table.borrow();
-        bincode::serialize_into(&mut writer, &*q_table).expect("Failed to write q_table");
+    fn save_q_table(&self, filepath: &str) -> Result<(), QTableError> {
+        q_table::save_q_table(&self.q_table.borrow(), filepath)
     }
 
-    fn load_q_table(&mut self, filepath: &str) {
-        let file = match File::open(filepath) {
-            Ok(file) => file,
-            Err(_) => return, // We do not wish to crash if the file is non-existant
-        };
-
-        let mut reader = BufReader::new(file);
-
-        let new_q_table: QTable =
-            bincode::deserialize_from(&mut reader).expect("Failed to read q_table");
-        let mut q_table = self.q_table.borrow_mut();
-        *q_table = new_q_table;
+    /// Replaces the table of the whole swarm
+    fn load_q_table(&mut self, filepath: &str, actions: &[Action]) -> Result<(), QTableError> {
+        let expected = Fingerprint::expected(actions, &self.state);
+        *self.q_table.borrow_mut() = q_table::load_q_table(filepath, Some(&expected))?;
+        Ok(())
     }
 
-    /// Epsilon-greedy action selection
-    fn choose_action(&self, state: &State, actions: &Vec<u32>) -> u32 {
+    /// Epsilon-greedy action selection. The actions are looked at in their order, so the
+    /// choice only depends on `rng`.
+    fn choose_action(&self, state: &State, actions: &[u32], rng: &mut impl Rng) -> u32 {
         let q_table = self.q_table.borrow();
-        if rand::random_range(0.0..1.) < self.exploration_rate || q_table.is_empty() {
-            return *actions.choose().unwrap();
+        if rng.random_range(0.0..1.) < self.exploration_rate || q_table.is_empty() {
+            return *actions.choose(rng).unwrap();
         }
 
-        let q_values = self.q_values_subset(state, actions);
-
-        let max_entry = q_values.iter().max_by(|a, b| a.1.total_cmp(b.1));
+        let q_values: Vec<(Action, f32)> = actions
+            .iter()
+            .filter_map(|action| {
+                q_table
+                    .get(&Q {
+                        state: state.clone(),
+                        action: *action,
+                    })
+                    .map(|value| (*action, *value))
+            })
+            .collect();
+        let max = q_values
+            .iter()
+            .map(|(_, value)| *value)
+            .max_by(f32::total_cmp);
 
-        if let Some((_, val)) = max_entry {
-            let max = val;
-            let possible_actions: HashMap<_, _> =
-                q_values.iter().filter(|(_, &v)| v == *max).collect();
-            let possible_actions: Vec<_> = possible_actions
+        if let Some(max) = max {
+            let possible_actions: Vec<Action> = q_values
                 .iter()
-                .map(|(&q, _)| q.action.clone())
+                .filter(|(_, value)| *value == max)
+                .map(|(action, _)| *action)
                 .collect();
-            return *possible_actions.choose().unwrap();
+            return *possible_actions.choose(rng).unwrap();
         }
 
-        return *actions.choose().unwrap();
+        *actions.choose(rng).unwrap()
     }
 
+    /// Q-learning update of the transition, then replay of past ones if the agent has a
+    /// replay buffer and planning if it uses Dyna-Q
     fn update(
         &mut self,
         state: &State,
         action: &u32,
         reward: f32,
         next_state: &State,
-        next_actions: &Vec<u32>,
+        next_actions: &[u32],
+        done: Done,
+        rng: &mut impl Rng,
     ) {
-        let old_q_value = self.get_q_value(state.clone(), *action);
-        let future_q_value = self.max_q_val(&self.q_values_subset(next_state, next_actions));
+        let transition = Transition {
+            state: state.clone(),
+            action: *action,
+            reward,
+            next_state: next_state.clone(),
+            next_actions: next_actions.to_vec(),
+            done,
+        };
+        self.learn(&transition);
 
-        // Q-learning update
-        let new_q_value = old_q_value
-            + self.learning_rate * (reward + self.discount_factor * future_q_value - old_q_value);
-        self.set_q_value(state.clone(), *action, new_q_value);
+        if let Some(replay) = self.replay.clone() {
+            let mut replay = replay.borrow_mut();
+            replay.push(transition.clone());
+            if replay.is_due() {
+                replay.replay_with(rng, |transition| self.learn(transition));
+            }
+        }
+
+        if let Some(mut dyna) = self.dyna.take() {
+            dyna.plan(rng, &transition, next_actions, |simulated| {
+                self.learn(simulated);
+            });
+            self.dyna = Some(dyna);
+        }
     }
 
     fn step(
@@ -141,11 +165,12 @@ impl IsAgent for SwarmAgent {
         state: &State,
         action: &Action,
     ) -> (Position, State, Reward, Done) {
-        (self.step_fn)(&self, env, position, state, action)
+        (self.step_fn)(self, env, position, state, action)
     }
 }
 
 impl SwarmAgent {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: u32,
         agent_type: &'static str,
@@ -165,6 +190,8 @@ impl SwarmAgent {
             agent_type,
             state,
             q_table,
+            replay: None,
+            dyna: None,
             learning_rate,
             discount_factor,
             exploration_rate,
@@ -172,8 +199,37 @@ impl SwarmAgent {
         }
     }
 
+    /// Q-learning update of the shared table, without future when the transition is done.
+    /// Returns the TD error.
+    pub fn learn(&mut self, transition: &Transition) -> f32 {
+        let Transition {
+            state,
+            action,
+            reward,
+            next_state,
+            next_actions,
+            done,
+        } = transition;
+        let old_q_value = self.get_q_value(state.clone(), *action);
+        let future_q_value = if *done {
+            0.
+        } else {
+            self.max_q_val(&self.q_values_subset(next_state, next_actions))
+        };
+
+        // Q-learning update
+        let td_error = reward + self.discount_factor * future_q_value - old_q_value;
+        self.set_q_value(
+            state.clone(),
+            *action,
+            old_q_value + self.learning_rate * td_error,
+        );
+
+        td_error
+    }
+
     /// Returns subset of q values with the same state and actions
-    fn q_values_subset(&self, state: &State, actions: &Vec<u32>) -> HashMap<Q, f32> {
+    fn q_values_subset(&self, state: &State, actions: &[u32]) -> HashMap<Q, f32> {
         let q_table = self.q_table.borrow();
         q_table
             .iter()
@@ -193,22 +249,10 @@ impl SwarmAgent {
     }
 }
 
-pub fn load_q_table(filepath: &str) -> Option<HashMap<Q, f32>> {
-    let file = match File::open(filepath) {
-        Ok(file) => file,
-        Err(_) => return None, // We do not wish to crash if the file is non-existant
-    };
-
-    let mut reader = BufReader::new(file);
-
-    let new_q_table: QTable =
-        bincode::deserialize_from(&mut reader).expect("Failed to read q_table");
-
-    Some(new_q_table)
-}
-
 #[cfg(test)]
 mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
     use masim::define_const;
 
     use crate::agent::{
@@ -248,23 +292,28 @@ mod tests {
 
         define_const!(ACTIONS => EAT, MOVE, DANCE, SING);
         let actions = Vec::from(ACTIONS);
+        // Seeded, so that the test always draws the same actions
+        let mut rng = StdRng::seed_from_u64(2);
 
         agent.set_q_value(default_state.clone(), EAT, 0.);
         agent.set_q_value(default_state.clone(), MOVE, 1.);
         agent.set_q_value(default_state.clone(), DANCE, 2.);
         agent.set_q_value(default_state.clone(), SING, 3.);
 
-        assert_eq!(agent.choose_action(&default_state, &actions), SING);
+        assert_eq!(
+            agent.choose_action(&default_state, &actions, &mut rng),
+            SING
+        );
         // assert_ne!(
-        //     agent.choose_action(&vec![Value::VBool(true)], &actions),
+        //     agent.choose_action(&vec![Value::VBool(true)], &actions, &mut rng),
         //     SING
         // );
 
         agent.set_q_value(default_state.clone(), EAT, 4.);
 
-        assert_eq!(agent.choose_action(&default_state, &actions), EAT);
+        assert_eq!(agent.choose_action(&default_state, &actions, &mut rng), EAT);
         // assert_ne!(
-        //     agent.choose_action(&vec![Value::VBool(false)], &actions),
+        //     agent.choose_action(&vec![Value::VBool(false)], &actions, &mut rng),
         //     EAT
         // );
 
@@ -274,7 +323,7 @@ mod tests {
         let mut count_move = 0;
 
         for _ in 0..1000 {
-            let result = agent.choose_action(&default_state, &actions);
+            let result = agent.choose_action(&default_state, &actions, &mut rng);
 
             match result {
                 EAT => count_eat += 1,
@@ -0,0 +1,476 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::Range,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+/// Value of each parameter of a run, by name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params(BTreeMap<String, f64>);
+
+impl Params {
+    pub fn new() -> Self {
+        Params::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: f64) {
+        self.0.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.0.get(name).copied()
+    }
+
+    /// Value of the parameter, `default` if it is not part of the sweep
+    pub fn get_or(&self, name: &str, default: f64) -> f64 {
+        self.get(name).unwrap_or(default)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), *value))
+    }
+
+    /// Set every parameter of `other`, replacing the values already there
+    pub fn extend(&mut self, other: &Params) {
+        for (name, value) in other.iter() {
+            self.set(name, value);
+        }
+    }
+}
+
+impl<const N: usize> From<[(&str, f64); N]> for Params {
+    fn from(params: [(&str, f64); N]) -> Self {
+        let mut result = Params::new();
+        for (name, value) in params {
+            result.set(name, value);
+        }
+        result
+    }
+}
+
+/// Measures reported by a run, by name (goals reached, mean reward, etc.)
+pub type Metrics = BTreeMap<String, f64>;
+
+/// Everything a run needs to know about itself
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunConfig {
+    /// Index of the combination of parameters
+    pub configuration: usize,
+    /// Index of the repetition of the configuration
+    pub repetition: usize,
+    /// Seed of the run. Repetition `i` has the same seed in every configuration.
+    pub seed: u64,
+    pub params: Params,
+}
+
+#[derive(Clone, Debug)]
+pub struct RunResult {
+    pub config: RunConfig,
+    /// Empty when the run failed
+    pub metrics: Metrics,
+    pub error: Option<String>,
+}
+
+/// Runs a scenario headless for many combinations of parameters and seeds.
+///
+/// The combinations are every combination of the `grid` values, each with `samples` random
+/// draws of the `sample` parameters. Every combination is run `repetitions` times.
+///
+/// ## Example
+/// ```rust
+/// let results = BatchRunner::builder()
+///     .grid("learning_rate", vec![0.05, 0.1, 0.2])
+///     .sample("exploration_rate", 0.0..0.3)
+///     .samples(4)
+///     .repetitions(5)
+///     .parallel(true)
+///     .build()
+///     .run(|config| batch_run(&Runner, config));
+///
+/// results.write_csv("results.csv")?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct BatchRunner {
+    grid: Vec<(String, Vec<f64>)>,
+    sampled: Vec<(String, Range<f64>)>,
+    samples: usize,
+    repetitions: usize,
+    seed: u64,
+    parallel: bool,
+}
+
+impl BatchRunner {
+    pub fn builder() -> BatchRunnerBuilder {
+        BatchRunnerBuilder {
+            grid: Vec::new(),
+            sampled: Vec::new(),
+            samples: None,
+            repetitions: None,
+            seed: None,
+            parallel: None,
+        }
+    }
+
+    /// Every combination of parameters, in the order they are run
+    pub fn configurations(&self) -> Vec<Params> {
+        let mut combinations = vec![Params::new()];
+        for (name, values) in &self.grid {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|params| {
+                    values.iter().map(move |value| {
+                        let mut params = params.clone();
+                        params.set(name, *value);
+                        params
+                    })
+                })
+                .collect();
+        }
+
+        if self.sampled.is_empty() {
+            return combinations;
+        }
+
+        // Drawn from the seed so the same batch always tries the same configurations
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        combinations
+            .into_iter()
+            .flat_map(|params| {
+                (0..self.samples)
+                    .map(|_| {
+                        let mut params = params.clone();
+                        for (name, range) in &self.sampled {
+                            let value = if range.is_empty() {
+                                range.start
+                            } else {
+                                rng.random_range(range.clone())
+                            };
+                            params.set(name, value);
+                        }
+                        params
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Every run of the batch: each configuration with each repetition
+    pub fn runs(&self) -> Vec<RunConfig> {
+        self.configurations()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(configuration, params)| {
+                (0..self.repetitions).map(move |repetition| RunConfig {
+                    configuration,
+                    repetition,
+                    seed: self.seed.wrapping_add(repetition as u64),
+                    params: params.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Run `run_fn` for every run of the batch. In parallel, each run builds its own
+    /// scheduler on its thread, so only `run_fn` has to be shared.
+    /// A run that fails records its error and the other runs go on.
+    pub fn run<E: Display>(
+        &self,
+        run_fn: impl Fn(&RunConfig) -> Result<Metrics, E> + Sync,
+    ) -> BatchResults {
+        let run = |config: &RunConfig| {
+            let (metrics, error) = match run_fn(config) {
+                Ok(metrics) => (metrics, None),
+                Err(error) => (Metrics::new(), Some(error.to_string())),
+            };
+            RunResult {
+                config: config.clone(),
+                metrics,
+                error,
+            }
+        };
+
+        let runs = self.runs();
+        let runs = if self.parallel {
+            runs.par_iter().map(run).collect()
+        } else {
+            runs.iter().map(run).collect()
+        };
+
+        BatchResults { runs }
+    }
+}
+
+pub struct BatchRunnerBuilder {
+    grid: Vec<(String, Vec<f64>)>,
+    sampled: Vec<(String, Range<f64>)>,
+    samples: Option<usize>,
+    repetitions: Option<usize>,
+    seed: Option<u64>,
+    parallel: Option<bool>,
+}
+
+impl BatchRunnerBuilder {
+    /// Try every value of a parameter
+    pub fn grid(mut self, name: &str, values: Vec<f64>) -> Self {
+        self.grid.push((name.to_string(), values));
+        self
+    }
+
+    /// Draw a parameter uniformly in `range` for each sample
+    pub fn sample(mut self, name: &str, range: Range<f64>) -> Self {
+        self.sampled.push((name.to_string(), range));
+        self
+    }
+
+    /// Number of random draws of the sampled parameters per grid combination. Default: 1
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = Some(samples);
+        self
+    }
+
+    /// Number of runs of each configuration, with different seeds. Default: 1
+    pub fn repetitions(mut self, repetitions: usize) -> Self {
+        self.repetitions = Some(repetitions);
+        self
+    }
+
+    /// Seed of the first repetition and of the sampling. Default: 0
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Spread the runs on the threads of rayon. Default: false
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = Some(parallel);
+        self
+    }
+
+    pub fn build(self) -> BatchRunner {
+        BatchRunner {
+            grid: self.grid,
+            sampled: self.sampled,
+            samples: self.samples.unwrap_or(1),
+            repetitions: self.repetitions.unwrap_or(1),
+            seed: self.seed.unwrap_or(0),
+            parallel: self.parallel.unwrap_or(false),
+        }
+    }
+}
+
+/// Sum and number of values of each metric
+type Sums = BTreeMap<String, (f64, u32)>;
+
+/// Results of every run of a batch, in the order of `BatchRunner::runs`
+#[derive(Clone, Debug)]
+pub struct BatchResults {
+    pub runs: Vec<RunResult>,
+}
+
+impl BatchResults {
+    /// Names of the parameters of all the runs, sorted
+    pub fn param_names(&self) -> Vec<String> {
+        let names: BTreeSet<&str> = self
+            .runs
+            .iter()
+            .flat_map(|run| run.config.params.iter().map(|(name, _)| name))
+            .collect();
+        names.into_iter().map(str::to_string).collect()
+    }
+
+    /// Names of the metrics reported by any run, sorted
+    pub fn metric_names(&self) -> Vec<String> {
+        let names: BTreeSet<&String> = self
+            .runs
+            .iter()
+            .flat_map(|run| run.metrics.keys())
+            .collect();
+        names.into_iter().cloned().collect()
+    }
+
+    /// Mean of each metric over the repetitions of every configuration.
+    /// A metric missing from some runs is averaged over the runs reporting it.
+    pub fn means(&self) -> Vec<(Params, Metrics)> {
+        // Sum and count of each metric, by configuration
+        let mut configurations: BTreeMap<usize, (Params, Sums)> = BTreeMap::new();
+        for run in &self.runs {
+            let (_, sums) = configurations
+                .entry(run.config.configuration)
+                .or_insert_with(|| (run.config.params.clone(), BTreeMap::new()));
+            for (name, value) in &run.metrics {
+                let (sum, count) = sums.entry(name.clone()).or_default();
+                *sum += value;
+                *count += 1;
+            }
+        }
+
+        configurations
+            .into_values()
+            .map(|(params, sums)| {
+                let means = sums
+                    .into_iter()
+                    .map(|(name, (sum, count))| (name, sum / count as f64))
+                    .collect();
+                (params, means)
+            })
+            .collect()
+    }
+
+    /// Runs that failed
+    pub fn errors(&self) -> impl Iterator<Item = (&RunConfig, &str)> {
+        self.runs
+            .iter()
+            .filter_map(|run| Some((&run.config, run.error.as_deref()?)))
+    }
+
+    /// One line per run: configuration, repetition, seed, parameters then metrics.
+    /// Metrics a run did not report are left empty. When some runs failed, a last `error`
+    /// column holds their errors.
+    pub fn to_csv(&self) -> String {
+        let params = self.param_names();
+        let metrics = self.metric_names();
+        let failed = self.errors().next().is_some();
+
+        let mut header = vec![
+            "configuration".to_string(),
+            "repetition".to_string(),
+            "seed".to_string(),
+        ];
+        header.extend(params.iter().chain(&metrics).map(|name| csv_field(name)));
+        if failed {
+            header.push("error".to_string());
+        }
+        let mut csv = header.join(",") + "\n";
+
+        for run in &self.runs {
+            let mut line = vec![
+                run.config.configuration.to_string(),
+                run.config.repetition.to_string(),
+                run.config.seed.to_string(),
+            ];
+            line.extend(params.iter().map(|name| {
+                run.config
+                    .params
+                    .get(name)
+                    .map_or(String::new(), |value| value.to_string())
+            }));
+            line.extend(metrics.iter().map(|name| {
+                run.metrics
+                    .get(name)
+                    .map_or(String::new(), |value| value.to_string())
+            }));
+            if failed {
+                line.push(csv_field(run.error.as_deref().unwrap_or_default()));
+            }
+            csv += &(line.join(",") + "\n");
+        }
+
+        csv
+    }
+
+    pub fn write_csv(&self, filepath: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filepath)?);
+        writer.write_all(self.to_csv().as_bytes())?;
+        writer.flush()
+    }
+}
+
+/// Quote a name if it would break the line
+fn csv_field(name: &str) -> String {
+    if name.contains([',', '"', '\n']) {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_and_samples() {
+        let runner = BatchRunner::builder()
+            .grid("learning_rate", vec![0.1, 0.2])
+            .grid("bots", vec![1., 2., 3.])
+            .sample("exploration_rate", 0.0..0.5)
+            .samples(2)
+            .repetitions(3)
+            .seed(7)
+            .build();
+
+        let configurations = runner.configurations();
+        assert_eq!(configurations.len(), 12);
+        assert_eq!(configurations[2].get("bots"), Some(2.));
+        assert_eq!(configurations[2].get("learning_rate"), Some(0.1));
+        assert!(configurations
+            .iter()
+            .all(|params| (0.0..0.5).contains(&params.get("exploration_rate").unwrap())));
+        // Sampling only depends on the seed
+        assert_eq!(configurations, runner.configurations());
+
+        let runs = runner.runs();
+        assert_eq!(runs.len(), 36);
+        assert_eq!((runs[4].configuration, runs[4].repetition), (1, 1));
+        assert_eq!(runs[4].seed, runs[1].seed);
+    }
+
+    #[test]
+    fn results_table() {
+        let results = BatchRunner::builder()
+            .grid("steps", vec![10., 20.])
+            .repetitions(2)
+            .parallel(true)
+            .build()
+            .run(|config| {
+                let mut metrics =
+                    Metrics::from([("score".to_string(), config.params.get_or("steps", 0.))]);
+                if config.repetition == 0 {
+                    metrics.insert("first".to_string(), 1.);
+                }
+                Ok::<_, String>(metrics)
+            });
+
+        let means = results.means();
+        assert_eq!(means.len(), 2);
+        assert_eq!(means[1].1["score"], 20.);
+        assert_eq!(means[1].1["first"], 1.);
+
+        let csv = results.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "configuration,repetition,seed,steps,first,score");
+        assert_eq!(lines[1], "0,0,0,10,1,10");
+        assert_eq!(lines[4], "1,1,1,20,,20");
+    }
+
+    #[test]
+    fn failed_runs() {
+        let results = BatchRunner::builder()
+            .grid("bots", vec![1., 2.])
+            .build()
+            .run(|config| {
+                let bots = config.params.get_or("bots", 0.);
+                if bots > 1. {
+                    return Err(format!("no room for {}, bots", bots));
+                }
+                Ok(Metrics::from([("score".to_string(), 3.)]))
+            });
+
+        assert_eq!(results.runs.len(), 2);
+        let errors: Vec<_> = results.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0.configuration, 1);
+        assert_eq!(results.means()[1].1, Metrics::new());
+
+        let csv = results.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "configuration,repetition,seed,bots,score,error");
+        assert_eq!(lines[1], "0,0,0,1,3,");
+        assert_eq!(lines[2], "1,0,0,2,,\"no room for 2, bots\"");
+    }
+}
@@ -0,0 +1,419 @@
+use std::{collections::HashMap, fmt, fs, io};
+
+use macroquad::{
+    color::{Color, DARKGRAY, GREEN},
+    math::Vec2,
+};
+
+use crate::{
+    agent::{agent::Action, state::Value},
+    interface::grid::GridSize,
+    scheduler::scheduler::Position,
+};
+
+use super::{environment::Env, registry::Key};
+
+/// Cells the agents try to reach, in reading order for a map. An agent of a scenario file
+/// reaching one starts again from a spawn position.
+pub const GOALS: Key<Vec<Position>> = Key::new("goals");
+
+/// What a character of an ASCII map stands for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symbol {
+    /// Nothing on the cell
+    Empty,
+    /// A persistent element (bush, mineral, etc.) of the given color that agents can walk on
+    Element(Color),
+    /// An obstacle of the given color, blocking movement and sight
+    Wall(Color),
+    /// An empty cell where agents can spawn
+    Spawn,
+    /// A goal cell of the given color
+    Goal(Color),
+}
+
+/// Maps the characters of an ASCII map to what they represent.
+///
+/// The default legend is:
+/// - `.` empty cell
+/// - `#` wall drawn in dark gray
+/// - `S` spawn point
+/// - `G` goal drawn in green
+#[derive(Clone, Debug)]
+pub struct Legend {
+    symbols: Vec<(char, Symbol)>,
+}
+
+impl Default for Legend {
+    fn default() -> Self {
+        Legend::empty()
+            .with('.', Symbol::Empty)
+            .with('#', Symbol::Wall(DARKGRAY))
+            .with('S', Symbol::Spawn)
+            .with('G', Symbol::Goal(GREEN))
+    }
+}
+
+impl Legend {
+    pub fn empty() -> Self {
+        Legend {
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Map (or remap) `character` to `symbol`
+    pub fn with(mut self, character: char, symbol: Symbol) -> Self {
+        self.symbols.retain(|(c, _)| *c != character);
+        self.symbols.push((character, symbol));
+        self
+    }
+
+    pub fn symbol(&self, character: char) -> Option<Symbol> {
+        self.symbols
+            .iter()
+            .find(|(c, _)| *c == character)
+            .map(|(_, symbol)| *symbol)
+    }
+
+    /// First character mapped to `symbol`
+    pub fn character(&self, symbol: Symbol) -> Option<char> {
+        self.symbols
+            .iter()
+            .find(|(_, s)| *s == symbol)
+            .map(|(c, _)| *c)
+    }
+
+    /// First character mapped to a wall of this color if `wall`, otherwise to an element or a goal
+    fn character_of_color(&self, color: Color, wall: bool) -> Option<char> {
+        self.symbols
+            .iter()
+            .find(|(_, symbol)| match symbol {
+                Symbol::Wall(c) => wall && *c == color,
+                Symbol::Element(c) | Symbol::Goal(c) => !wall && *c == color,
+                _ => false,
+            })
+            .map(|(c, _)| *c)
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    /// The map has no rows
+    Empty,
+    /// **line** and **column** start at 1
+    UnknownSymbol {
+        line: usize,
+        column: usize,
+        symbol: char,
+    },
+    /// A row does not have the same length as the first one
+    RaggedRow {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// Writing an environment with an element that has no character in the legend
+    UnmappedColor {
+        position: Position,
+        color: Color,
+    },
+    /// Writing an environment without any character for empty cells or spawn points in the legend
+    UnmappedSymbol(Symbol),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Io(error) => write!(f, "could not access the map file: {}", error),
+            MapError::Empty => write!(f, "the map is empty"),
+            MapError::UnknownSymbol {
+                line,
+                column,
+                symbol,
+            } => write!(
+                f,
+                "line {}, column {}: unknown symbol `{}`",
+                line, column, symbol
+            ),
+            MapError::RaggedRow {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} cells like the first row, found {}",
+                line, expected, found
+            ),
+            MapError::UnmappedColor { position, color } => write!(
+                f,
+                "no symbol in the legend for the element at ({}, {}) of color {:?}",
+                position.x, position.y, color
+            ),
+            MapError::UnmappedSymbol(symbol) => {
+                write!(f, "no character in the legend for {:?}", symbol)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+impl From<io::Error> for MapError {
+    fn from(error: io::Error) -> Self {
+        MapError::Io(error)
+    }
+}
+
+/// A level authored as plain text. Each character is a cell, each line a row.
+#[derive(Debug)]
+pub struct AsciiMap {
+    pub size: GridSize,
+    pub persistent_elements: HashMap<Position, Color>,
+    pub obstacles: Vec<Position>,
+    pub spawn_points: Vec<Position>,
+    pub goals: Vec<Position>,
+}
+
+impl AsciiMap {
+    pub fn parse(text: &str, legend: &Legend) -> Result<AsciiMap, MapError> {
+        // Trailing empty lines are not rows
+        let rows: Vec<&str> = text
+            .trim_end_matches(['\n', '\r'])
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .collect();
+
+        if rows.is_empty() || rows[0].is_empty() {
+            return Err(MapError::Empty);
+        }
+
+        let width = rows[0].chars().count();
+        let mut map = AsciiMap {
+            size: GridSize {
+                width,
+                heigth: rows.len(),
+            },
+            persistent_elements: HashMap::new(),
+            obstacles: Vec::new(),
+            spawn_points: Vec::new(),
+            goals: Vec::new(),
+        };
+
+        for (y, row) in rows.iter().enumerate() {
+            let found = row.chars().count();
+            if found != width {
+                return Err(MapError::RaggedRow {
+                    line: y + 1,
+                    expected: width,
+                    found,
+                });
+            }
+
+            for (x, character) in row.chars().enumerate() {
+                let position = Position {
+                    x: x as i32,
+                    y: y as i32,
+                };
+
+                match legend.symbol(character) {
+                    Some(Symbol::Empty) => {}
+                    Some(Symbol::Element(color)) => {
+                        map.persistent_elements.insert(position, color);
+                    }
+                    Some(Symbol::Wall(color)) => {
+                        map.persistent_elements.insert(position, color);
+                        map.obstacles.push(position);
+                    }
+                    Some(Symbol::Spawn) => map.spawn_points.push(position),
+                    Some(Symbol::Goal(color)) => {
+                        map.persistent_elements.insert(position, color);
+                        map.goals.push(position);
+                    }
+                    None => {
+                        return Err(MapError::UnknownSymbol {
+                            line: y + 1,
+                            column: x + 1,
+                            symbol: character,
+                        })
+                    }
+                }
+            }
+        }
+
+        Ok(map)
+    }
+
+    pub fn load(filepath: &str, legend: &Legend) -> Result<AsciiMap, MapError> {
+        AsciiMap::parse(&fs::read_to_string(filepath)?, legend)
+    }
+
+    /// Build an environment of the size of the map with its elements, obstacles and spawn points.
+    /// The goals are kept in the registry under `GOALS`.
+    pub fn into_env(
+        self,
+        start: Vec2,
+        end: Vec2,
+        actions: &[Action],
+        data: HashMap<u32, Value>,
+    ) -> Env {
+        let mut env = Env::new(
+            start,
+            end,
+            self.size,
+            self.persistent_elements,
+            actions,
+            data,
+        );
+        env.obstacles.extend(self.obstacles);
+        env.spawn_points = self.spawn_points;
+        env.registry.insert(GOALS, self.goals);
+
+        env
+    }
+}
+
+/// Dump an environment back to the ASCII format read by `AsciiMap::parse`
+pub fn write_ascii_map(env: &Env, legend: &Legend) -> Result<String, MapError> {
+    let empty = legend
+        .character(Symbol::Empty)
+        .ok_or(MapError::UnmappedSymbol(Symbol::Empty))?;
+
+    let GridSize { width, heigth } = *env.get_grid_size();
+    let mut rows = vec![vec![empty; width]; heigth];
+
+    for position in &env.spawn_points {
+        let spawn = legend
+            .character(Symbol::Spawn)
+            .ok_or(MapError::UnmappedSymbol(Symbol::Spawn))?;
+        rows[position.y as usize][position.x as usize] = spawn;
+    }
+
+    for (position, color) in &env.persistent_elements {
+        if !env.position_inbound(*position) {
+            continue;
+        }
+
+        let character = legend
+            .character_of_color(*color, env.obstacles.contains(position))
+            .ok_or(MapError::UnmappedColor {
+                position: *position,
+                color: *color,
+            })?;
+        rows[position.y as usize][position.x as usize] = character;
+    }
+
+    let mut text = String::with_capacity((width + 1) * heigth);
+    for row in rows {
+        text.extend(row);
+        text.push('\n');
+    }
+
+    Ok(text)
+}
+
+pub fn save_ascii_map(env: &Env, legend: &Legend, filepath: &str) -> Result<(), MapError> {
+    fs::write(filepath, write_ascii_map(env, legend)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::{color::BROWN, math::vec2};
+
+    use super::*;
+
+    const ARENA: &str = "\
+#######
+#S...G#
+#.##..#
+#....S#
+#######
+";
+
+    #[test]
+    fn loading_and_writing() {
+        let legend = Legend::default();
+        let map = AsciiMap::parse(ARENA, &legend).unwrap();
+
+        assert_eq!(
+            map.size,
+            GridSize {
+                width: 7,
+                heigth: 5
+            }
+        );
+        assert_eq!(
+            map.spawn_points,
+            vec![Position { x: 1, y: 1 }, Position { x: 5, y: 3 }]
+        );
+        assert_eq!(map.goals, vec![Position { x: 5, y: 1 }]);
+        assert_eq!(
+            map.persistent_elements.get(&Position { x: 2, y: 2 }),
+            Some(&DARKGRAY)
+        );
+        assert_eq!(map.obstacles.len(), 22);
+
+        let env = map.into_env(vec2(0., 0.), vec2(1., 1.), &[], HashMap::new());
+        assert!(!env.passable(Position { x: 2, y: 2 }));
+        assert!(env.passable(Position { x: 1, y: 2 }));
+        assert_eq!(
+            env.registry.get(GOALS),
+            Some(&vec![Position { x: 5, y: 1 }])
+        );
+        assert_eq!(write_ascii_map(&env, &legend).unwrap(), ARENA);
+    }
+
+    #[test]
+    fn custom_legend() {
+        let legend = Legend::default().with('T', Symbol::Element(BROWN));
+        let map = AsciiMap::parse("T.\n.T\n", &legend).unwrap();
+
+        assert_eq!(map.persistent_elements.len(), 2);
+        assert_eq!(
+            map.persistent_elements.get(&Position { x: 1, y: 1 }),
+            Some(&BROWN)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let legend = Legend::default();
+
+        match AsciiMap::parse("...\n.x.\n", &legend) {
+            Err(MapError::UnknownSymbol {
+                line,
+                column,
+                symbol,
+            }) => assert_eq!((line, column, symbol), (2, 2, 'x')),
+            other => panic!("Expected an unknown symbol, got {:?}", other),
+        }
+
+        match AsciiMap::parse("...\n..\n...", &legend) {
+            Err(MapError::RaggedRow {
+                line,
+                expected,
+                found,
+            }) => assert_eq!((line, expected, found), (2, 3, 2)),
+            other => panic!("Expected a ragged row, got {:?}", other),
+        }
+
+        assert!(matches!(
+            AsciiMap::parse("\n", &legend),
+            Err(MapError::Empty)
+        ));
+
+        // Writing an element of a color unknown to the legend
+        let mut env = AsciiMap::parse("..\n..", &legend).unwrap().into_env(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            &[],
+            HashMap::new(),
+        );
+        env.update_persistent_element(Position { x: 0, y: 0 }, BROWN);
+        assert!(matches!(
+            write_ascii_map(&env, &legend),
+            Err(MapError::UnmappedColor { .. })
+        ));
+    }
+}
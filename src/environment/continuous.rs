@@ -0,0 +1,389 @@
+use std::collections::{HashMap, HashSet};
+
+use macroquad::math::{IVec2, Vec2};
+
+use crate::{interface::grid::GridSize, scheduler::scheduler::Position};
+
+use super::topology::Topology;
+
+/// Physical properties of an agent living in a continuous space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Body {
+    pub position: Vec2,
+    /// Heading in radians. 0 points toward +x and angles grow clockwise on screen (+y is down).
+    pub heading: f32,
+    pub velocity: Vec2,
+}
+
+impl Body {
+    pub fn new(position: Vec2, heading: f32) -> Self {
+        Body {
+            position,
+            heading,
+            velocity: Vec2::ZERO,
+        }
+    }
+
+    /// Unit vector pointing where the body is heading
+    pub fn direction(&self) -> Vec2 {
+        Vec2::from_angle(self.heading)
+    }
+}
+
+/// Buckets the agents by square cells of `cell_size` so radius queries only look at nearby agents.
+pub struct SpatialHash {
+    cell_size: f32,
+    buckets: HashMap<IVec2, Vec<u32>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(
+            cell_size > 0.,
+            "The cell size of a spatial hash must be positive"
+        );
+
+        SpatialHash {
+            cell_size,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn bucket_of(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    pub fn insert(&mut self, id: u32, position: Vec2) {
+        self.buckets
+            .entry(self.bucket_of(position))
+            .or_default()
+            .push(id);
+    }
+
+    pub fn remove(&mut self, id: u32, position: Vec2) {
+        let bucket = self.bucket_of(position);
+        if let Some(ids) = self.buckets.get_mut(&bucket) {
+            ids.retain(|other| *other != id);
+            if ids.is_empty() {
+                self.buckets.remove(&bucket);
+            }
+        }
+    }
+
+    pub fn get(&self, bucket: &IVec2) -> &[u32] {
+        self.buckets.get(bucket).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+/// A 2D space where agents have real valued positions, headings and velocities.
+///
+/// The space spans `[0, width) x [0, heigth)`. Agents are identified by their unique id
+/// so a step function can find its own body with `agent.id`.
+pub struct ContinuousSpace {
+    pub width: f32,
+    pub heigth: f32,
+    pub topology: Topology,
+    bodies: HashMap<u32, Body>,
+    hash: SpatialHash,
+}
+
+impl ContinuousSpace {
+    /// **cell_size:** size of the spatial hash buckets. Should be close to the usual query radius.
+    pub fn new(width: f32, heigth: f32, topology: Topology, cell_size: f32) -> Self {
+        ContinuousSpace {
+            width,
+            heigth,
+            topology,
+            bodies: HashMap::new(),
+            hash: SpatialHash::new(cell_size),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bodies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bodies.is_empty()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.bodies.contains_key(&id)
+    }
+
+    pub fn body(&self, id: u32) -> Option<&Body> {
+        self.bodies.get(&id)
+    }
+
+    pub fn bodies(&self) -> impl Iterator<Item = (&u32, &Body)> {
+        self.bodies.iter()
+    }
+
+    /// Bring a position back inside the space according to the topology
+    pub fn normalize(&self, position: Vec2) -> Vec2 {
+        Vec2 {
+            x: self.topology.wrap_f32(position.x, self.width),
+            y: self.topology.wrap_f32(position.y, self.heigth),
+        }
+    }
+
+    /// Add a body to the space (or replace the existing one)
+    pub fn place(&mut self, id: u32, mut body: Body) {
+        self.remove(id);
+        body.position = self.normalize(body.position);
+        self.hash.insert(id, body.position);
+        self.bodies.insert(id, body);
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Body> {
+        let body = self.bodies.remove(&id)?;
+        self.hash.remove(id, body.position);
+        Some(body)
+    }
+
+    /// Move a body to a new position and returns where it ended up once the topology is applied
+    pub fn move_to(&mut self, id: u32, position: Vec2) -> Option<Vec2> {
+        let position = self.normalize(position);
+        let body = self.bodies.get_mut(&id)?;
+
+        if self.hash.bucket_of(body.position) != self.hash.bucket_of(position) {
+            self.hash.remove(id, body.position);
+            self.hash.insert(id, position);
+        }
+        body.position = position;
+
+        Some(position)
+    }
+
+    /// Move a body along its heading
+    pub fn forward(&mut self, id: u32, distance: f32) -> Option<Vec2> {
+        let body = self.bodies.get(&id)?;
+        let target = body.position + body.direction() * distance;
+        self.move_to(id, target)
+    }
+
+    /// Integrate the velocity of a body over `dt`
+    pub fn advance(&mut self, id: u32, dt: f32) -> Option<Vec2> {
+        let body = self.bodies.get(&id)?;
+        let target = body.position + body.velocity * dt;
+        self.move_to(id, target)
+    }
+
+    pub fn set_heading(&mut self, id: u32, heading: f32) {
+        if let Some(body) = self.bodies.get_mut(&id) {
+            body.heading = heading.rem_euclid(std::f32::consts::TAU);
+        }
+    }
+
+    /// Rotate a body by `angle` radians
+    pub fn turn(&mut self, id: u32, angle: f32) {
+        if let Some(heading) = self.bodies.get(&id).map(|body| body.heading) {
+            self.set_heading(id, heading + angle);
+        }
+    }
+
+    pub fn set_velocity(&mut self, id: u32, velocity: Vec2) {
+        if let Some(body) = self.bodies.get_mut(&id) {
+            body.velocity = velocity;
+        }
+    }
+
+    /// Shortest vector going from `from` to `to`, wrapping around the edges when toroidal
+    pub fn displacement(&self, from: Vec2, to: Vec2) -> Vec2 {
+        Vec2 {
+            x: self.topology.delta_f32(from.x, to.x, self.width),
+            y: self.topology.delta_f32(from.y, to.y, self.heigth),
+        }
+    }
+
+    pub fn distance(&self, from: Vec2, to: Vec2) -> f32 {
+        self.displacement(from, to).length()
+    }
+
+    /// Ids of all the bodies within `radius` of `position`
+    pub fn query_radius(&self, position: Vec2, radius: f32) -> Vec<u32> {
+        // The query box is wrapped rather than the buckets, which do not tile the space
+        // exactly when its size is not a multiple of the cell size
+        let position = self.normalize(position);
+        let ranges_x = self.query_ranges(position.x, radius, self.width);
+        let ranges_y = self.query_ranges(position.y, radius, self.heigth);
+
+        let mut visited: HashSet<IVec2> = HashSet::new();
+        let mut found = Vec::new();
+
+        for (min_x, max_x) in &ranges_x {
+            for (min_y, max_y) in &ranges_y {
+                let min = self.hash.bucket_of(Vec2::new(*min_x, *min_y));
+                let max = self.hash.bucket_of(Vec2::new(*max_x, *max_y));
+
+                for x in min.x..=max.x {
+                    for y in min.y..=max.y {
+                        let bucket = IVec2 { x, y };
+                        if !visited.insert(bucket) {
+                            continue;
+                        }
+
+                        for id in self.hash.get(&bucket) {
+                            let body = &self.bodies[id];
+                            if self.distance(position, body.position) <= radius {
+                                found.push(*id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Ids of the bodies within `radius` of the body `id`, excluding itself
+    pub fn neighbours(&self, id: u32, radius: f32) -> Vec<u32> {
+        match self.bodies.get(&id) {
+            Some(body) => self
+                .query_radius(body.position, radius)
+                .into_iter()
+                .filter(|other| *other != id)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Cell of a grid of `size` covering the whole space in which `position` falls.
+    /// This is how continuous agents are discretised for the scheduler and Q-learning.
+    pub fn to_cell(&self, position: Vec2, size: &GridSize) -> Position {
+        let position = self.normalize(position);
+        let x = (position.x / self.width * size.width as f32) as i32;
+        let y = (position.y / self.heigth * size.heigth as f32) as i32;
+
+        Position {
+            x: x.min(size.width as i32 - 1),
+            y: y.min(size.heigth as i32 - 1),
+        }
+    }
+
+    /// Center of a grid cell expressed in the coordinates of the space
+    pub fn cell_center(&self, cell: Position, size: &GridSize) -> Vec2 {
+        let cell_width = self.width / size.width as f32;
+        let cell_heigth = self.heigth / size.heigth as f32;
+
+        Vec2 {
+            x: (cell.x as f32 + 0.5) * cell_width,
+            y: (cell.y as f32 + 0.5) * cell_heigth,
+        }
+    }
+
+    /// Intervals of one axis covered by a query, split in two when it crosses the edge of a
+    /// toroidal space
+    fn query_ranges(&self, center: f32, radius: f32, extent: f32) -> Vec<(f32, f32)> {
+        let (min, max) = (center - radius, center + radius);
+        match self.topology {
+            Topology::Bounded => vec![(min, max)],
+            Topology::Toroidal if 2. * radius >= extent => vec![(0., extent)],
+            Topology::Toroidal if min < 0. => vec![(min + extent, extent), (0., max)],
+            Topology::Toroidal if max >= extent => vec![(min, extent), (0., max - extent)],
+            Topology::Toroidal => vec![(min, max)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut ids: Vec<u32>) -> Vec<u32> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn bounded_space_clamps_positions() {
+        let mut space = ContinuousSpace::new(10., 10., Topology::Bounded, 2.);
+        space.place(1, Body::new(Vec2::new(5., 5.), 0.));
+
+        let position = space.move_to(1, Vec2::new(12., -3.)).unwrap();
+        assert!(position.x < 10. && position.x > 9.99);
+        assert_eq!(position.y, 0.);
+    }
+
+    #[test]
+    fn toroidal_space_wraps_positions() {
+        let mut space = ContinuousSpace::new(10., 10., Topology::Toroidal, 2.);
+        space.place(1, Body::new(Vec2::new(9., 5.), 0.));
+
+        // heading 0 goes toward +x
+        let position = space.forward(1, 2.).unwrap();
+        assert!((position.x - 1.).abs() < 1e-5);
+        assert!((position.y - 5.).abs() < 1e-5);
+
+        assert!(
+            (space.distance(Vec2::new(0.5, 0.5), Vec2::new(9.5, 9.5)) - 2_f32.sqrt()).abs() < 1e-5
+        );
+    }
+
+    #[test]
+    fn radius_queries() {
+        let mut space = ContinuousSpace::new(20., 20., Topology::Toroidal, 2.);
+        space.place(1, Body::new(Vec2::new(1., 1.), 0.));
+        space.place(2, Body::new(Vec2::new(2., 1.), 0.));
+        space.place(3, Body::new(Vec2::new(19.5, 1.), 0.));
+        space.place(4, Body::new(Vec2::new(10., 10.), 0.));
+
+        assert_eq!(sorted(space.neighbours(1, 1.6)), vec![2, 3]);
+        assert_eq!(
+            sorted(space.query_radius(Vec2::new(10., 10.), 0.5)),
+            vec![4]
+        );
+
+        // Moving far away updates the buckets
+        space.move_to(2, Vec2::new(10.5, 10.)).unwrap();
+        assert_eq!(sorted(space.neighbours(1, 1.6)), vec![3]);
+        assert_eq!(
+            sorted(space.query_radius(Vec2::new(10., 10.), 1.)),
+            vec![2, 4]
+        );
+
+        // A bounded space does not see across the edges
+        let mut space = ContinuousSpace::new(20., 20., Topology::Bounded, 2.);
+        space.place(1, Body::new(Vec2::new(1., 1.), 0.));
+        space.place(3, Body::new(Vec2::new(19.5, 1.), 0.));
+        assert!(space.neighbours(1, 1.6).is_empty());
+    }
+
+    #[test]
+    fn radius_queries_across_the_seam() {
+        // 10 is not a multiple of 3, so the last column of buckets is narrower
+        let mut space = ContinuousSpace::new(10., 10., Topology::Toroidal, 3.);
+        space.place(1, Body::new(Vec2::new(0.2, 5.), 0.));
+        space.place(2, Body::new(Vec2::new(9.8, 0.1), 0.));
+
+        assert_eq!(space.query_radius(Vec2::new(9.5, 5.), 1.), vec![1]);
+        assert_eq!(space.query_radius(Vec2::new(0.5, 9.5), 1.), vec![2]);
+        assert_eq!(
+            sorted(space.query_radius(Vec2::new(5., 5.), 8.)),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn discretisation() {
+        let space = ContinuousSpace::new(100., 50., Topology::Bounded, 5.);
+        let size = GridSize {
+            width: 10,
+            heigth: 5,
+        };
+
+        assert_eq!(
+            space.to_cell(Vec2::new(0., 0.), &size),
+            Position { x: 0, y: 0 }
+        );
+        assert_eq!(
+            space.to_cell(Vec2::new(55., 49.), &size),
+            Position { x: 5, y: 4 }
+        );
+        assert_eq!(
+            space.cell_center(Position { x: 5, y: 4 }, &size),
+            Vec2::new(55., 45.)
+        );
+    }
+}
@@ -1,9 +1,14 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::Arc,
+};
 
 use macroquad::{
     color::Color,
     math::{IVec2, Vec2},
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use crate::{
     agent::{
@@ -11,7 +16,19 @@ use crate::{
         state::Value,
     },
     interface::grid::{Grid, GridSize},
-    scheduler::scheduler::{AgentRef, Position},
+    scheduler::{
+        events::{Event, EventId, EventQueue, Time},
+        scheduler::{AgentRef, Position},
+    },
+};
+
+use super::{
+    continuous::ContinuousSpace,
+    field::Field,
+    graph::{Graph, GraphError, NodeId},
+    registry::Registry,
+    resource::{Regrowth, ResourceLayer},
+    topology::Topology,
 };
 
 pub struct Env {
@@ -21,7 +38,32 @@ pub struct Env {
     /// Element with persistent long term position such as obstacles (walls, bushes, etc.), the goal cell, etc.
     /// Unlike the grid, those are in an hashmap because if an agent need to check a cell we want to have an access of O(1)
     pub persistent_elements: HashMap<Position, Color>,
+    /// Cells that block movement and sight (see `passable`). They are drawn as persistent elements.
+    pub obstacles: HashSet<Position>,
     pub data: HashMap<u32, Value>,
+    /// Typed data that does not need to be converted to `Value` (see `Key`)
+    pub registry: Registry,
+    /// Scalar layers (pheromones, heat, scent, etc.) updated once per tick. Keys are defined with `define_const!`.
+    pub fields: HashMap<u32, Field>,
+    /// Harvestable quantities (sugar, grass, minerals, etc.) regrowing once per tick. Keys are defined with `define_const!`.
+    pub resources: HashMap<u32, ResourceLayer>,
+    /// Whether moving past an edge of the grid wraps around (see `try_move` and the pathfinding)
+    pub topology: Topology,
+    /// Clock of the simulation and future events (see `Scheduler::run_until`)
+    pub events: EventQueue,
+    /// Number of ticks the environment went through
+    pub tick: u64,
+    /// Cells where agents appear when they are added without a position (see `get_spawn_position`)
+    pub spawn_points: Vec<Position>,
+    /// Optional continuous space for agents that do not live on the cells (flocking, robot swarms, etc.).
+    /// Their positions in the scheduler are the grid cells they fall in.
+    pub space: Option<ContinuousSpace>,
+    /// Optional network for agents living on nodes. Their positions are the layout positions of the nodes.
+    graph: Option<Graph>,
+    /// Random generator of the run: exploration of the agents, spawns, activation orders,
+    /// conflicts, replay and planning. Step functions should draw from it too, so that a run
+    /// only depends on its seed (see `set_seed`).
+    pub rng: StdRng,
 }
 
 impl Env {
@@ -36,9 +78,104 @@ impl Env {
         Env {
             grid: Grid::new(start, end, size),
             actions: Vec::from(actions),
-            persistent_elements: persistent_elements,
+            persistent_elements,
+            obstacles: HashSet::new(),
             data,
+            registry: Registry::new(),
+            fields: HashMap::new(),
+            resources: HashMap::new(),
+            topology: Topology::Bounded,
+            events: EventQueue::new(),
+            tick: 0,
+            spawn_points: Vec::new(),
+            space: None,
+            graph: None,
+            rng: StdRng::from_os_rng(),
+        }
+    }
+
+    /// Make every random draw of the run depend on `seed` only
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Run `f` with the environment and its random generator, for draws that also read the
+    /// environment
+    pub fn with_rng<T>(&mut self, f: impl FnOnce(&Env, &mut StdRng) -> T) -> T {
+        let mut rng = mem::replace(&mut self.rng, StdRng::seed_from_u64(0));
+        let result = f(self, &mut rng);
+        self.rng = rng;
+        result
+    }
+
+    /// Attach a continuous space on top of the grid
+    pub fn set_space(&mut self, space: ContinuousSpace) {
+        self.space = Some(space);
+    }
+
+    /// Grid cell in which a position of the continuous space falls.
+    ///
+    /// Panics if no continuous space was set on the environment.
+    pub fn discretise(&self, position: Vec2) -> Position {
+        self.space
+            .as_ref()
+            .expect("No continuous space set on the environment")
+            .to_cell(position, &self.grid.size)
+    }
+
+    /// Attach a graph to the environment. The grid must be big enough to lay out every node
+    /// (see `Graph::layout_size`).
+    ///
+    /// The actions of the environment become "move along edge k" (see `Graph::edge_actions`),
+    /// which step functions apply with `follow_edge`.
+    pub fn set_graph(&mut self, graph: Graph) -> Result<(), GraphError> {
+        if graph.node_count() == 0 {
+            return Err(GraphError::Empty);
+        }
+        let layout = graph.layout_size();
+        if layout.width > self.grid.size.width || layout.heigth > self.grid.size.heigth {
+            return Err(GraphError::TooLarge {
+                width: layout.width,
+                heigth: layout.heigth,
+            });
         }
+
+        self.actions = graph.edge_actions();
+        self.graph = Some(graph);
+        Ok(())
+    }
+
+    pub fn graph(&self) -> Option<&Graph> {
+        self.graph.as_ref()
+    }
+
+    /// Position reached by moving along the edge `action` of the node at `position`. Stays in
+    /// place when the node has no such edge.
+    ///
+    /// Panics if no graph was set on the environment.
+    pub fn follow_edge(&self, position: Position, action: Action) -> Position {
+        let graph = self
+            .graph
+            .as_ref()
+            .expect("No graph set on the environment");
+        graph
+            .node_at(position)
+            .and_then(|node| graph.follow_edge(node, action))
+            .map_or(position, |edge| graph.position_of(edge.to))
+    }
+
+    /// Node on which an agent at `position` stands.
+    ///
+    /// Panics if no graph was set on the environment.
+    pub fn node_of(&self, position: Position) -> Option<NodeId> {
+        self.graph
+            .as_ref()
+            .expect("No graph set on the environment")
+            .node_at(position)
+    }
+
+    pub fn get_grid_size(&self) -> &GridSize {
+        &self.grid.size
     }
 
     pub fn get_width(&self) -> &usize {
@@ -62,6 +199,8 @@ impl Env {
             grid_color,
             agent_positions,
             &self.persistent_elements,
+            &self.fields,
+            &self.resources,
         );
     }
 
@@ -72,13 +211,54 @@ impl Env {
         y >= 0 && y < self.grid.size.heigth as i32 // y
     }
 
+    /// Whether an agent can stand on a cell: inside the grid and not an obstacle
+    pub fn passable(&self, position: Position) -> bool {
+        self.position_inbound(position) && !self.obstacles.contains(&position)
+    }
+
+    /// Add a blocking cell drawn with `color`
+    pub fn add_obstacle(&mut self, position: Position, color: Color) {
+        self.obstacles.insert(position);
+        self.persistent_elements.insert(position, color);
+    }
+
+    pub fn remove_obstacle(&mut self, position: Position) {
+        if self.obstacles.remove(&position) {
+            self.persistent_elements.remove(&position);
+        }
+    }
+
+    /// Default movement rule: `to` (wrapped on a toroidal grid) if it is passable,
+    /// otherwise the agent stays on `from`
+    pub fn try_move(&self, from: Position, to: Position) -> Position {
+        self.topology
+            .wrap_cell(to, &self.grid.size)
+            .filter(|to| self.passable(*to))
+            .unwrap_or(from)
+    }
+
     pub fn step(&mut self, position: Position, agent: &mut AgentRef) -> (Position, Done) {
-        let mut agent = agent.borrow_mut();
+        let action = self.choose(agent);
+        self.apply(position, agent, action)
+    }
 
-        let action = agent.choose_action(&agent.get_state(), &self.actions);
+    /// First half of `step`: the agent picks an action from its current state
+    pub fn choose(&mut self, agent: &AgentRef) -> Action {
+        let agent = agent.borrow();
+        agent.choose_action(agent.get_state(), &self.actions, &mut self.rng)
+    }
+
+    /// Second half of `step`: run the step function of the agent with `action` and learn from it
+    pub fn apply(
+        &mut self,
+        position: Position,
+        agent: &mut AgentRef,
+        action: Action,
+    ) -> (Position, Done) {
+        let mut agent = agent.borrow_mut();
 
         let (new_position, next_state, reward, done) =
-            agent.step(self, position, &agent.get_state(), &action);
+            agent.step(self, position, agent.get_state(), &action);
 
         let state = agent.get_state().clone();
 
@@ -89,6 +269,8 @@ impl Env {
             reward,
             &next_state,
             &self.actions, // THIS SHOULD POSSIBLY VARY
+            done,
+            &mut self.rng,
         );
 
         agent.set_state(next_state);
@@ -96,13 +278,141 @@ impl Env {
         (new_position, done)
     }
 
-    pub fn get_random_position(&self) -> Position {
-        Position {
-            x: rand::random_range(0..*self.get_width() as i32),
-            y: rand::random_range(0..*self.get_heigth() as i32),
+    /// Apply the dynamics of the environment layers. Called by the scheduler once per tick.
+    pub fn update(&mut self) {
+        for field in self.fields.values_mut() {
+            field.update();
+        }
+
+        self.tick += 1;
+        for resource in self.resources.values_mut() {
+            resource.regrow(self.tick);
         }
     }
 
+    /// Put the tick and the clock back to 0 and drop the pending events, for a new episode
+    pub fn reset_clock(&mut self) {
+        self.tick = 0;
+        self.events.clear();
+    }
+
+    /// Current time of the simulation
+    pub fn now(&self) -> Time {
+        self.events.now()
+    }
+
+    /// Make the agent with this unique id act again `delay` after now
+    pub fn schedule_activation(&mut self, agent_id: u32, delay: Time, priority: i32) -> EventId {
+        self.events
+            .schedule_in(delay, priority, Event::Activate(agent_id))
+    }
+
+    /// Run `env_event_fn` on the environment `delay` after now
+    pub fn schedule(
+        &mut self,
+        delay: Time,
+        priority: i32,
+        env_event_fn: impl Fn(&mut Env) + Send + Sync + 'static,
+    ) -> EventId {
+        self.events
+            .schedule_in(delay, priority, Event::Env(Arc::new(env_event_fn)))
+    }
+
+    /// Returns false if the event already happened or was already cancelled
+    pub fn cancel_event(&mut self, id: EventId) -> bool {
+        self.events.cancel(id)
+    }
+
+    /// Add a field of the size of the grid
+    pub fn add_field(&mut self, id: u32, diffusion: f32, evaporation: f32) -> &mut Field {
+        self.fields
+            .entry(id)
+            .or_insert(Field::new(self.grid.size, diffusion, evaporation))
+    }
+
+    pub fn field(&self, id: u32) -> Option<&Field> {
+        self.fields.get(&id)
+    }
+
+    pub fn field_mut(&mut self, id: u32) -> Option<&mut Field> {
+        self.fields.get_mut(&id)
+    }
+
+    /// Add a resource layer of the size of the grid. Cells have no capacity until it is set.
+    pub fn add_resource(&mut self, id: u32, regrowth: Regrowth) -> &mut ResourceLayer {
+        self.resources
+            .entry(id)
+            .or_insert(ResourceLayer::new(self.grid.size, regrowth))
+    }
+
+    pub fn resource(&self, id: u32) -> Option<&ResourceLayer> {
+        self.resources.get(&id)
+    }
+
+    pub fn resource_mut(&mut self, id: u32) -> Option<&mut ResourceLayer> {
+        self.resources.get_mut(&id)
+    }
+
+    /// Take up to `amount` of the resource `id` at `position`. Returns the quantity actually taken.
+    pub fn harvest(&mut self, id: u32, position: Position, amount: f32) -> f32 {
+        let tick = self.tick;
+        match self.resources.get_mut(&id) {
+            Some(resource) => resource.harvest(position, amount, tick),
+            None => 0.,
+        }
+    }
+
+    /// Quantity currently available of every resource
+    pub fn resource_totals(&self) -> HashMap<u32, f32> {
+        self.resources
+            .iter()
+            .map(|(id, resource)| (*id, resource.total()))
+            .collect()
+    }
+
+    pub fn get_random_position(&mut self) -> Position {
+        self.with_rng(|env, rng| env.get_random_position_with(rng))
+    }
+
+    /// Same as `get_random_position` with a given random generator, for seeded runs
+    pub fn get_random_position_with(&self, rng: &mut impl Rng) -> Position {
+        // Agents on a graph can only stand on nodes
+        if let Some(graph) = &self.graph {
+            return graph.position_of(rng.random_range(0..graph.node_count()));
+        }
+
+        let (width, heigth) = (*self.get_width() as i32, *self.get_heigth() as i32);
+        let mut random_cell = || Position {
+            x: rng.random_range(0..width),
+            y: rng.random_range(0..heigth),
+        };
+
+        // Avoid obstacles, unless the grid is (almost) full of them
+        let mut position = random_cell();
+        for _ in 0..self.grid.size.width * self.grid.size.heigth {
+            if !self.obstacles.contains(&position) {
+                break;
+            }
+            position = random_cell();
+        }
+
+        position
+    }
+
+    /// Random spawn point, or a random position if the environment has none
+    pub fn get_spawn_position(&mut self) -> Position {
+        self.with_rng(|env, rng| env.get_spawn_position_with(rng))
+    }
+
+    /// Same as `get_spawn_position` with a given random generator, for seeded runs
+    pub fn get_spawn_position_with(&self, rng: &mut impl Rng) -> Position {
+        if self.spawn_points.is_empty() {
+            return self.get_random_position_with(rng);
+        }
+
+        self.spawn_points[rng.random_range(0..self.spawn_points.len())]
+    }
+
     pub fn set_persitent_elements(&mut self, persistent_elements: HashMap<Position, Color>) {
         self.persistent_elements = persistent_elements;
     }
@@ -111,14 +421,25 @@ impl Env {
         self.persistent_elements.insert(position, color);
     }
 
+    /// Move an element, replacing the one at `new_position`. An obstacle stays one.
     pub fn move_persistent_element(&mut self, current_position: Position, new_position: Position) {
         if let Some(element) = self.persistent_elements.remove(&current_position) {
             self.persistent_elements.insert(new_position, element);
+
+            let blocking = self.obstacles.remove(&current_position);
+            self.obstacles.remove(&new_position);
+            if blocking {
+                self.obstacles.insert(new_position);
+            }
         }
     }
 
+    /// Remove every element whose color is not in `exceptions`, with its obstacle
     pub fn reset_persistent_element(&mut self, exceptions: Vec<Color>) {
         self.persistent_elements
-            .retain(|_, color| exceptions.contains(&color));
+            .retain(|_, color| exceptions.contains(color));
+        let elements = &self.persistent_elements;
+        self.obstacles
+            .retain(|position| elements.contains_key(position));
     }
 }
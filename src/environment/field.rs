@@ -0,0 +1,225 @@
+use macroquad::{
+    color::Color,
+    math::{vec2, Vec2},
+};
+
+use crate::{interface::grid::GridSize, scheduler::scheduler::Position};
+
+const NEIGHBOURS_8: [Position; 8] = [
+    Position { x: -1, y: -1 },
+    Position { x: 0, y: -1 },
+    Position { x: 1, y: -1 },
+    Position { x: -1, y: 0 },
+    Position { x: 1, y: 0 },
+    Position { x: -1, y: 1 },
+    Position { x: 0, y: 1 },
+    Position { x: 1, y: 1 },
+];
+
+/// Dense scalar layer over the grid such as pheromones, heat or scent.
+///
+/// Agents deposit into it and sense its gradient, the environment makes it diffuse and
+/// evaporate once per tick (see `Env::update`).
+#[derive(Clone, Debug)]
+pub struct Field {
+    size: GridSize,
+    values: Vec<f32>,
+    /// Fraction of the value of a cell shared with its 8 neighbours each tick
+    pub diffusion: f32,
+    /// Fraction of the value of a cell lost each tick
+    pub evaporation: f32,
+    /// Color of the heatmap. The field is not displayed when `None`.
+    pub color: Option<Color>,
+    /// Value displayed with the full intensity of the color
+    pub display_max: f32,
+}
+
+impl Field {
+    pub fn new(size: GridSize, diffusion: f32, evaporation: f32) -> Self {
+        Field {
+            size,
+            values: vec![0.; size.width * size.heigth],
+            diffusion: diffusion.clamp(0., 1.),
+            evaporation: evaporation.clamp(0., 1.),
+            color: None,
+            display_max: 1.,
+        }
+    }
+
+    /// Display the field as a heatmap of `color`, fully opaque at `display_max`
+    pub fn with_heatmap(mut self, color: Color, display_max: f32) -> Self {
+        self.set_heatmap(color, display_max);
+        self
+    }
+
+    /// Same as `with_heatmap` for a field already in an environment:
+    /// `env.add_field(id, 0.1, 0.01).set_heatmap(RED, 5.);`
+    pub fn set_heatmap(&mut self, color: Color, display_max: f32) -> &mut Self {
+        self.color = Some(color);
+        self.display_max = display_max;
+        self
+    }
+
+    pub fn size(&self) -> &GridSize {
+        &self.size
+    }
+
+    pub fn inbound(&self, position: Position) -> bool {
+        position.x >= 0
+            && position.y >= 0
+            && (position.x as usize) < self.size.width
+            && (position.y as usize) < self.size.heigth
+    }
+
+    /// Value at a position, 0 when out of bound
+    pub fn get(&self, position: Position) -> f32 {
+        if self.inbound(position) {
+            self.values[self.index(position)]
+        } else {
+            0.
+        }
+    }
+
+    pub fn set(&mut self, position: Position, value: f32) {
+        if self.inbound(position) {
+            let index = self.index(position);
+            self.values[index] = value;
+        }
+    }
+
+    /// Add `amount` to a cell and returns its new value
+    pub fn deposit(&mut self, position: Position, amount: f32) -> f32 {
+        if !self.inbound(position) {
+            return 0.;
+        }
+
+        let index = self.index(position);
+        self.values[index] += amount;
+        self.values[index]
+    }
+
+    /// Direction in which the field increases the most around a position (central differences)
+    pub fn gradient(&self, position: Position) -> Vec2 {
+        let value = |dx: i32, dy: i32| {
+            let neighbour = position + Position { x: dx, y: dy };
+            if self.inbound(neighbour) {
+                self.get(neighbour)
+            } else {
+                self.get(position)
+            }
+        };
+
+        vec2(
+            (value(1, 0) - value(-1, 0)) / 2.,
+            (value(0, 1) - value(0, -1)) / 2.,
+        )
+    }
+
+    /// Neighbouring cell (8 directions) with the highest value, if it is higher than the current one
+    pub fn strongest_neighbour(&self, position: Position) -> Option<Position> {
+        NEIGHBOURS_8
+            .iter()
+            .map(|offset| position + *offset)
+            .filter(|neighbour| self.inbound(*neighbour))
+            .max_by(|a, b| self.get(*a).total_cmp(&self.get(*b)))
+            .filter(|best| self.get(*best) > self.get(position))
+    }
+
+    pub fn total(&self) -> f32 {
+        self.values.iter().sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.iter_mut().for_each(|value| *value = 0.);
+    }
+
+    /// Diffuse then evaporate the field, once per tick
+    pub fn update(&mut self) {
+        if self.diffusion > 0. {
+            let mut next = self.values.clone();
+            for index in 0..self.values.len() {
+                let value = self.values[index];
+                if value == 0. {
+                    continue;
+                }
+
+                // Like NetLogo: the share of the missing neighbours (edges) stays on the cell
+                let share = value * self.diffusion / NEIGHBOURS_8.len() as f32;
+                let position = self.position(index);
+                for offset in NEIGHBOURS_8 {
+                    let neighbour = position + offset;
+                    if self.inbound(neighbour) {
+                        next[self.index(neighbour)] += share;
+                        next[index] -= share;
+                    }
+                }
+            }
+            self.values = next;
+        }
+
+        if self.evaporation > 0. {
+            let kept = 1. - self.evaporation;
+            self.values.iter_mut().for_each(|value| *value *= kept);
+        }
+    }
+
+    /// Cells with a value different than 0
+    pub fn non_zero(&self) -> impl Iterator<Item = (Position, f32)> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| **value != 0.)
+            .map(|(index, value)| (self.position(index), *value))
+    }
+
+    fn index(&self, position: Position) -> usize {
+        position.y as usize * self.size.width + position.x as usize
+    }
+
+    fn position(&self, index: usize) -> Position {
+        Position {
+            x: (index % self.size.width) as i32,
+            y: (index / self.size.width) as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: GridSize = GridSize {
+        width: 5,
+        heigth: 5,
+    };
+
+    #[test]
+    fn diffusion_conserves_the_total() {
+        let mut field = Field::new(SIZE, 0.5, 0.);
+        field.deposit(Position { x: 2, y: 2 }, 8.);
+        field.deposit(Position { x: 0, y: 0 }, 8.);
+        field.update();
+
+        assert!((field.total() - 16.).abs() < 1e-4);
+        assert_eq!(field.get(Position { x: 2, y: 2 }), 4.);
+        assert_eq!(field.get(Position { x: 1, y: 1 }), 0.5 + 0.5);
+        // Only 3 neighbours in the corner, 5/8 of the value stays
+        assert_eq!(field.get(Position { x: 0, y: 0 }), 8. - 1.5);
+    }
+
+    #[test]
+    fn evaporation_and_gradient() {
+        let mut field = Field::new(SIZE, 0., 0.5);
+        field.set(Position { x: 3, y: 2 }, 4.);
+        field.update();
+        assert_eq!(field.get(Position { x: 3, y: 2 }), 2.);
+
+        let gradient = field.gradient(Position { x: 2, y: 2 });
+        assert!(gradient.x > 0. && gradient.y == 0.);
+        assert_eq!(
+            field.strongest_neighbour(Position { x: 2, y: 2 }),
+            Some(Position { x: 3, y: 2 })
+        );
+        assert_eq!(field.strongest_neighbour(Position { x: 3, y: 2 }), None);
+    }
+}
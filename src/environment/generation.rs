@@ -0,0 +1,665 @@
+//! Seeded procedural map generators.
+//!
+//! Every generator returns a `CellMap` telling which cells are filled. What "filled" means
+//! depends on the generator: walls for mazes, caves and rooms, minerals for the veins, etc.
+//! The same seed always gives the same map.
+
+use std::collections::{HashSet, VecDeque};
+
+use macroquad::color::Color;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{interface::grid::GridSize, scheduler::scheduler::Position};
+
+use super::environment::Env;
+
+const NEIGHBOURS_4: [Position; 4] = [
+    Position { x: 0, y: -1 },
+    Position { x: 0, y: 1 },
+    Position { x: -1, y: 0 },
+    Position { x: 1, y: 0 },
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CellMap {
+    pub size: GridSize,
+    cells: Vec<bool>,
+}
+
+impl CellMap {
+    pub fn new(size: GridSize, filled: bool) -> Self {
+        CellMap {
+            size,
+            cells: vec![filled; size.width * size.heigth],
+        }
+    }
+
+    pub fn inbound(&self, position: Position) -> bool {
+        position.x >= 0
+            && position.y >= 0
+            && (position.x as usize) < self.size.width
+            && (position.y as usize) < self.size.heigth
+    }
+
+    /// Out of bound cells are considered filled
+    pub fn is_filled(&self, position: Position) -> bool {
+        !self.inbound(position) || self.cells[self.index(position)]
+    }
+
+    pub fn set(&mut self, position: Position, filled: bool) {
+        if self.inbound(position) {
+            let index = self.index(position);
+            self.cells[index] = filled;
+        }
+    }
+
+    pub fn count_filled(&self) -> usize {
+        self.cells.iter().filter(|filled| **filled).count()
+    }
+
+    pub fn positions(&self, filled: bool) -> Vec<Position> {
+        (0..self.cells.len())
+            .filter(|i| self.cells[*i] == filled)
+            .map(|i| self.position(i))
+            .collect()
+    }
+
+    pub fn filled(&self) -> Vec<Position> {
+        self.positions(true)
+    }
+
+    /// Groups of open cells connected by their sides, the biggest first
+    pub fn open_regions(&self) -> Vec<Vec<Position>> {
+        let mut visited = vec![false; self.cells.len()];
+        let mut regions = Vec::new();
+
+        for start in 0..self.cells.len() {
+            if self.cells[start] || visited[start] {
+                continue;
+            }
+
+            visited[start] = true;
+            let mut region = Vec::new();
+            let mut queue = VecDeque::from([self.position(start)]);
+
+            while let Some(current) = queue.pop_front() {
+                region.push(current);
+                for offset in NEIGHBOURS_4 {
+                    let next = current + offset;
+                    if !self.is_filled(next) && !visited[self.index(next)] {
+                        visited[self.index(next)] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+
+            regions.push(region);
+        }
+
+        regions.sort_by_key(|region| std::cmp::Reverse(region.len()));
+        regions
+    }
+
+    /// Whether every open cell can be reached from every other one
+    pub fn is_connected(&self) -> bool {
+        self.open_regions().len() <= 1
+    }
+
+    /// Fill every open cell that is not part of the biggest open region
+    pub fn keep_largest_region(&mut self) {
+        for region in self.open_regions().iter().skip(1) {
+            for position in region {
+                self.set(*position, true);
+            }
+        }
+    }
+
+    /// Add the filled cells to the persistent elements of the environment with the given color
+    pub fn apply_to_env(&self, env: &mut Env, color: Color) {
+        for position in self.filled() {
+            env.update_persistent_element(position, color);
+        }
+    }
+
+    /// Add the filled cells to the obstacles of the environment with the given color
+    pub fn apply_as_obstacles(&self, env: &mut Env, color: Color) {
+        for position in self.filled() {
+            env.add_obstacle(position, color);
+        }
+    }
+
+    fn index(&self, position: Position) -> usize {
+        position.y as usize * self.size.width + position.x as usize
+    }
+
+    fn position(&self, index: usize) -> Position {
+        Position {
+            x: (index % self.size.width) as i32,
+            y: (index / self.size.width) as i32,
+        }
+    }
+}
+
+/*************** NOISE ***************/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// Random values on a lattice, smoothly interpolated
+    Value,
+    /// Random gradients on a lattice (Perlin)
+    Perlin,
+}
+
+/// Fractal noise in `[0, 1]` for every cell.
+///
+/// **scale:** size in cells of the first octave features
+///
+/// **octaves:** number of layers of details, each one twice as fine and half as strong
+pub fn noise_field(
+    size: GridSize,
+    kind: NoiseKind,
+    scale: f32,
+    octaves: u32,
+    seed: u64,
+) -> Vec<f32> {
+    let mut field = Vec::with_capacity(size.width * size.heigth);
+
+    for y in 0..size.heigth {
+        for x in 0..size.width {
+            let mut value = 0.;
+            let mut amplitude = 1.;
+            let mut total_amplitude = 0.;
+            let mut frequency = 1. / scale.max(f32::EPSILON);
+
+            for octave in 0..octaves.max(1) {
+                let (px, py) = (x as f32 * frequency, y as f32 * frequency);
+                let octave_seed = seed.wrapping_add(octave as u64);
+                let sample = match kind {
+                    NoiseKind::Value => value_noise(px, py, octave_seed),
+                    NoiseKind::Perlin => perlin_noise(px, py, octave_seed),
+                };
+
+                value += sample * amplitude;
+                total_amplitude += amplitude;
+                amplitude *= 0.5;
+                frequency *= 2.;
+            }
+
+            field.push((value / total_amplitude).clamp(0., 1.));
+        }
+    }
+
+    field
+}
+
+/// Cells whose noise is above `threshold` are filled
+pub fn noise_map(
+    size: GridSize,
+    kind: NoiseKind,
+    scale: f32,
+    octaves: u32,
+    threshold: f32,
+    seed: u64,
+) -> CellMap {
+    let field = noise_field(size, kind, scale, octaves, seed);
+
+    CellMap {
+        size,
+        cells: field.into_iter().map(|value| value > threshold).collect(),
+    }
+}
+
+/// Deterministic hash of a lattice point
+fn lattice_hash(x: i32, y: i32, seed: u64) -> u64 {
+    // splitmix64 finalizer
+    let mut z = seed
+        ^ (x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn lattice_value(x: i32, y: i32, seed: u64) -> f32 {
+    (lattice_hash(x, y, seed) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn value_noise(x: f32, y: f32, seed: u64) -> f32 {
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (tx, ty) = (fade(x - x0 as f32), fade(y - y0 as f32));
+
+    let top = lerp(
+        lattice_value(x0, y0, seed),
+        lattice_value(x0 + 1, y0, seed),
+        tx,
+    );
+    let bottom = lerp(
+        lattice_value(x0, y0 + 1, seed),
+        lattice_value(x0 + 1, y0 + 1, seed),
+        tx,
+    );
+
+    lerp(top, bottom, ty)
+}
+
+fn perlin_noise(x: f32, y: f32, seed: u64) -> f32 {
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (dx, dy) = (x - x0 as f32, y - y0 as f32);
+
+    let gradient = |ix: i32, iy: i32, ox: f32, oy: f32| {
+        let angle = lattice_value(ix, iy, seed) * std::f32::consts::TAU;
+        angle.cos() * ox + angle.sin() * oy
+    };
+
+    let (tx, ty) = (fade(dx), fade(dy));
+    let top = lerp(
+        gradient(x0, y0, dx, dy),
+        gradient(x0 + 1, y0, dx - 1., dy),
+        tx,
+    );
+    let bottom = lerp(
+        gradient(x0, y0 + 1, dx, dy - 1.),
+        gradient(x0 + 1, y0 + 1, dx - 1., dy - 1.),
+        tx,
+    );
+
+    // Perlin noise lies in [-sqrt(2)/2, sqrt(2)/2]
+    (lerp(top, bottom, ty) * std::f32::consts::FRAC_1_SQRT_2 + 0.5).clamp(0., 1.)
+}
+
+/*************** CAVES ***************/
+
+/// Cellular automata caves. Filled cells are walls.
+///
+/// Starts from random walls with `fill_probability`, then smooths the map `iterations` times
+/// (a cell becomes a wall if at least 5 of its 9 surrounding cells are walls).
+/// Only the biggest cave is kept so every open cell is reachable.
+pub fn cellular_caves(
+    size: GridSize,
+    fill_probability: f64,
+    iterations: u32,
+    seed: u64,
+) -> CellMap {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut map = CellMap::new(size, false);
+
+    for position in map.positions(false) {
+        let border = position.x == 0
+            || position.y == 0
+            || position.x as usize == size.width - 1
+            || position.y as usize == size.heigth - 1;
+        map.set(
+            position,
+            border || rng.random_bool(fill_probability.clamp(0., 1.)),
+        );
+    }
+
+    for _ in 0..iterations {
+        let mut next = map.clone();
+        for y in 0..size.heigth as i32 {
+            for x in 0..size.width as i32 {
+                let mut walls = 0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        if map.is_filled(Position {
+                            x: x + dx,
+                            y: y + dy,
+                        }) {
+                            walls += 1;
+                        }
+                    }
+                }
+                next.set(Position { x, y }, walls >= 5);
+            }
+        }
+        map = next;
+    }
+
+    map.keep_largest_region();
+    map
+}
+
+/*************** MAZES ***************/
+
+/// Maze cells are at odd coordinates, the cells in between are the walls that get carved
+fn maze_cells(size: GridSize) -> Vec<Position> {
+    let mut cells = Vec::new();
+    for y in (1..size.heigth.saturating_sub(1)).step_by(2) {
+        for x in (1..size.width.saturating_sub(1)).step_by(2) {
+            cells.push(Position {
+                x: x as i32,
+                y: y as i32,
+            });
+        }
+    }
+    cells
+}
+
+fn maze_neighbours(map: &CellMap, cell: Position) -> Vec<Position> {
+    NEIGHBOURS_4
+        .iter()
+        .map(|offset| cell + *offset * 2)
+        .filter(|next| {
+            map.inbound(*next)
+                && next.x as usize != map.size.width - 1
+                && next.y as usize != map.size.heigth - 1
+        })
+        .collect()
+}
+
+/// Perfect maze (exactly one path between two cells) carved with a recursive backtracker.
+/// Filled cells are walls. Odd sizes give a maze with walls all around.
+pub fn maze_backtracker(size: GridSize, seed: u64) -> CellMap {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut map = CellMap::new(size, true);
+
+    let Some(start) = maze_cells(size).first().copied() else {
+        return map;
+    };
+
+    map.set(start, false);
+    let mut stack = vec![start];
+
+    while let Some(current) = stack.last().copied() {
+        let mut unvisited: Vec<Position> = maze_neighbours(&map, current)
+            .into_iter()
+            .filter(|next| map.is_filled(*next))
+            .collect();
+
+        if unvisited.is_empty() {
+            stack.pop();
+            continue;
+        }
+
+        unvisited.shuffle(&mut rng);
+        let next = unvisited[0];
+        map.set((current + next) / 2, false);
+        map.set(next, false);
+        stack.push(next);
+    }
+
+    map
+}
+
+/// Perfect maze carved with randomized Prim's algorithm. Filled cells are walls.
+pub fn maze_prim(size: GridSize, seed: u64) -> CellMap {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut map = CellMap::new(size, true);
+
+    let Some(start) = maze_cells(size).first().copied() else {
+        return map;
+    };
+
+    map.set(start, false);
+    // (cell already in the maze, cell to add)
+    let mut frontier: Vec<(Position, Position)> = maze_neighbours(&map, start)
+        .into_iter()
+        .map(|next| (start, next))
+        .collect();
+
+    while !frontier.is_empty() {
+        let (from, to) = frontier.swap_remove(rng.random_range(0..frontier.len()));
+        if !map.is_filled(to) {
+            continue;
+        }
+
+        map.set((from + to) / 2, false);
+        map.set(to, false);
+
+        for next in maze_neighbours(&map, to) {
+            if map.is_filled(next) {
+                frontier.push((to, next));
+            }
+        }
+    }
+
+    map
+}
+
+/*************** ROOMS ***************/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Room {
+    pub position: Position,
+    pub width: i32,
+    pub heigth: i32,
+}
+
+impl Room {
+    pub fn center(&self) -> Position {
+        Position {
+            x: self.position.x + self.width / 2,
+            y: self.position.y + self.heigth / 2,
+        }
+    }
+
+    /// Whether the rooms overlap or touch
+    fn collides(&self, other: &Room) -> bool {
+        self.position.x <= other.position.x + other.width
+            && other.position.x <= self.position.x + self.width
+            && self.position.y <= other.position.y + other.heigth
+            && other.position.y <= self.position.y + self.heigth
+    }
+}
+
+/// Rectangular rooms linked by L shaped corridors. Filled cells are walls.
+///
+/// Up to `max_rooms` non overlapping rooms with sides between `min_side` and `max_side` are placed.
+/// Each room is linked to the previous one, so all the rooms are connected.
+pub fn rooms_and_corridors(
+    size: GridSize,
+    max_rooms: usize,
+    min_side: usize,
+    max_side: usize,
+    seed: u64,
+) -> (CellMap, Vec<Room>) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut map = CellMap::new(size, true);
+    let mut rooms: Vec<Room> = Vec::new();
+
+    let min_side = min_side.max(1);
+    let max_side = max_side.max(min_side);
+    if size.width < min_side + 2 || size.heigth < min_side + 2 {
+        return (map, rooms);
+    }
+
+    // Bounded number of attempts so crowded maps still terminate
+    for _ in 0..max_rooms * 10 {
+        if rooms.len() >= max_rooms {
+            break;
+        }
+
+        let width = rng.random_range(min_side..=max_side.min(size.width - 2)) as i32;
+        let heigth = rng.random_range(min_side..=max_side.min(size.heigth - 2)) as i32;
+        let room = Room {
+            position: Position {
+                x: rng.random_range(1..=size.width as i32 - 1 - width),
+                y: rng.random_range(1..=size.heigth as i32 - 1 - heigth),
+            },
+            width,
+            heigth,
+        };
+
+        if rooms.iter().any(|other| room.collides(other)) {
+            continue;
+        }
+
+        for y in room.position.y..room.position.y + heigth {
+            for x in room.position.x..room.position.x + width {
+                map.set(Position { x, y }, false);
+            }
+        }
+
+        if let Some(previous) = rooms.last() {
+            let (from, to) = (previous.center(), room.center());
+            let corner = if rng.random_bool(0.5) {
+                Position { x: to.x, y: from.y }
+            } else {
+                Position { x: from.x, y: to.y }
+            };
+            carve_line(&mut map, from, corner);
+            carve_line(&mut map, corner, to);
+        }
+
+        rooms.push(room);
+    }
+
+    (map, rooms)
+}
+
+/// Carve a straight horizontal or vertical line
+fn carve_line(map: &mut CellMap, from: Position, to: Position) {
+    let step = (to - from).signum();
+    let mut current = from;
+    map.set(current, false);
+    while current != to {
+        current += step;
+        map.set(current, false);
+    }
+}
+
+/*************** MINERAL VEINS ***************/
+
+/// Blobs growing from `num_blob` random seeds by random walk until `fill_ratio` of the map is filled.
+/// Filled cells are minerals.
+///
+/// A blob cell with no free neighbour cannot grow anymore, so the generation stops early
+/// if the whole map cannot reach the requested ratio.
+pub fn mineral_blobs(size: GridSize, fill_ratio: f32, num_blob: usize, seed: u64) -> CellMap {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut map = CellMap::new(size, false);
+
+    let total = size.width * size.heigth;
+    let target = ((total as f32 * fill_ratio.clamp(0., 1.)) as usize).min(total);
+    if target == 0 {
+        return map;
+    }
+
+    let mut blobs: Vec<Position> = Vec::new();
+    for _ in 0..num_blob.max(1) {
+        let position = Position {
+            x: rng.random_range(0..size.width as i32),
+            y: rng.random_range(0..size.heigth as i32),
+        };
+        if !map.is_filled(position) && blobs.len() < target {
+            map.set(position, true);
+            blobs.push(position);
+        }
+    }
+
+    // Blob cells that still have free neighbours
+    let mut growing: HashSet<Position> = blobs.iter().copied().collect();
+
+    while blobs.len() < target && !growing.is_empty() {
+        let mut grew = false;
+
+        for i in 0..blobs.len() {
+            if blobs.len() >= target {
+                break;
+            }
+
+            let current = blobs[i];
+            if !growing.contains(&current) {
+                continue;
+            }
+
+            let free: Vec<Position> = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| Position { x: dx, y: dy }))
+                .map(|offset| current + offset)
+                .filter(|next| !map.is_filled(*next))
+                .collect();
+
+            if free.is_empty() {
+                growing.remove(&current);
+                continue;
+            }
+
+            let next = free[rng.random_range(0..free.len())];
+            map.set(next, true);
+            blobs.push(next);
+            growing.insert(next);
+            grew = true;
+        }
+
+        if !grew {
+            break;
+        }
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: GridSize = GridSize {
+        width: 41,
+        heigth: 31,
+    };
+
+    #[test]
+    fn mazes_are_connected() {
+        for seed in 0..5 {
+            let maze = maze_backtracker(SIZE, seed);
+            assert!(maze.is_connected());
+            // Every maze cell is open
+            assert!(maze_cells(SIZE).iter().all(|cell| !maze.is_filled(*cell)));
+
+            let maze = maze_prim(SIZE, seed);
+            assert!(maze.is_connected());
+            assert!(maze_cells(SIZE).iter().all(|cell| !maze.is_filled(*cell)));
+        }
+
+        // A perfect maze is a tree: open cells = 2 * cells - 1
+        let maze = maze_backtracker(SIZE, 3);
+        assert_eq!(maze.positions(false).len(), 2 * maze_cells(SIZE).len() - 1);
+    }
+
+    #[test]
+    fn caves_and_rooms_are_connected() {
+        for seed in 0..5 {
+            let caves = cellular_caves(SIZE, 0.45, 4, seed);
+            assert!(caves.is_connected());
+            assert!(caves.is_filled(Position { x: 0, y: 0 }));
+
+            let (rooms_map, rooms) = rooms_and_corridors(SIZE, 8, 3, 7, seed);
+            assert!(!rooms.is_empty());
+            assert!(rooms_map.is_connected());
+        }
+    }
+
+    #[test]
+    fn seeded_generation() {
+        assert_eq!(maze_prim(SIZE, 1), maze_prim(SIZE, 1));
+        assert_eq!(
+            noise_map(SIZE, NoiseKind::Perlin, 8., 3, 0.5, 1),
+            noise_map(SIZE, NoiseKind::Perlin, 8., 3, 0.5, 1)
+        );
+        assert_ne!(
+            noise_map(SIZE, NoiseKind::Value, 8., 3, 0.5, 1),
+            noise_map(SIZE, NoiseKind::Value, 8., 3, 0.5, 2)
+        );
+        assert!(noise_field(SIZE, NoiseKind::Perlin, 8., 4, 1)
+            .iter()
+            .all(|value| (0.0..=1.).contains(value)));
+    }
+
+    #[test]
+    fn mineral_blobs_fill_ratio() {
+        let blobs = mineral_blobs(SIZE, 0.1, 10, 4);
+        assert_eq!(blobs.count_filled(), (41. * 31. * 0.1) as usize);
+
+        // Impossible ratios do not loop forever
+        let full = mineral_blobs(SIZE, 1., 1, 4);
+        assert_eq!(full.count_filled(), 41 * 31);
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt, fs, io,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{agent::agent::Action, interface::grid::GridSize, scheduler::scheduler::Position};
+
+pub type NodeId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edge {
+    pub to: NodeId,
+    pub weight: f32,
+}
+
+#[derive(Debug)]
+pub enum GraphError {
+    Io(io::Error),
+    /// **line** starts at 1
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// A graph without nodes cannot hold agents
+    Empty,
+    /// The grid of the environment cannot lay out every node
+    TooLarge {
+        width: usize,
+        heigth: usize,
+    },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Io(error) => write!(f, "could not read the edge list: {}", error),
+            GraphError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            GraphError::Empty => write!(f, "the graph has no nodes"),
+            GraphError::TooLarge { width, heigth } => write!(
+                f,
+                "a grid of at least {}x{} is needed to lay out the graph",
+                width, heigth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+impl From<io::Error> for GraphError {
+    fn from(error: io::Error) -> Self {
+        GraphError::Io(error)
+    }
+}
+
+/// A network on which agents live on the nodes (contact networks, road graphs, etc.)
+///
+/// Agents are still given a `Position` by the scheduler. Each node is laid out on a square
+/// grid (see `position_of` and `node_at`) so the graph can be displayed and scheduled like any other environment.
+pub struct Graph {
+    /// Outgoing edges of each node. The index of an edge in this list is the `k` of "move along edge k".
+    adjacency: Vec<Vec<Edge>>,
+    directed: bool,
+}
+
+impl Graph {
+    pub fn new(node_count: usize, directed: bool) -> Self {
+        Graph {
+            adjacency: vec![Vec::new(); node_count],
+            directed,
+        }
+    }
+
+    pub fn is_directed(&self) -> bool {
+        self.directed
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.adjacency.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        let count: usize = self.adjacency.iter().map(Vec::len).sum();
+        if self.directed {
+            return count;
+        }
+
+        // Undirected edges are stored in both directions, except self-loops
+        let self_loops = self
+            .adjacency
+            .iter()
+            .enumerate()
+            .map(|(node, edges)| edges.iter().filter(|edge| edge.to == node).count())
+            .sum::<usize>();
+        (count - self_loops) / 2 + self_loops
+    }
+
+    /// Add an edge. For undirected graphs the edge is added in both directions.
+    pub fn add_edge(&mut self, from: NodeId, to: NodeId, weight: f32) {
+        let needed = from.max(to) + 1;
+        if needed > self.adjacency.len() {
+            self.adjacency.resize(needed, Vec::new());
+        }
+
+        self.adjacency[from].push(Edge { to, weight });
+        if !self.directed && from != to {
+            self.adjacency[to].push(Edge { to: from, weight });
+        }
+    }
+
+    pub fn has_edge(&self, from: NodeId, to: NodeId) -> bool {
+        self.edges(from).iter().any(|edge| edge.to == to)
+    }
+
+    pub fn weight(&self, from: NodeId, to: NodeId) -> Option<f32> {
+        self.edges(from)
+            .iter()
+            .find(|edge| edge.to == to)
+            .map(|edge| edge.weight)
+    }
+
+    pub fn edges(&self, node: NodeId) -> &[Edge] {
+        self.adjacency.get(node).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn degree(&self, node: NodeId) -> usize {
+        self.edges(node).len()
+    }
+
+    pub fn max_degree(&self) -> usize {
+        self.adjacency.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    pub fn neighbours(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.edges(node).iter().map(|edge| edge.to)
+    }
+
+    /// Nodes reachable in at most `hops` edges, excluding `node` itself
+    pub fn neighbourhood(&self, node: NodeId, hops: usize) -> Vec<NodeId> {
+        let mut visited = HashSet::from([node]);
+        let mut queue = VecDeque::from([(node, 0)]);
+        let mut found = Vec::new();
+
+        while let Some((current, depth)) = queue.pop_front() {
+            if depth == hops {
+                continue;
+            }
+            for next in self.neighbours(current) {
+                if visited.insert(next) {
+                    found.push(next);
+                    queue.push_back((next, depth + 1));
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Move along the `k`-th outgoing edge of `node`
+    pub fn follow_edge(&self, node: NodeId, k: Action) -> Option<&Edge> {
+        self.edges(node).get(k as usize)
+    }
+
+    /// Actions "move along edge k" for every k up to the maximum degree.
+    /// An action greater than the degree of the current node simply does not lead anywhere.
+    pub fn edge_actions(&self) -> Vec<Action> {
+        (0..self.max_degree() as Action).collect()
+    }
+
+    /// Size of the square grid on which the nodes are laid out
+    pub fn layout_size(&self) -> GridSize {
+        let side = (self.node_count() as f32).sqrt().ceil().max(1.) as usize;
+        let heigth = self.node_count().div_ceil(side).max(1);
+
+        GridSize {
+            width: side,
+            heigth,
+        }
+    }
+
+    pub fn position_of(&self, node: NodeId) -> Position {
+        let width = self.layout_size().width;
+        Position {
+            x: (node % width) as i32,
+            y: (node / width) as i32,
+        }
+    }
+
+    /// Node laid out on a grid position, if any
+    pub fn node_at(&self, position: Position) -> Option<NodeId> {
+        let width = self.layout_size().width as i32;
+        if position.x < 0 || position.y < 0 || position.x >= width {
+            return None;
+        }
+
+        let node = (position.y * width + position.x) as NodeId;
+        (node < self.node_count()).then_some(node)
+    }
+
+    /// Parse an edge list. Each line is `from to [weight]` (weight defaults to 1).
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn from_edge_list(text: &str, directed: bool) -> Result<Graph, GraphError> {
+        let mut graph = Graph::new(0, directed);
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parse_error = |message: String| GraphError::Parse {
+                line: i + 1,
+                message,
+            };
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 || fields.len() > 3 {
+                return Err(parse_error(format!(
+                    "expected `from to [weight]`, got `{}`",
+                    line
+                )));
+            }
+
+            let node = |field: &str| {
+                field
+                    .parse::<NodeId>()
+                    .map_err(|_| parse_error(format!("invalid node id `{}`", field)))
+            };
+            let from = node(fields[0])?;
+            let to = node(fields[1])?;
+            let weight = match fields.get(2) {
+                Some(field) => field
+                    .parse::<f32>()
+                    .map_err(|_| parse_error(format!("invalid weight `{}`", field)))?,
+                None => 1.,
+            };
+
+            graph.add_edge(from, to, weight);
+        }
+
+        Ok(graph)
+    }
+
+    pub fn load_edge_list(filepath: &str, directed: bool) -> Result<Graph, GraphError> {
+        Graph::from_edge_list(&fs::read_to_string(filepath)?, directed)
+    }
+
+    /// Erdős–Rényi G(n, p): every pair of nodes is connected with probability `p`
+    pub fn erdos_renyi(n: usize, p: f64, seed: u64) -> Graph {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut graph = Graph::new(n, false);
+
+        for a in 0..n {
+            for b in a + 1..n {
+                if rng.random_bool(p) {
+                    graph.add_edge(a, b, 1.);
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Watts–Strogatz small world: a ring where each node is connected to its `k` nearest
+    /// neighbours (`k` even), then each edge is rewired with probability `beta`
+    pub fn watts_strogatz(n: usize, k: usize, beta: f64, seed: u64) -> Graph {
+        assert!(
+            k.is_multiple_of(2) && k < n,
+            "k must be even and smaller than n"
+        );
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut edges: HashSet<(NodeId, NodeId)> = HashSet::new();
+        let ordered = |a: NodeId, b: NodeId| (a.min(b), a.max(b));
+
+        for a in 0..n {
+            for offset in 1..=k / 2 {
+                edges.insert(ordered(a, (a + offset) % n));
+            }
+        }
+
+        // Rewire in a stable order so the result only depends on the seed
+        for a in 0..n {
+            for offset in 1..=k / 2 {
+                let b = (a + offset) % n;
+                if !rng.random_bool(beta) || !edges.contains(&ordered(a, b)) {
+                    continue;
+                }
+
+                // A node connected to everyone cannot be rewired
+                let degree = edges.iter().filter(|(x, y)| *x == a || *y == a).count();
+                if degree >= n - 1 {
+                    continue;
+                }
+
+                let mut target = rng.random_range(0..n);
+                while target == a || edges.contains(&ordered(a, target)) {
+                    target = rng.random_range(0..n);
+                }
+
+                edges.remove(&ordered(a, b));
+                edges.insert(ordered(a, target));
+            }
+        }
+
+        let mut edges: Vec<_> = edges.into_iter().collect();
+        edges.sort();
+
+        let mut graph = Graph::new(n, false);
+        for (a, b) in edges {
+            graph.add_edge(a, b, 1.);
+        }
+
+        graph
+    }
+
+    /// Barabási–Albert preferential attachment: starts from a clique of `m + 1` nodes,
+    /// then each new node connects to `m` existing nodes chosen proportionally to their degree
+    pub fn barabasi_albert(n: usize, m: usize, seed: u64) -> Graph {
+        assert!(m >= 1 && m < n, "m must be at least 1 and smaller than n");
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut graph = Graph::new(n, false);
+        // Each node appears once per edge it has, so sampling from it follows the degree distribution
+        let mut endpoints: Vec<NodeId> = Vec::new();
+
+        for a in 0..=m {
+            for b in a + 1..=m {
+                graph.add_edge(a, b, 1.);
+                endpoints.push(a);
+                endpoints.push(b);
+            }
+        }
+
+        for new_node in m + 1..n {
+            let mut targets: Vec<NodeId> = Vec::with_capacity(m);
+            while targets.len() < m {
+                let target = endpoints[rng.random_range(0..endpoints.len())];
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+
+            for target in targets {
+                graph.add_edge(new_node, target, 1.);
+                endpoints.push(new_node);
+                endpoints.push(target);
+            }
+        }
+
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use macroquad::{color::RED, math::vec2};
+
+    use super::*;
+    use crate::{
+        agent::{agent::StepFunction, learning_agent::LearningAgent},
+        environment::environment::Env,
+        scheduler::scheduler::Scheduler,
+    };
+
+    #[test]
+    fn edge_list_loading() {
+        let graph =
+            Graph::from_edge_list("# a small road network\n0 1 2.5\n1 2\n\n2 0 0.5\n", false)
+                .unwrap();
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 3);
+        assert_eq!(graph.weight(1, 0), Some(2.5));
+        assert_eq!(graph.weight(1, 2), Some(1.));
+        assert_eq!(graph.follow_edge(0, 1).map(|edge| edge.to), Some(2));
+        assert!(graph.follow_edge(0, 2).is_none());
+
+        // A self-loop is one edge, even stored once
+        let looped = Graph::from_edge_list("0 0\n0 1", false).unwrap();
+        assert_eq!(looped.edge_count(), 2);
+
+        let directed = Graph::from_edge_list("0 1\n1 2", true).unwrap();
+        assert!(directed.has_edge(0, 1));
+        assert!(!directed.has_edge(1, 0));
+
+        match Graph::from_edge_list("0 1\n1 two", false) {
+            Err(GraphError::Parse { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("The second line should not be accepted"),
+        }
+    }
+
+    #[test]
+    fn neighbourhood_follows_adjacency() {
+        let graph = Graph::from_edge_list("0 1\n1 2\n2 3\n3 4", false).unwrap();
+
+        let mut close = graph.neighbourhood(2, 1);
+        close.sort();
+        assert_eq!(close, vec![1, 3]);
+
+        let mut far = graph.neighbourhood(0, 3);
+        far.sort();
+        assert_eq!(far, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn layout_round_trip() {
+        let graph = Graph::new(10, false);
+        assert_eq!(
+            graph.layout_size(),
+            GridSize {
+                width: 4,
+                heigth: 3
+            }
+        );
+
+        for node in 0..graph.node_count() {
+            assert_eq!(graph.node_at(graph.position_of(node)), Some(node));
+        }
+        assert_eq!(graph.node_at(Position { x: 3, y: 2 }), None);
+    }
+
+    #[test]
+    fn generators() {
+        let er = Graph::erdos_renyi(30, 1., 7);
+        assert_eq!(er.edge_count(), 30 * 29 / 2);
+        assert_eq!(Graph::erdos_renyi(30, 0., 7).edge_count(), 0);
+
+        let ws = Graph::watts_strogatz(20, 4, 0.3, 7);
+        assert_eq!(ws.edge_count(), 20 * 4 / 2);
+        assert!((0..20).all(|node| !ws.has_edge(node, node)));
+
+        let ba = Graph::barabasi_albert(50, 2, 7);
+        // clique of 3 nodes + 2 edges per new node
+        assert_eq!(ba.edge_count(), 3 + 47 * 2);
+
+        // Same seed, same graph
+        let again = Graph::barabasi_albert(50, 2, 7);
+        assert!((0..50).all(|node| ba.edges(node) == again.edges(node)));
+    }
+
+    fn walker_step() -> StepFunction<LearningAgent> {
+        Rc::new(|_, env, position, state, action| {
+            (env.follow_edge(position, *action), state.clone(), 0., false)
+        })
+    }
+
+    #[test]
+    fn agents_step_along_edges() {
+        let mut env = Env::new(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            GridSize {
+                width: 3,
+                heigth: 3,
+            },
+            HashMap::new(),
+            &[],
+            HashMap::new(),
+        );
+        assert!(matches!(
+            env.set_graph(Graph::new(0, false)),
+            Err(GraphError::Empty)
+        ));
+        assert!(matches!(
+            env.set_graph(Graph::new(10, false)),
+            Err(GraphError::TooLarge { .. })
+        ));
+
+        // A star: the center has 4 edges, the leaves 1
+        let star = Graph::from_edge_list("0 1\n0 2\n0 3\n0 4", false).unwrap();
+        env.set_graph(star).unwrap();
+        assert_eq!(env.actions, vec![0, 1, 2, 3]);
+
+        let mut scheduler = Scheduler::new(env);
+        scheduler
+            .add_agents(
+                5,
+                None,
+                RED,
+                "walker",
+                vec![],
+                None,
+                None,
+                None,
+                &walker_step(),
+                None,
+            )
+            .unwrap();
+
+        for _ in 0..20 {
+            let before: Vec<NodeId> = scheduler
+                .agents
+                .iter()
+                .map(|(position, _, _)| scheduler.env.node_of(*position).unwrap())
+                .collect();
+            scheduler.take_step();
+
+            for ((position, _, _), from) in scheduler.agents.iter().zip(before) {
+                let to = scheduler.env.node_of(*position).unwrap();
+                let graph = scheduler.env.graph().unwrap();
+                assert!(to == from || graph.has_edge(from, to));
+            }
+        }
+    }
+}
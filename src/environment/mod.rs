@@ -1 +1,14 @@
+pub mod ascii_map;
+pub mod continuous;
+#[allow(clippy::module_inception)]
 pub mod environment;
+pub mod field;
+pub mod generation;
+pub mod graph;
+pub mod movement;
+pub mod observation;
+pub mod pathfinding;
+pub mod registry;
+pub mod resource;
+pub mod sight;
+pub mod topology;
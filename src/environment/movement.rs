@@ -0,0 +1,272 @@
+use masim::define_const;
+
+use crate::{agent::agent::Action, scheduler::scheduler::Position};
+
+use super::{environment::Env, pathfinding::Connectivity};
+
+// Movements on a square grid. Their values follow the order of `Connectivity::offsets`.
+define_const!(SQUARE_MOVES => UP, DOWN, LEFT, RIGHT, UP_LEFT, UP_RIGHT, DOWN_LEFT, DOWN_RIGHT, STAY);
+// Movements on a hexagonal grid ("odd-r" layout: odd rows are shifted half a cell to the right)
+define_const!(HEX_MOVES => EAST, WEST, NORTH_EAST, NORTH_WEST, SOUTH_EAST, SOUTH_WEST, HEX_STAY);
+
+pub static MOVES_4: &[Action] = &[UP, DOWN, LEFT, RIGHT];
+pub static MOVES_4_STAY: &[Action] = &[UP, DOWN, LEFT, RIGHT, STAY];
+pub static MOVES_8: &[Action] = &[
+    UP, DOWN, LEFT, RIGHT, UP_LEFT, UP_RIGHT, DOWN_LEFT, DOWN_RIGHT,
+];
+pub static MOVES_8_STAY: &[Action] = SQUARE_MOVES;
+pub static MOVES_HEX: &[Action] = &[EAST, WEST, NORTH_EAST, NORTH_WEST, SOUTH_EAST, SOUTH_WEST];
+pub static MOVES_HEX_STAY: &[Action] = HEX_MOVES;
+
+/// Actions an agent can take to move and where they lead
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovementSet {
+    /// `MOVES_4`
+    Four,
+    /// `MOVES_4_STAY`
+    FourStay,
+    /// `MOVES_8`
+    Eight,
+    /// `MOVES_8_STAY`
+    EightStay,
+    /// `MOVES_HEX`
+    Hex,
+    /// `MOVES_HEX_STAY`
+    HexStay,
+    /// Any actions with their offset, for example knight moves
+    Custom(Vec<(Action, Position)>),
+}
+
+impl MovementSet {
+    pub fn actions(&self) -> Vec<Action> {
+        match self {
+            MovementSet::Four => MOVES_4.to_vec(),
+            MovementSet::FourStay => MOVES_4_STAY.to_vec(),
+            MovementSet::Eight => MOVES_8.to_vec(),
+            MovementSet::EightStay => MOVES_8_STAY.to_vec(),
+            MovementSet::Hex => MOVES_HEX.to_vec(),
+            MovementSet::HexStay => MOVES_HEX_STAY.to_vec(),
+            MovementSet::Custom(moves) => moves.iter().map(|(action, _)| *action).collect(),
+        }
+    }
+
+    /// Offset of an action from `from`, `None` if the action is not part of the set.
+    /// Only the hexagonal offsets depend on `from` (on the parity of its row).
+    pub fn offset(&self, action: Action, from: Position) -> Option<Position> {
+        let square = |actions: &[Action]| {
+            actions.contains(&action).then(|| match action {
+                STAY => Position::ZERO,
+                _ => Connectivity::Eight.offsets()[action as usize],
+            })
+        };
+
+        match self {
+            MovementSet::Four => square(MOVES_4),
+            MovementSet::FourStay => square(MOVES_4_STAY),
+            MovementSet::Eight => square(MOVES_8),
+            MovementSet::EightStay => square(MOVES_8_STAY),
+            MovementSet::Hex | MovementSet::HexStay => {
+                if *self == MovementSet::Hex && action == HEX_STAY {
+                    return None;
+                }
+                // Odd rows are shifted to the right so their diagonal neighbours are too
+                let shift = from.y.rem_euclid(2);
+                let (x, y) = match action {
+                    EAST => (1, 0),
+                    WEST => (-1, 0),
+                    NORTH_EAST => (shift, -1),
+                    NORTH_WEST => (shift - 1, -1),
+                    SOUTH_EAST => (shift, 1),
+                    SOUTH_WEST => (shift - 1, 1),
+                    HEX_STAY => (0, 0),
+                    _ => return None,
+                };
+                Some(Position { x, y })
+            }
+            MovementSet::Custom(moves) => moves
+                .iter()
+                .find(|(a, _)| *a == action)
+                .map(|(_, offset)| *offset),
+        }
+    }
+
+    /// Name of an action, like `up` or `north_east`. `None` if the action is not part of
+    /// the set or the set is `Custom`.
+    pub fn action_name(&self, action: Action) -> Option<&'static str> {
+        if !self.actions().contains(&action) {
+            return None;
+        }
+
+        let names: &[&str] = match self {
+            MovementSet::Four
+            | MovementSet::FourStay
+            | MovementSet::Eight
+            | MovementSet::EightStay => &[
+                "up",
+                "down",
+                "left",
+                "right",
+                "up_left",
+                "up_right",
+                "down_left",
+                "down_right",
+                "stay",
+            ],
+            MovementSet::Hex | MovementSet::HexStay => &[
+                "east",
+                "west",
+                "north_east",
+                "north_west",
+                "south_east",
+                "south_west",
+                "stay",
+            ],
+            MovementSet::Custom(_) => return None,
+        };
+        names.get(action as usize).copied()
+    }
+}
+
+/// Result of `Env::apply_move`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveOutcome {
+    /// The agent is now on this cell
+    Moved(Position),
+    /// The action does not move (`STAY`) or is not part of the movement set
+    Stayed(Position),
+    /// The target cell is an obstacle, the agent did not move
+    Blocked(Position),
+    /// The target cell is past a bounded edge, the agent did not move
+    OutOfBounds(Position),
+}
+
+impl MoveOutcome {
+    /// Position of the agent after the move
+    pub fn position(&self) -> Position {
+        match *self {
+            MoveOutcome::Moved(position)
+            | MoveOutcome::Stayed(position)
+            | MoveOutcome::Blocked(position)
+            | MoveOutcome::OutOfBounds(position) => position,
+        }
+    }
+
+    /// Whether the agent tried to move but could not
+    pub fn is_blocked(&self) -> bool {
+        matches!(self, MoveOutcome::Blocked(_) | MoveOutcome::OutOfBounds(_))
+    }
+}
+
+impl Env {
+    /// Move from `position` with `action` honouring the obstacles and the topology
+    pub fn apply_move(
+        &self,
+        position: Position,
+        action: Action,
+        movements: &MovementSet,
+    ) -> MoveOutcome {
+        let offset = match movements.offset(action, position) {
+            Some(offset) if offset != Position::ZERO => offset,
+            _ => return MoveOutcome::Stayed(position),
+        };
+
+        match self
+            .topology
+            .wrap_cell(position + offset, self.get_grid_size())
+        {
+            None => MoveOutcome::OutOfBounds(position),
+            Some(target) if !self.passable(target) => MoveOutcome::Blocked(position),
+            Some(target) => MoveOutcome::Moved(target),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use macroquad::{color::DARKGRAY, math::vec2};
+
+    use super::*;
+    use crate::{environment::topology::Topology, interface::grid::GridSize};
+
+    fn env() -> Env {
+        let mut env = Env::new(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            GridSize {
+                width: 4,
+                heigth: 4,
+            },
+            HashMap::new(),
+            MOVES_8,
+            HashMap::new(),
+        );
+        env.add_obstacle(Position { x: 1, y: 0 }, DARKGRAY);
+        env
+    }
+
+    #[test]
+    fn square_moves() {
+        let mut env = env();
+        let corner = Position { x: 0, y: 0 };
+
+        assert_eq!(
+            env.apply_move(corner, DOWN_RIGHT, &MovementSet::Eight),
+            MoveOutcome::Moved(Position { x: 1, y: 1 })
+        );
+        assert_eq!(
+            env.apply_move(corner, RIGHT, &MovementSet::Four),
+            MoveOutcome::Blocked(corner)
+        );
+        assert_eq!(
+            env.apply_move(corner, UP, &MovementSet::Four),
+            MoveOutcome::OutOfBounds(corner)
+        );
+        // Not part of the set
+        assert_eq!(
+            env.apply_move(corner, DOWN_RIGHT, &MovementSet::Four),
+            MoveOutcome::Stayed(corner)
+        );
+        assert_eq!(
+            env.apply_move(corner, STAY, &MovementSet::FourStay),
+            MoveOutcome::Stayed(corner)
+        );
+
+        env.topology = Topology::Toroidal;
+        assert_eq!(
+            env.apply_move(corner, UP_LEFT, &MovementSet::Eight),
+            MoveOutcome::Moved(Position { x: 3, y: 3 })
+        );
+    }
+
+    #[test]
+    fn hex_and_custom_moves() {
+        let env = env();
+
+        // Even row
+        assert_eq!(
+            env.apply_move(Position { x: 2, y: 2 }, NORTH_WEST, &MovementSet::Hex),
+            MoveOutcome::Moved(Position { x: 1, y: 1 })
+        );
+        // Odd row
+        assert_eq!(
+            env.apply_move(Position { x: 2, y: 1 }, NORTH_WEST, &MovementSet::Hex),
+            MoveOutcome::Moved(Position { x: 2, y: 0 })
+        );
+        assert_eq!(
+            env.apply_move(Position { x: 2, y: 1 }, SOUTH_EAST, &MovementSet::Hex),
+            MoveOutcome::Moved(Position { x: 3, y: 2 })
+        );
+        assert_eq!(MovementSet::Hex.offset(HEX_STAY, Position::ZERO), None);
+        assert_eq!(MovementSet::Hex.action_name(NORTH_WEST), Some("north_west"));
+        assert_eq!(MovementSet::Four.action_name(STAY), None);
+
+        let knight = MovementSet::Custom(vec![(0, Position { x: 1, y: 2 })]);
+        assert_eq!(knight.actions(), vec![0]);
+        assert_eq!(
+            env.apply_move(Position { x: 0, y: 0 }, 0, &knight),
+            MoveOutcome::Moved(Position { x: 1, y: 2 })
+        );
+    }
+}
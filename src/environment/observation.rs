@@ -0,0 +1,340 @@
+use macroquad::{color::Color, math::Vec2};
+
+use crate::{
+    agent::state::{to_value, State},
+    scheduler::scheduler::Position,
+};
+
+use super::{environment::Env, sight::bresenham};
+
+/// Code of the cells outside of the grid
+pub const OUT_OF_BOUNDS: u32 = u32::MAX;
+/// Code of the cells hidden behind an obstacle
+pub const HIDDEN: u32 = u32::MAX - 1;
+
+/// What an observation shows of each cell. Every layer gives one code per cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Layer {
+    /// 1 on obstacles, 0 elsewhere
+    Obstacles,
+    /// `i + 1` when the persistent element of the cell has the `i`-th color of the list, 0 otherwise
+    Elements(Vec<Color>),
+    /// Value of a field split in `levels` steps between 0 and `max` (from 0 to `levels - 1`)
+    Field { id: u32, levels: u32, max: f32 },
+    /// How full a resource is, split in `levels` steps (from 0 to `levels - 1`)
+    Resource { id: u32, levels: u32 },
+}
+
+impl Layer {
+    fn code(&self, env: &Env, position: Position) -> u32 {
+        match self {
+            Layer::Obstacles => env.obstacles.contains(&position) as u32,
+            Layer::Elements(colors) => env
+                .persistent_elements
+                .get(&position)
+                .and_then(|color| colors.iter().position(|c| c == color))
+                .map_or(0, |i| i as u32 + 1),
+            Layer::Field { id, levels, max } => {
+                let value = env.field(*id).map_or(0., |field| field.get(position));
+                quantize(value / max, *levels)
+            }
+            Layer::Resource { id, levels } => {
+                let ratio = env.resource(*id).map_or(0., |resource| {
+                    let capacity = resource.capacity(position);
+                    if capacity > 0. {
+                        resource.get(position) / capacity
+                    } else {
+                        0.
+                    }
+                });
+                quantize(ratio, *levels)
+            }
+        }
+    }
+}
+
+fn quantize(ratio: f32, levels: u32) -> u32 {
+    let levels = levels.max(1);
+    ((ratio.clamp(0., 1.) * levels as f32) as u32).min(levels - 1)
+}
+
+/// Egocentric view of the `(2 * radius + 1)²` cells around an agent.
+///
+/// ## Example
+/// ```rust
+/// let observer = Observer::builder()
+///     .radius(2)
+///     .layer(Layer::Obstacles)
+///     .layer(Layer::Field { id: PHEROMONES, levels: 4, max: 10. })
+///     .line_of_sight(true)
+///     .build();
+///
+/// let state = observer.observe(env, position, None).to_state();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Observer {
+    radius: i32,
+    layers: Vec<Layer>,
+    line_of_sight: bool,
+}
+
+impl Observer {
+    pub fn builder() -> ObserverBuilder {
+        ObserverBuilder {
+            radius: None,
+            layers: Vec::new(),
+            line_of_sight: None,
+        }
+    }
+
+    /// Observe the surroundings of `position`.
+    ///
+    /// With a `heading`, the window is rotated so that the heading (snapped to the closest
+    /// of the 4 directions) is up, otherwise up is the top of the grid.
+    pub fn observe(&self, env: &Env, position: Position, heading: Option<Vec2>) -> Observation {
+        let forward = heading.map_or(Position { x: 0, y: -1 }, snap);
+        // Local (1, 0) goes to the right of the heading
+        let right = Position {
+            x: -forward.y,
+            y: forward.x,
+        };
+
+        let side = 2 * self.radius + 1;
+        let mut cells = Vec::with_capacity((side * side) as usize);
+        let mut codes = vec![Vec::with_capacity((side * side) as usize); self.layers.len()];
+
+        for local_y in -self.radius..=self.radius {
+            for local_x in -self.radius..=self.radius {
+                let offset = right * local_x - forward * local_y;
+                let cell = env
+                    .topology
+                    .wrap_cell(position + offset, env.get_grid_size());
+
+                let visible =
+                    cell.is_some() && (!self.line_of_sight || self.in_sight(env, position, offset));
+                for (layer, layer_codes) in self.layers.iter().zip(codes.iter_mut()) {
+                    layer_codes.push(match cell {
+                        None => OUT_OF_BOUNDS,
+                        Some(_) if !visible => HIDDEN,
+                        Some(cell) => layer.code(env, cell),
+                    });
+                }
+                cells.push(cell.filter(|_| visible));
+            }
+        }
+
+        Observation {
+            radius: self.radius,
+            cells,
+            codes,
+        }
+    }
+
+    /// Every cell between the agent and `position + offset` is passable
+    fn in_sight(&self, env: &Env, position: Position, offset: Position) -> bool {
+        let line = bresenham(position, position + offset);
+        line.len() <= 2
+            || line[1..line.len() - 1].iter().all(|cell| {
+                env.topology
+                    .wrap_cell(*cell, env.get_grid_size())
+                    .is_some_and(|cell| env.passable(cell))
+            })
+    }
+}
+
+/// Closest of the 4 directions
+fn snap(heading: Vec2) -> Position {
+    if heading.x.abs() >= heading.y.abs() {
+        Position {
+            x: if heading.x < 0. { -1 } else { 1 },
+            y: 0,
+        }
+    } else {
+        Position {
+            x: 0,
+            y: if heading.y < 0. { -1 } else { 1 },
+        }
+    }
+}
+
+pub struct ObserverBuilder {
+    radius: Option<i32>,
+    layers: Vec<Layer>,
+    line_of_sight: Option<bool>,
+}
+
+impl ObserverBuilder {
+    /// Number of cells seen in each direction. Default: 1
+    pub fn radius(mut self, radius: u32) -> Self {
+        self.radius = Some(radius as i32);
+        self
+    }
+
+    /// Add a layer to the observation. Layers are encoded in the order they are added.
+    pub fn layer(mut self, layer: Layer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Hide the cells behind obstacles. Default: false
+    pub fn line_of_sight(mut self, line_of_sight: bool) -> Self {
+        self.line_of_sight = Some(line_of_sight);
+        self
+    }
+
+    pub fn build(self) -> Observer {
+        Observer {
+            radius: self.radius.unwrap_or(1),
+            layers: self.layers,
+            line_of_sight: self.line_of_sight.unwrap_or(false),
+        }
+    }
+}
+
+/// Result of `Observer::observe`. Cells are stored row by row from the top left of the window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    radius: i32,
+    /// Position on the grid of each cell, `None` if out of bounds or hidden
+    cells: Vec<Option<Position>>,
+    /// Codes of each layer
+    codes: Vec<Vec<u32>>,
+}
+
+impl Observation {
+    /// Code of a layer at a position relative to the agent (up is `y < 0`)
+    pub fn get(&self, layer: usize, local: Position) -> Option<u32> {
+        let index = self.index(local)?;
+        self.codes.get(layer).map(|codes| codes[index])
+    }
+
+    /// Position on the grid of a visible cell relative to the agent
+    pub fn world_position(&self, local: Position) -> Option<Position> {
+        self.cells[self.index(local)?]
+    }
+
+    /// Codes of a layer for the whole window
+    pub fn layer(&self, layer: usize) -> &[u32] {
+        &self.codes[layer]
+    }
+
+    /// Position on the grid of the visible cells and their code in every layer
+    pub fn visible_cells(&self) -> impl Iterator<Item = (Position, Vec<u32>)> + '_ {
+        self.cells.iter().enumerate().filter_map(|(index, cell)| {
+            cell.map(|cell| (cell, self.codes.iter().map(|codes| codes[index]).collect()))
+        })
+    }
+
+    /// One `Vec<u32>` value per layer
+    pub fn to_state(&self) -> State {
+        self.codes
+            .iter()
+            .map(|codes| to_value(codes.clone()))
+            .collect()
+    }
+
+    fn index(&self, local: Position) -> Option<usize> {
+        let side = 2 * self.radius + 1;
+        (local.x.abs() <= self.radius && local.y.abs() <= self.radius)
+            .then(|| ((local.y + self.radius) * side + local.x + self.radius) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use macroquad::{
+        color::{BROWN, DARKGRAY},
+        math::vec2,
+    };
+
+    use super::*;
+    use crate::{agent::state::Value, interface::grid::GridSize};
+
+    fn env() -> Env {
+        let mut env = Env::new(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            GridSize {
+                width: 5,
+                heigth: 5,
+            },
+            HashMap::from([(Position { x: 2, y: 0 }, BROWN)]),
+            &[],
+            HashMap::new(),
+        );
+        env.add_obstacle(Position { x: 3, y: 2 }, DARKGRAY);
+        env
+    }
+
+    #[test]
+    fn egocentric_window() {
+        let env = env();
+        let observer = Observer::builder()
+            .radius(2)
+            .layer(Layer::Obstacles)
+            .layer(Layer::Elements(vec![BROWN]))
+            .build();
+
+        // Agent on the bottom row: the row under it is out of bounds
+        let observation = observer.observe(&env, Position { x: 2, y: 4 }, None);
+        assert_eq!(
+            observation.get(0, Position { x: 0, y: 1 }),
+            Some(OUT_OF_BOUNDS)
+        );
+        assert_eq!(observation.get(0, Position { x: 1, y: -2 }), Some(1));
+        assert_eq!(observation.get(1, Position { x: 0, y: -2 }), Some(0));
+        assert_eq!(observation.get(0, Position { x: 3, y: 0 }), None);
+
+        let observation = observer.observe(&env, Position { x: 2, y: 2 }, None);
+        assert_eq!(observation.get(1, Position { x: 0, y: -2 }), Some(1));
+        assert_eq!(
+            observation.world_position(Position { x: 1, y: 0 }),
+            Some(Position { x: 3, y: 2 })
+        );
+
+        let state = observation.to_state();
+        assert_eq!(state.len(), 2);
+        assert!(matches!(&state[0], Value::VVec(codes) if codes.len() == 25));
+    }
+
+    #[test]
+    fn rotation_and_line_of_sight() {
+        let env = env();
+        let observer = Observer::builder()
+            .radius(2)
+            .layer(Layer::Obstacles)
+            .line_of_sight(true)
+            .build();
+
+        // Facing right (east): the obstacle is right in front
+        let observation = observer.observe(&env, Position { x: 2, y: 2 }, Some(vec2(1., 0.1)));
+        assert_eq!(observation.get(0, Position { x: 0, y: -1 }), Some(1));
+        // and hides the cell behind it
+        assert_eq!(observation.get(0, Position { x: 0, y: -2 }), Some(HIDDEN));
+        assert_eq!(observation.world_position(Position { x: 0, y: -2 }), None);
+        // Left of the agent is up on the grid
+        assert_eq!(
+            observation.world_position(Position { x: -1, y: 0 }),
+            Some(Position { x: 2, y: 1 })
+        );
+    }
+
+    #[test]
+    fn quantized_layers() {
+        let mut env = env();
+        env.add_field(0, 0., 0.).set(Position { x: 2, y: 2 }, 7.);
+        let observer = Observer::builder()
+            .radius(0)
+            .layer(Layer::Field {
+                id: 0,
+                levels: 4,
+                max: 8.,
+            })
+            .build();
+
+        let observation = observer.observe(&env, Position { x: 2, y: 2 }, None);
+        assert_eq!(observation.layer(0), &[3]);
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, VecDeque},
+    f32::consts::SQRT_2,
+};
+
+use crate::{interface::grid::GridSize, scheduler::scheduler::Position};
+
+use super::{environment::Env, topology::Topology};
+
+/// Moves allowed when looking for a path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Up, down, left and right
+    Four,
+    /// Four plus the diagonals. Diagonals cannot cut the corner of an obstacle.
+    Eight,
+}
+
+impl Connectivity {
+    pub fn offsets(&self) -> &'static [Position] {
+        const OFFSETS: [Position; 8] = [
+            Position { x: 0, y: -1 },
+            Position { x: 0, y: 1 },
+            Position { x: -1, y: 0 },
+            Position { x: 1, y: 0 },
+            Position { x: -1, y: -1 },
+            Position { x: 1, y: -1 },
+            Position { x: -1, y: 1 },
+            Position { x: 1, y: 1 },
+        ];
+
+        match self {
+            Connectivity::Four => &OFFSETS[..4],
+            Connectivity::Eight => &OFFSETS,
+        }
+    }
+}
+
+/// A path from its first position to its last one, both included
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    pub positions: Vec<Position>,
+    pub cost: f32,
+}
+
+impl Path {
+    /// Number of moves
+    pub fn len(&self) -> usize {
+        self.positions.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Position after the first move, `None` if already at the end
+    pub fn next_step(&self) -> Option<Position> {
+        self.positions.get(1).copied()
+    }
+}
+
+/// Distances to a target from every cell and the move to make to get closer to it.
+/// Computed once and shared by all the agents going to the same target.
+#[derive(Clone, Debug)]
+pub struct FlowField {
+    size: GridSize,
+    target: Position,
+    distances: Vec<f32>,
+    next: Vec<Option<Position>>,
+}
+
+impl FlowField {
+    pub fn target(&self) -> Position {
+        self.target
+    }
+
+    /// Cost of the shortest path to the target, `None` if the target cannot be reached
+    pub fn distance(&self, position: Position) -> Option<f32> {
+        let distance = self.distances[index(&self.size, position)?];
+        distance.is_finite().then_some(distance)
+    }
+
+    /// Next cell on a shortest path to the target, `None` on the target or if unreachable
+    pub fn next_step(&self, position: Position) -> Option<Position> {
+        self.next[index(&self.size, position)?]
+    }
+
+    /// Follow the field from `from` to the target
+    pub fn path_from(&self, from: Position) -> Option<Path> {
+        let cost = self.distance(from)?;
+        let mut positions = vec![from];
+        let mut current = from;
+        while let Some(next) = self.next_step(current) {
+            positions.push(next);
+            current = next;
+        }
+
+        Some(Path { positions, cost })
+    }
+}
+
+/// Entry of the open set, the lowest priority comes out first
+struct Candidate {
+    priority: f32,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+
+fn index(size: &GridSize, position: Position) -> Option<usize> {
+    (position.x >= 0
+        && position.y >= 0
+        && (position.x as usize) < size.width
+        && (position.y as usize) < size.heigth)
+        .then(|| position.y as usize * size.width + position.x as usize)
+}
+
+fn position(size: &GridSize, index: usize) -> Position {
+    Position {
+        x: (index % size.width) as i32,
+        y: (index / size.width) as i32,
+    }
+}
+
+/// Shortest path cost estimate, never more than the real cost when costs are at least 1
+fn heuristic(
+    topology: Topology,
+    size: &GridSize,
+    from: Position,
+    to: Position,
+    connectivity: Connectivity,
+) -> f32 {
+    let dx = topology.delta_i32(from.x, to.x, size.width as i32).abs() as f32;
+    let dy = topology.delta_i32(from.y, to.y, size.heigth as i32).abs() as f32;
+
+    match connectivity {
+        Connectivity::Four => dx + dy,
+        // Octile distance
+        Connectivity::Eight => dx.max(dy) + (SQRT_2 - 1.) * dx.min(dy),
+    }
+}
+
+impl Env {
+    /// Passable cells reachable in one move from `position` and the length of the move
+    /// (1 or √2). Honours the obstacles and the topology of the environment.
+    pub fn neighbours(
+        &self,
+        position: Position,
+        connectivity: Connectivity,
+    ) -> impl Iterator<Item = (Position, f32)> + '_ {
+        let size = *self.get_grid_size();
+        connectivity.offsets().iter().filter_map(move |offset| {
+            let neighbour = self.topology.wrap_cell(position + *offset, &size)?;
+            if !self.passable(neighbour) {
+                return None;
+            }
+
+            if offset.x != 0 && offset.y != 0 {
+                // No corner cutting
+                let horizontal = Position { x: offset.x, y: 0 };
+                let vertical = Position { x: 0, y: offset.y };
+                let clear = |offset: Position| {
+                    self.topology
+                        .wrap_cell(position + offset, &size)
+                        .is_some_and(|cell| self.passable(cell))
+                };
+                if !clear(horizontal) || !clear(vertical) {
+                    return None;
+                }
+                return Some((neighbour, SQRT_2));
+            }
+
+            Some((neighbour, 1.))
+        })
+    }
+
+    /// Path with the fewest moves (breadth-first search)
+    pub fn bfs(&self, from: Position, to: Position, connectivity: Connectivity) -> Option<Path> {
+        let size = *self.get_grid_size();
+        let (start, goal) = (index(&size, from)?, index(&size, to)?);
+        if !self.passable(to) {
+            return None;
+        }
+
+        let mut previous: Vec<Option<usize>> = vec![None; size.width * size.heigth];
+        let mut visited = vec![false; size.width * size.heigth];
+        let mut queue = VecDeque::from([start]);
+        visited[start] = true;
+
+        while let Some(current) = queue.pop_front() {
+            if current == goal {
+                let positions = rebuild(&size, &previous, goal);
+                let cost = (positions.len() - 1) as f32;
+                return Some(Path { positions, cost });
+            }
+
+            for (neighbour, _) in self.neighbours(position(&size, current), connectivity) {
+                let neighbour = index(&size, neighbour).unwrap();
+                if !visited[neighbour] {
+                    visited[neighbour] = true;
+                    previous[neighbour] = Some(current);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Cheapest path where `cost` is the cost of entering a cell (multiplied by √2 for
+    /// diagonal moves). Cells with an infinite cost are avoided.
+    pub fn dijkstra(
+        &self,
+        from: Position,
+        to: Position,
+        connectivity: Connectivity,
+        cost: impl Fn(Position) -> f32,
+    ) -> Option<Path> {
+        self.search(from, to, connectivity, cost, false)
+    }
+
+    /// Same as `dijkstra`, guided towards `to`. Costs must be at least 1 for the path to be the cheapest.
+    pub fn a_star(
+        &self,
+        from: Position,
+        to: Position,
+        connectivity: Connectivity,
+        cost: impl Fn(Position) -> f32,
+    ) -> Option<Path> {
+        self.search(from, to, connectivity, cost, true)
+    }
+
+    /// Distances to `target` from every cell with the same costs as `dijkstra`
+    pub fn flow_field(
+        &self,
+        target: Position,
+        connectivity: Connectivity,
+        cost: impl Fn(Position) -> f32,
+    ) -> FlowField {
+        let size = *self.get_grid_size();
+        let mut field = FlowField {
+            size,
+            target,
+            distances: vec![f32::INFINITY; size.width * size.heigth],
+            next: vec![None; size.width * size.heigth],
+        };
+        let Some(goal) = index(&size, target).filter(|_| self.passable(target)) else {
+            return field;
+        };
+
+        // Dijkstra from the target, going from a cell to the ones that can move into it
+        field.distances[goal] = 0.;
+        let mut open = BinaryHeap::from([Candidate {
+            priority: 0.,
+            index: goal,
+        }]);
+
+        while let Some(Candidate {
+            priority,
+            index: current,
+        }) = open.pop()
+        {
+            if priority > field.distances[current] {
+                continue;
+            }
+
+            let current_position = position(&size, current);
+            let entering = cost(current_position);
+            if current != goal && !entering.is_finite() {
+                continue;
+            }
+
+            for (neighbour, length) in self.neighbours(current_position, connectivity) {
+                let neighbour_index = index(&size, neighbour).unwrap();
+                let distance = field.distances[current] + entering * length;
+                if distance < field.distances[neighbour_index] {
+                    field.distances[neighbour_index] = distance;
+                    field.next[neighbour_index] = Some(current_position);
+                    open.push(Candidate {
+                        priority: distance,
+                        index: neighbour_index,
+                    });
+                }
+            }
+        }
+
+        field
+    }
+
+    fn search(
+        &self,
+        from: Position,
+        to: Position,
+        connectivity: Connectivity,
+        cost: impl Fn(Position) -> f32,
+        guided: bool,
+    ) -> Option<Path> {
+        let size = *self.get_grid_size();
+        let (start, goal) = (index(&size, from)?, index(&size, to)?);
+        if !self.passable(to) {
+            return None;
+        }
+
+        let estimate = |position: Position| {
+            if guided {
+                heuristic(self.topology, &size, position, to, connectivity)
+            } else {
+                0.
+            }
+        };
+
+        let mut distances = vec![f32::INFINITY; size.width * size.heigth];
+        let mut previous: Vec<Option<usize>> = vec![None; size.width * size.heigth];
+        let mut closed = vec![false; size.width * size.heigth];
+        distances[start] = 0.;
+        let mut open = BinaryHeap::from([Candidate {
+            priority: estimate(from),
+            index: start,
+        }]);
+
+        while let Some(Candidate { index: current, .. }) = open.pop() {
+            if current == goal {
+                return Some(Path {
+                    positions: rebuild(&size, &previous, goal),
+                    cost: distances[goal],
+                });
+            }
+            if closed[current] {
+                continue;
+            }
+            closed[current] = true;
+
+            for (neighbour, length) in self.neighbours(position(&size, current), connectivity) {
+                let entering = cost(neighbour);
+                if !entering.is_finite() {
+                    continue;
+                }
+
+                let neighbour_index = index(&size, neighbour).unwrap();
+                let distance = distances[current] + entering * length;
+                if distance < distances[neighbour_index] {
+                    distances[neighbour_index] = distance;
+                    previous[neighbour_index] = Some(current);
+                    open.push(Candidate {
+                        priority: distance + estimate(neighbour),
+                        index: neighbour_index,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn rebuild(size: &GridSize, previous: &[Option<usize>], goal: usize) -> Vec<Position> {
+    let mut positions = vec![position(size, goal)];
+    let mut current = goal;
+    while let Some(before) = previous[current] {
+        positions.push(position(size, before));
+        current = before;
+    }
+    positions.reverse();
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use macroquad::math::vec2;
+
+    use super::*;
+    use crate::environment::ascii_map::{AsciiMap, Legend};
+
+    // S: start, G: goal
+    const MAZE: &str = "\
+S.#....
+.##.##.
+.......
+.#...#G
+";
+
+    fn maze() -> (Env, Position, Position) {
+        let map = AsciiMap::parse(MAZE, &Legend::default()).unwrap();
+        let (start, goal) = (map.spawn_points[0], map.goals[0]);
+        let env = map.into_env(vec2(0., 0.), vec2(1., 1.), &[], HashMap::new());
+
+        (env, start, goal)
+    }
+
+    #[test]
+    fn shortest_paths_in_a_maze() {
+        let (env, start, goal) = maze();
+
+        let bfs = env.bfs(start, goal, Connectivity::Four).unwrap();
+        assert_eq!(bfs.len(), 9);
+        assert_eq!(bfs.positions.first(), Some(&start));
+        assert_eq!(bfs.positions.last(), Some(&goal));
+        assert!(bfs.positions.iter().all(|position| env.passable(*position)));
+
+        let dijkstra = env
+            .dijkstra(start, goal, Connectivity::Four, |_| 1.)
+            .unwrap();
+        let a_star = env.a_star(start, goal, Connectivity::Four, |_| 1.).unwrap();
+        assert_eq!(dijkstra.cost, 9.);
+        assert_eq!(a_star.cost, 9.);
+
+        // Walled in
+        let mut env = env;
+        env.add_obstacle(Position { x: 5, y: 2 }, macroquad::color::DARKGRAY);
+        env.add_obstacle(Position { x: 6, y: 2 }, macroquad::color::DARKGRAY);
+        assert_eq!(env.bfs(start, goal, Connectivity::Four), None);
+        assert_eq!(env.a_star(start, goal, Connectivity::Eight, |_| 1.), None);
+    }
+
+    #[test]
+    fn costs_and_diagonals() {
+        let (env, start, goal) = maze();
+
+        // Diagonals are shorter in the open
+        let open = env
+            .a_star(
+                Position { x: 2, y: 2 },
+                Position { x: 4, y: 3 },
+                Connectivity::Eight,
+                |_| 1.,
+            )
+            .unwrap();
+        assert_eq!(open.cost, 1. + SQRT_2);
+
+        // but cannot cut the corner of a wall to reach the goal
+        let diagonal = env
+            .a_star(start, goal, Connectivity::Eight, |_| 1.)
+            .unwrap();
+        assert_eq!(
+            diagonal.positions[diagonal.len() - 1],
+            Position { x: 6, y: 2 }
+        );
+        for step in diagonal.positions.windows(2) {
+            let offset = step[1] - step[0];
+            if offset.x != 0 && offset.y != 0 {
+                assert!(env.passable(step[0] + Position { x: offset.x, y: 0 }));
+                assert!(env.passable(step[0] + Position { x: 0, y: offset.y }));
+            }
+        }
+
+        // Expensive mud on the way: going around by the top is cheaper
+        let mud = Position { x: 4, y: 2 };
+        let cost = |position: Position| if position == mud { 10. } else { 1. };
+        let around = env.dijkstra(start, goal, Connectivity::Four, cost).unwrap();
+        assert!(!around.positions.contains(&mud));
+        assert_eq!(around.cost, 13.);
+        assert_eq!(
+            env.a_star(start, goal, Connectivity::Four, cost)
+                .unwrap()
+                .cost,
+            around.cost
+        );
+    }
+
+    #[test]
+    fn flow_field_to_the_goal() {
+        let (env, start, goal) = maze();
+        let field = env.flow_field(goal, Connectivity::Four, |_| 1.);
+
+        assert_eq!(field.distance(goal), Some(0.));
+        assert_eq!(field.distance(start), Some(9.));
+        assert_eq!(field.distance(Position { x: 2, y: 0 }), None);
+        assert_eq!(field.path_from(start).unwrap().len(), 9);
+        assert_eq!(field.next_step(goal), None);
+    }
+
+    #[test]
+    fn toroidal_shortcuts() {
+        let (mut env, _, _) = maze();
+        env.topology = Topology::Toroidal;
+
+        // Going left from the first column wraps to the last one
+        let path = env
+            .bfs(
+                Position { x: 0, y: 2 },
+                Position { x: 6, y: 2 },
+                Connectivity::Four,
+            )
+            .unwrap();
+        assert_eq!(path.len(), 1);
+        assert_eq!(
+            env.a_star(
+                Position { x: 0, y: 2 },
+                Position { x: 6, y: 2 },
+                Connectivity::Four,
+                |_| 1.
+            )
+            .unwrap()
+            .cost,
+            1.
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    any::{type_name, Any},
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Name of an entry of the `Registry` and type of the data it holds.
+///
+/// ## Example
+/// ```rust
+/// const GOAL: Key<IVec2> = Key::new("goal");
+///
+/// env.registry.insert(GOAL, IVec2 { x: 8, y: 8 });
+/// let goal: Option<&IVec2> = env.registry.get(GOAL);
+/// ```
+pub struct Key<T> {
+    name: &'static str,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Key {
+            name,
+            _type: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+// Derives would require `T: Clone`
+impl<T> Clone for Key<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Key<T> {}
+
+impl<T> fmt::Debug for Key<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key<{}>({:?})", type_name::<T>(), self.name)
+    }
+}
+
+/// Serialized entries of a registry, by name
+pub type Snapshot = HashMap<String, Vec<u8>>;
+
+type SerializeFn = fn(&dyn Any) -> bincode::Result<Vec<u8>>;
+type DeserializeFn = fn(&[u8]) -> bincode::Result<Box<dyn Any + Send + Sync>>;
+
+struct Entry {
+    value: Box<dyn Any + Send + Sync>,
+    /// Only set when the type opted in with `Registry::insert_serializable`
+    serde: Option<(SerializeFn, DeserializeFn)>,
+}
+
+/// Typed data of the environment (goal, counters, maps, etc.) without conversion to `Value`.
+/// The data must be `Send + Sync` so the environment can be shared by threads.
+///
+/// Entries are either named by a `Key<T>`, or by their type when there is only one of them
+/// (`insert_by_type`, `by_type`). Reading an entry with the wrong type gives `None`.
+#[derive(Default)]
+pub struct Registry {
+    entries: HashMap<&'static str, Entry>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.entries.keys()).finish()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Insert or replace an entry. Returns the previous value if it had the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, key: Key<T>, value: T) -> Option<T> {
+        self.insert_entry(key.name, value, None)
+    }
+
+    /// Same as `insert`, but the entry is part of the snapshots of the registry
+    pub fn insert_serializable<T: Serialize + DeserializeOwned + Send + Sync + 'static>(
+        &mut self,
+        key: Key<T>,
+        value: T,
+    ) -> Option<T> {
+        self.insert_entry(
+            key.name,
+            value,
+            Some((serialize_entry::<T>, deserialize_entry::<T>)),
+        )
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self, key: Key<T>) -> Option<&T> {
+        self.entries.get(key.name)?.value.downcast_ref()
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self, key: Key<T>) -> Option<&mut T> {
+        self.entries.get_mut(key.name)?.value.downcast_mut()
+    }
+
+    /// Entry of `key`, inserted with `default` first if missing (or of another type)
+    pub fn get_or_insert_with<T: Send + Sync + 'static>(
+        &mut self,
+        key: Key<T>,
+        default: impl FnOnce() -> T,
+    ) -> &mut T {
+        if self.get::<T>(key).is_none() {
+            self.insert(key, default());
+        }
+        self.get_mut(key).unwrap()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self, key: Key<T>) -> Option<T> {
+        match self.entries.get(key.name) {
+            Some(entry) if entry.value.is::<T>() => self
+                .entries
+                .remove(key.name)
+                .and_then(|entry| entry.value.downcast().ok())
+                .map(|value| *value),
+            _ => None,
+        }
+    }
+
+    /// Whether there is an entry of type `T` for `key`
+    pub fn contains<T: Send + Sync + 'static>(&self, key: Key<T>) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert the single entry of type `T`
+    pub fn insert_by_type<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.insert(Key::new(type_name::<T>()), value)
+    }
+
+    /// The single entry of type `T`
+    pub fn by_type<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.get(Key::new(type_name::<T>()))
+    }
+
+    pub fn by_type_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.get_mut(Key::new(type_name::<T>()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serialize the entries inserted with `insert_serializable`
+    pub fn snapshot(&self) -> bincode::Result<Snapshot> {
+        let mut snapshot = Snapshot::new();
+        for (name, entry) in &self.entries {
+            if let Some((serialize, _)) = entry.serde {
+                snapshot.insert(name.to_string(), serialize(entry.value.as_ref())?);
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Put back the values of a snapshot into the serializable entries of the registry.
+    ///
+    /// Only entries already inserted with `insert_serializable` are restored since their
+    /// type is needed to read them back, the others are left as is.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> bincode::Result<()> {
+        for (name, entry) in self.entries.iter_mut() {
+            if let (Some((_, deserialize)), Some(bytes)) = (entry.serde, snapshot.get(*name)) {
+                entry.value = deserialize(bytes)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert_entry<T: Send + Sync + 'static>(
+        &mut self,
+        name: &'static str,
+        value: T,
+        serde: Option<(SerializeFn, DeserializeFn)>,
+    ) -> Option<T> {
+        let entry = Entry {
+            value: Box::new(value),
+            serde,
+        };
+
+        self.entries
+            .insert(name, entry)
+            .and_then(|previous| previous.value.downcast().ok())
+            .map(|value| *value)
+    }
+}
+
+fn serialize_entry<T: Serialize + Send + Sync + 'static>(
+    value: &dyn Any,
+) -> bincode::Result<Vec<u8>> {
+    // Entries are only created with their own serialize function
+    bincode::serialize(value.downcast_ref::<T>().unwrap())
+}
+
+fn deserialize_entry<T: DeserializeOwned + Send + Sync + 'static>(
+    bytes: &[u8],
+) -> bincode::Result<Box<dyn Any + Send + Sync>> {
+    Ok(Box::new(bincode::deserialize::<T>(bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCORE: Key<u32> = Key::new("score");
+    const NAMES: Key<Vec<String>> = Key::new("names");
+
+    #[test]
+    fn typed_entries() {
+        let mut registry = Registry::new();
+        assert_eq!(registry.get(SCORE), None);
+
+        registry.insert(SCORE, 3);
+        *registry.get_mut(SCORE).unwrap() += 1;
+        assert_eq!(registry.get(SCORE), Some(&4));
+
+        // Same name, other type
+        assert_eq!(registry.get(Key::<i64>::new("score")), None);
+        assert_eq!(registry.remove(Key::<i64>::new("score")), None);
+
+        registry
+            .get_or_insert_with(NAMES, Vec::new)
+            .push("ant".to_string());
+        assert_eq!(registry.get(NAMES).unwrap().len(), 1);
+
+        registry.insert_by_type(2.5_f32);
+        assert_eq!(registry.by_type::<f32>(), Some(&2.5));
+        assert_eq!(registry.by_type::<f64>(), None);
+
+        assert_eq!(registry.remove(SCORE), Some(4));
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut registry = Registry::new();
+        registry.insert_serializable(SCORE, 7);
+        registry.insert(NAMES, vec!["not serialized".to_string()]);
+
+        let snapshot = registry.snapshot().unwrap();
+        assert_eq!(snapshot.len(), 1);
+
+        registry.insert_serializable(SCORE, 0);
+        registry.restore(&snapshot).unwrap();
+        assert_eq!(registry.get(SCORE), Some(&7));
+    }
+}
@@ -0,0 +1,256 @@
+use std::f32::consts::TAU;
+
+use macroquad::color::Color;
+use serde::Deserialize;
+
+use crate::{interface::grid::GridSize, scheduler::scheduler::Position};
+
+/// How the quantity of a resource comes back after being harvested.
+/// In a scenario file: `{ kind = "fixed", rate = 0.1 }`
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Regrowth {
+    /// Never regrows
+    None,
+    /// Regrows by `rate` per tick up to the capacity
+    Fixed { rate: f32 },
+    /// Goes back to full capacity `delay` ticks after the last harvest
+    ToCapacity { delay: u64 },
+    /// Regrows by a rate oscillating between `rate - amplitude` and `rate + amplitude`
+    /// over `period` ticks. The rate is never negative.
+    Seasonal {
+        rate: f32,
+        amplitude: f32,
+        period: u64,
+    },
+}
+
+impl Regrowth {
+    /// Quantity regrown during the tick `tick`
+    pub fn rate_at(&self, tick: u64) -> f32 {
+        match *self {
+            Regrowth::None | Regrowth::ToCapacity { .. } => 0.,
+            Regrowth::Fixed { rate } => rate,
+            Regrowth::Seasonal {
+                rate,
+                amplitude,
+                period,
+            } => {
+                let phase = (tick % period.max(1)) as f32 / period.max(1) as f32;
+                (rate + amplitude * (phase * TAU).sin()).max(0.)
+            }
+        }
+    }
+}
+
+/// Quantities of a resource (sugar, grass, minerals, etc.) held by the cells of the grid.
+///
+/// Agents harvest it from their `StepFunction` through `Env::harvest`, and it regrows once per tick.
+#[derive(Clone, Debug)]
+pub struct ResourceLayer {
+    size: GridSize,
+    quantities: Vec<f32>,
+    capacities: Vec<f32>,
+    /// Tick of the last harvest of each cell
+    last_harvest: Vec<u64>,
+    pub regrowth: Regrowth,
+    /// Color of the heatmap (full intensity at full capacity). The layer is not displayed when `None`.
+    pub color: Option<Color>,
+    harvested: f32,
+    regrown: f32,
+}
+
+impl ResourceLayer {
+    /// An empty layer, cells have no capacity until `set_capacity` is called
+    pub fn new(size: GridSize, regrowth: Regrowth) -> Self {
+        let cells = size.width * size.heigth;
+        ResourceLayer {
+            size,
+            quantities: vec![0.; cells],
+            capacities: vec![0.; cells],
+            last_harvest: vec![0; cells],
+            regrowth,
+            color: None,
+            harvested: 0.,
+            regrown: 0.,
+        }
+    }
+
+    /// Display the layer as a heatmap of `color`
+    pub fn with_heatmap(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn inbound(&self, position: Position) -> bool {
+        position.x >= 0
+            && position.y >= 0
+            && (position.x as usize) < self.size.width
+            && (position.y as usize) < self.size.heigth
+    }
+
+    /// Set the maximum quantity of a cell and fill it
+    pub fn set_capacity(&mut self, position: Position, capacity: f32) {
+        if let Some(index) = self.index(position) {
+            self.capacities[index] = capacity.max(0.);
+            self.quantities[index] = capacity.max(0.);
+        }
+    }
+
+    pub fn capacity(&self, position: Position) -> f32 {
+        self.index(position)
+            .map(|index| self.capacities[index])
+            .unwrap_or(0.)
+    }
+
+    /// Quantity available on a cell, 0 when out of bound
+    pub fn get(&self, position: Position) -> f32 {
+        self.index(position)
+            .map(|index| self.quantities[index])
+            .unwrap_or(0.)
+    }
+
+    /// Take up to `amount` from a cell at tick `tick`. Returns the quantity actually taken.
+    pub fn harvest(&mut self, position: Position, amount: f32, tick: u64) -> f32 {
+        let Some(index) = self.index(position) else {
+            return 0.;
+        };
+
+        let taken = amount.max(0.).min(self.quantities[index]);
+        self.quantities[index] -= taken;
+        self.last_harvest[index] = tick;
+        self.harvested += taken;
+
+        taken
+    }
+
+    /// Take everything on a cell
+    pub fn harvest_all(&mut self, position: Position, tick: u64) -> f32 {
+        self.harvest(position, f32::INFINITY, tick)
+    }
+
+    /// Put every cell back to its capacity
+    pub fn fill(&mut self) {
+        self.quantities.copy_from_slice(&self.capacities);
+    }
+
+    /// Regrow every cell, once per tick
+    pub fn regrow(&mut self, tick: u64) {
+        let rate = self.regrowth.rate_at(tick);
+
+        for index in 0..self.quantities.len() {
+            let missing = self.capacities[index] - self.quantities[index];
+            if missing <= 0. {
+                continue;
+            }
+
+            let regrown = match self.regrowth {
+                Regrowth::ToCapacity { delay } => {
+                    if tick.saturating_sub(self.last_harvest[index]) >= delay {
+                        missing
+                    } else {
+                        0.
+                    }
+                }
+                _ => rate.min(missing),
+            };
+
+            self.quantities[index] += regrown;
+            self.regrown += regrown;
+        }
+    }
+
+    /// Quantity currently available on the whole layer
+    pub fn total(&self) -> f32 {
+        self.quantities.iter().sum()
+    }
+
+    pub fn total_capacity(&self) -> f32 {
+        self.capacities.iter().sum()
+    }
+
+    /// Quantity harvested since the creation of the layer
+    pub fn total_harvested(&self) -> f32 {
+        self.harvested
+    }
+
+    /// Quantity regrown since the creation of the layer
+    pub fn total_regrown(&self) -> f32 {
+        self.regrown
+    }
+
+    /// Cells with a capacity and how full they are, in `[0, 1]`
+    pub fn fill_ratios(&self) -> impl Iterator<Item = (Position, f32)> + '_ {
+        (0..self.quantities.len())
+            .filter(|index| self.capacities[*index] > 0.)
+            .map(|index| {
+                (
+                    Position {
+                        x: (index % self.size.width) as i32,
+                        y: (index / self.size.width) as i32,
+                    },
+                    self.quantities[index] / self.capacities[index],
+                )
+            })
+    }
+
+    fn index(&self, position: Position) -> Option<usize> {
+        self.inbound(position)
+            .then(|| position.y as usize * self.size.width + position.x as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: GridSize = GridSize {
+        width: 3,
+        heigth: 3,
+    };
+    const CELL: Position = Position { x: 1, y: 1 };
+
+    #[test]
+    fn harvesting_and_fixed_regrowth() {
+        let mut layer = ResourceLayer::new(SIZE, Regrowth::Fixed { rate: 1. });
+        layer.set_capacity(CELL, 4.);
+
+        assert_eq!(layer.harvest(CELL, 3., 0), 3.);
+        assert_eq!(layer.harvest(CELL, 3., 0), 1.);
+        assert_eq!(layer.get(CELL), 0.);
+        assert_eq!(layer.harvest(Position { x: 0, y: 0 }, 3., 0), 0.);
+
+        for tick in 1..=6 {
+            layer.regrow(tick);
+        }
+        assert_eq!(layer.get(CELL), 4.);
+        assert_eq!(layer.total_harvested(), 4.);
+        assert_eq!(layer.total_regrown(), 4.);
+    }
+
+    #[test]
+    fn regrowing_to_capacity_after_a_delay() {
+        let mut layer = ResourceLayer::new(SIZE, Regrowth::ToCapacity { delay: 3 });
+        layer.set_capacity(CELL, 5.);
+        layer.harvest_all(CELL, 10);
+
+        layer.regrow(12);
+        assert_eq!(layer.get(CELL), 0.);
+        layer.regrow(13);
+        assert_eq!(layer.get(CELL), 5.);
+    }
+
+    #[test]
+    fn seasonal_regrowth() {
+        let seasons = Regrowth::Seasonal {
+            rate: 1.,
+            amplitude: 2.,
+            period: 4,
+        };
+
+        assert_eq!(seasons.rate_at(0), 1.);
+        assert!((seasons.rate_at(1) - 3.).abs() < 1e-5);
+        // Never negative in the dry season
+        assert_eq!(seasons.rate_at(3), 0.);
+    }
+}
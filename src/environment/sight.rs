@@ -0,0 +1,140 @@
+use crate::scheduler::scheduler::Position;
+
+use super::environment::Env;
+
+/// Cells crossed by the segment going from `from` to `to` (both included), using Bresenham's algorithm
+pub fn bresenham(from: Position, to: Position) -> Vec<Position> {
+    let dx = (to.x - from.x).abs();
+    let dy = -(to.y - from.y).abs();
+    let step_x = if from.x < to.x { 1 } else { -1 };
+    let step_y = if from.y < to.y { 1 } else { -1 };
+
+    let mut cells = Vec::with_capacity(dx.max(-dy) as usize + 1);
+    let mut current = from;
+    let mut error = dx + dy;
+
+    loop {
+        cells.push(current);
+        if current == to {
+            break;
+        }
+
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            current.x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            current.y += step_y;
+        }
+    }
+
+    cells
+}
+
+impl Env {
+    /// Whether `to` can be seen from `from`: every cell between them must be passable.
+    /// The cell `to` itself can be an obstacle (a wall is visible).
+    pub fn line_of_sight(&self, from: Position, to: Position) -> bool {
+        if !self.position_inbound(to) {
+            return false;
+        }
+
+        let cells = bresenham(from, to);
+        cells.len() <= 2
+            || cells[1..cells.len() - 1]
+                .iter()
+                .all(|cell| self.passable(*cell))
+    }
+
+    /// Cells at a chebyshev distance of at most `radius` from `from` that are in its line of sight
+    pub fn visible_cells(&self, from: Position, radius: i32) -> Vec<Position> {
+        let mut cells = Vec::new();
+        for y in from.y - radius..=from.y + radius {
+            for x in from.x - radius..=from.x + radius {
+                let cell = Position { x, y };
+                if self.line_of_sight(from, cell) {
+                    cells.push(cell);
+                }
+            }
+        }
+
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use macroquad::{
+        color::{DARKGRAY, GREEN},
+        math::vec2,
+    };
+
+    use super::*;
+    use crate::interface::grid::GridSize;
+
+    #[test]
+    fn bresenham_lines() {
+        let cells = bresenham(Position { x: 0, y: 0 }, Position { x: 4, y: 2 });
+        assert_eq!(
+            cells,
+            vec![
+                Position { x: 0, y: 0 },
+                Position { x: 1, y: 1 },
+                Position { x: 2, y: 1 },
+                Position { x: 3, y: 2 },
+                Position { x: 4, y: 2 },
+            ]
+        );
+
+        // Symmetric in length and ends when reversed
+        let reversed = bresenham(Position { x: 4, y: 2 }, Position { x: 0, y: 0 });
+        assert_eq!(reversed.len(), 5);
+        assert_eq!(reversed.last(), Some(&Position { x: 0, y: 0 }));
+
+        assert_eq!(
+            bresenham(Position { x: 3, y: 3 }, Position { x: 3, y: 3 }),
+            vec![Position { x: 3, y: 3 }]
+        );
+    }
+
+    #[test]
+    fn walls_block_the_sight() {
+        let mut env = Env::new(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            GridSize {
+                width: 5,
+                heigth: 5,
+            },
+            HashMap::new(),
+            &[],
+            HashMap::new(),
+        );
+        env.add_obstacle(Position { x: 2, y: 2 }, DARKGRAY);
+
+        let eye = Position { x: 0, y: 2 };
+        assert!(env.line_of_sight(eye, Position { x: 2, y: 2 }));
+        assert!(!env.line_of_sight(eye, Position { x: 4, y: 2 }));
+        assert!(env.line_of_sight(eye, Position { x: 4, y: 0 }));
+        assert!(!env.line_of_sight(eye, Position { x: -1, y: 2 }));
+
+        let visible = env.visible_cells(Position { x: 2, y: 0 }, 2);
+        assert!(visible.contains(&Position { x: 2, y: 2 }));
+        assert!(!visible.contains(&Position { x: 2, y: 3 }));
+
+        // Moved walls block from their new cell, removed ones no longer block
+        env.move_persistent_element(Position { x: 2, y: 2 }, Position { x: 1, y: 2 });
+        assert!(!env.line_of_sight(eye, Position { x: 2, y: 2 }));
+        assert!(env.passable(Position { x: 2, y: 2 }));
+
+        env.update_persistent_element(Position { x: 3, y: 3 }, GREEN);
+        env.reset_persistent_element(vec![GREEN]);
+        assert!(env.line_of_sight(eye, Position { x: 4, y: 2 }));
+        assert!(env.obstacles.is_empty());
+        assert_eq!(env.persistent_elements.len(), 1);
+    }
+}
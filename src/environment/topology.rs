@@ -0,0 +1,80 @@
+use serde::Deserialize;
+
+use crate::{interface::grid::GridSize, scheduler::scheduler::Position};
+
+/// How a space behaves at its edges
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    /// The edges are hard limits. Anything going past them is kept inside.
+    #[default]
+    Bounded,
+    /// The edges wrap around, going out on the right comes back on the left (torus).
+    Toroidal,
+}
+
+impl Topology {
+    /// Bring a coordinate back inside `[0, extent)`
+    pub fn wrap_f32(&self, value: f32, extent: f32) -> f32 {
+        match self {
+            Topology::Bounded => value.clamp(0., extent.next_down().max(0.)),
+            Topology::Toroidal => {
+                // rem_euclid can round up to `extent` for tiny negative values
+                let wrapped = value.rem_euclid(extent);
+                if wrapped >= extent {
+                    0.
+                } else {
+                    wrapped
+                }
+            }
+        }
+    }
+
+    /// Shortest signed difference `to - from` along an axis of size `extent`
+    pub fn delta_f32(&self, from: f32, to: f32, extent: f32) -> f32 {
+        let delta = to - from;
+        match self {
+            Topology::Bounded => delta,
+            Topology::Toroidal => {
+                if delta > extent / 2. {
+                    delta - extent
+                } else if delta < -extent / 2. {
+                    delta + extent
+                } else {
+                    delta
+                }
+            }
+        }
+    }
+
+    /// Cell reached at `position` on a grid of size `size`, `None` when it is past a bounded edge
+    pub fn wrap_cell(&self, position: Position, size: &GridSize) -> Option<Position> {
+        let (width, heigth) = (size.width as i32, size.heigth as i32);
+        match self {
+            Topology::Bounded => {
+                (position.x >= 0 && position.y >= 0 && position.x < width && position.y < heigth)
+                    .then_some(position)
+            }
+            Topology::Toroidal => Some(Position {
+                x: position.x.rem_euclid(width),
+                y: position.y.rem_euclid(heigth),
+            }),
+        }
+    }
+
+    /// Shortest signed difference `to - from` along an axis of `extent` cells
+    pub fn delta_i32(&self, from: i32, to: i32, extent: i32) -> i32 {
+        let delta = to - from;
+        match self {
+            Topology::Bounded => delta,
+            Topology::Toroidal => {
+                let delta = delta.rem_euclid(extent);
+                if delta > extent / 2 {
+                    delta - extent
+                } else {
+                    delta
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::{cell::RefCell, collections::HashMap, f32::consts::PI, rc::Rc};
+
+use macroquad::{color::SKYBLUE, math::vec2};
+use masim::define_const;
+
+use crate::{
+    agent::{
+        agent::{Action, Done, IsAgent, QTable, Reward, StepFunction},
+        q_table::{load_q_table_or_empty, Fingerprint, QTableError},
+        state::{to_value, State},
+        swarm_agent::SwarmAgent,
+    },
+    batch::batch::{Metrics, Params},
+    environment::{continuous::ContinuousSpace, environment::Env, topology::Topology},
+    interface::grid::GridSize,
+    scenario::scenario::{Scenario, ScenarioOptions},
+    scheduler::scheduler::{Position, Scheduler},
+};
+
+define_const!(ACTIONS => TURN_LEFT, STRAIGHT, TURN_RIGHT);
+
+// The space is as big as the grid, one unit per cell
+const WIDTH: usize = 64;
+const HEIGTH: usize = 64;
+
+// boids
+/// Default distance covered each tick (parameter `speed`)
+const SPEED: f64 = 0.6;
+const TURN_ANGLE: f32 = PI / 8.;
+const VISION_RADIUS: f32 = 5.;
+const SEPARATION_RADIUS: f32 = 1.;
+/// Number of sectors used to discretise the angle between a boid and its neighbours
+const SECTORS: u32 = 8;
+
+/// Boids learning to fly together in a toroidal continuous space
+pub struct Flocking;
+
+impl Scenario for Flocking {
+    fn name(&self) -> &'static str {
+        "flocking"
+    }
+
+    fn description(&self) -> &'static str {
+        "Boids learning to align with their neighbours in a continuous space"
+    }
+
+    fn default_params(&self) -> Params {
+        Params::from([("speed", SPEED)])
+    }
+
+    fn build(&self, options: &ScenarioOptions) -> Result<Scheduler, QTableError> {
+        let speed = self.params(options).get_or("speed", SPEED) as f32;
+        let mut env = Env::new(
+            options.start,
+            options.end,
+            GridSize {
+                width: WIDTH,
+                heigth: HEIGTH,
+            },
+            HashMap::new(),
+            ACTIONS,
+            HashMap::new(),
+        );
+        env.set_space(ContinuousSpace::new(
+            WIDTH as f32,
+            HEIGTH as f32,
+            Topology::Toroidal,
+            VISION_RADIUS,
+        ));
+        if let Some(seed) = options.seed {
+            env.set_seed(seed);
+        }
+
+        let mut scheduler = Scheduler::new(env);
+
+        let boid_func: StepFunction<SwarmAgent> = Rc::new(
+            move |agent: &SwarmAgent,
+                  env: &mut Env,
+                  position: Position,
+                  _state: &State,
+                  action: &Action|
+                  -> (Position, State, Reward, Done) {
+                let space = env.space.as_mut().unwrap();
+
+                match *action {
+                    TURN_LEFT => space.turn(agent.id, -TURN_ANGLE),
+                    TURN_RIGHT => space.turn(agent.id, TURN_ANGLE),
+                    _ => {}
+                }
+
+                let Some(new_position) = space.forward(agent.id, speed) else {
+                    return (position, vec![to_value(SECTORS), to_value(false)], 0., true);
+                };
+
+                /************ UPDATING STATE *************/
+                let (sector, crowded, alignment) = observe(space, agent.id);
+                /*****************************************/
+
+                /************ REWARD SYSTEM **************/
+                let reward: Reward = match (sector, crowded) {
+                    // Alone
+                    (SECTORS, _) => -1.,
+                    (_, true) => -2.,
+                    _ => alignment,
+                };
+                /*****************************************/
+
+                (
+                    env.discretise(new_position),
+                    vec![to_value(sector), to_value(crowded)],
+                    reward,
+                    false,
+                )
+            },
+        );
+
+        let initial_state = vec![to_value(SECTORS), to_value(false)];
+        let flock_mind = Rc::new(RefCell::new(match options.q_table.as_deref() {
+            Some(filepath) => {
+                let expected = Fingerprint::expected(&scheduler.env.actions, &initial_state);
+                load_q_table_or_empty(filepath, Some(&expected))?
+            }
+            None => QTable::new(),
+        }));
+        scheduler.add_swarming_agents(
+            options.agents.unwrap_or(60),
+            None,
+            SKYBLUE,
+            "boid",
+            initial_state,
+            options.learning_rate,
+            options.discount_factor,
+            Some(options.exploration_rate.unwrap_or(0.05)),
+            &boid_func,
+            flock_mind,
+        );
+        scheduler.place_agents_in_space();
+
+        Ok(scheduler)
+    }
+
+    /// `alignment`: mean cosine of the angle between the boids and their neighbours,
+    /// `alone_ratio` and `crowded_ratio`
+    fn metrics(&self, scheduler: &Scheduler) -> Metrics {
+        let Some(space) = &scheduler.env.space else {
+            return Metrics::new();
+        };
+
+        let (mut alignment, mut alone, mut crowded) = (0., 0., 0.);
+        for (_, _, agent) in &scheduler.agents {
+            let (sector, is_crowded, cos) = observe(space, agent.borrow().get_unique_id());
+            alignment += cos as f64;
+            alone += (sector == SECTORS) as u32 as f64;
+            crowded += is_crowded as u32 as f64;
+        }
+
+        let boids = scheduler.agents.len().max(1) as f64;
+        Metrics::from([
+            ("alignment".to_string(), alignment / boids),
+            ("alone_ratio".to_string(), alone / boids),
+            ("crowded_ratio".to_string(), crowded / boids),
+        ])
+    }
+}
+
+/// Discretised observation of a boid:
+/// - the sector of the average heading of its neighbours relative to its own (`SECTORS` if alone)
+/// - whether a neighbour is too close
+/// - how aligned it is with its neighbours (cosine of the angle)
+fn observe(space: &ContinuousSpace, id: u32) -> (u32, bool, f32) {
+    let body = *space.body(id).unwrap();
+    let neighbours = space.neighbours(id, VISION_RADIUS);
+
+    if neighbours.is_empty() {
+        return (SECTORS, false, 0.);
+    }
+
+    let mut average_direction = vec2(0., 0.);
+    let mut crowded = false;
+    for other in neighbours {
+        let other = space.body(other).unwrap();
+        average_direction += other.direction();
+        crowded |= space.distance(body.position, other.position) < SEPARATION_RADIUS;
+    }
+
+    if average_direction.length_squared() < f32::EPSILON {
+        return (0, crowded, 0.);
+    }
+
+    let angle = body
+        .direction()
+        .angle_between(average_direction)
+        .rem_euclid(2. * PI);
+    let sector = ((angle / (2. * PI)) * SECTORS as f32) as u32 % SECTORS;
+
+    (sector, crowded, angle.cos())
+}
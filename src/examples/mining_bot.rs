@@ -1,33 +1,40 @@
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use macroquad::{
-    color::{Color, BLACK, BLUE, ORANGE, PURPLE, RED, YELLOW},
-    math::{vec2, IVec2},
-    window::{screen_height, screen_width},
+    color::{Color, BLACK, BLUE, ORANGE, PURPLE, RED, SKYBLUE, YELLOW},
+    math::IVec2,
 };
 use masim::define_const;
 
 use crate::{
     agent::{
-        agent::{Action, Done, Reward, StepFunction},
-        state::{to_value, State, Value},
-        swarm_agent::{load_q_table, SwarmAgent},
+        agent::{Action, Done, QTable, Reward, StepFunction},
+        q_table::{load_q_table_or_empty, Fingerprint, QTableError},
+        state::{to_value, State},
+        swarm_agent::SwarmAgent,
+    },
+    batch::batch::{Metrics, Params},
+    environment::{
+        environment::Env,
+        generation::mineral_blobs,
+        movement::{MovementSet, MOVES_4},
+        observation::{Layer, Observer},
+        registry::Key,
+        resource::Regrowth,
     },
-    environment::environment::Env,
     interface::grid::GridSize,
+    scenario::scenario::{Scenario, ScenarioOptions},
     scheduler::scheduler::{Position, Scheduler},
 };
 
-// Define your actions here
-define_const!(ACTIONS => UP, DOWN, LEFT, RIGHT);
-// Define your environment data here
-define_const!(ENV_DATA => VISITS);
+// Define your environment fields here
+define_const!(ENV_FIELDS => VISITS);
+// Define your environment resources here
+define_const!(ENV_RESOURCES => MINERALS);
 
 // map
 /// World => HashMap<Position, CELLTYPE>
 // type World = HashMap<(i32, i32), u32>;
-/// Visits => HashMap<Position, num_visits>
-type Visits = HashMap<(i32, i32), u32>;
 const WIDTH: usize = 160;
 const HEIGTH: usize = 160;
 define_const!(CELLTYPE =>
@@ -47,284 +54,297 @@ define_const!(COLOR_SCHEME: Color =>
     DISCOVERED_MINERAL_COLOR: PURPLE
 );
 
+/// Default ratio of the cave filled with veins (parameter `fill_ratio`)
+const FILL_RATIO: f64 = 0.1;
+/// Cells of the veins, to put the cave back in its initial state
+const VEINS: Key<Vec<Position>> = Key::new("veins");
+
 // bots
+/// Default radius of the field of view (parameter `fov`)
 const FOV: u32 = 2;
-
-pub fn main() -> Scheduler {
-    // The file that will save the trained data set
-    let q_table_filepath = "robot_explorer.bin";
-
-    // Get random procedural map generation
-    let blob_positions = generate_map(
-        GridSize {
-            width: WIDTH,
-            heigth: HEIGTH,
-        },
-        0.1,
-        10,
-    );
-
-    let mut persistent_elements = HashMap::new();
-    for (x, y) in blob_positions.clone() {
-        persistent_elements.insert(
-            IVec2 {
-                x: x as i32,
-                y: y as i32,
-            },
-            BASE_MINERAL,
-        );
+const OBSTACLES_LAYER: usize = 0;
+const ELEMENTS_LAYER: usize = 1;
+/// Quantity of ore mined by a bot standing on a vein
+const MINING_RATE: f32 = 1.;
+/// Quantity of ore in a vein cell
+const ORE_PER_CELL: f32 = 5.;
+
+/// Robots sharing a Q-table explore a cave and mine its veins
+pub struct MiningBot;
+
+impl Scenario for MiningBot {
+    fn name(&self) -> &'static str {
+        "mining_bot"
     }
 
-    let visits: Visits = HashMap::new();
-
-    let env = Env::new(
-        vec2(screen_width() * 0.1, screen_height() * 0.1),
-        vec2(screen_width() * 0.9, screen_height() * 0.9),
-        GridSize {
-            width: WIDTH,
-            heigth: HEIGTH,
-        },
-        persistent_elements.clone(),
-        ACTIONS,
-        // HashMap::new(),
-        // HashMap::from([(WORLD, to_value(world)), (VEINS, to_value(blob_positions))]),
-        HashMap::from([(VISITS, to_value(visits.clone()))]),
-    );
-
-    let mut scheduler = Scheduler::new(env);
-
-    let agent_func: StepFunction<SwarmAgent> = Rc::new(
-        move |_agent: &SwarmAgent,
-              env: &mut Env,
-              position: Position,
-              state: &State,
-              action: &Action|
-              -> (Position, State, Reward, Done) {
-            /***** DEFINE THE STATE HERE *************/
-            /* will crash if incorrect types defined */
-            // println!("state: {:?}", state); // DEBUG
-            let surrounding_cells: Vec<u32> = state[0].eq_type();
-            /*****************************************/
-            let (mut new_x, mut new_y) = (position.x, position.y);
-
-            // TODO add movements for omni-directionnal ones
-            match action {
-                &UP => new_y -= 1,
-                &DOWN => new_y += 1,
-                &LEFT => new_x -= 1,
-                &RIGHT => new_x += 1,
-                _ => {}
-            }
-
-            let new_position = Position { x: new_x, y: new_y };
-
-            /************ UPDATING STATE *************/
-            let new_cells = get_robot_state(position, env, FOV as i32);
-            let mut new_grid = Vec::new();
+    fn description(&self) -> &'static str {
+        "Robots sharing a Q-table explore a cave and mine its veins"
+    }
 
-            for (position, cell_type) in new_cells {
-                let (x, y) = position;
+    fn default_params(&self) -> Params {
+        Params::from([("fill_ratio", FILL_RATIO), ("fov", FOV as f64)])
+    }
 
-                new_grid.push(cell_type);
+    fn build(&self, options: &ScenarioOptions) -> Result<Scheduler, QTableError> {
+        let params = self.params(options);
+        let fov = params.get_or("fov", FOV as f64).max(1.) as u32;
 
-                match cell_type {
-                    DISCOVERED_EMPTY => {
-                        env.update_persistent_element(IVec2 { x, y }, DISCOVERED_EMPTY_COLOR)
-                    }
-                    DISCOVERED_MINERAL => {
-                        env.update_persistent_element(IVec2 { x, y }, DISCOVERED_MINERAL_COLOR)
-                    }
-                    JUST_DISCOVERED_EMPTY => {
-                        env.update_persistent_element(IVec2 { x, y }, JUST_DISCOVERED_EMPTY_COLOR)
-                    }
-                    JUST_DISCOVERED_MINERAL => {
-                        env.update_persistent_element(IVec2 { x, y }, JUST_DISCOVERED_MINERAL_COLOR)
+        // Get random procedural map generation
+        let seed = options.seed.unwrap_or_else(rand::random);
+        let veins = mineral_blobs(
+            GridSize {
+                width: WIDTH,
+                heigth: HEIGTH,
+            },
+            params.get_or("fill_ratio", FILL_RATIO) as f32,
+            10,
+            seed,
+        )
+        .filled();
+
+        let mut env = Env::new(
+            options.start,
+            options.end,
+            GridSize {
+                width: WIDTH,
+                heigth: HEIGTH,
+            },
+            HashMap::new(),
+            MOVES_4,
+            // HashMap::from([(WORLD, to_value(world)), (VEINS, to_value(blob_positions))]),
+            HashMap::new(),
+        );
+        fill_cave(&mut env, &veins);
+        env.registry.insert(VEINS, veins);
+        env.set_seed(seed);
+
+        let mut scheduler = Scheduler::new(env);
+
+        // What a bot sees around it
+        let observer = Observer::builder()
+            .radius(fov)
+            .layer(Layer::Obstacles) // OBSTACLES_LAYER
+            .layer(Layer::Elements(vec![
+                BASE_MINERAL,
+                JUST_DISCOVERED_EMPTY_COLOR,
+                DISCOVERED_EMPTY_COLOR,
+                JUST_DISCOVERED_MINERAL_COLOR,
+                DISCOVERED_MINERAL_COLOR,
+            ])) // ELEMENTS_LAYER
+            .line_of_sight(true)
+            .build();
+
+        let agent_func: StepFunction<SwarmAgent> = Rc::new(
+            move |_agent: &SwarmAgent,
+                  env: &mut Env,
+                  position: Position,
+                  state: &State,
+                  action: &Action|
+                  -> (Position, State, Reward, Done) {
+                /***** DEFINE THE STATE HERE *************/
+                /* will crash if incorrect types defined */
+                // println!("state: {:?}", state); // DEBUG
+                let surrounding_cells: Vec<u32> = state[0].eq_type();
+                /*****************************************/
+                let outcome = env.apply_move(position, *action, &MovementSet::Four);
+                let new_position = outcome.position();
+
+                /************ UPDATING STATE *************/
+                let new_cells = get_robot_state(position, &observer, fov, env);
+                let mut new_grid = Vec::new();
+
+                for (position, cell_type) in new_cells {
+                    let (x, y) = position;
+
+                    new_grid.push(cell_type);
+
+                    match cell_type {
+                        DISCOVERED_EMPTY => {
+                            env.update_persistent_element(IVec2 { x, y }, DISCOVERED_EMPTY_COLOR)
+                        }
+                        DISCOVERED_MINERAL => {
+                            env.update_persistent_element(IVec2 { x, y }, DISCOVERED_MINERAL_COLOR)
+                        }
+                        JUST_DISCOVERED_EMPTY => env
+                            .update_persistent_element(IVec2 { x, y }, JUST_DISCOVERED_EMPTY_COLOR),
+                        JUST_DISCOVERED_MINERAL => env.update_persistent_element(
+                            IVec2 { x, y },
+                            JUST_DISCOVERED_MINERAL_COLOR,
+                        ),
+                        ROBOT | WALL => {}
+                        cell_type => println!("uncovered cell_type: {}", cell_type),
                     }
-                    ROBOT | WALL => {}
-                    cell_type => println!("uncovered cell_type: {}", cell_type),
                 }
-            }
-            // if surrounding_cells.is_empty() {
-            //     surrounding_cells = get_robot_state(position, env, FOV as i32);
-            // }
-            /*****************************************/
-
-            /************ REWARD SYSTEM **************/
-            let mut reward: Reward = -1.;
-
-            let inbound = env.position_inbound(new_position);
-            let visits = env.data.get_mut(&VISITS).unwrap().as_map_mut().unwrap();
-
-            // Next move out of bound
-            if !inbound {
-                if surrounding_cells.len() > 1 {
-                    update_visits(visits, to_value((position.x, position.y)));
-                    return (position, vec![to_value(surrounding_cells)], -40., false);
-                } else {
-                    update_visits(visits, to_value((new_x, new_y)));
-                    return (position, vec![to_value(new_grid)], -40., false);
+                // if surrounding_cells.is_empty() {
+                //     surrounding_cells = get_robot_state(position, env, FOV as i32);
+                // }
+                /*****************************************/
+
+                /************ REWARD SYSTEM **************/
+                let mut reward: Reward = -1.;
+
+                let visits = env.field_mut(VISITS).unwrap();
+
+                // Next move out of bound or into a wall
+                if outcome.is_blocked() {
+                    if surrounding_cells.len() > 1 {
+                        visits.deposit(position, 1.);
+                        return (position, vec![to_value(surrounding_cells)], -40., false);
+                    } else {
+                        return (position, vec![to_value(new_grid)], -40., false);
+                    }
                 }
-            }
 
-            let num_visits = update_visits(visits, to_value((new_x, new_y)));
-            for cell in new_grid.clone() {
-                match cell {
-                    WALL => reward += -5.,
-                    // WALL => reward += -20.,
-                    ROBOT => reward += -3.,
-                    DISCOVERED_EMPTY => reward += if num_visits > 5 { -5. } else { 1. },
-                    // DISCOVERED_EMPTY => reward += -2.,
-                    DISCOVERED_MINERAL => reward += if num_visits > 5 { -3. } else { 2. },
-                    // DISCOVERED_MINERAL => reward += -1.,
-                    JUST_DISCOVERED_EMPTY => reward += 2.,
-                    JUST_DISCOVERED_MINERAL => reward += 30.,
-                    _ => panic!("THIS CASE IS NOT COVERED"),
+                let num_visits = visits.deposit(new_position, 1.);
+                env.harvest(MINERALS, new_position, MINING_RATE);
+                for cell in new_grid.clone() {
+                    match cell {
+                        WALL => reward += -5.,
+                        // WALL => reward += -20.,
+                        ROBOT => reward += -3.,
+                        DISCOVERED_EMPTY => reward += if num_visits > 5. { -5. } else { 1. },
+                        // DISCOVERED_EMPTY => reward += -2.,
+                        DISCOVERED_MINERAL => reward += if num_visits > 5. { -3. } else { 2. },
+                        // DISCOVERED_MINERAL => reward += -1.,
+                        JUST_DISCOVERED_EMPTY => reward += 2.,
+                        JUST_DISCOVERED_MINERAL => reward += 30.,
+                        _ => panic!("THIS CASE IS NOT COVERED"),
+                    }
                 }
-            }
-
-            // println!("reward: {}, position: {}", reward, new_position);
-
-            return (new_position, vec![to_value(new_grid)], reward, false);
-            /*****************************************/
-        },
-    );
-
-    /************ UPDATING SCHEDULER *********/
-    let robot_hive_mind = Rc::new(RefCell::new(
-        load_q_table(&q_table_filepath).unwrap_or(HashMap::new()),
-    ));
-
-    scheduler.add_swarming_agents(
-        10,
-        None,
-        BLUE,
-        "robot_explorer",
-        vec![to_value::<Vec<_>>(vec![0u32])],
-        None,
-        None,
-        Some(0.01),
-        // None,
-        &agent_func,
-        robot_hive_mind,
-    );
-
-    for _ in 0..4 {
-        scheduler.train_agents(400);
-        scheduler
-            .env
-            .set_persitent_elements(persistent_elements.clone());
 
-        // Giving random position to agents
-        for i in 0..scheduler.agents.len() {
-            scheduler.agents[i].0 = scheduler.env.get_random_position();
-        }
+                // println!("reward: {}, position: {}", reward, new_position);
 
-        // Reset visits
-        scheduler.env.data.insert(VISITS, to_value(visits.clone()));
-    }
+                (new_position, vec![to_value(new_grid)], reward, false)
+                /*****************************************/
+            },
+        );
 
-    /*****************************************/
+        /************ UPDATING SCHEDULER *********/
+        let initial_state = vec![to_value::<Vec<_>>(vec![0u32])];
+        let robot_hive_mind = Rc::new(RefCell::new(match options.q_table.as_deref() {
+            Some(filepath) => {
+                // After the first step, the states are the cells in sight
+                let side = 2 * fov as usize + 1;
+                let observed_state = vec![to_value(vec![WALL; side * side])];
+                let expected = Fingerprint::expected_states(
+                    &scheduler.env.actions,
+                    &[initial_state.clone(), observed_state],
+                );
+                load_q_table_or_empty(filepath, Some(&expected))?
+            }
+            None => QTable::new(),
+        }));
+
+        scheduler.add_swarming_agents(
+            options.agents.unwrap_or(10),
+            None,
+            BLUE,
+            "robot_explorer",
+            initial_state,
+            options.learning_rate,
+            options.discount_factor,
+            Some(options.exploration_rate.unwrap_or(0.01)),
+            &agent_func,
+            robot_hive_mind,
+        );
+        /*****************************************/
 
-    scheduler
-}
+        Ok(scheduler)
+    }
 
-fn get_random_position(x: i32, y: i32, grid_size: &GridSize) -> (i32, i32) {
-    let GridSize { width, heigth } = grid_size;
+    /// The robots keep their shared Q-table, the cave is undiscovered and full of ore again
+    fn reset(
+        &self,
+        scheduler: &mut Scheduler,
+        _options: &ScenarioOptions,
+    ) -> Result<(), QTableError> {
+        let env = &mut scheduler.env;
+        let veins = env.registry.remove(VEINS).unwrap_or_default();
+        fill_cave(env, &veins);
+        env.registry.insert(VEINS, veins);
+        env.reset_clock();
+
+        scheduler.respawn_agents();
+        Ok(())
+    }
 
-    let mut new_x = rand::random_range(x - 1..x + 2);
-    let mut new_y = rand::random_range(y - 1..y + 2);
+    /// `ore_mined`, `ore_mined_ratio` and `cells_discovered`
+    fn metrics(&self, scheduler: &Scheduler) -> Metrics {
+        let minerals = scheduler.env.resource(MINERALS).unwrap();
+        let discovered = scheduler
+            .env
+            .persistent_elements
+            .values()
+            .filter(|color| **color != BASE_MINERAL)
+            .count();
 
-    while new_x >= *width as i32 || new_x < 0 || new_y >= *heigth as i32 || new_y < 0 {
-        new_x = rand::random_range(x - 1..x + 2);
-        new_y = rand::random_range(y - 1..y + 2);
+        Metrics::from([
+            ("ore_mined".to_string(), minerals.total_harvested() as f64),
+            (
+                "ore_mined_ratio".to_string(),
+                (minerals.total_harvested() / minerals.total_capacity().max(f32::EPSILON)) as f64,
+            ),
+            ("cells_discovered".to_string(), discovered as f64),
+        ])
     }
-
-    (new_x, new_y)
 }
 
-fn generate_map(grid_size: GridSize, fill_ratio: f32, num_blob: i32) -> Vec<(i32, i32)> {
-    assert!(fill_ratio < 1.0);
-
-    let GridSize { width, heigth } = grid_size;
-    let target_fill = ((grid_size.heigth * grid_size.width) as f32 * fill_ratio) as i32;
-    let mut num_filled_cell = 0;
-    let mut blob_positions: Vec<(i32, i32)> = (0..num_blob)
-        .map(|_| {
-            (
-                rand::random_range(0..width) as i32,
-                rand::random_range(0..heigth) as i32,
-            )
-        })
+/// Undiscovered veins full of ore and no visit yet
+fn fill_cave(env: &mut Env, veins: &[Position]) {
+    env.persistent_elements = veins
+        .iter()
+        .map(|position| (*position, BASE_MINERAL))
         .collect();
 
-    // Filling position
-    while num_filled_cell < target_fill {
-        for i in 0..blob_positions.len() {
-            let (x, y) = blob_positions[i];
-            let (new_x, new_y) = get_random_position(x, y, &grid_size);
-            if (x != new_x || y != new_y) && !blob_positions.contains(&(new_x, new_y)) {
-                blob_positions.push((new_x, new_y));
-                num_filled_cell += 1;
-            }
-        }
+    // Number of visits per cell. Neither diffuses nor evaporates.
+    env.fields.remove(&VISITS);
+    env.add_field(VISITS, 0., 0.).set_heatmap(SKYBLUE, 10.);
+    // Ore in the veins. Once mined it does not come back.
+    env.resources.remove(&MINERALS);
+    let minerals = env.add_resource(MINERALS, Regrowth::None);
+    for position in veins {
+        minerals.set_capacity(*position, ORE_PER_CELL);
     }
-
-    blob_positions
 }
 
-fn get_robot_state(current_pos: IVec2, env: &Env, fov: i32) -> Vec<((i32, i32), u32)> {
-    let IVec2 {
-        x: init_x,
-        y: init_y,
-    } = current_pos;
+fn get_robot_state(
+    current_pos: IVec2,
+    observer: &Observer,
+    fov: u32,
+    env: &Env,
+) -> Vec<((i32, i32), u32)> {
+    let observation = observer.observe(env, current_pos, None);
+    let fov = fov as i32;
 
     let mut new_state: Vec<((i32, i32), u32)> = Vec::new();
 
-    // let world: World = env.data.get(&WORLD).unwrap().eq_type();
-    // let mut world = env.data.get_mut(&WORLD).unwrap().as_map_mut().unwrap();
-
-    for x in init_x - fov..init_x + fov + 1 {
-        for y in init_y - fov..init_y + fov + 1 {
-            if env.position_inbound(IVec2 { x, y }) {
-                // If cell is where the robot is
-                if x == init_x && y == init_y {
-                    new_state.push(((x, y), ROBOT));
-                    continue;
-                }
-
-                // TODO if ally is on cell
+    for x in -fov..=fov {
+        for y in -fov..=fov {
+            let local = IVec2 { x, y };
+            let IVec2 { x, y } = current_pos + local;
 
-                if let Some(color) = env.persistent_elements.get(&IVec2 { x, y }) {
-                    match color {
-                        &BASE_MINERAL => new_state.push(((x, y), JUST_DISCOVERED_MINERAL)),
-                        &JUST_DISCOVERED_EMPTY_COLOR | &DISCOVERED_EMPTY_COLOR => {
-                            new_state.push(((x, y), DISCOVERED_EMPTY))
-                        }
-                        &JUST_DISCOVERED_MINERAL_COLOR | &DISCOVERED_MINERAL_COLOR => {
-                            new_state.push(((x, y), DISCOVERED_MINERAL))
-                        }
-                        color => println!("uncovered color {}", color.to_vec()),
-                    }
-                } else {
-                    new_state.push(((x, y), JUST_DISCOVERED_EMPTY))
-                }
-            } else {
-                // If out of bound it will be considered a wall (will also need to be implement in case wall are inside the map)
-                new_state.push(((x, y), WALL));
+            // If cell is where the robot is
+            if local == IVec2::ZERO {
+                new_state.push(((x, y), ROBOT));
+                continue;
             }
+
+            // TODO if ally is on cell
+
+            // Out of bound, walls and cells hidden behind them are all considered walls
+            let cell_type = match (
+                observation.get(OBSTACLES_LAYER, local),
+                observation.get(ELEMENTS_LAYER, local),
+            ) {
+                (Some(0), Some(0)) => JUST_DISCOVERED_EMPTY,
+                (Some(0), Some(1)) => JUST_DISCOVERED_MINERAL,
+                (Some(0), Some(2 | 3)) => DISCOVERED_EMPTY,
+                (Some(0), Some(4 | 5)) => DISCOVERED_MINERAL,
+                _ => WALL,
+            };
+            new_state.push(((x, y), cell_type));
         }
     }
 
     new_state
 }
-
-fn update_visits(map: &mut HashMap<Value, Value>, key: Value) -> u32 {
-    let val: u32 = map
-        .get(&to_value(key.clone()))
-        .unwrap_or(&mut Value::VU32(0))
-        .eq_type();
-
-    map.insert(to_value(key), to_value(val + 1));
-
-    val + 1
-}
@@ -1,2 +1,12 @@
+use crate::scenario::scenario::ScenarioRegistry;
+
+pub mod flocking;
 pub mod mining_bot;
 pub mod runner;
+
+/// Add every example to the registry, in the order `list` shows them
+pub fn register(registry: &mut ScenarioRegistry) {
+    registry.register(runner::Runner);
+    registry.register(mining_bot::MiningBot);
+    registry.register(flocking::Flocking);
+}
@@ -1,50 +1,186 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{collections::HashMap, rc::Rc};
 
 use macroquad::{
     color::{GREEN, YELLOW},
-    math::{vec2, IVec2},
-    window::{screen_height, screen_width},
+    math::{IVec2, Vec2},
 };
-use masim::define_const;
 
 use crate::{
     agent::{
-        agent::{Action, Agent, Done, Reward, StepFunction},
+        agent::{Action, Done, Reward, StepFunction},
+        dyna::DynaConfig,
         learning_agent::LearningAgent,
+        q_table::QTableError,
         state::{to_value, State, Value},
     },
-    environment::environment::Env,
+    batch::batch::{Metrics, Params},
+    environment::{
+        environment::Env,
+        movement::{MovementSet, MOVES_4},
+        pathfinding::{Connectivity, FlowField},
+        registry::Key,
+    },
     interface::grid::GridSize,
+    scenario::scenario::{Scenario, ScenarioOptions},
     scheduler::scheduler::{Position, Scheduler},
 };
 
-define_const!(ACTIONS => UP, DOWN, LEFT, RIGHT);
-define_const!(ENV_DATA => GOAL);
+const GOAL: Key<IVec2> = Key::new("goal");
+/// Distances to the goal going around the walls
+const GOAL_FLOW: Key<FlowField> = Key::new("goal_flow");
+/// Number of times the goal was reached
+const GOALS_REACHED: Key<u32> = Key::new("goals_reached");
+
+/// Default width and height of the grid (parameter `size`)
+const SIZE: usize = 16;
+
+/// Runners chasing a goal that moves once reached
+pub struct Runner;
+
+impl Scenario for Runner {
+    fn name(&self) -> &'static str {
+        "runner"
+    }
+
+    fn description(&self) -> &'static str {
+        "Runners chasing a goal that moves once reached"
+    }
+
+    /// `planning_steps` above 0 makes the runners plan with Dyna-Q, and `exploration_bonus`
+    /// with Dyna-Q+, which helps them follow the goal once it moved
+    fn default_params(&self) -> Params {
+        Params::from([
+            ("size", SIZE as f64),
+            ("planning_steps", 0.),
+            ("exploration_bonus", 0.),
+        ])
+    }
+
+    fn build(&self, options: &ScenarioOptions) -> Result<Scheduler, QTableError> {
+        let params = self.params(options);
+        let size = params.get_or("size", SIZE as f64).max(1.) as usize;
+        let mut scheduler = new_scheduler(options.start, options.end, size);
+        if let Some(seed) = options.seed {
+            scheduler.env.set_seed(seed);
+        }
+
+        scheduler.add_agents(
+            options.agents.unwrap_or(10),
+            // Some(IVec2 { x: 0, y: 0 }), // Uncomment for the same starting point
+            None,
+            YELLOW,
+            "runner",
+            initial_state(),
+            options.learning_rate,
+            options.discount_factor,
+            Some(options.exploration_rate.unwrap_or(0.01)),
+            &runner_step_function(),
+            options.q_table.as_deref(),
+        )?;
+        scheduler.q_table_sync = options.q_table_sync;
+
+        let planning_steps = params.get_or("planning_steps", 0.).max(0.) as usize;
+        if planning_steps > 0 {
+            let config = DynaConfig {
+                planning_steps,
+                exploration_bonus: params.get_or("exploration_bonus", 0.).max(0.) as f32,
+            };
+            scheduler.set_dyna("runner", Some(config));
+        }
 
-pub fn main() -> Scheduler {
-    // The file that will save the trained data set
-    let q_table_filepath = "trained_runner.bin";
+        Ok(scheduler)
+    }
 
-    let goal = IVec2 { x: 8, y: 8 };
-    let persistent_elements = HashMap::from([(goal, GREEN)]);
-    let env = Env::new(
-        vec2(screen_width() * 0.1, screen_height() * 0.1),
-        vec2(screen_width() * 0.9, screen_height() * 0.9),
+    /// The runners keep what they learned, the goal goes back to the center
+    fn reset(
+        &self,
+        scheduler: &mut Scheduler,
+        _options: &ScenarioOptions,
+    ) -> Result<(), QTableError> {
+        let env = &mut scheduler.env;
+        let goal = *env.registry.get(GOAL).unwrap();
+        move_goal(env, goal, center(env));
+        env.registry.insert(GOALS_REACHED, 0);
+        env.reset_clock();
+
+        scheduler.respawn_agents();
+        Ok(())
+    }
+
+    /// `goals_reached` and `goals_per_step`
+    fn metrics(&self, scheduler: &Scheduler) -> Metrics {
+        let goals_reached = *scheduler.env.registry.get(GOALS_REACHED).unwrap_or(&0) as f64;
+        Metrics::from([
+            ("goals_reached".to_string(), goals_reached),
+            (
+                "goals_per_step".to_string(),
+                goals_reached / scheduler.env.tick.max(1) as f64,
+            ),
+        ])
+    }
+}
+
+/// Environment with the goal, moved each time a runner reaches it
+fn new_scheduler(start: Vec2, end: Vec2, size: usize) -> Scheduler {
+    let mut env = Env::new(
+        start,
+        end,
         GridSize {
-            width: 16,
-            heigth: 16,
+            width: size,
+            heigth: size,
         },
-        persistent_elements,
-        ACTIONS,
-        HashMap::from([(
-            GOAL,
-            to_value((goal.x, goal.y)), // (x, y)
-        )]),
+        HashMap::new(),
+        MOVES_4,
+        HashMap::new(),
     );
+    let goal = center(&env);
+    env.persistent_elements.insert(goal, GREEN);
+    env.registry.insert(GOAL, goal);
+    let flow = env.flow_field(goal, Connectivity::Four, |_| 1.);
+    env.registry.insert(GOAL_FLOW, flow);
 
     let mut scheduler = Scheduler::new(env);
 
-    let runner_func: StepFunction<LearningAgent> = Rc::new(
+    // Move the goal once an agent reached it
+    scheduler.add_post_step(Rc::new(|env: &mut Env, positions: &[Position]| {
+        let goal = *env.registry.get(GOAL).unwrap();
+
+        if positions.contains(&goal) {
+            *env.registry.get_or_insert_with(GOALS_REACHED, || 0) += 1;
+
+            let new_goal = env.get_random_position();
+            move_goal(env, goal, new_goal);
+        }
+    }));
+
+    scheduler
+}
+
+fn center(env: &Env) -> Position {
+    IVec2 {
+        x: *env.get_width() as i32 / 2,
+        y: *env.get_heigth() as i32 / 2,
+    }
+}
+
+fn move_goal(env: &mut Env, goal: Position, new_goal: Position) {
+    env.move_persistent_element(goal, new_goal);
+    env.registry.insert(GOAL, new_goal);
+    let flow = env.flow_field(new_goal, Connectivity::Four, |_| 1.);
+    env.registry.insert(GOAL_FLOW, flow);
+}
+
+fn initial_state() -> State {
+    vec![
+        Value::VBool(true), // ABOVE_TARGET
+        Value::VBool(true), // BELOW_TARGET
+        Value::VBool(true), // LEFT_OF_TARGET
+        Value::VBool(true), // RIGHT_OF_TARGET
+    ]
+}
+
+fn runner_step_function() -> StepFunction<LearningAgent> {
+    Rc::new(
         move |_agent: &LearningAgent,
               env: &mut Env,
               position: Position,
@@ -59,20 +195,15 @@ pub fn main() -> Scheduler {
             let left: bool = state[2].eq_type();
             let right: bool = state[3].eq_type();
             /*****************************************/
-            let (mut new_x, mut new_y) = (position.x, position.y);
-
-            match action {
-                &UP => new_y -= 1,
-                &DOWN => new_y += 1,
-                &LEFT => new_x -= 1,
-                &RIGHT => new_x += 1,
-                _ => {}
-            }
-
-            let new_position = Position { x: new_x, y: new_y };
+            let outcome = env.apply_move(position, *action, &MovementSet::Four);
+            let new_position = outcome.position();
+            let IVec2 { x: new_x, y: new_y } = new_position;
 
             /************ UPDATING STATE *************/
-            let (goal_x, goal_y): (i32, i32) = env.data.get(&GOAL).unwrap().eq_type();
+            let IVec2 {
+                x: goal_x,
+                y: goal_y,
+            } = *env.registry.get(GOAL).unwrap();
 
             fn get_new_state(
                 (new_x, new_y): (i32, i32),
@@ -96,7 +227,7 @@ pub fn main() -> Scheduler {
             let mut reward: Reward = -1.;
 
             // State doesn't check wall or border so we shall not change the reward
-            if !env.position_inbound(new_position) {
+            if outcome.is_blocked() {
                 let state = if !above && !below && !left && !right {
                     get_new_state((new_x, new_y), (goal_x, goal_y))
                 } else {
@@ -111,41 +242,18 @@ pub fn main() -> Scheduler {
                 // return (position, state.clone(), reward - 5., false);
             }
 
-            let prev_distance =
-                (((goal_x - position.x).pow(2) + (goal_y - position.y).pow(2)) as f32).sqrt();
-            let current_distance =
-                (((goal_x - new_x).pow(2) + (goal_y - new_y).pow(2)) as f32).sqrt();
+            let flow = env.registry.get(GOAL_FLOW).unwrap();
+            let prev_distance = flow.distance(position).unwrap_or(f32::INFINITY);
+            let current_distance = flow.distance(new_position).unwrap_or(f32::INFINITY);
 
             // When distance from goal is shorter
             if current_distance < prev_distance {
                 reward += 10.;
             }
 
-            // Goal reached
+            // Goal reached. The goal is moved by the environment at the end of the tick.
             if new_x == goal_x && new_y == goal_y {
                 reward += 50.;
-
-                // Generate new goal
-                let new_goal = env.get_random_position();
-
-                // Update goal
-                env.move_persistent_element(
-                    IVec2 {
-                        x: goal_x,
-                        y: goal_y,
-                    },
-                    new_goal,
-                );
-
-                env.data.insert(GOAL, to_value((new_goal.x, new_goal.y)));
-
-                // Done set to false in order to keep the demo running
-                return (
-                    new_position,
-                    get_new_state((new_x, new_y), (goal_x, goal_y)),
-                    reward,
-                    false,
-                );
             }
 
             (
@@ -156,54 +264,5 @@ pub fn main() -> Scheduler {
             )
             /*****************************************/
         },
-    );
-
-    // Comment the following to remove training
-    train_agent(&mut scheduler, &runner_func, q_table_filepath);
-
-    let n = 10;
-    scheduler.add_agents(
-        n,
-        // Some(IVec2 { x: 0, y: 0 }), // Uncomment for the same starting point
-        None,
-        YELLOW,
-        "runner",
-        vec![
-            Value::VBool(true), // ABOVE_TARGET
-            Value::VBool(true), // BELOW_TARGET
-            Value::VBool(true), // LEFT_OF_TARGET
-            Value::VBool(true), // RIGHT_OF_TARGET
-        ],
-        None,
-        None,
-        Some(0.01),
-        &runner_func,
-        Some(q_table_filepath),
-    );
-
-    scheduler
-}
-
-fn train_agent(
-    scheduler: &mut Scheduler,
-    step_fn: &StepFunction<LearningAgent>,
-    q_table_filepath: &str,
-) {
-    let mut new_agent = Rc::new(RefCell::new(Agent::Learning(LearningAgent::new(
-        1000,
-        "runner",
-        vec![
-            Value::VBool(true), // ABOVE_TARGET
-            Value::VBool(true), // BELOW_TARGET
-            Value::VBool(true), // LEFT_OF_TARGET
-            Value::VBool(true), // RIGHT_OF_TARGET
-        ],
-        None,
-        None,
-        Some(0.4),
-        step_fn,
-        Some(&q_table_filepath),
-    ))));
-
-    scheduler.save_q_table_to_file(&mut new_agent, 1000, &q_table_filepath, true);
+    )
 }
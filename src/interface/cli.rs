@@ -0,0 +1,956 @@
+use std::{fs, ops::Range, path::Path, process::ExitCode};
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    agent::{
+        agent::QTable,
+        merge::{merge_q_tables, MergeStrategy, QTableSync},
+        q_table::{
+            read_q_table, sorted_policy, write_q_table, ActionNames, Fingerprint, QTableDiff,
+            QTableError, QTableStats,
+        },
+        state::{State, Value},
+    },
+    batch::batch::{BatchResults, BatchRunner, Metrics, Params},
+    scenario::{
+        scenario::{batch_run_with, Scenario, ScenarioOptions, ScenarioRegistry, RUN_PARAMS},
+        scenario_file::{FileScenario, Moves, ScenarioFileError},
+        steps::StepRegistry,
+    },
+    scheduler::scheduler::Scheduler,
+};
+
+// Exit codes, for scripts. Invalid arguments exit with 2 (see clap).
+pub const SUCCESS: u8 = 0;
+pub const UNKNOWN_SCENARIO: u8 = 3;
+pub const FILE_ERROR: u8 = 4;
+pub const INVALID_SCENARIO_FILE: u8 = 5;
+pub const INVALID_Q_TABLE: u8 = 6;
+pub const INVALID_PARAM: u8 = 7;
+
+/// Scenario opened without a subcommand
+const DEFAULT_SCENARIO: &str = "mining_bot";
+/// Number of steps of the headless commands when none is given
+const DEFAULT_STEPS: u32 = 1000;
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  2  invalid arguments
+  3  unknown scenario
+  4  a file could not be read or written
+  5  invalid scenario file
+  6  invalid Q-table file
+  7  unknown parameter of the scenario";
+
+#[derive(Parser, Debug)]
+#[command(name = "masim", about = "Multi-agent simulator", after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Opens the default scenario in a window when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List the scenarios
+    List,
+    /// Run a scenario in a window (press S to start), or headless and print its metrics
+    Run {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+        /// Run without a window
+        #[arg(long)]
+        headless: bool,
+    },
+    /// Train the agents of a scenario headless and save their Q-tables in the output directory
+    Train {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+    },
+    /// Run a scenario headless without exploration and print its metrics
+    Eval {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+    },
+    /// Run a scenario headless for many parameters and seeds and write the metrics of every
+    /// run in `batch.csv` of the output directory. Runs that cannot be built are reported in
+    /// its `error` column and the others go on.
+    Batch {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+        #[command(flatten)]
+        sweep: SweepArgs,
+    },
+    /// Export, import and inspect Q-table files
+    #[command(name = "q-table", subcommand)]
+    QTable(QTableCommand),
+}
+
+/// Q-tables are read and written in the format of their extension: `.csv`, `.json`, or the
+/// bincode file of the agents otherwise
+#[derive(Subcommand, Debug)]
+pub enum QTableCommand {
+    /// Write a Q-table as CSV or JSON, with readable states and action names
+    Export {
+        table: String,
+        /// `.csv` or `.json`
+        output: String,
+        #[command(flatten)]
+        actions: ActionArgs,
+    },
+    /// Turn an exported (and edited) table back into a Q-table file for the agents
+    Import { input: String, output: String },
+    /// Print the number of states, the range of values and the use of each action
+    Stats {
+        table: String,
+        /// Also print the greedy action of every state
+        #[arg(long)]
+        policy: bool,
+        #[command(flatten)]
+        actions: ActionArgs,
+    },
+    /// Print the entries and greedy actions that differ between two Q-tables
+    Diff {
+        left: String,
+        right: String,
+        /// Smallest change of a value that is reported
+        #[arg(long, default_value_t = 1e-6)]
+        tolerance: f32,
+        #[command(flatten)]
+        actions: ActionArgs,
+    },
+    /// Merge the Q-tables of several training runs into one. Files written by the agents
+    /// do not know the visits of their entries, so every table weighs the same.
+    Merge {
+        #[arg(required = true, num_args = 2..)]
+        tables: Vec<String>,
+        #[arg(long)]
+        output: String,
+        #[arg(long, value_enum, default_value_t = MergeStrategy::Average)]
+        strategy: MergeStrategy,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct ActionArgs {
+    /// Name the actions after a movement set (`up`, `north_east`, etc.)
+    #[arg(long, value_enum, conflicts_with = "actions")]
+    pub moves: Option<Moves>,
+    /// Names of the actions 0, 1, 2... separated by commas
+    #[arg(long, value_delimiter = ',')]
+    pub actions: Vec<String>,
+}
+
+impl ActionArgs {
+    fn names(&self) -> ActionNames {
+        match self.moves {
+            Some(moves) => ActionNames::from_movement_set(&moves.movement_set()),
+            None => {
+                ActionNames::from_list(&self.actions.iter().map(String::as_str).collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ScenarioArgs {
+    /// Name of the scenario (see `list`), or path of a scenario file (.toml)
+    pub scenario: String,
+    /// Seed of the world generation and of every random draw of the run: exploration,
+    /// spawns, activation order, conflicts, replay and planning
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Number of steps. Default: `run.steps` of a scenario file, otherwise 1000 headless and
+    /// no limit in a window
+    #[arg(long)]
+    pub steps: Option<u32>,
+    /// Number of agents of each type
+    #[arg(long)]
+    pub agents: Option<usize>,
+    /// Q-table file loaded by the agents
+    #[arg(long)]
+    pub q_table: Option<String>,
+    #[arg(long)]
+    pub exploration_rate: Option<f32>,
+    /// Merge the Q-tables of the learning agents of each type every TICKS ticks. Default:
+    /// `run.sync` of a scenario file, otherwise every agent learns alone
+    #[arg(long, value_name = "TICKS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub sync_interval: Option<u32>,
+    /// How the Q-tables are merged with `--sync-interval`
+    #[arg(long, value_enum, default_value_t = MergeStrategy::Average, requires = "sync_interval")]
+    pub merge: MergeStrategy,
+    /// Parameter of the scenario, as `name=value` (see `list`). Can be repeated.
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, f64)>,
+    /// Directory of the saved Q-tables and of `metrics.csv`. Default: current directory for
+    /// `train`, nothing written otherwise
+    #[arg(long)]
+    pub output_dir: Option<String>,
+}
+
+/// Parameters tried by `batch`. `--seed` is the seed of the sampling and of the first
+/// repetition, the next repetitions use the next seeds.
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// Try every value of a parameter, as `name=value,value,...`. Can be repeated, every
+    /// combination is tried. Besides the parameters of the scenario, `steps`, `agents`,
+    /// `learning_rate`, `discount_factor` and `exploration_rate` can be swept.
+    #[arg(long, value_name = "NAME=VALUES", value_parser = parse_grid)]
+    pub grid: Vec<(String, Vec<f64>)>,
+    /// Draw a parameter uniformly, as `name=min..max`. Can be repeated.
+    #[arg(long, value_name = "NAME=MIN..MAX", value_parser = parse_sample)]
+    pub sample: Vec<(String, Range<f64>)>,
+    /// Number of draws of the `--sample` parameters per combination of the grid
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: u32,
+    /// Number of runs of each configuration
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repetitions: u32,
+    /// Run on every core
+    #[arg(long)]
+    pub parallel: bool,
+}
+
+/// What `main` has to do once the command line is handled
+pub enum Launch {
+    /// Exit right away with this code
+    Exit(ExitCode),
+    /// Open a window running the scenario. The other scenarios of the registry can be
+    /// chosen from the window.
+    Window {
+        registry: ScenarioRegistry,
+        /// Name of the scenario in the registry
+        scenario: String,
+        options: ScenarioOptions,
+        steps: Option<u32>,
+    },
+}
+
+/// Run the headless commands, or tell `main` which scenario to open in a window
+pub fn execute(cli: Cli) -> Launch {
+    let mut registry = ScenarioRegistry::with_examples();
+    let result = match cli.command {
+        None => Ok(Launch::Window {
+            registry,
+            scenario: DEFAULT_SCENARIO.to_string(),
+            options: ScenarioOptions::default(),
+            steps: None,
+        }),
+        Some(Command::List) => {
+            list(&registry);
+            Ok(Launch::Exit(ExitCode::from(SUCCESS)))
+        }
+        Some(Command::Run {
+            mut scenario,
+            headless,
+        }) => register_file(&mut registry, &mut scenario)
+            .and_then(|_| run(registry, scenario, headless)),
+        Some(Command::Train { mut scenario }) => {
+            register_file(&mut registry, &mut scenario).and_then(|_| train(&registry, scenario))
+        }
+        Some(Command::Eval { mut scenario }) => {
+            register_file(&mut registry, &mut scenario).and_then(|_| eval(&registry, scenario))
+        }
+        Some(Command::Batch {
+            mut scenario,
+            sweep,
+        }) => register_file(&mut registry, &mut scenario)
+            .and_then(|_| batch(&registry, scenario, sweep)),
+        Some(Command::QTable(command)) => q_table(command),
+    };
+
+    result.unwrap_or_else(|code| Launch::Exit(ExitCode::from(code)))
+}
+
+/// Name, description and default parameters of every scenario
+fn list(registry: &ScenarioRegistry) {
+    for scenario in registry.iter() {
+        println!("{:<12} {}", scenario.name(), scenario.description());
+        let params = scenario.default_params();
+        if params.iter().next().is_some() {
+            let params: Vec<String> = params
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            println!("{:<12} --param {}", "", params.join(" --param "));
+        }
+    }
+}
+
+fn run(registry: ScenarioRegistry, args: ScenarioArgs, headless: bool) -> Result<Launch, u8> {
+    let (scenario, options) = prepare(&registry, &args, true)?;
+
+    if !headless {
+        let steps = args.steps.or(scenario.steps());
+        let scenario = scenario.name().to_string();
+        return Ok(Launch::Window {
+            registry,
+            scenario,
+            options,
+            steps,
+        });
+    }
+
+    let scheduler = simulate(scenario, &options, args.steps)?;
+    report(&scenario.metrics(&scheduler), args.output_dir.as_deref())?;
+
+    Ok(Launch::Exit(ExitCode::from(SUCCESS)))
+}
+
+fn train(registry: &ScenarioRegistry, args: ScenarioArgs) -> Result<Launch, u8> {
+    // The Q-table is created by the training if it does not exist yet
+    let (scenario, options) = prepare(registry, &args, false)?;
+    let output_dir = args.output_dir.as_deref().unwrap_or(".");
+    fs::create_dir_all(output_dir).map_err(|error| {
+        eprintln!("Cannot create the directory {}: {}", output_dir, error);
+        FILE_ERROR
+    })?;
+
+    let scheduler = simulate(scenario, &options, args.steps)?;
+    let filepaths = scheduler
+        .save_q_tables(output_dir)
+        .map_err(|error| q_table_error(output_dir, error))?;
+    for filepath in filepaths {
+        println!("Saved {}", filepath.display());
+    }
+    report(&scenario.metrics(&scheduler), Some(output_dir))?;
+
+    Ok(Launch::Exit(ExitCode::from(SUCCESS)))
+}
+
+fn eval(registry: &ScenarioRegistry, args: ScenarioArgs) -> Result<Launch, u8> {
+    let (scenario, mut options) = prepare(registry, &args, true)?;
+    // Only exploit what was learned
+    options.exploration_rate = Some(options.exploration_rate.unwrap_or(0.));
+
+    let scheduler = simulate(scenario, &options, args.steps)?;
+    report(&scenario.metrics(&scheduler), args.output_dir.as_deref())?;
+
+    Ok(Launch::Exit(ExitCode::from(SUCCESS)))
+}
+
+fn batch(registry: &ScenarioRegistry, args: ScenarioArgs, sweep: SweepArgs) -> Result<Launch, u8> {
+    let (scenario, mut options) = prepare(registry, &args, true)?;
+    let names = sweep.grid.iter().map(|(name, _)| name);
+    check_params(
+        scenario,
+        names.chain(sweep.sample.iter().map(|(name, _)| name)),
+        &RUN_PARAMS,
+    )?;
+    if let Some(steps) = args.steps {
+        options.params.set("steps", steps as f64);
+    }
+
+    let mut builder = BatchRunner::builder()
+        .samples(sweep.samples as usize)
+        .repetitions(sweep.repetitions as usize)
+        .parallel(sweep.parallel);
+    if let Some(seed) = args.seed {
+        builder = builder.seed(seed);
+    }
+    for (name, values) in sweep.grid {
+        builder = builder.grid(&name, values);
+    }
+    for (name, range) in sweep.sample {
+        builder = builder.sample(&name, range);
+    }
+    let results = builder
+        .build()
+        .run(|config| batch_run_with(scenario, &options, config));
+
+    print_means(&results);
+    let output_dir = args.output_dir.as_deref().unwrap_or(".");
+    let filepath = Path::new(output_dir).join("batch.csv");
+    fs::create_dir_all(output_dir)
+        .and_then(|_| results.write_csv(&filepath.to_string_lossy()))
+        .map_err(|error| {
+            eprintln!("Cannot write {}: {}", filepath.display(), error);
+            FILE_ERROR
+        })?;
+    println!("Saved {}", filepath.display());
+
+    let mut failed = false;
+    for (config, error) in results.errors() {
+        eprintln!(
+            "Run {} of configuration {} (seed {}): {}",
+            config.repetition, config.configuration, config.seed, error
+        );
+        failed = true;
+    }
+    if failed {
+        return Err(INVALID_Q_TABLE);
+    }
+
+    Ok(Launch::Exit(ExitCode::from(SUCCESS)))
+}
+
+/// Mean of the metrics of each configuration over its repetitions
+fn print_means(results: &BatchResults) {
+    for (configuration, (params, metrics)) in results.means().iter().enumerate() {
+        let params: Vec<String> = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        let metrics: Vec<String> = metrics
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        println!(
+            "{} [{}] {}",
+            configuration,
+            params.join(" "),
+            metrics.join(", ")
+        );
+    }
+}
+
+/// When the scenario is a scenario file, load it in the registry and use its name
+fn register_file(registry: &mut ScenarioRegistry, args: &mut ScenarioArgs) -> Result<(), u8> {
+    if !args.scenario.ends_with(".toml") {
+        return Ok(());
+    }
+
+    let scenario =
+        FileScenario::load(&args.scenario, StepRegistry::with_builtins()).map_err(|error| {
+            eprintln!("{}: {}", args.scenario, error);
+            match error {
+                ScenarioFileError::Io { .. } => FILE_ERROR,
+                _ => INVALID_SCENARIO_FILE,
+            }
+        })?;
+    args.scenario = scenario.name().to_string();
+    registry.register(scenario);
+
+    Ok(())
+}
+
+/// Find the scenario and turn the arguments into its options
+fn prepare<'a>(
+    registry: &'a ScenarioRegistry,
+    args: &ScenarioArgs,
+    q_table_must_exist: bool,
+) -> Result<(&'a dyn Scenario, ScenarioOptions), u8> {
+    let Some(scenario) = registry.get(&args.scenario) else {
+        eprintln!(
+            "Unknown scenario {:?}, expected one of: {}",
+            args.scenario,
+            registry.names().join(", ")
+        );
+        return Err(UNKNOWN_SCENARIO);
+    };
+
+    if let Some(q_table) = &args.q_table {
+        if q_table_must_exist && !Path::new(q_table).is_file() {
+            eprintln!("Q-table not found: {}", q_table);
+            return Err(FILE_ERROR);
+        }
+    }
+
+    check_params(scenario, args.params.iter().map(|(name, _)| name), &[])?;
+    let mut params = Params::new();
+    for (name, value) in &args.params {
+        params.set(name, *value);
+    }
+
+    let options = ScenarioOptions {
+        seed: args.seed,
+        agents: args.agents,
+        q_table: args.q_table.clone(),
+        exploration_rate: args.exploration_rate,
+        q_table_sync: args.sync_interval.map(|interval| QTableSync {
+            interval,
+            strategy: args.merge,
+        }),
+        params,
+        ..ScenarioOptions::default()
+    };
+
+    Ok((scenario, options))
+}
+
+/// Fail on the parameters the scenario does not read: its `default_params` and `extra`
+fn check_params<'a>(
+    scenario: &dyn Scenario,
+    names: impl Iterator<Item = &'a String>,
+    extra: &[&str],
+) -> Result<(), u8> {
+    let default_params = scenario.default_params();
+    let mut known: Vec<&str> = default_params.iter().map(|(name, _)| name).collect();
+    known.extend(extra);
+
+    for name in names {
+        if !known.contains(&name.as_str()) {
+            if known.is_empty() {
+                eprintln!("Unknown parameter {:?}, {} has none", name, scenario.name());
+            } else {
+                eprintln!(
+                    "Unknown parameter {:?} of {}, expected one of: {}",
+                    name,
+                    scenario.name(),
+                    known.join(", ")
+                );
+            }
+            return Err(INVALID_PARAM);
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the scenario headless and run it
+fn simulate(
+    scenario: &dyn Scenario,
+    options: &ScenarioOptions,
+    steps: Option<u32>,
+) -> Result<Scheduler, u8> {
+    let mut scheduler = scenario.build(options).map_err(|error| {
+        q_table_error(options.q_table.as_deref().unwrap_or(scenario.name()), error)
+    })?;
+    for _ in 0..steps.or(scenario.steps()).unwrap_or(DEFAULT_STEPS) {
+        scheduler.take_step();
+    }
+
+    Ok(scheduler)
+}
+
+/// Print the metrics, and write them in `output_dir/metrics.csv`
+fn report(metrics: &Metrics, output_dir: Option<&str>) -> Result<(), u8> {
+    let mut csv = String::from("metric,value\n");
+    for (name, value) in metrics {
+        println!("{}: {}", name, value);
+        csv += &format!("{},{}\n", name, value);
+    }
+
+    if let Some(output_dir) = output_dir {
+        let filepath = Path::new(output_dir).join("metrics.csv");
+        fs::create_dir_all(output_dir)
+            .and_then(|_| fs::write(&filepath, csv))
+            .map_err(|error| {
+                eprintln!("Cannot write {}: {}", filepath.display(), error);
+                FILE_ERROR
+            })?;
+    }
+
+    Ok(())
+}
+
+fn q_table(command: QTableCommand) -> Result<Launch, u8> {
+    match command {
+        QTableCommand::Export {
+            table,
+            output,
+            actions,
+        } => {
+            write(&read(&table)?, &output, &actions.names())?;
+            println!("Exported {}", output);
+        }
+        QTableCommand::Import { input, output } => {
+            write(&read(&input)?, &output, &ActionNames::default())?;
+            println!("Imported {}", output);
+        }
+        QTableCommand::Stats {
+            table,
+            policy,
+            actions,
+        } => {
+            let q_table = read(&table)?;
+            let names = actions.names();
+            print_stats(&QTableStats::of(&q_table), &names);
+            if policy {
+                println!();
+                for (state, (action, value)) in sorted_policy(&q_table) {
+                    println!("{}  {}  {}", state, names.name(action), value);
+                }
+            }
+        }
+        QTableCommand::Diff {
+            left,
+            right,
+            tolerance,
+            actions,
+        } => {
+            let diff = QTableDiff::between(&read(&left)?, &read(&right)?, tolerance);
+            print_diff(&diff, &actions.names());
+        }
+        QTableCommand::Merge {
+            tables,
+            output,
+            strategy,
+        } => {
+            let q_tables = tables
+                .iter()
+                .map(|table| read(table))
+                .collect::<Result<Vec<_>, _>>()?;
+            // Tables of the same scenario have states of the same layouts
+            let expected = Fingerprint {
+                actions: Vec::new(),
+                ..Fingerprint::of(&q_tables[0])
+            };
+            for (table, q_table) in tables.iter().zip(&q_tables).skip(1) {
+                Fingerprint::of(q_table)
+                    .check(&expected)
+                    .map_err(|error| q_table_error(table, error))?;
+            }
+
+            let sources: Vec<_> = q_tables.iter().map(|q_table| (q_table, None)).collect();
+            let merged = merge_q_tables(&sources, strategy);
+            write(&merged, &output, &ActionNames::default())?;
+            println!("Merged {} tables into {}", tables.len(), output);
+        }
+    }
+
+    Ok(Launch::Exit(ExitCode::from(SUCCESS)))
+}
+
+fn read(filepath: &str) -> Result<QTable, u8> {
+    read_q_table(filepath).map_err(|error| q_table_error(filepath, error))
+}
+
+fn write(q_table: &QTable, filepath: &str, names: &ActionNames) -> Result<(), u8> {
+    write_q_table(q_table, filepath, names).map_err(|error| q_table_error(filepath, error))
+}
+
+fn q_table_error(filepath: &str, error: QTableError) -> u8 {
+    eprintln!("{}: {}", filepath, error);
+    match error {
+        QTableError::Io { .. } => FILE_ERROR,
+        _ => INVALID_Q_TABLE,
+    }
+}
+
+fn print_stats(stats: &QTableStats, names: &ActionNames) {
+    println!("entries: {}", stats.entries);
+    println!("states: {}", stats.states);
+    println!(
+        "values: {} to {}, mean {}",
+        stats.min, stats.max, stats.mean
+    );
+    println!();
+    println!(
+        "{:<12} {:>8} {:>12} {:>8}",
+        "action", "entries", "mean", "greedy"
+    );
+    for (action, action_stats) in &stats.actions {
+        println!(
+            "{:<12} {:>8} {:>12.4} {:>8}",
+            names.name(*action),
+            action_stats.entries,
+            action_stats.mean,
+            action_stats.greedy
+        );
+    }
+}
+
+/// `-` for the entries of the left table only, `+` for the right one, `~` for the values
+/// that changed
+fn print_diff(diff: &QTableDiff, names: &ActionNames) {
+    let state = |state: &State| Value::VVec(state.clone()).to_string();
+
+    println!(
+        "removed: {}, added: {}, changed: {}, greedy actions changed: {}",
+        diff.removed.len(),
+        diff.added.len(),
+        diff.changed.len(),
+        diff.policy.len()
+    );
+    for (q, value) in &diff.removed {
+        println!("- {} {}: {}", state(&q.state), names.name(q.action), value);
+    }
+    for (q, value) in &diff.added {
+        println!("+ {} {}: {}", state(&q.state), names.name(q.action), value);
+    }
+    for (q, left, right) in &diff.changed {
+        println!(
+            "~ {} {}: {} -> {}",
+            state(&q.state),
+            names.name(q.action),
+            left,
+            right
+        );
+    }
+    for (policy_state, left, right) in &diff.policy {
+        println!(
+            "greedy {}: {} -> {}",
+            state(policy_state),
+            names.name(*left),
+            names.name(*right)
+        );
+    }
+}
+
+/// `name=value` of `--param`
+fn parse_param(param: &str) -> Result<(String, f64), String> {
+    let (name, value) = param
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {:?}", param))?;
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{:?} is not a number", value))?;
+
+    Ok((name.trim().to_string(), value))
+}
+
+/// `name=value,value,...` of `--grid`
+fn parse_grid(grid: &str) -> Result<(String, Vec<f64>), String> {
+    let (name, values) = grid
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE,VALUE,..., got {:?}", grid))?;
+    let values = values
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{:?} is not a number", value))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok((name.trim().to_string(), values))
+}
+
+/// `name=min..max` of `--sample`
+fn parse_sample(sample: &str) -> Result<(String, Range<f64>), String> {
+    let (name, range) = sample
+        .split_once('=')
+        .and_then(|(name, range)| Some((name, range.split_once("..")?)))
+        .ok_or_else(|| format!("expected NAME=MIN..MAX, got {:?}", sample))?;
+    let bound = |value: &str| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("{:?} is not a number", value))
+    };
+
+    Ok((name.trim().to_string(), bound(range.0)?..bound(range.1)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Option<Command> {
+        Cli::try_parse_from(["masim"].iter().chain(args))
+            .unwrap()
+            .command
+    }
+
+    /// Exit code of a command line
+    fn exit_code(args: &[&str]) -> ExitCode {
+        let cli = Cli::try_parse_from(["masim"].iter().chain(args)).unwrap();
+        match execute(cli) {
+            Launch::Exit(code) => code,
+            Launch::Window { .. } => panic!("{:?} opened a window", args),
+        }
+    }
+
+    #[test]
+    fn subcommands() {
+        assert!(parse(&[]).is_none());
+        assert!(matches!(parse(&["list"]), Some(Command::List)));
+
+        let Some(Command::Run { scenario, headless }) = parse(&[
+            "run",
+            "runner",
+            "--headless",
+            "--seed",
+            "4",
+            "--param",
+            "size=8",
+            "--sync-interval",
+            "10",
+            "--merge",
+            "max",
+        ]) else {
+            panic!("expected run");
+        };
+        assert!(headless);
+        assert_eq!(scenario.scenario, "runner");
+        assert_eq!(scenario.seed, Some(4));
+        assert_eq!(scenario.params, vec![("size".to_string(), 8.)]);
+        assert_eq!(scenario.sync_interval, Some(10));
+        assert_eq!(scenario.merge, MergeStrategy::Max);
+
+        let Some(Command::Train { scenario }) =
+            parse(&["train", "runner", "--steps", "50", "--output-dir", "out"])
+        else {
+            panic!("expected train");
+        };
+        assert_eq!(scenario.steps, Some(50));
+        assert_eq!(scenario.output_dir.as_deref(), Some("out"));
+
+        let Some(Command::Eval { scenario }) =
+            parse(&["eval", "runner", "--q-table", "runner.bin"])
+        else {
+            panic!("expected eval");
+        };
+        assert_eq!(scenario.q_table.as_deref(), Some("runner.bin"));
+
+        let Some(Command::Batch { sweep, .. }) = parse(&[
+            "batch",
+            "runner",
+            "--grid",
+            "size=8,16",
+            "--sample",
+            "exploration_rate=0..0.5",
+            "--samples",
+            "3",
+            "--repetitions",
+            "2",
+            "--parallel",
+        ]) else {
+            panic!("expected batch");
+        };
+        assert_eq!(sweep.grid, vec![("size".to_string(), vec![8., 16.])]);
+        assert_eq!(
+            sweep.sample,
+            vec![("exploration_rate".to_string(), 0.0..0.5)]
+        );
+        assert_eq!((sweep.samples, sweep.repetitions), (3, 2));
+        assert!(sweep.parallel);
+
+        assert!(matches!(
+            parse(&["q-table", "export", "a.bin", "a.csv", "--moves", "four"]),
+            Some(Command::QTable(QTableCommand::Export { actions, .. }))
+                if actions.moves == Some(Moves::Four)
+        ));
+        assert!(matches!(
+            parse(&["q-table", "import", "a.csv", "a.bin"]),
+            Some(Command::QTable(QTableCommand::Import { .. }))
+        ));
+        assert!(matches!(
+            parse(&["q-table", "stats", "a.bin", "--policy", "--actions", "up,down"]),
+            Some(Command::QTable(QTableCommand::Stats { policy: true, actions, .. }))
+                if actions.actions == ["up", "down"]
+        ));
+        assert!(matches!(
+            parse(&["q-table", "diff", "a.bin", "b.bin", "--tolerance", "0.1"]),
+            Some(Command::QTable(QTableCommand::Diff { tolerance, .. })) if tolerance == 0.1
+        ));
+        assert!(matches!(
+            parse(&["q-table", "merge", "a.bin", "b.bin", "--output", "c.bin"]),
+            Some(Command::QTable(QTableCommand::Merge { tables, .. })) if tables.len() == 2
+        ));
+
+        // Invalid arguments are left to clap, which exits with 2
+        for args in [
+            &["run", "runner", "--param", "size"][..],
+            &["run", "runner", "--merge", "max"],
+            &["batch", "runner", "--grid", "size=8,big"],
+            &["batch", "runner", "--sample", "size=8"],
+            &["q-table", "merge", "a.bin"],
+        ] {
+            let error = Cli::try_parse_from(["masim"].iter().chain(args)).unwrap_err();
+            assert_eq!(error.exit_code(), 2, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(&["list"]), SUCCESS.into());
+        let run = ["run", "runner", "--headless", "--steps", "5", "--seed", "1"];
+        assert_eq!(exit_code(&run), SUCCESS.into());
+
+        assert_eq!(
+            exit_code(&["run", "nowhere", "--headless"]),
+            UNKNOWN_SCENARIO.into()
+        );
+        assert_eq!(exit_code(&["batch", "nowhere"]), UNKNOWN_SCENARIO.into());
+
+        assert_eq!(
+            exit_code(&[&run[..], &["--param", "sise=8"]].concat()),
+            INVALID_PARAM.into()
+        );
+        // Run options are swept by `batch` but are not parameters of the scenario
+        assert_eq!(
+            exit_code(&[&run[..], &["--param", "agents=3"]].concat()),
+            INVALID_PARAM.into()
+        );
+        assert_eq!(
+            exit_code(&["batch", "runner", "--grid", "sise=8,16"]),
+            INVALID_PARAM.into()
+        );
+
+        let directory = std::env::temp_dir().join("masim_cli_exit_codes");
+        fs::create_dir_all(&directory).unwrap();
+        let not_a_q_table = directory.join("runner.bin");
+        fs::write(&not_a_q_table, "not a Q-table").unwrap();
+        let not_a_q_table = not_a_q_table.to_string_lossy();
+        let missing = directory.join("missing.bin");
+        let missing = missing.to_string_lossy();
+        let output = directory.join("runner.csv");
+        let output = output.to_string_lossy();
+
+        assert_eq!(
+            exit_code(&[&run[..], &["--q-table", &not_a_q_table]].concat()),
+            INVALID_Q_TABLE.into()
+        );
+        assert_eq!(
+            exit_code(&["q-table", "stats", &not_a_q_table]),
+            INVALID_Q_TABLE.into()
+        );
+        assert_eq!(
+            exit_code(&["q-table", "export", &not_a_q_table, &output]),
+            INVALID_Q_TABLE.into()
+        );
+        assert_eq!(
+            exit_code(&[&run[..], &["--q-table", &missing]].concat()),
+            FILE_ERROR.into()
+        );
+        assert_eq!(
+            exit_code(&["q-table", "stats", &missing]),
+            FILE_ERROR.into()
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn batch() {
+        let directory = std::env::temp_dir().join("masim_cli_batch");
+        let output_dir = directory.to_string_lossy().into_owned();
+        let q_table = directory.join("robot_explorer.bin");
+        let q_table = q_table.to_string_lossy();
+
+        let args = ["--seed", "3", "--agents", "2", "--steps", "10"];
+        let train = [
+            "train",
+            "mining_bot",
+            "--param",
+            "fov=2",
+            "--output-dir",
+            &output_dir,
+        ];
+        let train: Vec<&str> = train.iter().chain(&args).copied().collect();
+        assert_eq!(exit_code(&train), SUCCESS.into());
+
+        // The runs of another field of view cannot load the Q-table, the others go on
+        let batch = [
+            "batch",
+            "mining_bot",
+            "--grid",
+            "fov=2,3",
+            "--repetitions",
+            "2",
+            "--parallel",
+            "--q-table",
+            &q_table,
+            "--output-dir",
+            &output_dir,
+        ];
+        let batch: Vec<&str> = batch.iter().chain(&args).copied().collect();
+        assert_eq!(exit_code(&batch), INVALID_Q_TABLE.into());
+
+        let csv = fs::read_to_string(directory.join("batch.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("configuration,repetition,seed,fov,"));
+        assert!(lines[0].ends_with(",error"));
+        assert!(lines[1].starts_with("0,0,3,2,") && lines[1].ends_with(','));
+        assert!(lines[4].starts_with("1,1,4,3,,"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
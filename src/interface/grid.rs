@@ -6,8 +6,12 @@ use macroquad::{
     shapes::{draw_circle, draw_line, draw_rectangle},
 };
 
-use crate::scheduler::scheduler::Position;
+use crate::{
+    environment::{field::Field, resource::ResourceLayer},
+    scheduler::scheduler::Position,
+};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct GridSize {
     pub width: usize,
     pub heigth: usize,
@@ -110,7 +114,12 @@ impl Grid {
     ///
     /// **persistent_elements:** Element with persistent long term position such as obstacles (walls, bushes, etc.), the goal cell, etc.
     ///
+    /// **fields:** scalar layers drawn as heatmaps under the persistent elements
+    ///
+    /// **resources:** resource layers drawn as heatmaps of how full the cells are
+    ///
     /// NOTE: Some line appear thicker from time to time
+    #[allow(clippy::too_many_arguments)]
     pub fn display(
         &mut self,
         start: Vec2,
@@ -118,6 +127,8 @@ impl Grid {
         grid_color: Color,
         agent_positions: Vec<(IVec2, Color)>,
         persistent_elements: &HashMap<Position, Color>,
+        fields: &HashMap<u32, Field>,
+        resources: &HashMap<u32, ResourceLayer>,
     ) {
         // IF ORIGIN DIFFERENT UPDATE LINES
         if !self.start.eq(&start) || !self.end.eq(&end) {
@@ -140,6 +151,38 @@ impl Grid {
             (y_end - y_start) / self.size.heigth as f32,
         );
 
+        // Draw fields and resources as heatmaps
+        let mut heatmaps: Vec<(Color, Vec<(Position, f32)>)> = Vec::new();
+        for field in fields.values() {
+            if let Some(color) = field.color {
+                let cells = field
+                    .non_zero()
+                    .map(|(position, value)| (position, value / field.display_max));
+                heatmaps.push((color, cells.collect()));
+            }
+        }
+        for resource in resources.values() {
+            if let Some(color) = resource.color {
+                heatmaps.push((color, resource.fill_ratios().collect()));
+            }
+        }
+
+        for (color, cells) in heatmaps {
+            for (IVec2 { x, y }, intensity) in cells {
+                let intensity = intensity.clamp(0., 1.);
+                draw_rectangle(
+                    x_start + (x as f32 * cell_width),
+                    y_start + (y as f32 * cell_heigth),
+                    cell_width,
+                    cell_heigth,
+                    Color {
+                        a: color.a * intensity,
+                        ..color
+                    },
+                );
+            }
+        }
+
         // Draw persitent elements
         for (position, color) in persistent_elements {
             let IVec2 { x, y } = position;
@@ -4,13 +4,14 @@ use crate::interface::context::CAM_SPEED;
 
 use super::{context::Context, settings::Settings};
 
-pub const KEY_MAPPINGS: [(&str, &str); 10] = [
+pub const KEY_MAPPINGS: [(&str, &str); 11] = [
     ("[arrows][W/A/S/D]", "Control the camera"),
     ("[LeftClick + mouse mouvement]", "Control the camera"),
     ("[R]", "Reset Camera"),
     ("[Escape]", "Open/Close settings"),
     ("[Q]", "Quit the application"),
     ("[K]", "Open the keymapping"),
+    ("[P]", "Switch or restart the scenario"),
     ("[B]", "Toggle the debug output"),
     ("[T]", "Switch theme"),
     ("[Mousewheel UP]", "Zoom"),
@@ -55,6 +56,7 @@ pub fn apply_input(ctx: &mut Context, settings: &mut Settings) {
     { // Settings related //
     if is_key_pressed(KeyCode::Escape) { settings.toggle_display_settings(); }
     if is_key_pressed(KeyCode::K)      { settings.toggle_display_keymapping(); }
+    if is_key_pressed(KeyCode::P)      { settings.toggle_display_scenarios(); }
     if is_key_pressed(KeyCode::B)      { settings.toggle_debug(); }
     if is_key_pressed(KeyCode::T)      { settings.switch_theme(); }
     }
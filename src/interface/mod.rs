@@ -1,3 +1,4 @@
+pub mod cli;
 pub mod context;
 pub mod grid;
 pub mod keymapping;
@@ -12,6 +12,7 @@ use super::ui::default_skin;
 pub struct Settings {
     pub display_settings: bool,
     pub display_keymapping: bool,
+    pub display_scenarios: bool,
     pub dark_theme: bool,
     pub debug: bool,
     pub skin: HashMap<String, Skin>,
@@ -25,6 +26,7 @@ impl Settings {
         SettingsBuilder {
             display_settings: None,
             display_keymapping: None,
+            display_scenarios: None,
             dark_theme: None,
             debug: None,
             skin: None,
@@ -47,6 +49,10 @@ impl Settings {
         self.display_keymapping = !self.display_keymapping;
     }
 
+    pub fn toggle_display_scenarios(&mut self) {
+        self.display_scenarios = !self.display_scenarios;
+    }
+
     pub fn switch_theme(&mut self) {
         self.dark_theme = !self.dark_theme;
     }
@@ -69,6 +75,7 @@ impl Settings {
 pub struct SettingsBuilder {
     display_settings: Option<bool>,
     display_keymapping: Option<bool>,
+    display_scenarios: Option<bool>,
     dark_theme: Option<bool>,
     debug: Option<bool>,
     skin: Option<HashMap<String, Skin>>,
@@ -89,6 +96,11 @@ impl SettingsBuilder {
         self
     }
 
+    pub fn display_scenarios(mut self, display: bool) -> Self {
+        self.display_scenarios = Some(display);
+        self
+    }
+
     pub fn dark_theme(mut self, dark_theme: bool) -> Self {
         self.dark_theme = Some(dark_theme);
         self
@@ -129,6 +141,7 @@ impl SettingsBuilder {
         Settings {
             display_settings: self.display_settings.unwrap_or(false),
             display_keymapping: self.display_keymapping.unwrap_or(false),
+            display_scenarios: self.display_scenarios.unwrap_or(false),
             dark_theme: self.dark_theme.unwrap_or(false),
             debug: self.debug.unwrap_or(false),
             skin: self.skin.unwrap_or(HashMap::from([(
@@ -5,6 +5,8 @@ use macroquad::ui::{
     Skin,
 };
 
+use crate::scenario::scenario::ScenarioRegistry;
+
 use super::context::Context;
 use super::keymapping::KEY_MAPPINGS;
 use super::settings::Settings;
@@ -40,14 +42,14 @@ pub async fn default_skin() -> Skin {
 }
 
 pub fn show_settings(settings: &mut Settings) {
-    let (_, skin) = settings.skin.get_key_value(&"Default".to_string()).unwrap();
+    let (_, skin) = settings.skin.get_key_value("Default").unwrap();
     root_ui().push_skin(skin);
     settings.refresh_position();
 
     widgets::Window::new(hash!(), settings.position, settings.window_size)
         .label("Settings")
         .titlebar(true)
-        .ui(&mut *root_ui(), |ui| {
+        .ui(&mut root_ui(), |ui| {
             ui.checkbox(hash!(), "Dark theme", &mut settings.dark_theme);
             ui.checkbox(hash!(), "Debug mode", &mut settings.debug);
 
@@ -125,17 +127,14 @@ pub async fn keymappings_skin() -> Skin {
 
 pub fn show_keymapping(settings: &mut Settings) {
     settings.refresh_position();
-    let (_, skin) = settings
-        .skin
-        .get_key_value(&"Keymapping".to_string())
-        .unwrap();
+    let (_, skin) = settings.skin.get_key_value("Keymapping").unwrap();
 
     let mut close_clicked = false;
 
     widgets::Window::new(hash!(), settings.position, settings.window_size)
         .label("Keymappings")
         .titlebar(true)
-        .ui(&mut *root_ui(), |ui| {
+        .ui(&mut root_ui(), |ui| {
             ui.push_skin(skin);
             for (key, description) in KEY_MAPPINGS {
                 ui.separator();
@@ -162,3 +161,58 @@ pub fn show_keymapping(settings: &mut Settings) {
         settings.toggle_display_keymapping();
     }
 }
+
+/// What was picked in the scenario window
+pub enum ScenarioChoice {
+    /// Build the scenario with this name
+    Switch(String),
+    /// Start a new episode of the current scenario (see `Scenario::reset`)
+    Restart,
+}
+
+/// Lists the scenarios of the registry, the current one marked with `>`.
+/// The window closes once something is picked.
+pub fn show_scenarios(
+    settings: &mut Settings,
+    registry: &ScenarioRegistry,
+    current: &str,
+) -> Option<ScenarioChoice> {
+    let (_, skin) = settings.skin.get_key_value("Default").unwrap();
+    root_ui().push_skin(skin);
+    // Wider than the other windows to fit the descriptions
+    let window_size = vec2(480., 60. + 50. * registry.len() as f32);
+    let position = vec2(screen_width(), screen_height()) / 2. - window_size / 2.;
+
+    let mut choice = None;
+    let mut close_clicked = false;
+
+    widgets::Window::new(hash!(), position, window_size)
+        .label("Scenarios")
+        .titlebar(true)
+        .ui(&mut root_ui(), |ui| {
+            for scenario in registry.iter() {
+                let marker = if scenario.name() == current { ">" } else { " " };
+                if ui.button(None, format!("{} {}", marker, scenario.name())) {
+                    choice = Some(ScenarioChoice::Switch(scenario.name().to_string()));
+                }
+                ui.label(None, scenario.description());
+                ui.separator();
+            }
+
+            if ui.button(None, "Restart") {
+                choice = Some(ScenarioChoice::Restart);
+            }
+            ui.same_line(0.);
+            // Exit button
+            if ui.button(None, "Close") {
+                close_clicked = true;
+            }
+        });
+    root_ui().pop_skin();
+
+    if close_clicked || choice.is_some() {
+        settings.toggle_display_scenarios();
+    }
+
+    choice
+}
@@ -1,22 +1,51 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, process::ExitCode};
 
-use examples::{mining_bot, runner};
+use clap::Parser;
 use interface::{
+    cli::{execute, Cli, Launch},
     context::Context,
     keymapping::apply_input,
     settings::Settings,
-    ui::{default_skin, keymappings_skin, show_debug_info, show_keymapping, show_settings},
+    ui::{
+        default_skin, keymappings_skin, show_debug_info, show_keymapping, show_scenarios,
+        show_settings, ScenarioChoice,
+    },
 };
-use macroquad::{prelude::*, ui::root_ui};
+use macroquad::{prelude::*, ui::root_ui, Window};
+use scenario::scenario::{ScenarioOptions, ScenarioRegistry};
 
 pub mod agent;
+pub mod batch;
 pub mod environment;
 pub mod examples;
 pub mod interface;
+pub mod scenario;
 pub mod scheduler;
+pub mod script;
+
+fn main() -> ExitCode {
+    match execute(Cli::parse()) {
+        Launch::Exit(code) => code,
+        Launch::Window {
+            registry,
+            scenario,
+            options,
+            steps,
+        } => {
+            Window::new("MASim", gui(registry, scenario, options, steps));
+            ExitCode::SUCCESS
+        }
+    }
+}
 
-#[macroquad::main("MASim")]
-async fn main() {
+/// Display the scenario, stepping it once S is pressed, at most `steps` times.
+/// The scenario window (P) switches to another scenario of the registry or restarts this one.
+async fn gui(
+    registry: ScenarioRegistry,
+    mut scenario: String,
+    options: ScenarioOptions,
+    steps: Option<u32>,
+) {
     let mut settings = Settings::builder()
         .skin(HashMap::from([
             ("Default".to_string(), default_skin().await),
@@ -31,8 +60,34 @@ async fn main() {
         camera,
     };
 
-    // let mut scheduler = runner::main();
-    let mut scheduler = mining_bot::main();
+    let options = ScenarioOptions {
+        start: vec2(screen_width() * 0.1, screen_height() * 0.1),
+        end: vec2(screen_width() * 0.9, screen_height() * 0.9),
+        ..options
+    };
+    // The options of the command line are only meant for the scenario they were given with
+    let other_options = ScenarioOptions {
+        start: options.start,
+        end: options.end,
+        seed: options.seed,
+        ..ScenarioOptions::default()
+    };
+    let launched = scenario.clone();
+    let options_of = |name: &str| {
+        if name == launched {
+            &options
+        } else {
+            &other_options
+        }
+    };
+
+    let mut scheduler = match registry.get(&scenario).unwrap().build(&options) {
+        Ok(scheduler) => scheduler,
+        Err(error) => {
+            eprintln!("Cannot build {}: {}", scenario, error);
+            return;
+        }
+    };
 
     let mut start_sim = false;
     loop {
@@ -47,7 +102,7 @@ async fn main() {
         if is_key_pressed(KeyCode::S) {
             start_sim = true;
         }
-        if start_sim {
+        if start_sim && steps.is_none_or(|steps| scheduler.env.tick < steps as u64) {
             scheduler.take_step();
         }
 
@@ -70,7 +125,7 @@ async fn main() {
         // println!("screen_heigth: {}", screen_height())
 
         // Buttons
-        let (_, skin) = settings.skin.get_key_value(&"Default".to_string()).unwrap();
+        let (_, skin) = settings.skin.get_key_value("Default").unwrap();
         root_ui().push_skin(skin);
         if root_ui().button(vec2(screen_width() - 80., 20.), "Settings  ") {
             settings.toggle_display_settings();
@@ -80,6 +135,10 @@ async fn main() {
             settings.toggle_display_keymapping();
         }
 
+        if root_ui().button(vec2(screen_width() - 80., 60.), "Scenarios ") {
+            settings.toggle_display_scenarios();
+        }
+
         root_ui().pop_skin();
 
         #[cfg_attr(any(), rustfmt::skip)]
@@ -89,6 +148,32 @@ async fn main() {
         if settings.debug { show_debug_info(&ctx, &settings); }
         }
 
+        if settings.display_scenarios {
+            match show_scenarios(&mut settings, &registry, &scenario) {
+                // On failure the current scenario keeps running
+                Some(ScenarioChoice::Switch(name)) => {
+                    match registry.get(&name).unwrap().build(options_of(&name)) {
+                        Ok(new_scheduler) => {
+                            scheduler = new_scheduler;
+                            scenario = name;
+                            start_sim = false;
+                        }
+                        Err(error) => eprintln!("Cannot build {}: {}", name, error),
+                    }
+                }
+                Some(ScenarioChoice::Restart) => {
+                    if let Err(error) = registry
+                        .get(&scenario)
+                        .unwrap()
+                        .reset(&mut scheduler, options_of(&scenario))
+                    {
+                        eprintln!("Cannot restart {}: {}", scenario, error);
+                    }
+                }
+                None => {}
+            }
+        }
+
         next_frame().await
     }
 }
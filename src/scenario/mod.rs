@@ -0,0 +1,4 @@
+#[allow(clippy::module_inception)]
+pub mod scenario;
+pub mod scenario_file;
+pub mod steps;
@@ -0,0 +1,356 @@
+use macroquad::math::Vec2;
+
+use crate::{
+    agent::{merge::QTableSync, q_table::QTableError},
+    batch::batch::{Metrics, Params, RunConfig},
+    examples,
+    scheduler::scheduler::Scheduler,
+};
+
+/// Settings a scenario is built with. Every `None` is the default of the scenario.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScenarioOptions {
+    /// Top left corner of the grid on the screen
+    pub start: Vec2,
+    /// Bottom right corner of the grid on the screen
+    pub end: Vec2,
+    /// Seed of the generation of the world and of every random draw of the run (see
+    /// `Env::set_seed`)
+    pub seed: Option<u64>,
+    /// Number of agents of each type
+    pub agents: Option<usize>,
+    /// Q-table file loaded by the agents
+    pub q_table: Option<String>,
+    pub learning_rate: Option<f32>,
+    pub discount_factor: Option<f32>,
+    pub exploration_rate: Option<f32>,
+    /// Merging of the Q-tables of the learning agents (see `Scheduler::q_table_sync`)
+    pub q_table_sync: Option<QTableSync>,
+    /// Parameters of the scenario replacing its `Scenario::default_params`
+    pub params: Params,
+}
+
+impl Default for ScenarioOptions {
+    /// Headless: the grid is not meant to be displayed
+    fn default() -> Self {
+        ScenarioOptions {
+            start: Vec2::ZERO,
+            end: Vec2::ONE,
+            seed: None,
+            agents: None,
+            q_table: None,
+            learning_rate: None,
+            discount_factor: None,
+            exploration_rate: None,
+            q_table_sync: None,
+            params: Params::new(),
+        }
+    }
+}
+
+/// Parameters of a batch run read by every scenario, besides its `default_params`
+pub const RUN_PARAMS: [&str; 5] = [
+    "steps",
+    "agents",
+    "learning_rate",
+    "discount_factor",
+    "exploration_rate",
+];
+
+impl ScenarioOptions {
+    /// Options of a batch run: `agents`, `learning_rate`, `discount_factor` and
+    /// `exploration_rate` are read from the parameters, the seed is the seed of the run.
+    /// Every parameter is also passed to the scenario.
+    pub fn from_run_config(config: &RunConfig) -> Self {
+        ScenarioOptions::default().with_run_config(config)
+    }
+
+    /// These options with the seed and the parameters of a batch run, see `from_run_config`.
+    /// The parameters of the run replace the ones already set.
+    pub fn with_run_config(self, config: &RunConfig) -> Self {
+        let params = &config.params;
+        let float = |name, value: Option<f32>| params.get(name).map(|v| v as f32).or(value);
+        let mut options = ScenarioOptions {
+            seed: Some(config.seed),
+            agents: params
+                .get("agents")
+                .map(|agents| agents as usize)
+                .or(self.agents),
+            learning_rate: float("learning_rate", self.learning_rate),
+            discount_factor: float("discount_factor", self.discount_factor),
+            exploration_rate: float("exploration_rate", self.exploration_rate),
+            ..self
+        };
+        options.params.extend(params);
+        options
+    }
+}
+
+/// A simulation that can be chosen by name, from the command line or the UI.
+///
+/// ## Example
+/// ```rust
+/// pub struct Runner;
+///
+/// impl Scenario for Runner {
+///     fn name(&self) -> &'static str {
+///         "runner"
+///     }
+///
+///     fn description(&self) -> &'static str {
+///         "Runners chasing a goal"
+///     }
+///
+///     fn default_params(&self) -> Params {
+///         Params::from([("size", 16.)])
+///     }
+///
+///     fn build(&self, options: &ScenarioOptions) -> Result<Scheduler, QTableError> {
+///         let size = self.params(options).get_or("size", 16.) as usize;
+///         ...
+///         Ok(scheduler)
+///     }
+/// }
+///
+/// registry.register(Runner);
+/// ```
+pub trait Scenario: Send + Sync {
+    /// Unique name, used to choose the scenario
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Parameters read by `build` and their default value
+    fn default_params(&self) -> Params {
+        Params::new()
+    }
+
+    /// Fails when the Q-table of the options cannot be loaded
+    fn build(&self, options: &ScenarioOptions) -> Result<Scheduler, QTableError>;
+
+    /// Put the world back in its initial state for a new episode. By default the scenario
+    /// is built again, so the agents forget what they learned unless they load a Q-table.
+    fn reset(
+        &self,
+        scheduler: &mut Scheduler,
+        options: &ScenarioOptions,
+    ) -> Result<(), QTableError> {
+        *scheduler = self.build(options)?;
+        Ok(())
+    }
+
+    /// Number of steps of a headless run, `None` for the default of the command line
+    fn steps(&self) -> Option<u32> {
+        None
+    }
+
+    /// Measures of the simulation so far, for evaluations and batch runs
+    fn metrics(&self, _scheduler: &Scheduler) -> Metrics {
+        Metrics::new()
+    }
+
+    /// `default_params` replaced by the parameters of the options
+    fn params(&self, options: &ScenarioOptions) -> Params {
+        let mut params = self.default_params();
+        params.extend(&options.params);
+        params
+    }
+}
+
+/// Scenarios by name, in the order they were registered
+#[derive(Default)]
+pub struct ScenarioRegistry {
+    scenarios: Vec<Box<dyn Scenario>>,
+}
+
+impl ScenarioRegistry {
+    pub fn new() -> Self {
+        ScenarioRegistry::default()
+    }
+
+    /// Registry with every scenario of `examples`
+    pub fn with_examples() -> Self {
+        let mut registry = ScenarioRegistry::new();
+        examples::register(&mut registry);
+        registry
+    }
+
+    /// Add a scenario, replacing the one with the same name
+    pub fn register(&mut self, scenario: impl Scenario + 'static) {
+        self.scenarios
+            .retain(|registered| registered.name() != scenario.name());
+        self.scenarios.push(Box::new(scenario));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Scenario> {
+        self.iter().find(|scenario| scenario.name() == name)
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.iter().map(|scenario| scenario.name()).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Scenario> {
+        self.scenarios.iter().map(|scenario| scenario.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.scenarios.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scenarios.is_empty()
+    }
+}
+
+/// Build the scenario headless and run it for `steps` ticks (parameter `steps`, default
+/// `Scenario::steps` or 1000), for `BatchRunner::run`. Fails when the scenario cannot be
+/// built, which `BatchRunner::run` records for this run only.
+pub fn batch_run(scenario: &dyn Scenario, config: &RunConfig) -> Result<Metrics, QTableError> {
+    batch_run_with(scenario, &ScenarioOptions::default(), config)
+}
+
+/// `batch_run` on top of `options`, e.g. to load a Q-table in every run
+pub fn batch_run_with(
+    scenario: &dyn Scenario,
+    options: &ScenarioOptions,
+    config: &RunConfig,
+) -> Result<Metrics, QTableError> {
+    let options = options.clone().with_run_config(config);
+    let mut scheduler = scenario.build(&options)?;
+    let steps = scenario.steps().unwrap_or(1000) as f64;
+    for _ in 0..options.params.get_or("steps", steps) as u32 {
+        scheduler.take_step();
+    }
+
+    Ok(scenario.metrics(&scheduler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Empty(&'static str);
+
+    impl Scenario for Empty {
+        fn name(&self) -> &'static str {
+            "empty"
+        }
+
+        fn description(&self) -> &'static str {
+            self.0
+        }
+
+        fn default_params(&self) -> Params {
+            Params::from([("size", 16.), ("speed", 1.)])
+        }
+
+        fn build(&self, _options: &ScenarioOptions) -> Result<Scheduler, QTableError> {
+            examples::runner::Runner.build(&ScenarioOptions::default())
+        }
+    }
+
+    #[test]
+    fn registry() {
+        let mut registry = ScenarioRegistry::with_examples();
+        assert_eq!(registry.names(), ["runner", "mining_bot", "flocking"]);
+        assert!(registry.get("unknown").is_none());
+
+        registry.register(Empty("first"));
+        registry.register(Empty("second"));
+        assert_eq!(registry.len(), 4);
+        assert_eq!(registry.get("empty").unwrap().description(), "second");
+
+        let options = ScenarioOptions {
+            params: Params::from([("size", 8.)]),
+            ..ScenarioOptions::default()
+        };
+        let params = registry.get("empty").unwrap().params(&options);
+        assert_eq!(params, Params::from([("size", 8.), ("speed", 1.)]));
+    }
+
+    #[test]
+    fn reset_keeps_the_agents() {
+        let runner = examples::runner::Runner;
+        let options = ScenarioOptions {
+            agents: Some(3),
+            params: Params::from([("size", 8.)]),
+            ..ScenarioOptions::default()
+        };
+        let mut scheduler = runner.build(&options).unwrap();
+        assert_eq!(*scheduler.env.get_width(), 8);
+        for _ in 0..20 {
+            scheduler.take_step();
+        }
+
+        let agent = scheduler.agents[0].2.clone();
+        runner.reset(&mut scheduler, &options).unwrap();
+        assert_eq!(scheduler.env.tick, 0);
+        assert_eq!(runner.metrics(&scheduler)["goals_reached"], 0.);
+        assert_eq!(scheduler.agents.len(), 3);
+        assert!(std::rc::Rc::ptr_eq(&scheduler.agents[0].2, &agent));
+    }
+
+    #[test]
+    fn q_tables_of_another_field_of_view() {
+        let directory = std::env::temp_dir().join("masim_scenario_fov");
+        std::fs::create_dir_all(&directory).unwrap();
+        let filepath = directory.join("robot_explorer.bin");
+
+        let mining_bot = examples::mining_bot::MiningBot;
+        let options = |fov: f64, q_table: Option<String>| ScenarioOptions {
+            seed: Some(1),
+            agents: Some(2),
+            q_table,
+            params: Params::from([("fov", fov)]),
+            ..ScenarioOptions::default()
+        };
+        let mut scheduler = mining_bot.build(&options(2., None)).unwrap();
+        for _ in 0..10 {
+            scheduler.take_step();
+        }
+        scheduler
+            .save_q_tables(&directory.to_string_lossy())
+            .unwrap();
+
+        let q_table = Some(filepath.to_string_lossy().into_owned());
+        assert!(mining_bot.build(&options(2., q_table.clone())).is_ok());
+        assert!(matches!(
+            mining_bot.build(&options(3., q_table)),
+            Err(QTableError::Incompatible { .. })
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn seeded_batch_runs_repeat() {
+        let config = |seed| RunConfig {
+            configuration: 0,
+            repetition: 0,
+            seed,
+            params: Params::from([
+                ("agents", 5.),
+                ("size", 8.),
+                ("steps", 300.),
+                ("exploration_rate", 0.3),
+                ("planning_steps", 3.),
+            ]),
+        };
+
+        let runner = examples::runner::Runner;
+        let metrics = batch_run(&runner, &config(11)).unwrap();
+        assert!(metrics["goals_reached"] > 0.);
+        assert_eq!(batch_run(&runner, &config(11)).unwrap(), metrics);
+
+        let mining_bot = examples::mining_bot::MiningBot;
+        let config = RunConfig {
+            params: Params::from([("agents", 3.), ("steps", 100.)]),
+            ..config(5)
+        };
+        assert_eq!(
+            batch_run(&mining_bot, &config).unwrap(),
+            batch_run(&mining_bot, &config).unwrap()
+        );
+    }
+}
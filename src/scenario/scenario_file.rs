@@ -0,0 +1,1318 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::Path,
+    rc::Rc,
+};
+
+use clap::ValueEnum;
+use macroquad::color::{self, Color};
+use serde::Deserialize;
+
+use crate::{
+    agent::{dyna::DynaConfig, merge::QTableSync, q_table::QTableError, replay::ReplayConfig},
+    batch::batch::Metrics,
+    environment::{
+        ascii_map::{AsciiMap, Legend, GOALS},
+        environment::Env,
+        generation::{cellular_caves, maze_backtracker, mineral_blobs, rooms_and_corridors},
+        movement::MovementSet,
+        pathfinding::Connectivity,
+        resource::Regrowth,
+        topology::Topology,
+    },
+    interface::grid::GridSize,
+    scheduler::scheduler::{Position, Scheduler},
+    script::script::{Script, SCRIPT_ERRORS},
+};
+
+use super::{
+    scenario::{Scenario, ScenarioOptions},
+    steps::{StepRegistry, GOALS_REACHED, GOAL_FLOWS, MOVEMENTS, TOTAL_REWARD},
+};
+
+/// A scenario described in a TOML file instead of Rust.
+///
+/// Only `name` and `agents` are required. Every step function and reward is chosen by name
+/// in a `StepRegistry`, or written in a Rhai script (see `Script`).
+///
+/// ## Example
+/// ```toml
+/// name = "warehouse"
+/// description = "Pickers learning their way to the loading bay"
+///
+/// [grid]
+/// topology = "bounded"   # or "toroidal"
+/// moves = "four"         # four, four_stay, eight, eight_stay, hex or hex_stay
+///
+/// [map]
+/// source = "ascii"       # empty, ascii, caves, maze, rooms or blobs
+/// path = "../maps/warehouse.txt"
+///
+/// [[fields]]
+/// name = "visits"
+/// deposit = 1.0          # left by every agent on its cell each tick
+/// color = "skyblue"      # heatmap, fully opaque at `display_max`
+/// display_max = 20.0
+///
+/// [[agents]]
+/// type = "picker"
+/// count = 8
+/// color = "orange"
+/// spawn = "spawn_points" # anywhere, spawn_points or a cell [x, y]
+/// step = "goal_seeker"
+/// reward = "goal"
+/// exploration_rate = 0.1
+///
+/// [[agents]]
+/// type = "cleaner"
+/// count = 2
+/// script = "cleaner.rhai" # defines `step`, or `reward` for the step function of `step`
+///
+/// [run]
+/// steps = 2000
+/// metrics = ["goals_reached", "total_reward"]
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioFile {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub grid: GridSpec,
+    #[serde(default)]
+    pub map: MapSpec,
+    #[serde(default)]
+    pub fields: Vec<FieldSpec>,
+    #[serde(default)]
+    pub resources: Vec<ResourceSpec>,
+    pub agents: Vec<AgentSpec>,
+    /// Rhai script defining `fn update(env, positions)`, called each tick after the agents
+    pub script: Option<String>,
+    #[serde(default)]
+    pub run: RunSpec,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GridSpec {
+    /// Required unless the map is an ASCII file, which gives the size
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    #[serde(default)]
+    pub topology: Topology,
+    #[serde(default)]
+    pub moves: Moves,
+}
+
+/// Movement set of the agents (see `MovementSet`)
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Moves {
+    #[default]
+    Four,
+    FourStay,
+    Eight,
+    EightStay,
+    Hex,
+    HexStay,
+}
+
+impl Moves {
+    /// Connectivity of the paths, none for hexagonal moves
+    pub fn connectivity(self) -> Option<Connectivity> {
+        match self {
+            Moves::Four | Moves::FourStay => Some(Connectivity::Four),
+            Moves::Eight | Moves::EightStay => Some(Connectivity::Eight),
+            Moves::Hex | Moves::HexStay => None,
+        }
+    }
+
+    pub fn movement_set(self) -> MovementSet {
+        match self {
+            Moves::Four => MovementSet::Four,
+            Moves::FourStay => MovementSet::FourStay,
+            Moves::Eight => MovementSet::Eight,
+            Moves::EightStay => MovementSet::EightStay,
+            Moves::Hex => MovementSet::Hex,
+            Moves::HexStay => MovementSet::HexStay,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MapSpec {
+    #[serde(default)]
+    pub source: MapSource,
+    /// ASCII map read with the default `Legend`, relative to the scenario file
+    pub path: Option<String>,
+    /// Seed of the generated maps. Default: seed of the run
+    pub seed: Option<u64>,
+    /// Ratio of walls of `caves`, of elements of `blobs`
+    pub fill_ratio: Option<f64>,
+    /// Color of the generated walls or elements
+    pub color: Option<String>,
+    /// Goal cells `[x, y]`, added to the `G` of an ASCII map
+    #[serde(default)]
+    pub goals: Vec<[i32; 2]>,
+}
+
+/// Where the walls and elements of the grid come from (see `generation`)
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MapSource {
+    /// Nothing on the grid
+    #[default]
+    Empty,
+    /// `path` read with `AsciiMap`
+    Ascii,
+    /// `cellular_caves`
+    Caves,
+    /// `maze_backtracker`
+    Maze,
+    /// `rooms_and_corridors`
+    Rooms,
+    /// `mineral_blobs`, as elements agents can walk on
+    Blobs,
+}
+
+/// A field (see `Env::add_field`)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub name: String,
+    #[serde(default)]
+    pub diffusion: f32,
+    #[serde(default)]
+    pub evaporation: f32,
+    /// Quantity left by every agent on its cell each tick
+    #[serde(default)]
+    pub deposit: f32,
+    /// Color of its heatmap. Default: not displayed
+    pub color: Option<String>,
+    /// Value displayed with the full intensity of the color
+    #[serde(default = "one")]
+    pub display_max: f32,
+}
+
+fn one() -> f32 {
+    1.
+}
+
+/// A resource (see `Env::add_resource`)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceSpec {
+    pub name: String,
+    /// Capacity of each cell holding the resource
+    pub capacity: f32,
+    #[serde(default)]
+    pub on: ResourceCells,
+    /// For example `{ kind = "fixed", rate = 0.1 }`. Default: never regrows
+    #[serde(default = "no_regrowth")]
+    pub regrowth: Regrowth,
+}
+
+fn no_regrowth() -> Regrowth {
+    Regrowth::None
+}
+
+/// Cells holding a resource
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceCells {
+    /// Every cell that is not a wall
+    #[default]
+    All,
+    /// The elements of the map that are neither walls nor goals (`blobs` for example)
+    Elements,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentSpec {
+    #[serde(rename = "type")]
+    pub agent_type: String,
+    pub count: usize,
+    /// Default: yellow
+    pub color: Option<String>,
+    /// Default: the spawn points of the map if it has some, anywhere otherwise
+    pub spawn: Option<Spawn>,
+    /// Name of the step function in the `StepRegistry`. Required unless the script defines
+    /// `fn step`.
+    pub step: Option<String>,
+    /// Name of the reward in the `StepRegistry`. Default: `step_cost`
+    pub reward: Option<String>,
+    /// Rhai script defining `fn step(env, agent, state, action)`, or
+    /// `fn reward(env, agent, position, outcome)` replacing `reward`. Relative to the
+    /// scenario file.
+    pub script: Option<String>,
+    pub learning_rate: Option<f32>,
+    pub discount_factor: Option<f32>,
+    pub exploration_rate: Option<f32>,
+    /// Q-table loaded by the agents, relative to the scenario file
+    pub q_table: Option<String>,
+    /// Experience replay, off by default
+    pub replay: Option<ReplayConfig>,
+    /// Dyna-Q planning, off by default
+    pub dyna: Option<DynaConfig>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Spawn {
+    Rule(SpawnRule),
+    /// Every agent on this cell `[x, y]`
+    Cell([i32; 2]),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpawnRule {
+    /// Any cell that is not a wall
+    Anywhere,
+    /// The spawn points (`S`) of the ASCII map
+    SpawnPoints,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunSpec {
+    /// Number of steps of a headless run
+    pub steps: Option<u32>,
+    /// Default seed of the run, replaced by the one of the command line
+    pub seed: Option<u64>,
+    /// Merging of the Q-tables of the agents of a type, replaced by the one of the command line
+    pub sync: Option<QTableSync>,
+    /// `agents`, `tick`, `goals_reached`, `total_reward`, `script_errors`,
+    /// `harvested.<resource>`, `remaining.<resource>` or `field.<field>`. Default: all of them
+    #[serde(default)]
+    pub metrics: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ScenarioFileError {
+    Io {
+        filepath: String,
+        error: io::Error,
+    },
+    /// Not TOML, or a key that is unknown or of the wrong type
+    Parse(toml::de::Error),
+    /// A value that does not make sense. **key** is its path in the file, like `agents[0].step`
+    Invalid {
+        key: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ScenarioFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioFileError::Io { filepath, error } => {
+                write!(f, "could not read {}: {}", filepath, error)
+            }
+            ScenarioFileError::Parse(error) => write!(f, "{}", error),
+            ScenarioFileError::Invalid { key, message } => write!(f, "`{}`: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioFileError {}
+
+impl From<toml::de::Error> for ScenarioFileError {
+    fn from(error: toml::de::Error) -> Self {
+        ScenarioFileError::Parse(error)
+    }
+}
+
+fn invalid<T>(key: impl Into<String>, message: impl Into<String>) -> Result<T, ScenarioFileError> {
+    Err(ScenarioFileError::Invalid {
+        key: key.into(),
+        message: message.into(),
+    })
+}
+
+/// What a metric of `RunSpec::metrics` measures
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Metric {
+    Agents,
+    Tick,
+    GoalsReached,
+    TotalReward,
+    /// Id of the resource
+    Harvested(u32),
+    Remaining(u32),
+    /// Id of the field
+    Field(u32),
+    ScriptErrors,
+}
+
+/// A script of the file, kept as text so the scenario can be shared between threads
+#[derive(Clone, Debug)]
+struct ScriptSource {
+    path: String,
+    source: String,
+}
+
+impl ScriptSource {
+    /// Read and compile the script, to find its errors before any build
+    fn load(path: &str, key: &str) -> Result<(ScriptSource, Script), ScenarioFileError> {
+        let source = fs::read_to_string(path).map_err(|error| ScenarioFileError::Invalid {
+            key: key.to_string(),
+            message: format!("could not read {}: {}", path, error),
+        })?;
+        let script =
+            Script::compile(path, &source).map_err(|error| ScenarioFileError::Invalid {
+                key: key.to_string(),
+                message: error.to_string(),
+            })?;
+
+        Ok((
+            ScriptSource {
+                path: path.to_string(),
+                source,
+            },
+            script,
+        ))
+    }
+
+    /// Compile the script again for a new scheduler
+    fn compile(&self) -> Script {
+        Script::compile(&self.path, &self.source).expect("checked when loaded")
+    }
+}
+
+/// A checked `ScenarioFile`, ready to build schedulers
+pub struct FileScenario {
+    file: ScenarioFile,
+    steps: StepRegistry,
+    /// Text of the ASCII map, read once
+    ascii_map: Option<String>,
+    size: GridSize,
+    goals: Vec<Position>,
+    metrics: Vec<(String, Metric)>,
+    /// Script of each agent type
+    agent_scripts: Vec<Option<ScriptSource>>,
+    /// Script updating the environment
+    script: Option<ScriptSource>,
+    // The scenario and the scheduler use `&'static str`. Leaked once per loaded file.
+    name: &'static str,
+    description: &'static str,
+    agent_types: Vec<&'static str>,
+}
+
+impl FileScenario {
+    pub fn load(filepath: &str, steps: StepRegistry) -> Result<FileScenario, ScenarioFileError> {
+        let text = fs::read_to_string(filepath).map_err(|error| ScenarioFileError::Io {
+            filepath: filepath.to_string(),
+            error,
+        })?;
+        let directory = Path::new(filepath).parent().unwrap_or(Path::new(""));
+
+        FileScenario::parse(&text, directory, steps)
+    }
+
+    /// Parse and check a scenario file. Its paths are relative to `directory`.
+    pub fn parse(
+        text: &str,
+        directory: &Path,
+        steps: StepRegistry,
+    ) -> Result<FileScenario, ScenarioFileError> {
+        let mut file: ScenarioFile = toml::from_str(text)?;
+        let relative = |path: &str| directory.join(path).to_string_lossy().into_owned();
+        for agent in &mut file.agents {
+            agent.q_table = agent.q_table.as_deref().map(relative);
+            agent.script = agent.script.as_deref().map(relative);
+        }
+        file.script = file.script.as_deref().map(relative);
+
+        if file.name.trim().is_empty() {
+            return invalid("name", "the name cannot be empty");
+        }
+
+        // The map first, it may give the size of the grid
+        let mut map = None;
+        let mut ascii_map = None;
+        match (file.map.source, &file.map.path) {
+            (MapSource::Ascii, None) => {
+                return invalid("map.path", "an ascii map needs the path of its file")
+            }
+            (MapSource::Ascii, Some(path)) => {
+                let text = fs::read_to_string(relative(path)).map_err(|error| {
+                    ScenarioFileError::Invalid {
+                        key: "map.path".to_string(),
+                        message: format!("could not read {}: {}", relative(path), error),
+                    }
+                })?;
+                map = Some(AsciiMap::parse(&text, &Legend::default()).map_err(|error| {
+                    ScenarioFileError::Invalid {
+                        key: "map.path".to_string(),
+                        message: error.to_string(),
+                    }
+                })?);
+                ascii_map = Some(text);
+            }
+            (_, Some(_)) => return invalid("map.path", "only an ascii map is read from a file"),
+            _ => {}
+        }
+
+        let size = size(&file.grid, map.as_ref())?;
+        let inside =
+            |[x, y]: [i32; 2]| x >= 0 && y >= 0 && x < size.width as i32 && y < size.heigth as i32;
+
+        if let Some(fill_ratio) = file.map.fill_ratio {
+            if !(0. ..=1.).contains(&fill_ratio) {
+                return invalid("map.fill_ratio", "expected a ratio between 0 and 1");
+            }
+        }
+        if let Some(color) = &file.map.color {
+            parse_color(color).ok_or_else(|| unknown_color("map.color", color))?;
+        }
+
+        let mut goals = map.as_ref().map_or(Vec::new(), |map| map.goals.clone());
+        for (i, goal) in file.map.goals.iter().enumerate() {
+            if !inside(*goal) {
+                return invalid(format!("map.goals[{}]", i), "outside of the grid");
+            }
+            goals.push(Position::from_array(*goal));
+        }
+
+        check_names(
+            file.fields.iter().map(|field| field.name.as_str()),
+            "fields",
+        )?;
+        check_names(
+            file.resources.iter().map(|resource| resource.name.as_str()),
+            "resources",
+        )?;
+        for (i, field) in file.fields.iter().enumerate() {
+            if let Some(color) = &field.color {
+                parse_color(color)
+                    .ok_or_else(|| unknown_color(&format!("fields[{}].color", i), color))?;
+            }
+        }
+        for (i, resource) in file.resources.iter().enumerate() {
+            if resource.capacity < 0. {
+                return invalid(format!("resources[{}].capacity", i), "cannot be negative");
+            }
+        }
+
+        if file.agents.is_empty() {
+            return invalid("agents", "expected at least one agent type");
+        }
+        check_names(
+            file.agents.iter().map(|agent| agent.agent_type.as_str()),
+            "agents",
+        )?;
+        let spawn_points = map.as_ref().map_or(0, |map| map.spawn_points.len());
+        let mut agent_scripts = Vec::new();
+        for (i, agent) in file.agents.iter().enumerate() {
+            let key = |name: &str| format!("agents[{}].{}", i, name);
+
+            if let Some(color) = &agent.color {
+                parse_color(color).ok_or_else(|| unknown_color(&key("color"), color))?;
+            }
+
+            match agent.spawn {
+                Some(Spawn::Cell(cell)) if !inside(cell) => {
+                    return invalid(key("spawn"), "outside of the grid")
+                }
+                Some(Spawn::Cell(cell))
+                    if map
+                        .as_ref()
+                        .is_some_and(|map| map.obstacles.contains(&Position::from_array(cell))) =>
+                {
+                    return invalid(key("spawn"), "the cell is a wall")
+                }
+                Some(Spawn::Rule(SpawnRule::SpawnPoints)) if spawn_points == 0 => {
+                    return invalid(key("spawn"), "the map has no spawn point (`S`)")
+                }
+                _ => {}
+            }
+
+            let script = match &agent.script {
+                Some(path) => Some(ScriptSource::load(path, &key("script"))?),
+                None => None,
+            };
+            let has = |function: &str| {
+                script
+                    .as_ref()
+                    .is_some_and(|(_, script)| script.has_function(function, 4))
+            };
+            let (scripted_step, scripted_reward) = (has("step"), has("reward"));
+            if script.is_some() && !scripted_step && !scripted_reward {
+                return invalid(
+                    key("script"),
+                    "expected a function `step(env, agent, state, action)` or \
+                     `reward(env, agent, position, outcome)`",
+                );
+            }
+            agent_scripts.push(script.map(|(source, _)| source));
+
+            match (&agent.step, scripted_step) {
+                (Some(_), true) => {
+                    return invalid(key("step"), "the script already defines `step`")
+                }
+                (None, false) => {
+                    return invalid(
+                        key("step"),
+                        "expected a step function, or a script defining `step`",
+                    )
+                }
+                (Some(name), false) => {
+                    let Some(step) = steps.step(name) else {
+                        return invalid(
+                            key("step"),
+                            format!(
+                                "unknown step function {:?}, expected one of: {}",
+                                name,
+                                steps.step_names().join(", ")
+                            ),
+                        );
+                    };
+                    if step.needs_goals && goals.is_empty() {
+                        return invalid(key("step"), no_goal(name));
+                    }
+                }
+                (None, true) => {}
+            }
+
+            if let Some(replay) = &agent.replay {
+                if replay.capacity == 0 {
+                    return invalid(key("replay.capacity"), "expected at least one transition");
+                }
+                if replay.batch_size == 0 || replay.batch_size > replay.capacity {
+                    return invalid(
+                        key("replay.batch_size"),
+                        format!(
+                            "expected 1 to {} transitions (the capacity)",
+                            replay.capacity
+                        ),
+                    );
+                }
+                if replay.frequency == 0 {
+                    return invalid(key("replay.frequency"), "expected at least one transition");
+                }
+                if !(replay.priority_exponent >= 0. && replay.priority_exponent.is_finite()) {
+                    return invalid(key("replay.priority_exponent"), "expected 0 or more");
+                }
+            }
+
+            if let Some(dyna) = &agent.dyna {
+                if !(dyna.exploration_bonus >= 0. && dyna.exploration_bonus.is_finite()) {
+                    return invalid(key("dyna.exploration_bonus"), "expected 0 or more");
+                }
+            }
+
+            if agent.reward.is_some() && (scripted_step || scripted_reward) {
+                return invalid(key("reward"), "the script already gives the reward");
+            }
+            if !scripted_step && !scripted_reward {
+                let reward_name = agent.reward.as_deref().unwrap_or("step_cost");
+                let Some(reward) = steps.reward(reward_name) else {
+                    return invalid(
+                        key("reward"),
+                        format!(
+                            "unknown reward {:?}, expected one of: {}",
+                            reward_name,
+                            steps.reward_names().join(", ")
+                        ),
+                    );
+                };
+                if reward.needs_goals && goals.is_empty() {
+                    return invalid(key("reward"), no_goal(reward_name));
+                }
+            }
+
+            for (name, rate) in [
+                ("learning_rate", agent.learning_rate),
+                ("discount_factor", agent.discount_factor),
+                ("exploration_rate", agent.exploration_rate),
+            ] {
+                if rate.is_some_and(|rate| !(0. ..=1.).contains(&rate)) {
+                    return invalid(key(name), "expected a rate between 0 and 1");
+                }
+            }
+        }
+
+        let script = match &file.script {
+            Some(path) => {
+                let (source, script) = ScriptSource::load(path, "script")?;
+                if !script.has_function("update", 2) {
+                    return invalid("script", "expected a function `update(env, positions)`");
+                }
+                Some(source)
+            }
+            None => None,
+        };
+
+        if file.run.steps == Some(0) {
+            return invalid("run.steps", "expected at least one step");
+        }
+        if file.run.sync.is_some_and(|sync| sync.interval == 0) {
+            return invalid("run.sync.interval", "expected at least one tick");
+        }
+        let names = if file.run.metrics.is_empty() {
+            default_metrics(&file)
+        } else {
+            file.run.metrics.clone()
+        };
+        let mut metrics = Vec::new();
+        for (i, name) in names.into_iter().enumerate() {
+            let Some(metric) = parse_metric(&name, &file) else {
+                return invalid(
+                    format!("run.metrics[{}]", i),
+                    format!("unknown metric {:?}", name),
+                );
+            };
+            metrics.push((name, metric));
+        }
+
+        Ok(FileScenario {
+            name: Box::leak(file.name.clone().into_boxed_str()),
+            description: Box::leak(file.description.clone().into_boxed_str()),
+            agent_types: file
+                .agents
+                .iter()
+                .map(|agent| &*Box::leak(agent.agent_type.clone().into_boxed_str()))
+                .collect(),
+            file,
+            steps,
+            ascii_map,
+            size,
+            goals,
+            metrics,
+            agent_scripts,
+            script,
+        })
+    }
+
+    pub fn file(&self) -> &ScenarioFile {
+        &self.file
+    }
+
+    /// The environment: grid, map, goals and layers
+    fn build_env(&self, options: &ScenarioOptions, seed: u64) -> Env {
+        let file = &self.file;
+        let actions = file.grid.moves.movement_set().actions();
+
+        let mut env = match &self.ascii_map {
+            Some(text) => AsciiMap::parse(text, &Legend::default())
+                .expect("The map was checked when the file was loaded")
+                .into_env(options.start, options.end, &actions, HashMap::new()),
+            None => Env::new(
+                options.start,
+                options.end,
+                self.size,
+                HashMap::new(),
+                &actions,
+                HashMap::new(),
+            ),
+        };
+        env.topology = file.grid.topology;
+        env.registry
+            .insert(MOVEMENTS, file.grid.moves.movement_set());
+
+        let map = &file.map;
+        let seed = map.seed.unwrap_or(seed);
+        let color = |default: Color| {
+            map.color
+                .as_deref()
+                .and_then(parse_color)
+                .unwrap_or(default)
+        };
+        match map.source {
+            MapSource::Empty | MapSource::Ascii => {}
+            MapSource::Caves => cellular_caves(self.size, map.fill_ratio.unwrap_or(0.45), 4, seed)
+                .apply_as_obstacles(&mut env, color(color::DARKGRAY)),
+            MapSource::Maze => maze_backtracker(self.size, seed)
+                .apply_as_obstacles(&mut env, color(color::DARKGRAY)),
+            MapSource::Rooms => rooms_and_corridors(self.size, 12, 4, 10, seed)
+                .0
+                .apply_as_obstacles(&mut env, color(color::DARKGRAY)),
+            MapSource::Blobs => {
+                mineral_blobs(self.size, map.fill_ratio.unwrap_or(0.1) as f32, 10, seed)
+                    .apply_to_env(&mut env, color(color::BLACK))
+            }
+        }
+
+        // Goals are never walls, even on a generated map
+        for goal in &self.goals {
+            env.obstacles.remove(goal);
+            env.update_persistent_element(*goal, color::GREEN);
+        }
+        env.registry.insert(GOALS, self.goals.clone());
+        if let Some(connectivity) = file.grid.moves.connectivity() {
+            let flows = self
+                .goals
+                .iter()
+                .map(|goal| env.flow_field(*goal, connectivity, |_| 1.))
+                .collect();
+            env.registry.insert(GOAL_FLOWS, flows);
+        }
+
+        for (id, field) in file.fields.iter().enumerate() {
+            let layer = env.add_field(id as u32, field.diffusion, field.evaporation);
+            if let Some(color) = field.color.as_deref().and_then(parse_color) {
+                layer.set_heatmap(color, field.display_max);
+            }
+        }
+
+        let elements: Vec<Position> = env
+            .persistent_elements
+            .keys()
+            .filter(|position| {
+                !env.obstacles.contains(*position) && !self.goals.contains(*position)
+            })
+            .copied()
+            .collect();
+        for (id, resource) in file.resources.iter().enumerate() {
+            let cells = match resource.on {
+                ResourceCells::All => (0..self.size.heigth as i32)
+                    .flat_map(|y| (0..self.size.width as i32).map(move |x| Position { x, y }))
+                    .filter(|position| !env.obstacles.contains(position))
+                    .collect(),
+                ResourceCells::Elements => elements.clone(),
+            };
+            let layer = env.add_resource(id as u32, resource.regrowth);
+            for position in cells {
+                layer.set_capacity(position, resource.capacity);
+            }
+        }
+
+        env
+    }
+}
+
+impl Scenario for FileScenario {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn steps(&self) -> Option<u32> {
+        self.file.run.steps
+    }
+
+    /// `agents`, `learning_rate`, `discount_factor`, `exploration_rate` and `q_table` of the
+    /// options replace those of every agent type
+    fn build(&self, options: &ScenarioOptions) -> Result<Scheduler, QTableError> {
+        let file = &self.file;
+        let seed = options.seed.or(file.run.seed).unwrap_or_else(rand::random);
+        let mut env = self.build_env(options, seed);
+        env.set_seed(seed);
+        let mut scheduler = Scheduler::new(env);
+        scheduler.q_table_sync = options.q_table_sync.or(file.run.sync);
+
+        let deposits: Vec<(u32, f32)> = file
+            .fields
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.deposit != 0.)
+            .map(|(id, field)| (id as u32, field.deposit))
+            .collect();
+        if !deposits.is_empty() {
+            scheduler.add_post_step(Rc::new(move |env: &mut Env, positions: &[Position]| {
+                for (id, amount) in &deposits {
+                    let field = env.field_mut(*id).unwrap();
+                    for position in positions {
+                        field.deposit(*position, *amount);
+                    }
+                }
+            }));
+        }
+
+        if let Some(script) = &self.script {
+            scheduler.add_post_step(script.compile().env_step_function().unwrap());
+        }
+
+        for ((agent, agent_type), script) in file
+            .agents
+            .iter()
+            .zip(&self.agent_types)
+            .zip(&self.agent_scripts)
+        {
+            let script = script.as_ref().map(ScriptSource::compile);
+            // The state of a scripted step is the one it returns, empty before the first step
+            let (initial_state, step_fn) = match (&agent.step, script) {
+                (Some(name), script) => {
+                    let step = self.steps.step(name).unwrap();
+                    let reward = match script {
+                        Some(script) => script.reward_function().unwrap(),
+                        None => self
+                            .steps
+                            .reward(agent.reward.as_deref().unwrap_or("step_cost"))
+                            .unwrap()
+                            .reward_function(),
+                    };
+                    ((step.initial_state)(), (step.step_fn)(reward))
+                }
+                (None, Some(script)) => (Vec::new(), script.step_function().unwrap()),
+                (None, None) => unreachable!("checked by `FileScenario::parse`"),
+            };
+            let position = match agent.spawn {
+                Some(Spawn::Cell(cell)) => Some(Position::from_array(cell)),
+                _ => None,
+            };
+
+            let first = scheduler.agents.len();
+            scheduler.add_agents(
+                options.agents.unwrap_or(agent.count),
+                position,
+                agent
+                    .color
+                    .as_deref()
+                    .and_then(parse_color)
+                    .unwrap_or(color::YELLOW),
+                agent_type,
+                initial_state,
+                options.learning_rate.or(agent.learning_rate),
+                options.discount_factor.or(agent.discount_factor),
+                options.exploration_rate.or(agent.exploration_rate),
+                &step_fn,
+                options.q_table.as_deref().or(agent.q_table.as_deref()),
+            )?;
+            if agent.replay.is_some() {
+                scheduler.set_replay(agent_type, agent.replay);
+            }
+            if agent.dyna.is_some() {
+                scheduler.set_dyna(agent_type, agent.dyna);
+            }
+
+            // Spawn points are the default of the scheduler
+            if agent.spawn == Some(Spawn::Rule(SpawnRule::Anywhere)) {
+                for (position, _, _) in &mut scheduler.agents[first..] {
+                    *position = scheduler.env.get_random_position();
+                }
+            }
+        }
+
+        Ok(scheduler)
+    }
+
+    /// The metrics of `run.metrics`
+    fn metrics(&self, scheduler: &Scheduler) -> Metrics {
+        let env = &scheduler.env;
+        self.metrics
+            .iter()
+            .map(|(name, metric)| {
+                let value = match *metric {
+                    Metric::Agents => scheduler.agents.len() as f64,
+                    Metric::Tick => env.tick as f64,
+                    Metric::GoalsReached => *env.registry.get(GOALS_REACHED).unwrap_or(&0) as f64,
+                    Metric::TotalReward => *env.registry.get(TOTAL_REWARD).unwrap_or(&0.),
+                    Metric::Harvested(id) => env
+                        .resource(id)
+                        .map_or(0., |resource| resource.total_harvested() as f64),
+                    Metric::Remaining(id) => env
+                        .resource(id)
+                        .map_or(0., |resource| resource.total() as f64),
+                    Metric::Field(id) => env.field(id).map_or(0., |field| field.total() as f64),
+                    Metric::ScriptErrors => env
+                        .registry
+                        .get(SCRIPT_ERRORS)
+                        .map_or(0., |errors| errors.count() as f64),
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    }
+}
+
+/// Size of the ASCII map, or the one of the grid
+fn size(grid: &GridSpec, map: Option<&AsciiMap>) -> Result<GridSize, ScenarioFileError> {
+    let Some(map) = map else {
+        return match (grid.width, grid.height) {
+            (None, _) => invalid("grid.width", "required without an ascii map"),
+            (_, None) => invalid("grid.height", "required without an ascii map"),
+            (Some(0), _) => invalid("grid.width", "the grid cannot be empty"),
+            (_, Some(0)) => invalid("grid.height", "the grid cannot be empty"),
+            (Some(width), Some(heigth)) => Ok(GridSize { width, heigth }),
+        };
+    };
+
+    for (key, given, size) in [
+        ("grid.width", grid.width, map.size.width),
+        ("grid.height", grid.height, map.size.heigth),
+    ] {
+        if given.is_some_and(|given| given != size) {
+            return invalid(key, format!("the ascii map gives {}", size));
+        }
+    }
+
+    Ok(map.size)
+}
+
+/// Names of a list of tables must be unique and not empty
+fn check_names<'a>(
+    names: impl Iterator<Item = &'a str>,
+    table: &str,
+) -> Result<(), ScenarioFileError> {
+    let key = if table == "agents" { "type" } else { "name" };
+    let mut seen = HashSet::new();
+    for (i, name) in names.enumerate() {
+        if name.trim().is_empty() {
+            return invalid(format!("{}[{}].{}", table, i, key), "cannot be empty");
+        }
+        if !seen.insert(name) {
+            return invalid(
+                format!("{}[{}].{}", table, i, key),
+                format!("{:?} is defined twice", name),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn no_goal(name: &str) -> String {
+    format!(
+        "{:?} needs a goal, a `G` in the ascii map or `map.goals`",
+        name
+    )
+}
+
+fn unknown_color(key: &str, color: &str) -> ScenarioFileError {
+    ScenarioFileError::Invalid {
+        key: key.to_string(),
+        message: format!("unknown color {:?}, expected a name or #rrggbb", color),
+    }
+}
+
+fn default_metrics(file: &ScenarioFile) -> Vec<String> {
+    let mut metrics: Vec<String> = ["agents", "goals_reached", "total_reward"]
+        .map(String::from)
+        .to_vec();
+    for resource in &file.resources {
+        metrics.push(format!("harvested.{}", resource.name));
+    }
+    for field in &file.fields {
+        metrics.push(format!("field.{}", field.name));
+    }
+    if file.script.is_some() || file.agents.iter().any(|agent| agent.script.is_some()) {
+        metrics.push("script_errors".to_string());
+    }
+    metrics
+}
+
+fn parse_metric(name: &str, file: &ScenarioFile) -> Option<Metric> {
+    let resource = |layer: &str| {
+        file.resources
+            .iter()
+            .position(|resource| resource.name == layer)
+            .map(|id| id as u32)
+    };
+
+    match name.split_once('.') {
+        None => match name {
+            "agents" => Some(Metric::Agents),
+            "tick" => Some(Metric::Tick),
+            "goals_reached" => Some(Metric::GoalsReached),
+            "total_reward" => Some(Metric::TotalReward),
+            "script_errors" => Some(Metric::ScriptErrors),
+            _ => None,
+        },
+        Some(("harvested", layer)) => resource(layer).map(Metric::Harvested),
+        Some(("remaining", layer)) => resource(layer).map(Metric::Remaining),
+        Some(("field", layer)) => file
+            .fields
+            .iter()
+            .position(|field| field.name == layer)
+            .map(|id| Metric::Field(id as u32)),
+        _ => None,
+    }
+}
+
+/// Named color of macroquad (`skyblue`, `darkgray`, etc.) or `#rrggbb`
+pub fn parse_color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        return (hex.len() == 6)
+            .then(|| u32::from_str_radix(hex, 16).ok())
+            .flatten()
+            .map(Color::from_hex);
+    }
+
+    let color = match name.to_lowercase().as_str() {
+        "beige" => color::BEIGE,
+        "black" => color::BLACK,
+        "blue" => color::BLUE,
+        "brown" => color::BROWN,
+        "darkblue" => color::DARKBLUE,
+        "darkbrown" => color::DARKBROWN,
+        "darkgray" => color::DARKGRAY,
+        "darkgreen" => color::DARKGREEN,
+        "darkpurple" => color::DARKPURPLE,
+        "gold" => color::GOLD,
+        "gray" => color::GRAY,
+        "green" => color::GREEN,
+        "lightgray" => color::LIGHTGRAY,
+        "lime" => color::LIME,
+        "magenta" => color::MAGENTA,
+        "maroon" => color::MAROON,
+        "orange" => color::ORANGE,
+        "pink" => color::PINK,
+        "purple" => color::PURPLE,
+        "red" => color::RED,
+        "skyblue" => color::SKYBLUE,
+        "violet" => color::VIOLET,
+        "white" => color::WHITE,
+        "yellow" => color::YELLOW,
+        _ => return None,
+    };
+    Some(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{agent::Agent, merge::MergeStrategy, replay::ReplayRef};
+
+    const FIELDS: &str = r#"
+        name = "fields"
+
+        [grid]
+        width = 12
+        height = 8
+        topology = "toroidal"
+
+        [map]
+        goals = [[10, 4]]
+
+        [[resources]]
+        name = "grass"
+        capacity = 2.0
+        regrowth = { kind = "fixed", rate = 0.5 }
+
+        [[agents]]
+        type = "sheep"
+        count = 5
+        color = "white"
+        spawn = "anywhere"
+        step = "goal_seeker"
+        reward = "goal"
+
+        [[agents]]
+        type = "goat"
+        count = 2
+        spawn = [1, 1]
+        step = "explorer"
+        reward = "harvest"
+
+        [run]
+        steps = 50
+        seed = 4
+    "#;
+
+    fn parse(text: &str) -> Result<FileScenario, ScenarioFileError> {
+        FileScenario::parse(text, Path::new("."), StepRegistry::with_builtins())
+    }
+
+    /// Key of the error of an invalid file
+    fn invalid_key(text: &str) -> String {
+        match parse(text) {
+            Err(ScenarioFileError::Invalid { key, .. }) => key,
+            Err(error) => panic!("expected an invalid value, got {}", error),
+            Ok(_) => panic!("expected an invalid value"),
+        }
+    }
+
+    #[test]
+    fn build() {
+        let scenario = parse(FIELDS).unwrap();
+        assert_eq!(scenario.name(), "fields");
+        assert_eq!(scenario.steps(), Some(50));
+
+        let mut scheduler = scenario.build(&ScenarioOptions::default()).unwrap();
+        assert_eq!(scheduler.agents.len(), 7);
+        assert_eq!(scheduler.agents_per_types["goat"].len(), 2);
+        assert!(scheduler.agents[5..]
+            .iter()
+            .all(|(position, _, _)| *position == Position { x: 1, y: 1 }));
+        assert_eq!(scheduler.env.topology, Topology::Toroidal);
+        assert_eq!(scheduler.env.resource(0).unwrap().total(), 2. * 12. * 8.);
+
+        for _ in 0..50 {
+            scheduler.take_step();
+        }
+        let metrics = scenario.metrics(&scheduler);
+        assert_eq!(
+            metrics.keys().collect::<Vec<_>>(),
+            ["agents", "goals_reached", "harvested.grass", "total_reward"]
+        );
+        assert!(metrics["harvested.grass"] > 0.);
+
+        // The options replace the file
+        let options = ScenarioOptions {
+            agents: Some(1),
+            ..ScenarioOptions::default()
+        };
+        assert_eq!(scenario.build(&options).unwrap().agents.len(), 2);
+
+        let scenario =
+            parse(&FIELDS.replace("seed = 4", "sync = { interval = 10, strategy = \"max\" }"))
+                .unwrap();
+        let scheduler = scenario.build(&ScenarioOptions::default()).unwrap();
+        assert_eq!(
+            scheduler.q_table_sync,
+            Some(QTableSync {
+                interval: 10,
+                strategy: MergeStrategy::Max
+            })
+        );
+
+        // The sheep share a replay buffer, the goats have one each
+        let scenario = parse(
+            &FIELDS
+                .replace(
+                    "reward = \"goal\"",
+                    "reward = \"goal\"\nreplay = { shared = true }",
+                )
+                .replace(
+                    "reward = \"harvest\"",
+                    "reward = \"harvest\"\nreplay = { batch_size = 4 }",
+                ),
+        )
+        .unwrap();
+        let scheduler = scenario.build(&ScenarioOptions::default()).unwrap();
+        let replays: Vec<ReplayRef> = scheduler
+            .agents
+            .iter()
+            .map(|(_, _, agent)| match &*agent.borrow() {
+                Agent::Learning(agent) => agent.replay.clone().unwrap(),
+                Agent::Swarm(_) => unreachable!(),
+            })
+            .collect();
+        assert!(Rc::ptr_eq(&replays[0], &replays[4]));
+        assert!(!Rc::ptr_eq(&replays[5], &replays[6]));
+        assert_eq!(replays[5].borrow().config.batch_size, 4);
+    }
+
+    #[test]
+    fn ascii_map() {
+        let scenario =
+            FileScenario::load("scenarios/warehouse.toml", StepRegistry::with_builtins()).unwrap();
+        let scheduler = scenario.build(&ScenarioOptions::default()).unwrap();
+
+        assert_eq!(*scheduler.env.get_grid_size(), scenario.size);
+        assert!(scheduler
+            .agents
+            .iter()
+            .all(|(position, _, _)| scheduler.env.spawn_points.contains(position)));
+    }
+
+    #[test]
+    fn scripts() {
+        let scenario =
+            FileScenario::load("scenarios/garden.toml", StepRegistry::with_builtins()).unwrap();
+        let mut scheduler = scenario.build(&ScenarioOptions::default()).unwrap();
+        for _ in 0..20 {
+            scheduler.take_step();
+        }
+        assert_eq!(scenario.metrics(&scheduler)["script_errors"], 0.);
+        assert!(!scheduler.env.persistent_elements.is_empty());
+
+        let replace = |from: &str, to: &str| FIELDS.replace(from, to);
+        let script = "script = \"scenarios/garden.rhai\"";
+        // The script defines `step`
+        assert_eq!(
+            invalid_key(&replace("reward = \"harvest\"", script)),
+            "agents[1].step"
+        );
+        assert_eq!(
+            invalid_key(&replace("step = \"explorer\"", script)),
+            "agents[1].reward"
+        );
+        assert!(parse(&replace(
+            "step = \"explorer\"\n        reward = \"harvest\"",
+            script
+        ))
+        .is_ok());
+        assert_eq!(
+            invalid_key(&replace(
+                "reward = \"harvest\"",
+                "script = \"missing.rhai\""
+            )),
+            "agents[1].script"
+        );
+        // No `fn update`
+        assert_eq!(
+            invalid_key(&format!("script = \"Cargo.toml\"\n{}", FIELDS)),
+            "script"
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_key() {
+        let replace = |from: &str, to: &str| FIELDS.replace(from, to);
+
+        assert_eq!(invalid_key(&replace("width = 12", "")), "grid.width");
+        assert_eq!(
+            invalid_key(&replace("goals = [[10, 4]]", "goals = [[12, 4]]")),
+            "map.goals[0]"
+        );
+        assert_eq!(
+            invalid_key(&replace("name = \"grass\"", "name = \"\"")),
+            "resources[0].name"
+        );
+        assert_eq!(
+            invalid_key(&replace("type = \"goat\"", "type = \"sheep\"")),
+            "agents[1].type"
+        );
+        assert_eq!(
+            invalid_key(&replace("\"explorer\"", "\"teleporter\"")),
+            "agents[1].step"
+        );
+        assert_eq!(
+            invalid_key(&replace("color = \"white\"", "color = \"#12345\"")),
+            "agents[0].color"
+        );
+        assert_eq!(
+            invalid_key(&replace("[1, 1]", "\"spawn_points\"")),
+            "agents[1].spawn"
+        );
+        assert_eq!(
+            invalid_key(&replace("goals = [[10, 4]]", "")),
+            "agents[0].step"
+        );
+        assert_eq!(
+            invalid_key(&replace(
+                "seed = 4",
+                "metrics = [\"tick\", \"harvested.hay\"]"
+            )),
+            "run.metrics[1]"
+        );
+        assert_eq!(
+            invalid_key(&replace("seed = 4", "sync = { interval = 0 }")),
+            "run.sync.interval"
+        );
+        assert_eq!(
+            invalid_key(&replace(
+                "reward = \"harvest\"",
+                "reward = \"harvest\"\nreplay = { capacity = 8, batch_size = 16 }"
+            )),
+            "agents[1].replay.batch_size"
+        );
+        assert_eq!(
+            invalid_key(&replace(
+                "reward = \"goal\"",
+                "reward = \"goal\"\ndyna = { exploration_bonus = -1.0 }"
+            )),
+            "agents[0].dyna.exploration_bonus"
+        );
+
+        // Unknown keys and wrong types are found by the parser, with their line
+        let error = parse(&replace("height", "heigth")).err().unwrap();
+        assert!(matches!(error, ScenarioFileError::Parse(_)));
+        assert!(error.to_string().contains("heigth"));
+        assert!(matches!(
+            parse(&replace("count = 5", "count = \"five\"")),
+            Err(ScenarioFileError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{
+    agent::{
+        agent::{Action, Done, Reward, StepFunction},
+        learning_agent::LearningAgent,
+        state::{to_value, State},
+    },
+    environment::{
+        ascii_map::GOALS,
+        environment::Env,
+        movement::{MoveOutcome, MovementSet},
+        pathfinding::FlowField,
+        registry::Key,
+    },
+    scheduler::scheduler::Position,
+};
+
+/// Moves of the agents, `MovementSet::Four` when missing
+pub const MOVEMENTS: Key<MovementSet> = Key::new("movements");
+/// Distances to each goal going around the walls. Manhattan distances are used without them.
+pub const GOAL_FLOWS: Key<Vec<FlowField>> = Key::new("goal_flows");
+/// Number of times an agent reached a goal
+pub const GOALS_REACHED: Key<u32> = Key::new("goals_reached");
+/// Sum of the rewards of every agent so far
+pub const TOTAL_REWARD: Key<f64> = Key::new("total_reward");
+
+/// Reward of a move of an agent from `position`
+pub type RewardFunction = Rc<dyn Fn(&LearningAgent, &mut Env, Position, &MoveOutcome) -> Reward>;
+
+/// A step function that can be chosen by name in a scenario file
+#[derive(Clone, Copy)]
+pub struct StepEntry {
+    pub description: &'static str,
+    /// State of the agents before their first step
+    pub initial_state: fn() -> State,
+    /// Build the step function of an agent type, rewarding its moves with `RewardFunction`
+    pub step_fn: fn(RewardFunction) -> StepFunction<LearningAgent>,
+    /// Whether the environment must have goals (see `GOALS`)
+    pub needs_goals: bool,
+}
+
+/// A reward that can be chosen by name in a scenario file
+#[derive(Clone, Copy)]
+pub struct RewardEntry {
+    pub description: &'static str,
+    pub reward_fn: fn(env: &mut Env, position: Position, outcome: &MoveOutcome) -> Reward,
+    /// Whether the environment must have goals (see `GOALS`)
+    pub needs_goals: bool,
+}
+
+/// Step functions and rewards by name, for the scenario files.
+///
+/// Entries are plain functions so a loaded scenario can be shared between threads, each
+/// build creates its own step functions.
+#[derive(Clone, Default)]
+pub struct StepRegistry {
+    steps: BTreeMap<String, StepEntry>,
+    rewards: BTreeMap<String, RewardEntry>,
+}
+
+impl RewardEntry {
+    /// The reward for `StepEntry::step_fn`
+    pub fn reward_function(&self) -> RewardFunction {
+        let reward_fn = self.reward_fn;
+        Rc::new(move |_agent, env, position, outcome| reward_fn(env, position, outcome))
+    }
+}
+
+impl StepRegistry {
+    pub fn new() -> Self {
+        StepRegistry::default()
+    }
+
+    /// Registry with the step functions and rewards of this module:
+    /// - steps: `explorer` and `goal_seeker`
+    /// - rewards: `step_cost`, `goal` and `harvest`
+    pub fn with_builtins() -> Self {
+        let mut registry = StepRegistry::new();
+
+        registry.register_step(
+            "explorer",
+            StepEntry {
+                description: "Moves on the grid, the state is its cell",
+                initial_state: || vec![to_value(0), to_value(0)],
+                step_fn: |reward_fn| grid_step(reward_fn, cell_state),
+                needs_goals: false,
+            },
+        );
+        registry.register_step(
+            "goal_seeker",
+            StepEntry {
+                description:
+                    "Moves on the grid, the state is the direction of the way to the nearest goal",
+                initial_state: || vec![to_value(false); 4],
+                step_fn: |reward_fn| grid_step(reward_fn, goal_state),
+                needs_goals: true,
+            },
+        );
+
+        registry.register_reward(
+            "step_cost",
+            RewardEntry {
+                description: "-1 per step, -5 when blocked",
+                reward_fn: step_cost,
+                needs_goals: false,
+            },
+        );
+        registry.register_reward(
+            "goal",
+            RewardEntry {
+                description: "+50 on a goal, -1 per step, +5 per cell closer to the nearest goal",
+                reward_fn: goal_reward,
+                needs_goals: true,
+            },
+        );
+        registry.register_reward(
+            "harvest",
+            RewardEntry {
+                description: "Quantity harvested from every resource of the cell, -1 if none",
+                reward_fn: harvest_reward,
+                needs_goals: false,
+            },
+        );
+
+        registry
+    }
+
+    /// Add a step function, replacing the one with the same name
+    pub fn register_step(&mut self, name: &str, entry: StepEntry) {
+        self.steps.insert(name.to_string(), entry);
+    }
+
+    /// Add a reward, replacing the one with the same name
+    pub fn register_reward(&mut self, name: &str, entry: RewardEntry) {
+        self.rewards.insert(name.to_string(), entry);
+    }
+
+    pub fn step(&self, name: &str) -> Option<&StepEntry> {
+        self.steps.get(name)
+    }
+
+    pub fn reward(&self, name: &str) -> Option<&RewardEntry> {
+        self.rewards.get(name)
+    }
+
+    /// Names of the step functions, sorted
+    pub fn step_names(&self) -> Vec<&str> {
+        self.steps.keys().map(String::as_str).collect()
+    }
+
+    /// Names of the rewards, sorted
+    pub fn reward_names(&self) -> Vec<&str> {
+        self.rewards.keys().map(String::as_str).collect()
+    }
+}
+
+/// Move with the `MOVEMENTS` of the environment, reward the move and observe the new cell.
+/// An agent reaching a goal starts again from a spawn position.
+fn grid_step(
+    reward_fn: RewardFunction,
+    observe: fn(&Env, Position) -> State,
+) -> StepFunction<LearningAgent> {
+    Rc::new(
+        move |agent: &LearningAgent,
+              env: &mut Env,
+              position: Position,
+              _state: &State,
+              action: &Action|
+              -> (Position, State, Reward, Done) {
+            let outcome = env.apply_move(
+                position,
+                *action,
+                env.registry.get(MOVEMENTS).unwrap_or(&MovementSet::Four),
+            );
+            let reward = reward_fn(agent, env, position, &outcome);
+            *env.registry.get_or_insert_with(TOTAL_REWARD, || 0.) += reward as f64;
+
+            let mut new_position = outcome.position();
+            if is_goal(env, new_position) {
+                *env.registry.get_or_insert_with(GOALS_REACHED, || 0) += 1;
+                new_position = env.get_spawn_position();
+            }
+
+            (new_position, observe(env, new_position), reward, false)
+        },
+    )
+}
+
+fn cell_state(_env: &Env, position: Position) -> State {
+    vec![to_value(position.x), to_value(position.y)]
+}
+
+/// Whether the way to the nearest goal goes up, down, left and right
+fn goal_state(env: &Env, position: Position) -> State {
+    let next = match env.registry.get(GOAL_FLOWS) {
+        Some(flows) => flows
+            .iter()
+            .filter(|flow| flow.distance(position).is_some())
+            .min_by(|a, b| {
+                a.distance(position)
+                    .partial_cmp(&b.distance(position))
+                    .unwrap()
+            })
+            .and_then(|flow| flow.next_step(position)),
+        None => nearest_goal(env, position),
+    };
+    let Some(goal) = next else {
+        return vec![to_value(false); 4];
+    };
+
+    vec![
+        to_value(position.y < goal.y),
+        to_value(position.y > goal.y),
+        to_value(position.x < goal.x),
+        to_value(position.x > goal.x),
+    ]
+}
+
+fn is_goal(env: &Env, position: Position) -> bool {
+    env.registry
+        .get(GOALS)
+        .is_some_and(|goals| goals.contains(&position))
+}
+
+/// Nearest goal in Manhattan distance, ignoring walls
+fn nearest_goal(env: &Env, position: Position) -> Option<Position> {
+    env.registry
+        .get(GOALS)?
+        .iter()
+        .min_by_key(|goal| manhattan(position, **goal))
+        .copied()
+}
+
+fn manhattan(a: Position, b: Position) -> i32 {
+    (a.x - b.x).abs() + (a.y - b.y).abs()
+}
+
+/// Distance to the nearest goal, with the `GOAL_FLOWS` if there are some
+fn goal_distance(env: &Env, position: Position) -> Option<f32> {
+    match env.registry.get(GOAL_FLOWS) {
+        Some(flows) => flows
+            .iter()
+            .filter_map(|flow| flow.distance(position))
+            .min_by(|a, b| a.partial_cmp(b).unwrap()),
+        None => nearest_goal(env, position).map(|goal| manhattan(position, goal) as f32),
+    }
+}
+
+fn step_cost(_env: &mut Env, _position: Position, outcome: &MoveOutcome) -> Reward {
+    if outcome.is_blocked() {
+        -5.
+    } else {
+        -1.
+    }
+}
+
+fn goal_reward(env: &mut Env, position: Position, outcome: &MoveOutcome) -> Reward {
+    let new_position = outcome.position();
+    if is_goal(env, new_position) {
+        return 50.;
+    }
+
+    // As much lost going away as won getting closer, so going back and forth never pays
+    let progress = match (
+        goal_distance(env, position),
+        goal_distance(env, new_position),
+    ) {
+        (Some(before), Some(after)) => before - after,
+        _ => 0.,
+    };
+    -1. + 5. * progress
+}
+
+fn harvest_reward(env: &mut Env, _position: Position, outcome: &MoveOutcome) -> Reward {
+    let ids: Vec<u32> = env.resources.keys().copied().collect();
+    let harvested: f32 = ids
+        .into_iter()
+        .map(|id| env.harvest(id, outcome.position(), 1.))
+        .sum();
+
+    if harvested > 0. {
+        harvested
+    } else {
+        -1.
+    }
+}
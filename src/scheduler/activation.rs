@@ -0,0 +1,298 @@
+use std::{collections::HashMap, rc::Rc};
+
+use rand::{seq::IndexedRandom, Rng};
+
+use crate::{
+    agent::agent::Action,
+    environment::{environment::Env, movement::MovementSet},
+};
+
+use super::scheduler::{AgentRef, Position};
+
+/// Function run for every agent during a stage. Returns the new position of the agent.
+pub type StageFunction = Rc<dyn Fn(&mut Env, Position, &AgentRef) -> Position>;
+
+/// Phase of a tick with `Activation::Staged`
+#[derive(Clone)]
+pub enum Stage {
+    /// The agents take their usual step (choose an action, run the step function and learn)
+    Step,
+    /// Run a function for every agent, for example to eat or reproduce after everyone moved
+    Each(StageFunction),
+}
+
+/// What happens when several agents want to end their simultaneous step on the same cell
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConflictResolution {
+    /// Agents can share cells
+    Allow,
+    /// One of the agents gets the cell (an agent that does not move keeps it, otherwise one at
+    /// random) and the others stay on their cell without taking their step.
+    ///
+    /// Where an action leads is predicted with the movement set before any step function
+    /// runs, so the step functions should move the agents with the same set.
+    Random(MovementSet),
+}
+
+/// Order in which the agents act during a tick, like the schedulers of Mesa
+#[derive(Clone, Default)]
+pub enum Activation {
+    /// One after the other, the last added first. Later agents see the world changed by
+    /// the earlier ones.
+    #[default]
+    Sequential,
+    /// One after the other in a new random order every tick
+    Random,
+    /// The types in a random order, and the agents of each type in a random order
+    RandomByType,
+    /// Every agent chooses its action from the same world, then the conflicts over the
+    /// cells are resolved, then the actions of the agents still stepping are applied in a
+    /// random order
+    Simultaneous(ConflictResolution),
+    /// Every stage is run for all the agents before the next one starts
+    Staged { stages: Vec<Stage>, shuffle: bool },
+}
+
+/// Indices of the agents sorted by type, each type being a group of indices
+pub(crate) fn group_by_type(types: &[&'static str]) -> Vec<Vec<usize>> {
+    let mut groups: HashMap<&'static str, Vec<usize>> = HashMap::new();
+    for (i, agent_type) in types.iter().enumerate() {
+        groups.entry(agent_type).or_default().push(i);
+    }
+
+    let mut groups: Vec<(&'static str, Vec<usize>)> = groups.into_iter().collect();
+    groups.sort_by_key(|(agent_type, _)| *agent_type);
+    groups.into_iter().map(|(_, indices)| indices).collect()
+}
+
+/// Which agents take their simultaneous step, given the positions before the step and the
+/// actions chosen: all of them with `ConflictResolution::Allow`, otherwise those that do not
+/// lose a conflict over the cell their action leads to.
+pub fn simultaneous_steps(
+    env: &Env,
+    previous: &[Position],
+    actions: &[Action],
+    resolution: &ConflictResolution,
+    rng: &mut impl Rng,
+) -> Vec<bool> {
+    let ConflictResolution::Random(movements) = resolution else {
+        return vec![true; previous.len()];
+    };
+
+    let targets: Vec<Position> = previous
+        .iter()
+        .zip(actions)
+        .map(|(position, action)| env.apply_move(*position, *action, movements).position())
+        .collect();
+    let mut next = targets.clone();
+    resolve_conflicts(previous, &mut next, resolution, rng);
+
+    // Sent back to where it was although its action led elsewhere
+    targets
+        .iter()
+        .zip(next)
+        .map(|(target, position)| *target == position)
+        .collect()
+}
+
+/// Send back the agents that lost a conflict over a cell until no two agents share a cell
+/// they moved into. `previous` and `next` are the positions before and after the step.
+/// Winners among movers are drawn with `rng`.
+pub fn resolve_conflicts(
+    previous: &[Position],
+    next: &mut [Position],
+    resolution: &ConflictResolution,
+    rng: &mut impl Rng,
+) {
+    if *resolution == ConflictResolution::Allow {
+        return;
+    }
+
+    // Every round sends at least one mover back for good, so this ends
+    loop {
+        let mut cells: HashMap<Position, Vec<usize>> = HashMap::new();
+        for (i, position) in next.iter().enumerate() {
+            cells.entry(*position).or_default().push(i);
+        }
+
+        // Winners are drawn cell by cell in a fixed order, so a seed gives the same winners
+        let mut conflicts: Vec<(Position, Vec<usize>)> = cells
+            .into_iter()
+            .filter(|(_, agents)| agents.len() > 1)
+            .collect();
+        conflicts.sort_by_key(|(position, _)| (position.y, position.x));
+
+        let mut changed = false;
+        for (_, agents) in conflicts {
+            let movers: Vec<usize> = agents
+                .iter()
+                .copied()
+                .filter(|i| next[*i] != previous[*i])
+                .collect();
+
+            // Agents that stayed keep the cell, otherwise one of the movers gets it
+            let winner = if movers.len() < agents.len() {
+                None
+            } else {
+                movers.choose(rng).copied()
+            };
+
+            for i in movers {
+                if Some(i) != winner {
+                    next[i] = previous[i];
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use macroquad::{color::RED, math::vec2};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        agent::{
+            agent::{Agent, IsAgent, StepFunction},
+            learning_agent::LearningAgent,
+            state::Value,
+        },
+        environment::{movement::RIGHT, registry::Key},
+        interface::grid::GridSize,
+        scheduler::scheduler::Scheduler,
+    };
+
+    const STEPS: Key<u32> = Key::new("steps");
+
+    const A: Position = Position { x: 0, y: 0 };
+    const B: Position = Position { x: 1, y: 0 };
+    const C: Position = Position { x: 2, y: 0 };
+
+    #[test]
+    fn conflicts_are_resolved() {
+        let mut rng = rand::rng();
+
+        // Two agents rushing to the same cell: only one gets it
+        let previous = [A, C];
+        let mut next = [B, B];
+        resolve_conflicts(
+            &previous,
+            &mut next,
+            &ConflictResolution::Random(MovementSet::Four),
+            &mut rng,
+        );
+        assert!(next == [B, C] || next == [A, B]);
+
+        // An agent staying on its cell keeps it and the one pushed back
+        // makes the agent that took its cell go back too
+        let previous = [B, A, C];
+        let mut next = [B, B, A];
+        resolve_conflicts(
+            &previous,
+            &mut next,
+            &ConflictResolution::Random(MovementSet::Four),
+            &mut rng,
+        );
+        assert_eq!(next, [B, A, C]);
+
+        let mut next = [B, B, A];
+        resolve_conflicts(&previous, &mut next, &ConflictResolution::Allow, &mut rng);
+        assert_eq!(next, [B, B, A]);
+    }
+
+    #[test]
+    fn seeded_resolutions_are_deterministic() {
+        // Twenty pairs of agents rushing to twenty cells at once
+        let previous: Vec<Position> = (0..20)
+            .flat_map(|y| [Position { x: 0, y }, Position { x: 2, y }])
+            .collect();
+        let resolve = || {
+            let mut next: Vec<Position> = (0..40).map(|i| Position { x: 1, y: i / 2 }).collect();
+            let mut rng = StdRng::seed_from_u64(7);
+            resolve_conflicts(
+                &previous,
+                &mut next,
+                &ConflictResolution::Random(MovementSet::Four),
+                &mut rng,
+            );
+            next
+        };
+
+        let next = resolve();
+        for _ in 0..5 {
+            assert_eq!(resolve(), next);
+        }
+    }
+
+    #[test]
+    fn losers_do_not_step() {
+        let mut env = Env::new(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            GridSize {
+                width: 3,
+                heigth: 1,
+            },
+            HashMap::new(),
+            &[RIGHT],
+            HashMap::new(),
+        );
+        env.registry.insert(STEPS, 0);
+
+        // Every step is counted, so a step function running for a loser would show
+        let step_fn: StepFunction<LearningAgent> = Rc::new(|_, env, position, _, action| {
+            *env.registry.get_mut(STEPS).unwrap() += 1;
+            let position = env
+                .apply_move(position, *action, &MovementSet::Four)
+                .position();
+            (position, vec![Value::VI32(position.x)], 1., false)
+        });
+
+        let mut scheduler = Scheduler::new(env);
+        scheduler.activation =
+            Activation::Simultaneous(ConflictResolution::Random(MovementSet::Four));
+        scheduler
+            .add_agents(
+                2,
+                Some(A),
+                RED,
+                "walker",
+                vec![Value::VI32(0)],
+                None,
+                None,
+                None,
+                &step_fn,
+                None,
+            )
+            .unwrap();
+        scheduler.take_step();
+
+        // Both wanted B: one got it, the other did not change anything
+        assert_eq!(*scheduler.env.registry.get(STEPS).unwrap(), 1);
+        let mut positions = scheduler.agent_positions();
+        positions.sort_by_key(|position| position.x);
+        assert_eq!(positions, vec![A, B]);
+        for (position, _, agent) in &scheduler.agents {
+            let Agent::Learning(agent) = &*agent.borrow() else {
+                unreachable!()
+            };
+            let learned = agent.q_table().len();
+            assert_eq!(learned, if *position == B { 1 } else { 0 });
+            assert_eq!(agent.get_state(), &vec![Value::VI32(position.x)]);
+        }
+    }
+
+    #[test]
+    fn groups_of_types() {
+        let groups = group_by_type(&["wolf", "sheep", "wolf", "grass"]);
+        assert_eq!(groups, vec![vec![3], vec![1], vec![0, 2]]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+    fmt,
+    sync::Arc,
+};
+
+use crate::environment::environment::Env;
+
+/// Simulated time. Stepped agents act at every whole unit of time.
+pub type Time = f64;
+pub type EventId = u64;
+/// Function run by the environment when its event happens
+pub type EnvEventFunction = Arc<dyn Fn(&mut Env) + Send + Sync>;
+
+#[derive(Clone)]
+pub enum Event {
+    /// The agent with this unique id takes a step
+    Activate(u32),
+    /// Run a function on the environment
+    Env(EnvEventFunction),
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Activate(id) => write!(f, "Activate({})", id),
+            Event::Env(_) => write!(f, "Env"),
+        }
+    }
+}
+
+struct Scheduled {
+    time: Time,
+    priority: i32,
+    id: EventId,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    /// The greatest is the next one: earliest time, then highest priority, then first scheduled
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .time
+            .total_cmp(&self.time)
+            .then(self.priority.cmp(&other.priority))
+            .then(other.id.cmp(&self.id))
+    }
+}
+
+/// Future events of the simulation and its clock
+#[derive(Default)]
+pub struct EventQueue {
+    now: Time,
+    next_id: EventId,
+    queue: BinaryHeap<Scheduled>,
+    /// Events scheduled and not cancelled yet
+    pending: HashSet<EventId>,
+}
+
+impl EventQueue {
+    pub fn new() -> Self {
+        EventQueue::default()
+    }
+
+    /// Current time of the simulation
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
+    /// Schedule an event at an absolute time. Events in the past happen right away.
+    /// Among events at the same time, the highest priority comes first.
+    pub fn schedule_at(&mut self, time: Time, priority: i32, event: Event) -> EventId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.queue.push(Scheduled {
+            time: time.max(self.now),
+            priority,
+            id,
+            event,
+        });
+        self.pending.insert(id);
+
+        id
+    }
+
+    /// Schedule an event `delay` after now
+    pub fn schedule_in(&mut self, delay: Time, priority: i32, event: Event) -> EventId {
+        self.schedule_at(self.now + delay, priority, event)
+    }
+
+    /// Returns false if the event already happened or was already cancelled
+    pub fn cancel(&mut self, id: EventId) -> bool {
+        self.pending.remove(&id)
+    }
+
+    pub fn is_pending(&self, id: EventId) -> bool {
+        self.pending.contains(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Time of the next event
+    pub fn peek_time(&mut self) -> Option<Time> {
+        self.drop_cancelled();
+        self.queue.peek().map(|scheduled| scheduled.time)
+    }
+
+    /// Remove the next event and move the clock to its time
+    pub fn pop(&mut self) -> Option<(Time, Event)> {
+        self.drop_cancelled();
+        let scheduled = self.queue.pop()?;
+        self.pending.remove(&scheduled.id);
+        self.now = scheduled.time;
+
+        Some((scheduled.time, scheduled.event))
+    }
+
+    /// Move the clock forward without any event
+    pub fn advance_to(&mut self, time: Time) {
+        self.now = self.now.max(time);
+    }
+
+    /// Remove every event and put the clock back to 0
+    pub fn clear(&mut self) {
+        *self = EventQueue {
+            next_id: self.next_id,
+            ..EventQueue::default()
+        };
+    }
+
+    fn drop_cancelled(&mut self) {
+        while let Some(scheduled) = self.queue.peek() {
+            if self.pending.contains(&scheduled.id) {
+                break;
+            }
+            self.queue.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(event: Option<(Time, Event)>) -> Option<(Time, u32)> {
+        match event {
+            Some((time, Event::Activate(id))) => Some((time, id)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn chronological_order() {
+        let mut events = EventQueue::new();
+        events.schedule_at(2., 0, Event::Activate(1));
+        events.schedule_at(1., 0, Event::Activate(2));
+        events.schedule_at(2., 5, Event::Activate(3));
+        events.schedule_at(2., 0, Event::Activate(4));
+
+        assert_eq!(agent(events.pop()), Some((1., 2)));
+        assert_eq!(events.now(), 1.);
+        // Highest priority first, then in the order they were scheduled
+        assert_eq!(agent(events.pop()), Some((2., 3)));
+        assert_eq!(agent(events.pop()), Some((2., 1)));
+        assert_eq!(agent(events.pop()), Some((2., 4)));
+        assert!(events.pop().is_none());
+
+        // Relative to the clock, never in the past
+        events.schedule_in(0.5, 0, Event::Activate(5));
+        events.schedule_at(0., 0, Event::Activate(6));
+        assert_eq!(agent(events.pop()), Some((2., 6)));
+        assert_eq!(agent(events.pop()), Some((2.5, 5)));
+    }
+
+    #[test]
+    fn cancelling() {
+        let mut events = EventQueue::new();
+        let first = events.schedule_at(1., 0, Event::Activate(1));
+        events.schedule_at(3., 0, Event::Activate(2));
+
+        assert!(events.cancel(first));
+        assert!(!events.cancel(first));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events.peek_time(), Some(3.));
+        assert_eq!(agent(events.pop()), Some((3., 2)));
+        assert!(events.is_empty());
+    }
+}
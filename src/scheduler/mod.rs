@@ -1 +1,5 @@
+pub mod activation;
+pub mod events;
+pub mod parallel_scheduler;
+#[allow(clippy::module_inception)]
 pub mod scheduler;
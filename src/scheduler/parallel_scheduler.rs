@@ -0,0 +1,473 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use macroquad::{color::Color, math::Vec2};
+use rand::{rngs::StdRng, SeedableRng};
+use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator,
+        ParallelIterator,
+    },
+    ThreadPool, ThreadPoolBuilder,
+};
+
+use crate::{
+    agent::{
+        agent::{Action, Done, QTable, Reward},
+        dyna::{Dyna, DynaConfig},
+        merge::{merge_q_tables, MergeStrategy, QTableSync},
+        parallel_agent::{ParallelAgent, ParallelStepFunction, ShardedQTable},
+        replay::{ReplayBuffer, ReplayConfig},
+        state::State,
+    },
+    environment::environment::Env,
+};
+
+use super::{
+    activation::{simultaneous_steps, ConflictResolution},
+    events::{Event, Time},
+    scheduler::{display_agents, EnvStepFunction, Hooks, Position},
+};
+
+/// Result of the step of one agent, learned from once every agent stepped
+struct Transition {
+    action: Action,
+    position: Position,
+    next_state: State,
+    reward: Reward,
+    done: Done,
+}
+
+/// Scheduler stepping all its agents at the same time on a thread pool, like
+/// `Activation::Simultaneous`.
+///
+/// Each tick, every agent chooses its action in parallel from the same environment, the
+/// conflicts are resolved, and the agents still stepping run their step function in
+/// parallel against the same (read-only) environment. Then, in the order of the agents, the
+/// Q-tables are updated, with the replays and the planning of the agents. Every agent has its
+/// own random generator derived from the seed, so a run only depends on the seed, not on the
+/// number of threads.
+pub struct ParallelScheduler {
+    /// Position, color, agent and its random generator
+    pub agents: Vec<(Position, Color, ParallelAgent, StdRng)>,
+    pub env: Env,
+    /// What happens when several agents end their step on the same cell
+    pub conflicts: ConflictResolution,
+    /// Periodic merging of the Q-tables of the agents of a type that do not share one
+    pub q_table_sync: Option<QTableSync>,
+    seed: u64,
+    /// Global thread pool of rayon when `None`
+    pool: Option<ThreadPool>,
+    hooks: Hooks,
+}
+
+impl ParallelScheduler {
+    /// The random generator of `env`, which resolves the conflicts and places the agents, is
+    /// seeded with `seed` too
+    pub fn new(mut env: Env, seed: u64) -> Self {
+        env.set_seed(seed);
+        ParallelScheduler {
+            agents: Vec::new(),
+            env,
+            conflicts: ConflictResolution::Allow,
+            q_table_sync: None,
+            seed,
+            pool: None,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Step the agents on a dedicated pool of `threads` threads instead of the global one
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.pool = Some(
+            ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .expect("Failed to build the thread pool"),
+        );
+        self
+    }
+
+    /// Add a function called each tick before the agents take their step
+    pub fn add_pre_step(&mut self, env_step_fn: EnvStepFunction) {
+        self.hooks.add_pre_step(env_step_fn);
+    }
+
+    /// Add a function called each tick after the agents took their step
+    pub fn add_post_step(&mut self, env_step_fn: EnvStepFunction) {
+        self.hooks.add_post_step(env_step_fn);
+    }
+
+    pub fn agent_positions(&self) -> Vec<Position> {
+        self.agents
+            .iter()
+            .map(|(position, _, _, _)| *position)
+            .collect()
+    }
+
+    pub fn display_env(&mut self, start: Vec2, end: Vec2, grid_color: Color) {
+        let agents = self
+            .agents
+            .iter()
+            .map(|(position, color, _, _)| (*position, *color));
+        display_agents(&mut self.env, start, end, grid_color, agents);
+    }
+
+    /// Add **Multiple** agents. With a `q_table` they all share it, otherwise each one gets
+    /// its own.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_agents(
+        &mut self,
+        n: usize,
+        position: Option<Position>,
+        color: Color,
+        agent_type: &'static str,
+        state: State,
+        learning_rate: Option<f32>,
+        discount_factor: Option<f32>,
+        exploration_rate: Option<f32>,
+        step_fn: &ParallelStepFunction,
+        q_table: Option<Arc<ShardedQTable>>,
+    ) {
+        for _ in 0..n {
+            let position = position.unwrap_or_else(|| self.env.get_spawn_position());
+            let id = self.hooks.generate_id();
+
+            let agent = ParallelAgent::new(
+                id,
+                agent_type,
+                state.clone(),
+                learning_rate,
+                discount_factor,
+                exploration_rate,
+                step_fn,
+                q_table.clone().unwrap_or_default(),
+            );
+            // Derived from the id so an agent draws the same numbers whoever else is there
+            let rng =
+                StdRng::seed_from_u64(self.seed ^ (id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+            self.agents.push((position, color, agent, rng));
+        }
+    }
+
+    /// Give the agents of a type experience replay, with a buffer each or one for all of them
+    /// (`ReplayConfig::shared`). `None` turns it off.
+    pub fn set_replay(&mut self, agent_type: &str, config: Option<ReplayConfig>) {
+        let new_buffer = || config.map(|config| Arc::new(Mutex::new(ReplayBuffer::new(config))));
+        let shared = new_buffer().filter(|_| config.is_some_and(|config| config.shared));
+        for (_, _, agent, _) in &mut self.agents {
+            if agent.agent_type == agent_type {
+                agent.replay = shared.clone().or_else(new_buffer);
+            }
+        }
+    }
+
+    /// Make the agents of a type plan with Dyna-Q, each with its own model. `None` turns it
+    /// off.
+    pub fn set_dyna(&mut self, agent_type: &str, config: Option<DynaConfig>) {
+        for (_, _, agent, _) in &mut self.agents {
+            if agent.agent_type == agent_type {
+                agent.dyna = config.map(Dyna::new);
+            }
+        }
+    }
+
+    /// Merge the Q-tables of the agents of each type, weighing them by what they learned
+    /// since the last merge, and give the result to all of them. Nothing changes for a type
+    /// whose agents all share the same table.
+    pub fn sync_q_tables(&mut self, strategy: MergeStrategy) {
+        let mut agent_types: Vec<&'static str> = self
+            .agents
+            .iter()
+            .map(|(_, _, agent, _)| agent.agent_type)
+            .collect();
+        agent_types.sort();
+        agent_types.dedup();
+
+        for agent_type in agent_types {
+            let mut agents: Vec<&mut ParallelAgent> = self
+                .agents
+                .iter_mut()
+                .map(|(_, _, agent, _)| agent)
+                .filter(|agent| agent.agent_type == agent_type)
+                .collect();
+            if agents
+                .iter()
+                .all(|agent| Arc::ptr_eq(agent.q_table(), agents[0].q_table()))
+            {
+                continue;
+            }
+
+            let q_tables: Vec<QTable> = agents
+                .iter()
+                .map(|agent| agent.q_table().to_q_table())
+                .collect();
+            let tables: Vec<_> = q_tables
+                .iter()
+                .zip(&agents)
+                .map(|(q_table, agent)| (q_table, Some(agent.visits())))
+                .collect();
+            let merged = merge_q_tables(&tables, strategy);
+
+            // A shared table is replaced once
+            let mut replaced: Vec<*const ShardedQTable> = Vec::new();
+            for agent in &mut agents {
+                let q_table = Arc::as_ptr(agent.q_table());
+                if replaced.contains(&q_table) {
+                    agent.clear_visits();
+                } else {
+                    replaced.push(q_table);
+                    agent.replace_q_table(merged.clone());
+                }
+            }
+        }
+    }
+
+    /// Advance the simulation by one tick: the environment events until then, then the agents.
+    /// `Event::Activate` is ignored since every agent acts every tick.
+    pub fn take_step(&mut self) {
+        let next_tick = (self.env.tick + 1) as Time;
+        while self
+            .env
+            .events
+            .peek_time()
+            .is_some_and(|time| time <= next_tick)
+        {
+            if let Some((_, Event::Env(env_event_fn))) = self.env.events.pop() {
+                env_event_fn(&mut self.env);
+            }
+        }
+        self.env.events.advance_to(next_tick);
+
+        self.tick();
+    }
+
+    /// Run `nb_steps` ticks
+    pub fn train(&mut self, nb_steps: u32) {
+        for _ in 0..nb_steps {
+            self.take_step();
+        }
+    }
+
+    fn tick(&mut self) {
+        let positions = self.agent_positions();
+        self.hooks.run_pre_step(&mut self.env, &positions);
+
+        // Everyone chooses from the same world, on the thread pool
+        let env = &self.env;
+        let actions: Vec<Action> = install(&self.pool, || {
+            self.agents
+                .par_iter_mut()
+                .map(|(_, _, agent, rng)| agent.choose_action(&agent.state, &env.actions, rng))
+                .collect()
+        });
+
+        // The losers of a conflict do not step
+        let previous: Vec<Position> = self.agents.iter().map(|(position, ..)| *position).collect();
+        let conflicts = &self.conflicts;
+        let steps = self
+            .env
+            .with_rng(|env, rng| simultaneous_steps(env, &previous, &actions, conflicts, rng));
+        let env = &self.env;
+
+        let transitions: Vec<Option<Transition>> = install(&self.pool, || {
+            self.agents
+                .par_iter_mut()
+                .zip(actions.par_iter().zip(steps.par_iter()))
+                .map(|((position, _, agent, rng), (action, step))| {
+                    step.then(|| {
+                        let (position, next_state, reward, done) =
+                            agent.step(env, *position, *action, rng);
+                        Transition {
+                            action: *action,
+                            position,
+                            next_state,
+                            reward,
+                            done,
+                        }
+                    })
+                })
+                .collect()
+        });
+
+        // Learn in the order of the agents so shared Q-tables and replay buffers end up the
+        // same every run
+        let mut done = HashSet::new();
+        for ((position, _, agent, rng), transition) in self.agents.iter_mut().zip(transitions) {
+            let Some(transition) = transition else {
+                continue;
+            };
+
+            let state = agent.state.clone();
+            agent.update(
+                &state,
+                transition.action,
+                transition.reward,
+                &transition.next_state,
+                &self.env.actions,
+                transition.done,
+                rng,
+            );
+            agent.state = transition.next_state;
+
+            *position = transition.position;
+            if transition.done {
+                done.insert(agent.id);
+            }
+        }
+
+        self.agents
+            .retain(|(_, _, agent, _)| !done.contains(&agent.id));
+
+        let positions = self.agent_positions();
+        self.hooks.run_post_step(&mut self.env, &positions);
+
+        if let Some(sync) = self.q_table_sync.filter(|sync| sync.is_due(self.env.tick)) {
+            self.sync_q_tables(sync.strategy);
+        }
+    }
+}
+
+/// Run `f` on `pool`, or on the global thread pool of rayon
+fn install<T: Send>(pool: &Option<ThreadPool>, f: impl FnOnce() -> T + Send) -> T {
+    match pool {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use macroquad::{color::RED, math::vec2};
+    use rand::Rng;
+
+    use super::*;
+    use crate::{
+        agent::{agent::QTable, state::Value},
+        environment::movement::{MovementSet, MOVES_4},
+        interface::grid::GridSize,
+    };
+
+    /// 40 walkers trained for 30 ticks, sharing a Q-table or each with its own, after
+    /// `configure`
+    fn trained(
+        threads: usize,
+        seed: u64,
+        shared: bool,
+        configure: impl Fn(&mut ParallelScheduler),
+    ) -> ParallelScheduler {
+        let env = Env::new(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            GridSize {
+                width: 8,
+                heigth: 8,
+            },
+            HashMap::new(),
+            MOVES_4,
+            HashMap::new(),
+        );
+
+        // Go right, with some noise to check the generators of the agents
+        let step_fn: ParallelStepFunction = Arc::new(|_, env, position, _, action, rng| {
+            let position = env
+                .apply_move(position, *action, &MovementSet::Four)
+                .position();
+            let reward = position.x as f32 + rng.random_range(0.0..0.1);
+            (position, vec![Value::VI32(position.x)], reward, false)
+        });
+
+        let mut scheduler = ParallelScheduler::new(env, seed).with_threads(threads);
+        scheduler.conflicts = ConflictResolution::Random(MovementSet::Four);
+        // Crowded enough for several conflicts every tick
+        scheduler.add_agents(
+            40,
+            None,
+            RED,
+            "walker",
+            vec![Value::VI32(0)],
+            None,
+            None,
+            None,
+            &step_fn,
+            shared.then(|| Arc::new(ShardedQTable::new(4))),
+        );
+        configure(&mut scheduler);
+        scheduler.train(30);
+
+        scheduler
+    }
+
+    fn run(threads: usize, seed: u64) -> (Vec<Position>, QTable) {
+        let scheduler = trained(threads, seed, true, |_| {});
+        let q_table = scheduler.agents[0].2.q_table().to_q_table();
+        (scheduler.agent_positions(), q_table)
+    }
+
+    fn q_tables(scheduler: &ParallelScheduler) -> Vec<QTable> {
+        scheduler
+            .agents
+            .iter()
+            .map(|(_, _, agent, _)| agent.q_table().to_q_table())
+            .collect()
+    }
+
+    #[test]
+    fn seeded_runs_are_deterministic() {
+        let (positions, q_table) = run(1, 42);
+        assert_eq!(run(4, 42), (positions.clone(), q_table));
+
+        // No two agents on the same cell
+        let cells: HashSet<Position> = positions.iter().copied().collect();
+        assert_eq!(cells.len(), positions.len());
+    }
+
+    #[test]
+    fn sync_replay_and_planning() {
+        let configure = |scheduler: &mut ParallelScheduler| {
+            scheduler.q_table_sync = Some(QTableSync {
+                interval: 10,
+                strategy: MergeStrategy::Average,
+            });
+            scheduler.set_replay(
+                "walker",
+                Some(ReplayConfig {
+                    batch_size: 4,
+                    ..ReplayConfig::default()
+                }),
+            );
+            scheduler.set_dyna(
+                "walker",
+                Some(DynaConfig {
+                    planning_steps: 3,
+                    exploration_bonus: 0.,
+                }),
+            );
+        };
+        let scheduler = trained(1, 42, false, configure);
+        let other = trained(4, 42, false, configure);
+        assert_eq!(other.agent_positions(), scheduler.agent_positions());
+        assert_eq!(q_tables(&other), q_tables(&scheduler));
+
+        // Merged after the last tick
+        let merged = q_tables(&scheduler);
+        assert!(merged.iter().all(|q_table| *q_table == merged[0]));
+        assert!(scheduler
+            .agents
+            .iter()
+            .all(|(_, _, agent, _)| agent.visits().is_empty()));
+
+        let (_, _, agent, _) = &scheduler.agents[0];
+        assert!(!agent.replay.as_ref().unwrap().lock().unwrap().is_empty());
+        assert!(!agent.dyna.as_ref().unwrap().model.is_empty());
+
+        // The tables learned alone differ
+        let alone = q_tables(&trained(1, 42, false, |_| {}));
+        assert!(alone.iter().any(|q_table| *q_table != alone[0]));
+    }
+}
@@ -1,29 +1,105 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{RefCell, RefMut},
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use macroquad::{
     color::Color,
     math::{IVec2, Vec2},
 };
+use rand::{seq::SliceRandom, Rng};
 
 use crate::{
     agent::{
-        agent::{Agent, IsAgent, QTable, StepFunction},
+        agent::{Action, Agent, IsAgent, QTable, StepFunction},
+        dyna::{Dyna, DynaConfig},
         learning_agent::LearningAgent,
+        merge::{merge_q_tables, MergeStrategy, QTableSync},
+        q_table::{load_q_table_or_empty, Fingerprint, QTableError},
+        replay::{ReplayBuffer, ReplayConfig},
         state::State,
         swarm_agent::SwarmAgent,
     },
-    environment::environment::Env,
+    environment::{continuous::Body, environment::Env},
+};
+
+use super::{
+    activation::{group_by_type, simultaneous_steps, Activation, Stage},
+    events::{Event, Time},
 };
 
 pub type AgentRef = Rc<RefCell<Agent>>;
 // pub type AgentRef = Rc<RefCell<LearningAgent>>;
 pub type Position = IVec2;
+/// Makes the world itself evolve (moving a goal, spreading fire, etc.).
+/// Receives the environment and the positions of the agents.
+pub type EnvStepFunction = Rc<dyn Fn(&mut Env, &[Position])>;
+
+/// What every scheduler does around the steps of its agents: the functions making the world
+/// evolve each tick, and the ids of the new agents
+#[derive(Default)]
+pub(crate) struct Hooks {
+    /// Called each tick before the agents take their step
+    pre_step: Vec<EnvStepFunction>,
+    /// Called each tick after the agents took their step, before `Env::update`
+    post_step: Vec<EnvStepFunction>,
+    /// This is the count of id and next id to be given to an agent
+    current_id: u32,
+}
+
+impl Hooks {
+    pub(crate) fn add_pre_step(&mut self, env_step_fn: EnvStepFunction) {
+        self.pre_step.push(env_step_fn);
+    }
+
+    pub(crate) fn add_post_step(&mut self, env_step_fn: EnvStepFunction) {
+        self.post_step.push(env_step_fn);
+    }
+
+    pub(crate) fn run_pre_step(&self, env: &mut Env, positions: &[Position]) {
+        for env_step_fn in &self.pre_step {
+            env_step_fn(env, positions);
+        }
+    }
+
+    /// Run the post step functions then update the environment (fields, resources and tick)
+    pub(crate) fn run_post_step(&self, env: &mut Env, positions: &[Position]) {
+        for env_step_fn in &self.post_step {
+            env_step_fn(env, positions);
+        }
+        env.update();
+    }
+
+    pub(crate) fn generate_id(&mut self) -> u32 {
+        self.current_id += 1;
+        self.current_id
+    }
+}
+
+/// Draw the grid of `env` with the agents on it
+pub(crate) fn display_agents(
+    env: &mut Env,
+    start: Vec2,
+    end: Vec2,
+    grid_color: Color,
+    agents: impl Iterator<Item = (Position, Color)>,
+) {
+    env.display_grid(start, end, grid_color, agents.collect());
+}
+
 pub struct Scheduler {
     pub agents: Vec<(Position, Color, AgentRef)>,
     pub agents_per_types: HashMap<&'static str, Vec<AgentRef>>,
     pub env: Env,
-    /// This is the count of id and next id to be given to an agent
-    current_id: u32,
+    /// Order in which the agents act each tick
+    pub activation: Activation,
+    /// Unique ids of the agents acting on `Event::Activate` instead of every tick
+    event_driven: HashSet<u32>,
+    /// Periodic merging of the Q-tables of the learning agents, who learn alone otherwise
+    pub q_table_sync: Option<QTableSync>,
+    hooks: Hooks,
     // pub function_step: HashMap<&'static str, StepFunction>,
 }
 
@@ -33,24 +109,83 @@ impl Scheduler {
             agents: Vec::new(),
             agents_per_types: HashMap::new(),
             env,
-            current_id: 0,
+            activation: Activation::default(),
+            event_driven: HashSet::new(),
+            q_table_sync: None,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Add a function called each tick before the agents take their step
+    pub fn add_pre_step(&mut self, env_step_fn: EnvStepFunction) {
+        self.hooks.add_pre_step(env_step_fn);
+    }
+
+    /// Add a function called each tick after the agents took their step
+    pub fn add_post_step(&mut self, env_step_fn: EnvStepFunction) {
+        self.hooks.add_post_step(env_step_fn);
+    }
+
+    pub fn agent_positions(&self) -> Vec<Position> {
+        self.agents
+            .iter()
+            .map(|(position, _, _)| *position)
+            .collect()
+    }
+
+    fn run_pre_step(&mut self, positions: &[Position]) {
+        self.hooks.run_pre_step(&mut self.env, positions);
+    }
+
+    /// Run the post step functions then update the environment (fields, resources and tick).
+    /// Merge the Q-tables when `q_table_sync` is due.
+    fn run_post_step(&mut self, positions: &[Position]) {
+        self.hooks.run_post_step(&mut self.env, positions);
+
+        if let Some(sync) = self.q_table_sync.filter(|sync| sync.is_due(self.env.tick)) {
+            self.sync_q_tables(sync.strategy);
+        }
+    }
+
+    /// Merge the Q-tables of the learning agents of each type, weighing them by what they
+    /// learned since the last merge, and give the result to all of them. Swarming agents
+    /// already share theirs.
+    pub fn sync_q_tables(&mut self, strategy: MergeStrategy) {
+        for agents in self.agents_per_types.values() {
+            let mut agents: Vec<RefMut<Agent>> =
+                agents.iter().map(|agent| agent.borrow_mut()).collect();
+            let mut learners: Vec<&mut LearningAgent> = agents
+                .iter_mut()
+                .filter_map(|agent| match &mut **agent {
+                    Agent::Learning(agent) => Some(agent),
+                    Agent::Swarm(_) => None,
+                })
+                .collect();
+            if learners.len() < 2 {
+                continue;
+            }
+
+            let tables: Vec<_> = learners
+                .iter()
+                .map(|agent| (agent.q_table(), Some(agent.visits())))
+                .collect();
+            let merged = merge_q_tables(&tables, strategy);
+            for agent in &mut learners {
+                agent.replace_q_table(merged.clone());
+            }
         }
     }
 
     pub fn display_env(&mut self, start: Vec2, end: Vec2, grid_color: Color) {
-        let agent_positions: Vec<(Position, Color)> = self
+        let agents = self
             .agents
             .iter()
-            .map(|(position, color, _)| (*position, *color))
-            .collect();
-
-        self.env
-            .display_grid(start, end, grid_color, agent_positions);
+            .map(|(position, color, _)| (*position, *color));
+        display_agents(&mut self.env, start, end, grid_color, agents);
     }
 
     fn generate_id(&mut self) -> u32 {
-        self.current_id += 1;
-        self.current_id
+        self.hooks.generate_id()
     }
 
     // Add **ONE** agent
@@ -94,7 +229,10 @@ impl Scheduler {
     //     }
     // }
 
-    /// Add **Multiple** learning agents
+    /// Add **Multiple** learning agents. They start from the Q-table of `q_table_filepath` if
+    /// the file exists, which must have been trained with the actions of the environment and
+    /// states like `state`.
+    #[allow(clippy::too_many_arguments)]
     pub fn add_agents(
         &mut self,
         n: usize,
@@ -107,14 +245,18 @@ impl Scheduler {
         exploration_rate: Option<f32>,
         step_fn: &StepFunction<LearningAgent>,
         q_table_filepath: Option<&str>,
-    ) {
+    ) -> Result<(), QTableError> {
+        let q_table = match q_table_filepath {
+            Some(filepath) => {
+                let expected = Fingerprint::expected(&self.env.actions, &state);
+                load_q_table_or_empty(filepath, Some(&expected))?
+            }
+            None => QTable::new(),
+        };
         let mut new_agents: Vec<(Position, Color, AgentRef)> = Vec::with_capacity(n);
 
         for _ in 0..n {
-            let position = position.unwrap_or(Position {
-                x: rand::random_range(0..*self.env.get_width() as i32),
-                y: rand::random_range(0..*self.env.get_heigth() as i32),
-            });
+            let position = position.unwrap_or_else(|| self.env.get_spawn_position());
 
             let new_agent = Rc::new(RefCell::new(Agent::Learning(LearningAgent::new(
                 self.generate_id(),
@@ -124,22 +266,23 @@ impl Scheduler {
                 discount_factor,
                 exploration_rate,
                 step_fn,
-                q_table_filepath,
+                q_table.clone(),
             ))));
 
             new_agents.push((position, color, new_agent));
         }
 
+        let mut new_agents_type: Vec<AgentRef> = new_agents
+            .iter()
+            .map(|(_, _, agent)| agent.clone())
+            .collect();
+
         // Add all the new agents in Vector with all the other agents
         self.agents.append(&mut new_agents);
 
         // Add new agent in agents_per_types
         let agents = self.agents_per_types.get_mut(agent_type);
 
-        let mut new_agents_type: Vec<AgentRef> = new_agents
-            .iter()
-            .map(|(_, _, agent)| agent.clone())
-            .collect();
         match agents {
             Some(list) => {
                 list.append(&mut new_agents_type);
@@ -148,9 +291,11 @@ impl Scheduler {
                 let _ = self.agents_per_types.insert(agent_type, new_agents_type);
             }
         }
+        Ok(())
     }
 
     /// Add **Multiple** swarming agents
+    #[allow(clippy::too_many_arguments)]
     pub fn add_swarming_agents(
         &mut self,
         n: usize,
@@ -168,10 +313,7 @@ impl Scheduler {
         let mut new_agents_type: Vec<AgentRef> = Vec::with_capacity(n);
 
         for _ in 0..n {
-            let position = position.unwrap_or(Position {
-                x: rand::random_range(0..*self.env.get_width() as i32),
-                y: rand::random_range(0..*self.env.get_heigth() as i32),
-            });
+            let position = position.unwrap_or_else(|| self.env.get_spawn_position());
 
             let new_agent = Rc::new(RefCell::new(Agent::Swarm(SwarmAgent::new(
                 self.generate_id(),
@@ -204,38 +346,214 @@ impl Scheduler {
         }
     }
 
+    /// Give the agents of a type experience replay, with a buffer each or one for all of them
+    /// (`ReplayConfig::shared`). `None` turns it off.
+    pub fn set_replay(&mut self, agent_type: &str, config: Option<ReplayConfig>) {
+        let Some(agents) = self.agents_per_types.get(agent_type) else {
+            return;
+        };
+
+        let new_buffer = || config.map(|config| Rc::new(RefCell::new(ReplayBuffer::new(config))));
+        let shared = new_buffer().filter(|_| config.is_some_and(|config| config.shared));
+        for agent in agents {
+            let replay = shared.clone().or_else(new_buffer);
+            match &mut *agent.borrow_mut() {
+                Agent::Learning(agent) => agent.replay = replay,
+                Agent::Swarm(agent) => agent.replay = replay,
+            }
+        }
+    }
+
+    /// Make the agents of a type plan with Dyna-Q, each with its own model. `None` turns it
+    /// off.
+    pub fn set_dyna(&mut self, agent_type: &str, config: Option<DynaConfig>) {
+        let Some(agents) = self.agents_per_types.get(agent_type) else {
+            return;
+        };
+
+        for agent in agents {
+            let dyna = config.map(Dyna::new);
+            match &mut *agent.borrow_mut() {
+                Agent::Learning(agent) => agent.dyna = dyna,
+                Agent::Swarm(agent) => agent.dyna = dyna,
+            }
+        }
+    }
+
+    /// Give a body in the continuous space to every agent that does not have one yet.
+    /// The body is placed at the center of the agent's cell with a random heading.
+    pub fn place_agents_in_space(&mut self) {
+        let size = *self.env.get_grid_size();
+        let Env { space, rng, .. } = &mut self.env;
+        let Some(space) = space else {
+            return;
+        };
+
+        for (position, _, agent) in &self.agents {
+            let id = agent.borrow().get_unique_id();
+            if !space.contains(id) {
+                let center = space.cell_center(*position, &size);
+                let heading = rng.random_range(0.0..std::f32::consts::TAU);
+                space.place(id, Body::new(center, heading));
+            }
+        }
+    }
+
+    /// Move every agent to a spawn position, for a new episode. The agents keep their
+    /// Q-tables.
+    pub fn respawn_agents(&mut self) {
+        for (position, _, _) in &mut self.agents {
+            *position = self.env.get_spawn_position();
+        }
+    }
+
+    /// Advance the simulation by one tick: the events until then, then the stepped agents
     pub fn take_step(&mut self) {
-        // Iterate over agents and remove those that are done
-        for i in (0..self.agents.len()).rev() {
-            let mut remove = false;
-            {
-                let (position, _, agent) = &mut self.agents[i];
+        self.run_until((self.env.tick + 1) as Time);
+    }
 
-                let (new_position, done) = self.env.step(*position, agent);
+    /// Agents of this type stop acting every tick and only act when an `Event::Activate`
+    /// targets them. Their first activation is scheduled at `first_activation`, the next ones
+    /// are scheduled by their step function (see `Env::schedule_activation`).
+    pub fn drive_by_events(&mut self, agent_type: &str, first_activation: Time) {
+        let Some(agents) = self.agents_per_types.get(agent_type) else {
+            return;
+        };
+
+        for agent in agents {
+            let id = agent.borrow().get_unique_id();
+            if self.event_driven.insert(id) {
+                self.env
+                    .events
+                    .schedule_at(first_activation, 0, Event::Activate(id));
+            }
+        }
+    }
 
-                // update new position
-                *position = new_position;
+    /// Process the events and the ticks in chronological order until the clock reaches `until`.
+    /// Tick `n` happens at time `n`, after the events of that time.
+    pub fn run_until(&mut self, until: Time) {
+        loop {
+            let next_tick = (self.env.tick + 1) as Time;
+            let next_event = self.env.events.peek_time();
 
-                let agent = agent.borrow();
-                if done {
-                    println!("DONE");
+            match next_event {
+                Some(time) if time <= next_tick && time <= until => {
+                    self.next_event();
+                }
+                _ if next_tick <= until => {
+                    self.env.events.advance_to(next_tick);
+                    self.tick();
+                }
+                _ => {
+                    self.env.events.advance_to(until);
+                    break;
+                }
+            }
+        }
+    }
 
-                    remove = true;
+    /// Process only the next event, moving the clock to its time. Returns false if there is none.
+    pub fn next_event(&mut self) -> bool {
+        let Some((_, event)) = self.env.events.pop() else {
+            return false;
+        };
+
+        match event {
+            Event::Activate(id) => {
+                // The agent may be done already
+                if let Some(i) = self
+                    .agents
+                    .iter()
+                    .position(|(_, _, agent)| agent.borrow().get_unique_id() == id)
+                {
+                    if let Some(id) = self.step_agent(i) {
+                        self.remove_agents(&HashSet::from([id]));
+                    }
+                }
+            }
+            Event::Env(env_event_fn) => env_event_fn(&mut self.env),
+        }
+
+        true
+    }
+
+    /// Every stepped agent takes a step, in the order given by `activation`
+    fn tick(&mut self) {
+        self.run_pre_step(&self.agent_positions());
+
+        let order = self.activation_order();
+        let mut done = HashSet::new();
+
+        match self.activation.clone() {
+            Activation::Sequential | Activation::Random | Activation::RandomByType => {
+                for i in order {
+                    if let Some(id) = self.step_agent(i) {
+                        done.insert(id);
+                    }
+                }
+            }
+            Activation::Simultaneous(resolution) => {
+                // Everyone chooses from the same world before anything changes
+                let actions: Vec<Action> = order
+                    .iter()
+                    .map(|i| self.env.choose(&self.agents[*i].2))
+                    .collect();
+                let previous: Vec<Position> = order.iter().map(|i| self.agents[*i].0).collect();
+
+                // The losers of a conflict are known before anyone steps, so they neither
+                // change the world nor learn from a move they do not make
+                let steps = self.env.with_rng(|env, rng| {
+                    simultaneous_steps(env, &previous, &actions, &resolution, rng)
+                });
+                for ((i, action), step) in order.iter().zip(actions).zip(steps) {
+                    if !step {
+                        continue;
+                    }
 
-                    match self.agents_per_types.get_mut(agent.get_type()) {
-                      // NOTE: Could be replaced by hashmap for faster delete
-                      Some(agents) => agents.retain(|a| a.borrow().get_unique_id() != agent.get_unique_id()),
-                      None => panic!("Trying to remove agent from inexisting type. This is not supposed to be possible :|"),
+                    let (position, _, agent) = &mut self.agents[*i];
+                    let (new_position, is_done) = self.env.apply(*position, agent, action);
+                    *position = new_position;
+                    if is_done {
+                        done.insert(agent.borrow().get_unique_id());
                     }
                 }
             }
+            Activation::Staged { stages, shuffle } => {
+                for stage in stages {
+                    let mut order = order.clone();
+                    if shuffle {
+                        order.shuffle(&mut self.env.rng);
+                    }
 
-            // Remove agent if done
-            if remove {
-                self.agents.remove(i);
+                    for i in order {
+                        let id = self.agents[i].2.borrow().get_unique_id();
+                        if done.contains(&id) {
+                            continue;
+                        }
+
+                        match &stage {
+                            Stage::Step => {
+                                if let Some(id) = self.step_agent(i) {
+                                    done.insert(id);
+                                }
+                            }
+                            Stage::Each(stage_fn) => {
+                                let (position, _, agent) = &self.agents[i];
+                                let new_position = stage_fn(&mut self.env, *position, agent);
+                                self.agents[i].0 = new_position;
+                            }
+                        }
+                    }
+                }
             }
         }
 
+        // Remove the agents that are done
+        self.remove_agents(&done);
+
+        self.run_post_step(&self.agent_positions());
+
         // DEBUG
         // println!("nb agents in agents: {}", self.agents.len());
         // println!("nb agents per types:");
@@ -244,19 +562,98 @@ impl Scheduler {
         // }
     }
 
+    /// Indices of the agents acting every tick in the order of `activation`
+    fn activation_order(&mut self) -> Vec<usize> {
+        let stepped: Vec<usize> = (0..self.agents.len())
+            .rev()
+            .filter(|i| {
+                let id = self.agents[*i].2.borrow().get_unique_id();
+                !self.event_driven.contains(&id)
+            })
+            .collect();
+
+        let rng = &mut self.env.rng;
+        match self.activation {
+            Activation::Sequential | Activation::Staged { .. } => stepped,
+            Activation::Random | Activation::Simultaneous(_) => {
+                let mut order = stepped;
+                order.shuffle(rng);
+                order
+            }
+            Activation::RandomByType => {
+                let types: Vec<&'static str> = stepped
+                    .iter()
+                    .map(|i| self.agents[*i].2.borrow().get_type())
+                    .collect();
+
+                let mut groups = group_by_type(&types);
+                groups.shuffle(rng);
+                groups
+                    .into_iter()
+                    .flat_map(|mut group| {
+                        group.shuffle(rng);
+                        group.into_iter().map(|j| stepped[j]).collect::<Vec<_>>()
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Step the agent at index `i`. Returns its unique id if it is done.
+    fn step_agent(&mut self, i: usize) -> Option<u32> {
+        let (position, _, agent) = &mut self.agents[i];
+
+        let (new_position, done) = self.env.step(*position, agent);
+
+        // update new position
+        *position = new_position;
+
+        done.then(|| agent.borrow().get_unique_id())
+    }
+
+    /// Remove agents by their unique ids
+    fn remove_agents(&mut self, ids: &HashSet<u32>) {
+        if ids.is_empty() {
+            return;
+        }
+
+        for (_, _, agent) in &self.agents {
+            let agent = agent.borrow();
+            if !ids.contains(&agent.get_unique_id()) {
+                continue;
+            }
+            println!("DONE");
+
+            if let Some(space) = &mut self.env.space {
+                space.remove(agent.get_unique_id());
+            }
+            self.event_driven.remove(&agent.get_unique_id());
+
+            match self.agents_per_types.get_mut(agent.get_type()) {
+              // NOTE: Could be replaced by hashmap for faster delete
+              Some(agents) => agents.retain(|a| a.borrow().get_unique_id() != agent.get_unique_id()),
+              None => panic!("Trying to remove agent from inexisting type. This is not supposed to be possible :|"),
+            }
+        }
+
+        self.agents
+            .retain(|(_, _, agent)| !ids.contains(&agent.borrow().get_unique_id()));
+    }
+
     pub fn save_q_table_to_file(
         &mut self,
         agent: &mut AgentRef,
         nb_steps: u32,
         filepath: &str,
         show_progression: bool,
-    ) {
+    ) -> Result<(), QTableError> {
         let mut position = IVec2 { x: 0, y: 0 };
 
         // Splitted like this for performance reasons.
         // When there is a lot of steps, checking if progression is shown each step would be slower than just once before
         if show_progression {
             for step in 0..nb_steps {
+                self.run_pre_step(&[position]);
                 let (new_position, done) = self.env.step(position, agent);
 
                 // update position
@@ -266,6 +663,8 @@ impl Scheduler {
                     position = new_position;
                 }
 
+                self.run_post_step(&[position]);
+
                 // Print progression
                 println!(
                     "Training agents progressions: {}%",
@@ -274,6 +673,7 @@ impl Scheduler {
             }
         } else {
             for _ in 0..nb_steps {
+                self.run_pre_step(&[position]);
                 let (new_position, done) = self.env.step(position, agent);
 
                 // update position
@@ -282,15 +682,37 @@ impl Scheduler {
                 } else {
                     position = new_position;
                 }
+
+                self.run_post_step(&[position]);
             }
         }
 
-        agent.borrow().save_q_table(filepath);
+        agent.borrow().save_q_table(filepath)
+    }
+
+    /// Save the Q-table of the first agent of each type in `directory/<agent_type>.bin`.
+    /// Returns the paths of the files, sorted by type.
+    pub fn save_q_tables(&self, directory: &str) -> Result<Vec<PathBuf>, QTableError> {
+        let mut agent_types: Vec<&&'static str> = self.agents_per_types.keys().collect();
+        agent_types.sort();
+
+        let mut filepaths = Vec::new();
+        for agent_type in agent_types {
+            if let Some(agent) = self.agents_per_types[*agent_type].first() {
+                let filepath = Path::new(directory).join(format!("{}.bin", agent_type));
+                agent.borrow().save_q_table(&filepath.to_string_lossy())?;
+                filepaths.push(filepath);
+            }
+        }
+
+        Ok(filepaths)
     }
 
     /// Train all the agent in the scheduler individually
-    pub fn train_agents(&mut self, nb_steps: u32) {
+    pub fn train_agents(&mut self, nb_steps: u32) -> Result<(), QTableError> {
         for step in 0..nb_steps {
+            self.run_pre_step(&self.agent_positions());
+
             for i in (0..self.agents.len()).rev() {
                 let (position, _, agent) = &mut self.agents[i];
 
@@ -300,6 +722,8 @@ impl Scheduler {
                 *position = new_position;
             }
 
+            self.run_post_step(&self.agent_positions());
+
             // Print progression
             println!(
                 "Training agents progressions: {}%",
@@ -314,17 +738,13 @@ impl Scheduler {
             // }
         }
 
-        for (agent_type, agents) in self.agents_per_types.clone() {
-            if let Some(agent) = agents.get(0) {
-                agent.borrow().save_q_table(&format!("{}.bin", agent_type));
-                // println!("\t agent_type: {}, nb: {}", agent_type, agents.len());
-            }
-        }
+        self.save_q_tables(".")?;
         // DEBUG
         // println!("nb agents in agents: {}", self.agents.len());
         // println!("nb agents per types:");
         // for (agent_type, agents) in self.agents_per_types.clone() {
         //     println!("\t agent_type: {}, nb: {}", agent_type, agents.len());
         // }
+        Ok(())
     }
 }
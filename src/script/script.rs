@@ -0,0 +1,768 @@
+use std::{cell::RefCell, collections::BTreeMap, fmt, fs, io, mem, rc::Rc};
+
+use macroquad::{color::Color, math::Vec2};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT, INT};
+
+use crate::{
+    agent::{
+        agent::{Action, Done, IsAgent, Reward, StepFunction},
+        learning_agent::LearningAgent,
+        state::{State, Value},
+    },
+    environment::{
+        environment::Env,
+        movement::{MoveOutcome, MovementSet},
+        registry::Key,
+    },
+    interface::grid::GridSize,
+    scenario::{
+        scenario_file::parse_color,
+        steps::{RewardFunction, MOVEMENTS},
+    },
+    scheduler::scheduler::{EnvStepFunction, Position},
+};
+
+/// Errors of the scripts while the simulation runs
+pub const SCRIPT_ERRORS: Key<ScriptErrors> = Key::new("script_errors");
+
+/// Operations a call to a script can make before it is stopped, so an endless loop is an
+/// error instead of a frozen simulation
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A Rhai script implementing step functions, rewards or environment updates.
+///
+/// The script defines any of:
+/// - `fn step(env, agent, state, action)` returning `#{ position, state, reward, done }`.
+///   Missing keys keep the position and the state, with a reward of 0 and `done` false.
+/// - `fn reward(env, agent, position, outcome)` returning the reward of a move
+/// - `fn update(env, positions)` called each tick after the agents
+///
+/// Positions are arrays `[x, y]`, states are arrays of `Value`s (see `to_dynamic`), `agent`
+/// is `#{ id, type, position }` and `outcome` is `#{ position, moved, blocked }`.
+///
+/// `env` is a safe view of the environment:
+/// - `env.width`, `env.height`, `env.tick`
+/// - `env.try_move(position, action)`: outcome of a move, without moving anything
+/// - `env.passable(position)`, `env.random_position()`, `env.spawn_position()`
+/// - `env.element(position)` (`#rrggbb` or `()`), `env.set_element(position, color)`,
+///   `env.remove_element(position)`
+/// - `env.field(id, position)`, `env.deposit(id, position, amount)`,
+///   `env.resource(id, position)`, `env.harvest(id, position, amount)`
+/// - `env.get(key)` and `env.set(key, value)` on the data of the environment
+///
+/// An error in a script never stops the simulation: the agent stays where it is with a
+/// reward of 0, and the error is recorded in `SCRIPT_ERRORS`.
+///
+/// ## Example
+/// ```rust
+/// let script = Script::compile(
+///     "walker",
+///     r#"
+///         fn step(env, agent, state, action) {
+///             let outcome = env.try_move(agent.position, action);
+///             #{ position: outcome.position, state: outcome.position, reward: -1.0 }
+///         }
+///     "#,
+/// )?;
+/// scheduler.add_agents(10, None, YELLOW, "walker", vec![], None, None, None,
+///     &script.step_function()?, None)?;
+/// ```
+#[derive(Clone)]
+pub struct Script {
+    name: String,
+    engine: Rc<Engine>,
+    ast: Rc<AST>,
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io {
+        filepath: String,
+        error: io::Error,
+    },
+    /// Syntax error, with its line and position
+    Compile {
+        name: String,
+        message: String,
+    },
+    /// The script does not define the function with this number of parameters
+    MissingFunction {
+        name: String,
+        function: &'static str,
+        parameters: usize,
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Io { filepath, error } => {
+                write!(f, "could not read {}: {}", filepath, error)
+            }
+            ScriptError::Compile { name, message } => write!(f, "{}: {}", name, message),
+            ScriptError::MissingFunction {
+                name,
+                function,
+                parameters,
+            } => write!(
+                f,
+                "{}: expected a function `{}` with {} parameters",
+                name, function, parameters
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Errors of the scripts, recorded in `SCRIPT_ERRORS` instead of stopping the simulation
+#[derive(Clone, Debug, Default)]
+pub struct ScriptErrors {
+    /// Errors of `step` and `reward`, by unique id of agent
+    pub agents: BTreeMap<u32, ScriptErrorLog>,
+    /// Errors of `update`
+    pub env: Option<ScriptErrorLog>,
+}
+
+impl ScriptErrors {
+    /// Number of errors so far
+    pub fn count(&self) -> u32 {
+        self.agents.values().map(|log| log.count).sum::<u32>()
+            + self.env.as_ref().map_or(0, |log| log.count)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ScriptErrorLog {
+    pub count: u32,
+    /// Tick of the last error
+    pub tick: u64,
+    /// Message of the last error
+    pub message: String,
+}
+
+impl Script {
+    pub fn load(filepath: &str) -> Result<Script, ScriptError> {
+        let source = fs::read_to_string(filepath).map_err(|error| ScriptError::Io {
+            filepath: filepath.to_string(),
+            error,
+        })?;
+
+        Script::compile(filepath, &source)
+    }
+
+    /// **name** is used in the error messages
+    pub fn compile(name: &str, source: &str) -> Result<Script, ScriptError> {
+        let engine = engine();
+        let ast = engine
+            .compile(source)
+            .map_err(|error| ScriptError::Compile {
+                name: name.to_string(),
+                message: error.to_string(),
+            })?;
+
+        Ok(Script {
+            name: name.to_string(),
+            engine: Rc::new(engine),
+            ast: Rc::new(ast),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the script defines `function` with this number of parameters
+    pub fn has_function(&self, function: &str, parameters: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == function && f.params.len() == parameters)
+    }
+
+    fn require(&self, function: &'static str, parameters: usize) -> Result<(), ScriptError> {
+        if self.has_function(function, parameters) {
+            Ok(())
+        } else {
+            Err(ScriptError::MissingFunction {
+                name: self.name.clone(),
+                function,
+                parameters,
+            })
+        }
+    }
+
+    /// Step function calling `fn step(env, agent, state, action)`
+    pub fn step_function<A: IsAgent + 'static>(&self) -> Result<StepFunction<A>, ScriptError> {
+        self.require("step", 4)?;
+        let script = self.clone();
+
+        Ok(Rc::new(
+            move |agent: &A,
+                  env: &mut Env,
+                  position: Position,
+                  state: &State,
+                  action: &Action|
+                  -> (Position, State, Reward, Done) {
+                let id = agent.get_unique_id();
+                let args = vec![
+                    agent_map(agent, position),
+                    Dynamic::from_array(state.iter().map(to_dynamic).collect()),
+                    (*action as INT).into(),
+                ];
+
+                let result = script
+                    .call(env, "step", args)
+                    .and_then(|result| step_result(result, position, state));
+                match result {
+                    Ok(result) => result,
+                    Err(message) => {
+                        script.report(env, Some(id), message);
+                        (position, state.clone(), 0., false)
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Reward calling `fn reward(env, agent, position, outcome)`, for the step functions of
+    /// `StepRegistry`
+    pub fn reward_function(&self) -> Result<RewardFunction, ScriptError> {
+        self.require("reward", 4)?;
+        let script = self.clone();
+
+        Ok(Rc::new(
+            move |agent: &LearningAgent,
+                  env: &mut Env,
+                  position: Position,
+                  outcome: &MoveOutcome| {
+                let args = vec![
+                    agent_map(agent, position),
+                    from_position(position).into(),
+                    outcome_map(outcome),
+                ];
+
+                let result = script.call(env, "reward", args).and_then(|reward| {
+                    reward
+                        .as_float()
+                        .or_else(|_| reward.as_int().map(|reward| reward as FLOAT))
+                        .map(|reward| reward as Reward)
+                        .map_err(|found| {
+                            format!("`reward` returned a {} instead of a number", found)
+                        })
+                });
+                result.unwrap_or_else(|message| {
+                    script.report(env, Some(agent.get_unique_id()), message);
+                    0.
+                })
+            },
+        ))
+    }
+
+    /// Environment update calling `fn update(env, positions)`
+    pub fn env_step_function(&self) -> Result<EnvStepFunction, ScriptError> {
+        self.require("update", 2)?;
+        let script = self.clone();
+
+        Ok(Rc::new(move |env: &mut Env, positions: &[Position]| {
+            let positions: Array = positions.iter().map(|p| from_position(*p).into()).collect();
+            if let Err(message) = script.call(env, "update", vec![positions.into()]) {
+                script.report(env, None, message);
+            }
+        }))
+    }
+
+    /// Call a function of the script with the environment as first argument
+    fn call(&self, env: &mut Env, function: &str, args: Vec<Dynamic>) -> Result<Dynamic, String> {
+        // The script gets a shared handle on the environment for the length of the call
+        let shared = ScriptEnv(Rc::new(RefCell::new(mem::replace(env, placeholder_env()))));
+        let mut all_args = vec![Dynamic::from(shared.clone())];
+        all_args.extend(args);
+        let result =
+            self.engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, all_args);
+        *env = shared.0.replace(placeholder_env());
+
+        result.map_err(|error| error.to_string())
+    }
+
+    /// Record an error, printed the first time for each agent
+    fn report(&self, env: &mut Env, agent_id: Option<u32>, message: String) {
+        let tick = env.tick;
+        let errors = env
+            .registry
+            .get_or_insert_with(SCRIPT_ERRORS, ScriptErrors::default);
+
+        let log = match agent_id {
+            Some(id) => errors.agents.entry(id).or_default(),
+            None => errors.env.get_or_insert_with(ScriptErrorLog::default),
+        };
+        log.count += 1;
+        log.tick = tick;
+        log.message = message;
+
+        if log.count == 1 {
+            match agent_id {
+                Some(id) => eprintln!(
+                    "{} (agent {}, tick {}): {}",
+                    self.name, id, tick, log.message
+                ),
+                None => eprintln!("{} (tick {}): {}", self.name, tick, log.message),
+            }
+        }
+    }
+}
+
+/// Environment seen by the scripts, only valid during a call
+#[derive(Clone)]
+struct ScriptEnv(Rc<RefCell<Env>>);
+
+/// Stands in for the environment while a script holds it
+fn placeholder_env() -> Env {
+    Env::new(
+        Vec2::ZERO,
+        Vec2::ONE,
+        GridSize {
+            width: 1,
+            heigth: 1,
+        },
+        Default::default(),
+        &[],
+        Default::default(),
+    )
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    engine
+        .register_type_with_name::<ScriptEnv>("Env")
+        .register_get("width", |env: &mut ScriptEnv| {
+            *env.0.borrow().get_width() as INT
+        })
+        .register_get("height", |env: &mut ScriptEnv| {
+            *env.0.borrow().get_heigth() as INT
+        })
+        .register_get("tick", |env: &mut ScriptEnv| env.0.borrow().tick as INT)
+        .register_fn(
+            "try_move",
+            |env: &mut ScriptEnv, position: Array, action: INT| -> ScriptResult<Map> {
+                let env = env.0.borrow();
+                let outcome = env.apply_move(
+                    to_position(&position)?,
+                    action as Action,
+                    env.registry.get(MOVEMENTS).unwrap_or(&MovementSet::Four),
+                );
+                Ok(outcome_map(&outcome).cast())
+            },
+        )
+        .register_fn(
+            "passable",
+            |env: &mut ScriptEnv, position: Array| -> ScriptResult<bool> {
+                Ok(env.0.borrow().passable(to_position(&position)?))
+            },
+        )
+        .register_fn("random_position", |env: &mut ScriptEnv| {
+            from_position(env.0.borrow_mut().get_random_position())
+        })
+        .register_fn("spawn_position", |env: &mut ScriptEnv| {
+            from_position(env.0.borrow_mut().get_spawn_position())
+        })
+        .register_fn(
+            "element",
+            |env: &mut ScriptEnv, position: Array| -> ScriptResult<Dynamic> {
+                let env = env.0.borrow();
+                Ok(env
+                    .persistent_elements
+                    .get(&to_position(&position)?)
+                    .map_or(Dynamic::UNIT, |color| color_string(*color).into()))
+            },
+        )
+        .register_fn(
+            "set_element",
+            |env: &mut ScriptEnv, position: Array, color: &str| -> ScriptResult<()> {
+                let color =
+                    parse_color(color).ok_or_else(|| format!("unknown color {:?}", color))?;
+                env.0
+                    .borrow_mut()
+                    .update_persistent_element(to_position(&position)?, color);
+                Ok(())
+            },
+        )
+        .register_fn(
+            "remove_element",
+            |env: &mut ScriptEnv, position: Array| -> ScriptResult<()> {
+                let position = to_position(&position)?;
+                let mut env = env.0.borrow_mut();
+                // Walls are part of the map, not of the behaviour
+                if !env.obstacles.contains(&position) {
+                    env.persistent_elements.remove(&position);
+                }
+                Ok(())
+            },
+        )
+        .register_fn(
+            "field",
+            |env: &mut ScriptEnv, id: INT, position: Array| -> ScriptResult<FLOAT> {
+                let position = to_position(&position)?;
+                Ok(env
+                    .0
+                    .borrow()
+                    .field(id as u32)
+                    .map_or(0., |field| field.get(position) as FLOAT))
+            },
+        )
+        .register_fn(
+            "deposit",
+            |env: &mut ScriptEnv, id: INT, position: Array, amount: FLOAT| -> ScriptResult<FLOAT> {
+                let position = to_position(&position)?;
+                Ok(env
+                    .0
+                    .borrow_mut()
+                    .field_mut(id as u32)
+                    .map_or(0., |field| field.deposit(position, amount as f32) as FLOAT))
+            },
+        )
+        .register_fn(
+            "resource",
+            |env: &mut ScriptEnv, id: INT, position: Array| -> ScriptResult<FLOAT> {
+                let position = to_position(&position)?;
+                Ok(env
+                    .0
+                    .borrow()
+                    .resource(id as u32)
+                    .map_or(0., |resource| resource.get(position) as FLOAT))
+            },
+        )
+        .register_fn(
+            "harvest",
+            |env: &mut ScriptEnv, id: INT, position: Array, amount: FLOAT| -> ScriptResult<FLOAT> {
+                let position = to_position(&position)?;
+                Ok(env
+                    .0
+                    .borrow_mut()
+                    .harvest(id as u32, position, amount as f32) as FLOAT)
+            },
+        )
+        .register_fn("get", |env: &mut ScriptEnv, key: INT| {
+            env.0
+                .borrow()
+                .data
+                .get(&(key as u32))
+                .map_or(Dynamic::UNIT, to_dynamic)
+        })
+        .register_fn(
+            "set",
+            |env: &mut ScriptEnv, key: INT, value: Dynamic| -> ScriptResult<()> {
+                env.0
+                    .borrow_mut()
+                    .data
+                    .insert(key as u32, from_dynamic(value)?);
+                Ok(())
+            },
+        );
+
+    engine
+}
+
+/// `Value` seen by a script: integers, floats, strings, booleans, arrays (`VPair` and
+/// `VVec`) and object maps (`VMap`, the keys written as strings)
+pub fn to_dynamic(value: &Value) -> Dynamic {
+    match value {
+        Value::VI32(value) => (*value as INT).into(),
+        Value::VU32(value) => (*value as INT).into(),
+        Value::VFloat(bits) => (f32::from_bits(*bits) as FLOAT).into(),
+        Value::VString(value) => value.clone().into(),
+        Value::VBool(value) => (*value).into(),
+        Value::VPair((first, second)) => {
+            Dynamic::from_array(vec![to_dynamic(first), to_dynamic(second)])
+        }
+        Value::VVec(values) => Dynamic::from_array(values.iter().map(to_dynamic).collect()),
+        Value::VMap(map) => Dynamic::from_map(
+            map.iter()
+                .map(|(key, value)| (to_dynamic(key).to_string().into(), to_dynamic(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// `Value` from a script. Integers are `VI32`, arrays are `VVec` and object maps are `VMap`
+/// with `VString` keys.
+pub fn from_dynamic(value: Dynamic) -> Result<Value, String> {
+    if let Ok(value) = value.as_int() {
+        return i32::try_from(value)
+            .map(Value::VI32)
+            .map_err(|_| format!("{} does not fit in a state value", value));
+    }
+    if let Ok(value) = value.as_float() {
+        return Ok(Value::VFloat((value as f32).to_bits()));
+    }
+    if let Ok(value) = value.as_bool() {
+        return Ok(Value::VBool(value));
+    }
+
+    let type_name = value.type_name();
+    if value.is_string() {
+        return Ok(Value::VString(value.into_string().unwrap()));
+    }
+    if value.is_array() {
+        return value
+            .into_array()
+            .unwrap()
+            .into_iter()
+            .map(from_dynamic)
+            .collect::<Result<_, _>>()
+            .map(Value::VVec);
+    }
+    if value.is_map() {
+        return value
+            .cast::<Map>()
+            .into_iter()
+            .map(|(key, value)| Ok((Value::VString(key.to_string()), from_dynamic(value)?)))
+            .collect::<Result<_, String>>()
+            .map(Value::VMap);
+    }
+
+    Err(format!("a {} cannot be a state value", type_name))
+}
+
+/// `(position, state, reward, done)` from the map returned by `step`
+fn step_result(
+    result: Dynamic,
+    position: Position,
+    state: &State,
+) -> Result<(Position, State, Reward, Done), String> {
+    let type_name = result.type_name();
+    let mut result = result
+        .try_cast::<Map>()
+        .ok_or_else(|| format!("`step` returned a {} instead of a map", type_name))?;
+
+    let new_position = match result.remove("position") {
+        Some(new_position) => {
+            let array = new_position
+                .into_array()
+                .map_err(|found| format!("`position` is a {} instead of [x, y]", found))?;
+            to_position(&array).map_err(|error| error.to_string())?
+        }
+        None => position,
+    };
+    let new_state = match result.remove("state") {
+        Some(new_state) if new_state.is_array() => new_state
+            .into_array()
+            .unwrap()
+            .into_iter()
+            .map(from_dynamic)
+            .collect::<Result<_, _>>()?,
+        // A single value is a state of one value
+        Some(new_state) => vec![from_dynamic(new_state)?],
+        None => state.clone(),
+    };
+    let reward = match result.remove("reward") {
+        Some(reward) => reward
+            .as_float()
+            .or_else(|_| reward.as_int().map(|reward| reward as FLOAT))
+            .map_err(|found| format!("`reward` is a {} instead of a number", found))?,
+        None => 0.,
+    };
+    let done = match result.remove("done") {
+        Some(done) => done
+            .as_bool()
+            .map_err(|found| format!("`done` is a {} instead of a bool", found))?,
+        None => false,
+    };
+
+    Ok((new_position, new_state, reward as Reward, done))
+}
+
+fn agent_map(agent: &impl IsAgent, position: Position) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("id".into(), (agent.get_unique_id() as INT).into());
+    map.insert("type".into(), agent.get_type().into());
+    map.insert("position".into(), from_position(position).into());
+    Dynamic::from_map(map)
+}
+
+fn outcome_map(outcome: &MoveOutcome) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("position".into(), from_position(outcome.position()).into());
+    map.insert(
+        "moved".into(),
+        matches!(outcome, MoveOutcome::Moved(_)).into(),
+    );
+    map.insert("blocked".into(), outcome.is_blocked().into());
+    Dynamic::from_map(map)
+}
+
+fn to_position(array: &Array) -> ScriptResult<Position> {
+    match array.as_slice() {
+        [x, y] if x.is_int() && y.is_int() => Ok(Position {
+            x: x.as_int().unwrap() as i32,
+            y: y.as_int().unwrap() as i32,
+        }),
+        _ => Err(format!("expected a position [x, y], got {:?}", array).into()),
+    }
+}
+
+fn from_position(position: Position) -> Array {
+    vec![(position.x as INT).into(), (position.y as INT).into()]
+}
+
+fn color_string(color: Color) -> String {
+    let [r, g, b, _]: [u8; 4] = color.into();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use macroquad::{color::RED, math::vec2};
+
+    use super::*;
+    use crate::{environment::movement::MOVES_4, scheduler::scheduler::Scheduler};
+
+    const WALKER: &str = r#"
+        fn step(env, agent, state, action) {
+            let outcome = env.try_move(agent.position, action);
+            env.set(agent.id, outcome.position);
+            if outcome.position[0] == 0 {
+                env.set_element(outcome.position, "red");
+            }
+            #{ position: outcome.position, state: [outcome.blocked, "moved"], reward: -1 }
+        }
+
+        fn update(env, positions) {
+            env.set(100, env.tick * 10 + positions.len());
+        }
+    "#;
+
+    fn scheduler(source: &str, agents: usize) -> Scheduler {
+        let env = Env::new(
+            vec2(0., 0.),
+            vec2(1., 1.),
+            GridSize {
+                width: 4,
+                heigth: 4,
+            },
+            HashMap::new(),
+            MOVES_4,
+            HashMap::new(),
+        );
+
+        let script = Script::compile("test", source).unwrap();
+        let mut scheduler = Scheduler::new(env);
+        scheduler
+            .add_agents(
+                agents,
+                Some(Position { x: 1, y: 1 }),
+                RED,
+                "walker",
+                vec![],
+                None,
+                None,
+                None,
+                &script.step_function().unwrap(),
+                None,
+            )
+            .unwrap();
+        if script.has_function("update", 2) {
+            scheduler.add_post_step(script.env_step_function().unwrap());
+        }
+        scheduler
+    }
+
+    #[test]
+    fn compile_errors() {
+        let error = Script::compile("broken", "fn step(env, agent { }")
+            .err()
+            .unwrap();
+        assert!(matches!(error, ScriptError::Compile { .. }));
+        assert!(error.to_string().starts_with("broken: "));
+
+        let script = Script::compile("reward only", "fn reward(env, agent, p, o) { 1 }").unwrap();
+        assert!(matches!(
+            script.step_function::<LearningAgent>(),
+            Err(ScriptError::MissingFunction {
+                function: "step",
+                ..
+            })
+        ));
+        assert!(script.reward_function().is_ok());
+    }
+
+    #[test]
+    fn scripted_step() {
+        let mut scheduler = scheduler(WALKER, 2);
+        for _ in 0..10 {
+            scheduler.take_step();
+        }
+
+        let env = &scheduler.env;
+        for (position, _, agent) in &scheduler.agents {
+            let id = agent.borrow().get_unique_id();
+            assert_eq!(
+                env.data[&id],
+                Value::VVec(vec![Value::VI32(position.x), Value::VI32(position.y)])
+            );
+            assert_eq!(
+                agent.borrow().get_state()[1],
+                Value::VString("moved".into())
+            );
+        }
+        assert_eq!(env.data[&100], Value::VI32(9 * 10 + 2));
+        assert!(env
+            .persistent_elements
+            .keys()
+            .all(|position| position.x == 0));
+        assert!(env.registry.get(SCRIPT_ERRORS).is_none());
+    }
+
+    #[test]
+    fn errors_are_recorded() {
+        let failing = r#"
+            fn step(env, agent, state, action) {
+                if agent.id == 1 { throw "stuck"; }
+                if agent.id == 2 { loop { } }
+                #{ position: [9, "nine"] }
+            }
+        "#;
+        let mut scheduler = scheduler(failing, 3);
+        for _ in 0..3 {
+            scheduler.take_step();
+        }
+
+        // Nobody moved and the simulation went on
+        assert_eq!(scheduler.env.tick, 3);
+        assert!(scheduler
+            .agents
+            .iter()
+            .all(|(position, _, _)| *position == Position { x: 1, y: 1 }));
+
+        let errors = scheduler.env.registry.get(SCRIPT_ERRORS).unwrap();
+        assert_eq!(errors.count(), 9);
+        assert_eq!(errors.agents[&1].tick, 2);
+        assert!(errors.agents[&1].message.contains("stuck"));
+        assert!(errors.agents[&2].message.contains("operations"));
+        assert!(errors.agents[&3].message.contains("position"));
+    }
+
+    #[test]
+    fn values() {
+        let value = Value::VVec(vec![
+            Value::VI32(-3),
+            Value::VBool(true),
+            Value::VFloat(0.5f32.to_bits()),
+            Value::VString("text".into()),
+        ]);
+        assert_eq!(from_dynamic(to_dynamic(&value)).unwrap(), value);
+
+        let pair = Value::VPair((Box::new(Value::VU32(1)), Box::new(Value::VU32(2))));
+        assert_eq!(
+            from_dynamic(to_dynamic(&pair)).unwrap(),
+            Value::VVec(vec![Value::VI32(1), Value::VI32(2)])
+        );
+        assert!(from_dynamic(Dynamic::from(i64::MAX)).is_err());
+    }
+}